@@ -1,5 +1,9 @@
-use clap::{Args, Parser, Subcommand};
+use clap::error::ErrorKind;
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
+use markdown_splitter::types::DEFAULT_PART_HEADING;
+use markdown_splitter::{ContentFetcher, ContentSelector};
 use std::path::PathBuf;
+use std::time::Duration;
 
 #[derive(Parser)]
 #[command(name = "md-split")]
@@ -10,62 +14,446 @@ pub struct Cli {
     pub command: Commands,
 
     /// Enable verbose logging
-    #[arg(short, long, global = true)]
+    #[arg(short, long, global = true, env = "MDSPLIT_VERBOSE")]
     pub verbose: bool,
 
     /// Output directory for split files
-    #[arg(short, long, global = true, default_value = "./output")]
+    #[arg(short, long, global = true, env = "MDSPLIT_OUTPUT", default_value = "./output")]
     pub output: PathBuf,
 }
 
+/// Environment variable holding whitespace-separated sources when none are given on the command line
+pub const SOURCES_ENV: &str = "MDSPLIT_SOURCES";
+
+impl Cli {
+    /// Fills empty source lists from `MDSPLIT_SOURCES`; errors if no sources are given either way.
+    ///
+    /// Every other option reads its `MDSPLIT_*` variable through clap, so the
+    /// precedence is: command-line flag, then environment, then built-in default.
+    pub fn resolve_env_sources(&mut self) -> Result<(), clap::Error> {
+        let sources = match &mut self.command {
+            Commands::Split(args) if args.content.is_some() => return Ok(()),
+            Commands::Analyze(args) if args.content.is_some() => return Ok(()),
+            Commands::Split(args) => &mut args.sources,
+            Commands::Analyze(args) => &mut args.sources,
+            Commands::Validate(args) => &mut args.sources,
+            Commands::Append(args) => &mut args.sources,
+            Commands::Resplit(_) | Commands::Schema(_) | Commands::Extract(_) | Commands::Format(_) | Commands::Locate(_) => {
+                return Ok(())
+            }
+        };
+
+        if sources.is_empty() {
+            if let Ok(value) = std::env::var(SOURCES_ENV) {
+                sources.extend(value.split_whitespace().map(str::to_string));
+            }
+        }
+
+        if sources.is_empty() {
+            return Err(Cli::command().error(
+                ErrorKind::MissingRequiredArgument,
+                format!("at least one SOURCE is required (or set {})", SOURCES_ENV),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
 #[derive(Subcommand)]
 pub enum Commands {
     /// Split markdown files into multiple parts
-    Split(SplitArgs),
+    Split(Box<SplitArgs>),
     
     /// Analyze markdown files without splitting
     Analyze(AnalyzeArgs),
     
     /// Validate input sources
     Validate(ValidateArgs),
+
+    /// Re-split a previous split run with new parameters
+    Resplit(ResplitArgs),
+
+    /// Print the JSON Schema of a JSON output file
+    Schema(SchemaArgs),
+
+    /// Print a page or range of pages (to stdout, or to the file given with -o)
+    Extract(ExtractArgs),
+
+    /// Append pages to a rotating series of files, rolling over at a size limit
+    Append(AppendArgs),
+
+    /// Show which page and split file a source line ends up in
+    Locate(LocateArgs),
+
+    /// Print the document as one normalized markdown file (to stdout, or to the file given with -o)
+    Format(FormatArgs),
 }
 
-#[derive(Args)]
+#[derive(Args, Clone)]
 pub struct SplitArgs {
-    /// Input sources (file paths or URLs)
-    #[arg(required = true, value_name = "SOURCE")]
+    /// Input sources (file paths, directories or URLs); defaults to whitespace-separated MDSPLIT_SOURCES
+    #[arg(value_name = "SOURCE")]
     pub sources: Vec<String>,
 
-    /// Number of splits to create
-    #[arg(short, long, default_value = "5")]
-    pub splits: usize,
+    /// Markdown given inline instead of as SOURCE arguments
+    #[arg(long, value_name = "MARKDOWN", conflicts_with = "sources")]
+    pub content: Option<String>,
+
+    /// File name stem for --content, used in output and report names [default: inline]
+    #[arg(long, value_name = "STEM", requires = "content")]
+    pub output_stem: Option<String>,
+
+    /// Name the source as NAME (e.g. report.md) in output file names and metadata instead of
+    /// its file name; handy for standard input, which is otherwise named "stdin"
+    #[arg(
+        long,
+        value_name = "NAME",
+        conflicts_with = "content",
+        value_parser = parse_source_name
+    )]
+    pub source_name: Option<String>,
+
+    /// Follow symlinks when scanning directory sources
+    #[arg(long, env = "MDSPLIT_FOLLOW_SYMLINKS")]
+    pub follow_symlinks: bool,
+
+    #[command(flatten)]
+    pub layout: SplitLayoutArgs,
 
     /// Preserve document structure with separators
-    #[arg(long, default_value = "true")]
+    #[arg(long, env = "MDSPLIT_PRESERVE_STRUCTURE", default_value = "true")]
     pub preserve_structure: bool,
 
     /// Include metadata file
-    #[arg(long, default_value = "true")]
+    #[arg(long, env = "MDSPLIT_INCLUDE_METADATA", default_value = "true")]
     pub include_metadata: bool,
 
-    /// Custom page break marker (regex pattern)
-    #[arg(long, value_name = "PATTERN")]
-    pub page_marker: Option<String>,
+    #[command(flatten)]
+    pub parsing: ParserArgs,
 
     /// Force overwrite existing output files
     #[arg(long)]
     pub force: bool,
+
+    /// Repeat the enclosing headings at the top of splits that start mid-section
+    #[arg(long, env = "MDSPLIT_HEADING_CONTEXT")]
+    pub heading_context: bool,
+
+    /// Logical source name hashed into chunk IDs instead of the file name
+    #[arg(long, value_name = "NAME", env = "MDSPLIT_SOURCE_ID")]
+    pub source_id: Option<String>,
+
+    /// Write each split's chunk ID into its header
+    #[arg(long, env = "MDSPLIT_CHUNK_ID_HEADER")]
+    pub chunk_id_header: bool,
+
+    /// Hard-wrap paragraph text at N columns (code, tables, headings and lists are kept)
+    #[arg(long, value_name = "N", env = "MDSPLIT_WRAP")]
+    pub wrap: Option<usize>,
+
+    /// Write a CSV index of all split files
+    #[arg(long, value_name = "FILE")]
+    pub csv_index: Option<PathBuf>,
+
+    /// Write <name>_TOC.md next to each source's splits, linking every split with its pages and title
+    #[arg(long, env = "MDSPLIT_TOC")]
+    pub toc: bool,
+
+    /// Print the files the split would write, with their pages and sizes, without writing anything
+    #[arg(long, env = "MDSPLIT_DRY_RUN")]
+    pub dry_run: bool,
+
+    /// Write one <name>_annotated.md with the --page-marker (default <!-- SPLIT n -->) at each split boundary instead of split files
+    #[arg(
+        long,
+        env = "MDSPLIT_ANNOTATE_ONLY",
+        conflicts_with_all = ["group_by_field", "shards", "toc", "extract_code", "filename_template", "format"]
+    )]
+    pub annotate_only: bool,
+
+    /// Line ending of the split files; auto keeps the one most source lines use
+    #[arg(long, value_enum, env = "MDSPLIT_LINE_ENDING", default_value = "auto")]
+    pub line_ending: LineEndingArg,
+
+    /// Write an XML sitemap of the split files (requires --base-url)
+    #[arg(long, value_name = "FILE", requires = "base_url", env = "MDSPLIT_SITEMAP")]
+    pub sitemap: Option<PathBuf>,
+
+    /// URL the output directory is published under, for --sitemap
+    #[arg(long, value_name = "URL", requires = "sitemap", env = "MDSPLIT_BASE_URL")]
+    pub base_url: Option<String>,
+
+    /// Record the split files of each source in a JSON manifest
+    #[arg(long, value_name = "FILE", env = "MDSPLIT_MANIFEST")]
+    pub manifest: Option<PathBuf>,
+
+    /// Replace an existing manifest, or merge this run's sources into it
+    #[arg(long, value_enum, env = "MDSPLIT_MANIFEST_MODE", default_value = "overwrite")]
+    pub manifest_mode: ManifestModeArg,
+
+    /// Collect warnings (oversized pages, merged pages, imbalance, cross-split anchors) into a JSON file
+    #[arg(long, value_name = "FILE", env = "MDSPLIT_WARNINGS_REPORT")]
+    pub warnings_report: Option<PathBuf>,
+
+    /// Also write each fenced code block to DIR/<name>_NNN.<ext>, named by its source and language
+    #[arg(long, value_name = "DIR", env = "MDSPLIT_EXTRACT_CODE")]
+    pub extract_code: Option<PathBuf>,
+
+    /// Open each split with a "# Part N: <title>" heading
+    #[arg(long, env = "MDSPLIT_PART_HEADINGS")]
+    pub part_headings: bool,
+
+    /// Text of the part heading; {index} and {title} are filled in per split
+    #[arg(
+        long,
+        value_name = "TEMPLATE",
+        env = "MDSPLIT_PART_HEADING_TEMPLATE",
+        default_value = DEFAULT_PART_HEADING,
+        requires = "part_headings"
+    )]
+    pub part_heading_template: String,
+
+    /// Repeat the document's leading <!-- key: value --> comment block in every split
+    #[arg(long, env = "MDSPLIT_PROPAGATE_COMMENT_METADATA")]
+    pub propagate_comment_metadata: bool,
+
+    /// Repeat the document's leading YAML (---) or TOML (+++) front matter at the top of every split
+    #[arg(long, env = "MDSPLIT_REPEAT_FRONT_MATTER")]
+    pub repeat_front_matter: bool,
+
+    /// Add a one-line summary (the first sentence of prose) to each split's header
+    #[arg(long, env = "MDSPLIT_SUMMARIES")]
+    pub summaries: bool,
+
+    /// Drop lines repeated on most pages, such as running headers and footers
+    #[arg(long, env = "MDSPLIT_STRIP_BOILERPLATE")]
+    pub strip_boilerplate: bool,
+
+    /// Leave the extracted code blocks out of the split files
+    #[arg(long, requires = "extract_code", env = "MDSPLIT_DROP_CODE")]
+    pub drop_code: bool,
+
+    /// Write split files, or one JSON list of base64 data URIs per source
+    #[arg(long, value_enum, env = "MDSPLIT_FORMAT", default_value = "files")]
+    pub format: OutputFormat,
+
+    /// Unix time written instead of the current time in metadata and manifests
+    #[arg(long, value_name = "SECONDS", env = "MDSPLIT_SOURCE_DATE_EPOCH")]
+    pub source_date_epoch: Option<i64>,
+
+    /// Rewrite #anchor links whose heading lands in another split to point at that file
+    #[arg(long, env = "MDSPLIT_FIX_CROSS_SPLIT_ANCHORS")]
+    pub fix_cross_split_anchors: bool,
+
+    /// Write the source type and path/URL into each split's header
+    #[arg(long, env = "MDSPLIT_TAG_ORIGIN")]
+    pub tag_origin: bool,
+
+    /// Retry writes failing with transient errors (EAGAIN, EBUSY) up to N times
+    #[arg(long, value_name = "N", env = "MDSPLIT_WRITE_RETRIES", default_value_t = 3)]
+    pub write_retries: usize,
+
+    /// Refuse input files larger than SIZE (e.g. 50M)
+    #[arg(long, value_name = "SIZE", env = "MDSPLIT_MAX_FILE_SIZE", value_parser = parse_size)]
+    pub max_file_size: Option<usize>,
+
+    /// Fetch URLs at most N times per second (fractions allowed); files are not affected
+    #[arg(long, value_name = "N", env = "MDSPLIT_RATE_LIMIT", value_parser = parse_rate)]
+    pub rate_limit: Option<f64>,
+
+    /// Serve URLs from responses saved in DIR, skipping the network on a hit
+    #[arg(long, value_name = "DIR", env = "MDSPLIT_FETCH_CACHE_DIR")]
+    pub fetch_cache_dir: Option<PathBuf>,
+
+    /// Save URL responses fetched from the network into --fetch-cache-dir
+    #[arg(long, requires = "fetch_cache_dir")]
+    pub fetch_cache_write: bool,
+
+    /// Give up on a URL request that has not completed after DURATION (e.g. 30s, 2m)
+    #[arg(long, value_name = "DURATION", env = "MDSPLIT_TIMEOUT", value_parser = parse_duration)]
+    pub timeout: Option<Duration>,
+
+    /// Retry URL requests failing with a connection error, timeout or 5xx status up to N times, with exponential backoff
+    #[arg(long, value_name = "N", env = "MDSPLIT_RETRIES", default_value = "0")]
+    pub retries: u32,
+
+    /// Pull the markdown out of a wrapper file first: a JSON/YAML path such as
+    /// '$.info.description' or '$.items[*].body', or a regex whose first group is the markdown
+    #[arg(long, value_name = "SELECTOR", env = "MDSPLIT_EXTRACT_FROM", value_parser = parse_selector)]
+    pub extract_from: Option<ContentSelector>,
+
+    /// Rejoin hard-wrapped lines from converted documents before parsing
+    #[arg(long, env = "MDSPLIT_DEWRAP")]
+    pub dewrap: bool,
+
+    /// With --dewrap, also pull wrapped continuation lines up into list items
+    #[arg(long, requires = "dewrap")]
+    pub dewrap_lists: bool,
+
+    /// Abort the batch (exit code 124) once it has run this long (e.g. 90s, 15m, 2h)
+    #[arg(long, value_name = "DURATION", env = "MDSPLIT_MAX_RUNTIME", value_parser = parse_duration)]
+    pub max_runtime: Option<Duration>,
+
+    /// Write a JSON report of the run: each source's split result or error and time taken, plus totals
+    #[arg(long, value_name = "FILE", env = "MDSPLIT_REPORT")]
+    pub report: Option<PathBuf>,
+
+    /// Fetch and split up to N sources at once; failed sources are reported at the end without stopping the others
+    #[arg(
+        long,
+        value_name = "N",
+        env = "MDSPLIT_CONCURRENCY",
+        default_value = "4",
+        value_parser = clap::value_parser!(u16).range(1..)
+    )]
+    pub concurrency: u16,
+}
+
+/// Options deciding which split each page lands in and what the split files are
+/// called, shared by `split` and `locate` so both plan the same files
+#[derive(Args, Clone)]
+pub struct SplitLayoutArgs {
+    /// Number of splits to create
+    #[arg(short, long, env = "MDSPLIT_SPLITS", default_value = "5")]
+    pub splits: usize,
+
+    /// Choose the number of splits from the document size (overrides --splits)
+    #[arg(long)]
+    pub auto_splits: bool,
+
+    /// Target size per split for --auto-splits (e.g. 40k, 2M)
+    #[arg(long, value_name = "SIZE", env = "MDSPLIT_TARGET_SIZE", default_value = "40k", value_parser = parse_size)]
+    pub target_size: usize,
+
+    /// Create as many splits as needed so none exceeds N words, keeping pages whole (overrides --splits)
+    #[arg(
+        long,
+        value_name = "N",
+        env = "MDSPLIT_MAX_WORDS",
+        conflicts_with_all = ["auto_splits", "balance", "section_max_pages", "section_max_bytes", "bisect_depth", "group_by_field"]
+    )]
+    pub max_words: Option<usize>,
+
+    /// Create as many splits as needed so none exceeds N estimated tokens, headers included (overrides --splits)
+    #[arg(
+        long,
+        value_name = "N",
+        env = "MDSPLIT_MAX_TOKENS",
+        conflicts_with_all = ["max_words", "auto_splits", "balance", "section_max_pages", "section_max_bytes", "bisect_depth", "group_by_field"]
+    )]
+    pub max_tokens: Option<usize>,
+
+    /// How --max-tokens estimates tokens
+    #[arg(long, value_enum, env = "MDSPLIT_TOKEN_MODEL", default_value = "bpe")]
+    pub token_model: TokenModelArg,
+
+    /// Balance splits by content size instead of giving each the same number of pages
+    #[arg(long, value_enum, value_name = "COST", env = "MDSPLIT_BALANCE")]
+    pub balance: Option<BalanceBy>,
+
+    /// Where leftover pages go when the page count does not divide evenly
+    #[arg(long, value_enum, env = "MDSPLIT_REMAINDER", default_value = "separate")]
+    pub remainder: RemainderArg,
+
+    /// Merge a trailing split smaller than SIZE into the previous split (e.g. 2k)
+    #[arg(long, value_name = "SIZE", env = "MDSPLIT_MIN_SPLIT_BYTES", value_parser = parse_size)]
+    pub min_split_bytes: Option<usize>,
+
+    /// Split on H1 sections, dividing any section longer than N pages into parts
+    #[arg(long, value_name = "N", env = "MDSPLIT_SECTION_MAX_PAGES", conflicts_with = "section_max_bytes")]
+    pub section_max_pages: Option<usize>,
+
+    /// Split on H1 sections, dividing any section larger than SIZE into parts
+    #[arg(long, value_name = "SIZE", env = "MDSPLIT_SECTION_MAX_BYTES", value_parser = parse_size)]
+    pub section_max_bytes: Option<usize>,
+
+    /// Halve the document by size, then each half, N times over (2^N files named by tree path, e.g. 1.2)
+    #[arg(
+        long,
+        value_name = "N",
+        env = "MDSPLIT_BISECT_DEPTH",
+        conflicts_with_all = ["section_max_pages", "section_max_bytes"]
+    )]
+    pub bisect_depth: Option<usize>,
+
+    /// One file per value of this page frontmatter field (pages without it go to <name>_ungrouped.md)
+    #[arg(
+        long,
+        value_name = "FIELD",
+        env = "MDSPLIT_GROUP_BY_FIELD",
+        conflicts_with_all = ["section_max_pages", "section_max_bytes", "bisect_depth"]
+    )]
+    pub group_by_field: Option<String>,
+
+    /// Name split files from a template with {base}, {index}, {index:03}, {total} and {title} placeholders
+    #[arg(
+        long,
+        value_name = "TEMPLATE",
+        env = "MDSPLIT_FILENAME_TEMPLATE",
+        conflicts_with_all = ["section_max_pages", "section_max_bytes", "bisect_depth", "group_by_field"]
+    )]
+    pub filename_template: Option<String>,
+
+    /// Repeat the last N pages of each split at the top of the next one (must be fewer than the pages per split)
+    #[arg(
+        long,
+        value_name = "N",
+        env = "MDSPLIT_OVERLAP_PAGES",
+        default_value = "0",
+        conflicts_with_all = ["section_max_pages", "section_max_bytes", "bisect_depth", "group_by_field", "max_words", "max_tokens"]
+    )]
+    pub overlap_pages: usize,
+
+    /// Number split files by document position or by descending size
+    #[arg(long, value_enum, env = "MDSPLIT_ORDER_BY", default_value = "document")]
+    pub order_by: OrderBy,
+
+    /// Number split files back to front, so the end of the document is file 1
+    #[arg(long, env = "MDSPLIT_REVERSE")]
+    pub reverse: bool,
+
+    /// Only split pages whose title matches this regex (kept pages are renumbered)
+    #[arg(long, value_name = "REGEX", env = "MDSPLIT_TITLE_FILTER")]
+    pub title_filter: Option<String>,
+
+    /// With --title-filter, keep pages without a title instead of dropping them
+    #[arg(long, requires = "title_filter", env = "MDSPLIT_KEEP_UNTITLED")]
+    pub keep_untitled: bool,
+
+    /// Never write more than N files for a single source
+    #[arg(long, value_name = "N", env = "MDSPLIT_MAX_SPLITS_PER_SOURCE")]
+    pub max_splits_per_source: Option<usize>,
+
+    /// What to do when a source would exceed --max-splits-per-source
+    #[arg(long, value_enum, env = "MDSPLIT_ON_SPLIT_LIMIT", default_value = "coarsen")]
+    pub on_split_limit: SplitLimitArg,
+
+    /// Distribute split files round-robin across N shard-<i> subdirectories
+    #[arg(long, value_name = "N", env = "MDSPLIT_SHARDS")]
+    pub shards: Option<usize>,
 }
 
 #[derive(Args)]
 pub struct AnalyzeArgs {
-    /// Input sources (file paths or URLs)
-    #[arg(required = true, value_name = "SOURCE")]
+    /// Input sources (file paths, directories or URLs); defaults to whitespace-separated MDSPLIT_SOURCES
+    #[arg(value_name = "SOURCE")]
     pub sources: Vec<String>,
 
-    /// Custom page break marker (regex pattern)
-    #[arg(long, value_name = "PATTERN")]
-    pub page_marker: Option<String>,
+    /// Markdown given inline instead of as SOURCE arguments
+    #[arg(long, value_name = "MARKDOWN", conflicts_with = "sources")]
+    pub content: Option<String>,
+
+    /// File name stem for --content, used in output and report names [default: inline]
+    #[arg(long, value_name = "STEM", requires = "content")]
+    pub output_stem: Option<String>,
+
+    /// Follow symlinks when scanning directory sources
+    #[arg(long, env = "MDSPLIT_FOLLOW_SYMLINKS")]
+    pub follow_symlinks: bool,
+
+    #[command(flatten)]
+    pub parsing: ParserArgs,
 
     /// Output analysis to JSON file
     #[arg(long, value_name = "FILE")]
@@ -74,15 +462,663 @@ pub struct AnalyzeArgs {
     /// Show detailed page information
     #[arg(long)]
     pub detailed: bool,
+
+    /// Preview split scenarios with the last N pages of each split repeated in the next
+    #[arg(
+        long,
+        value_name = "N",
+        env = "MDSPLIT_OVERLAP_PAGES",
+        default_value = "0",
+        conflicts_with_all = ["max_words", "max_tokens"]
+    )]
+    pub overlap_pages: usize,
+
+    /// Also preview how many splits a budget of N words per split would create
+    #[arg(long, value_name = "N")]
+    pub max_words: Option<usize>,
+
+    /// Also preview how many splits a budget of N tokens per split would create
+    #[arg(long, value_name = "N")]
+    pub max_tokens: Option<usize>,
+
+    /// How tokens are estimated for --max-tokens and the --detailed page list
+    #[arg(long, value_enum, env = "MDSPLIT_TOKEN_MODEL", default_value = "bpe")]
+    pub token_model: TokenModelArg,
+
+    /// List the raw pages merged into their predecessor, and why
+    #[arg(long)]
+    pub explain_merges: bool,
+
+    /// Fail unless every source has at least N pages
+    #[arg(long, value_name = "N")]
+    pub min_pages: Option<usize>,
+
+    /// Fail when any page is larger than SIZE (e.g. 20k)
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    pub max_page_bytes: Option<usize>,
+
+    /// Fail when any page has no title
+    #[arg(long)]
+    pub require_titles: bool,
+
+    /// Refuse input files larger than SIZE (e.g. 50M)
+    #[arg(long, value_name = "SIZE", env = "MDSPLIT_MAX_FILE_SIZE", value_parser = parse_size)]
+    pub max_file_size: Option<usize>,
+
+    /// Fetch URLs at most N times per second (fractions allowed); files are not affected
+    #[arg(long, value_name = "N", env = "MDSPLIT_RATE_LIMIT", value_parser = parse_rate)]
+    pub rate_limit: Option<f64>,
+
+    /// Serve URLs from responses saved in DIR, skipping the network on a hit
+    #[arg(long, value_name = "DIR", env = "MDSPLIT_FETCH_CACHE_DIR")]
+    pub fetch_cache_dir: Option<PathBuf>,
+
+    /// Save URL responses fetched from the network into --fetch-cache-dir
+    #[arg(long, requires = "fetch_cache_dir")]
+    pub fetch_cache_write: bool,
+
+    /// Give up on a URL request that has not completed after DURATION (e.g. 30s, 2m)
+    #[arg(long, value_name = "DURATION", env = "MDSPLIT_TIMEOUT", value_parser = parse_duration)]
+    pub timeout: Option<Duration>,
+
+    /// Retry URL requests failing with a connection error, timeout or 5xx status up to N times, with exponential backoff
+    #[arg(long, value_name = "N", env = "MDSPLIT_RETRIES", default_value = "0")]
+    pub retries: u32,
+
+    /// Pull the markdown out of a wrapper file first: a JSON/YAML path such as
+    /// '$.info.description' or '$.items[*].body', or a regex whose first group is the markdown
+    #[arg(long, value_name = "SELECTOR", env = "MDSPLIT_EXTRACT_FROM", value_parser = parse_selector)]
+    pub extract_from: Option<ContentSelector>,
+
+    /// Rejoin hard-wrapped lines from converted documents before parsing
+    #[arg(long, env = "MDSPLIT_DEWRAP")]
+    pub dewrap: bool,
+
+    /// With --dewrap, also pull wrapped continuation lines up into list items
+    #[arg(long, requires = "dewrap")]
+    pub dewrap_lists: bool,
 }
 
 #[derive(Args)]
 pub struct ValidateArgs {
-    /// Input sources (file paths or URLs)
-    #[arg(required = true, value_name = "SOURCE")]
+    /// Input sources (file paths or URLs); defaults to whitespace-separated MDSPLIT_SOURCES
+    #[arg(value_name = "SOURCE")]
     pub sources: Vec<String>,
 
-    /// Check if sources are accessible
+    /// Check if sources are accessible (HEAD requests for URLs, no body download)
     #[arg(long)]
     pub check_access: bool,
-}
\ No newline at end of file
+
+    /// Give up on a URL request that has not completed after DURATION (e.g. 30s, 2m)
+    #[arg(long, value_name = "DURATION", env = "MDSPLIT_TIMEOUT", value_parser = parse_duration)]
+    pub timeout: Option<Duration>,
+
+    /// Retry URL requests failing with a connection error, timeout or 5xx status up to N times, with exponential backoff
+    #[arg(long, value_name = "N", env = "MDSPLIT_RETRIES", default_value = "0")]
+    pub retries: u32,
+
+    /// Write the access check results to a JSON file
+    #[arg(long, value_name = "FILE", requires = "check_access")]
+    pub json_output: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct ResplitArgs {
+    /// Metadata file written by a previous split run
+    #[arg(required = true, value_name = "METADATA")]
+    pub metadata: PathBuf,
+
+    /// Number of splits to create
+    #[arg(short, long, env = "MDSPLIT_SPLITS", default_value = "5")]
+    pub splits: usize,
+
+    /// Preserve document structure with separators
+    #[arg(long, env = "MDSPLIT_PRESERVE_STRUCTURE", default_value = "true")]
+    pub preserve_structure: bool,
+
+    /// Include metadata file
+    #[arg(long, env = "MDSPLIT_INCLUDE_METADATA", default_value = "true")]
+    pub include_metadata: bool,
+
+    #[command(flatten)]
+    pub parsing: ParserArgs,
+
+    /// Force overwrite existing output files
+    #[arg(long)]
+    pub force: bool,
+
+    /// Distribute split files round-robin across N shard-<i> subdirectories
+    #[arg(long, value_name = "N", env = "MDSPLIT_SHARDS")]
+    pub shards: Option<usize>,
+
+    /// Merge a trailing split smaller than SIZE into the previous split (e.g. 2k)
+    #[arg(long, value_name = "SIZE", env = "MDSPLIT_MIN_SPLIT_BYTES", value_parser = parse_size)]
+    pub min_split_bytes: Option<usize>,
+
+    /// Repeat the enclosing headings at the top of splits that start mid-section
+    #[arg(long, env = "MDSPLIT_HEADING_CONTEXT")]
+    pub heading_context: bool,
+
+    /// Logical source name hashed into chunk IDs instead of the file name
+    #[arg(long, value_name = "NAME", env = "MDSPLIT_SOURCE_ID")]
+    pub source_id: Option<String>,
+
+    /// Write each split's chunk ID into its header
+    #[arg(long, env = "MDSPLIT_CHUNK_ID_HEADER")]
+    pub chunk_id_header: bool,
+
+    /// Hard-wrap paragraph text at N columns (code, tables, headings and lists are kept)
+    #[arg(long, value_name = "N", env = "MDSPLIT_WRAP")]
+    pub wrap: Option<usize>,
+
+    /// Unix time written instead of the current time in metadata
+    #[arg(long, value_name = "SECONDS", env = "MDSPLIT_SOURCE_DATE_EPOCH")]
+    pub source_date_epoch: Option<i64>,
+}
+
+#[derive(Args)]
+pub struct AppendArgs {
+    /// Input sources (file paths or URLs); defaults to whitespace-separated MDSPLIT_SOURCES
+    #[arg(value_name = "SOURCE")]
+    pub sources: Vec<String>,
+
+    /// Base name of the rotating files (<NAME>_0001.md, <NAME>_0002.md, ...)
+    #[arg(long, value_name = "NAME", default_value = "rotating")]
+    pub name: String,
+
+    /// Start a new file rather than grow the current one past SIZE (e.g. 64k)
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    pub max_size: usize,
+
+    #[command(flatten)]
+    pub parsing: ParserArgs,
+}
+
+#[derive(Args)]
+pub struct ExtractArgs {
+    /// Input source (file path or URL)
+    #[arg(required = true, value_name = "SOURCE")]
+    pub source: String,
+
+    /// Single page to extract (1-based)
+    #[arg(long, value_name = "N", required_unless_present = "pages", conflicts_with = "pages")]
+    pub page: Option<usize>,
+
+    /// Inclusive page range to extract, e.g. 3-7
+    #[arg(long, value_name = "FIRST-LAST", value_parser = parse_page_range)]
+    pub pages: Option<(usize, usize)>,
+
+    /// File to write the pages to instead of stdout
+    #[arg(long, value_name = "FILE")]
+    pub out: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub parsing: ParserArgs,
+}
+
+#[derive(Args)]
+pub struct LocateArgs {
+    /// Input source (file path or URL)
+    #[arg(required = true, value_name = "SOURCE")]
+    pub source: String,
+
+    /// 1-based source line to look up
+    #[arg(long, value_name = "N")]
+    pub line: usize,
+
+    #[command(flatten)]
+    pub layout: SplitLayoutArgs,
+
+    #[command(flatten)]
+    pub parsing: ParserArgs,
+}
+
+#[derive(Args)]
+pub struct FormatArgs {
+    /// Input source (file path or URL)
+    #[arg(required = true, value_name = "SOURCE")]
+    pub source: String,
+
+    /// File to write the formatted document to instead of stdout
+    #[arg(long, value_name = "FILE")]
+    pub out: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub parsing: ParserArgs,
+}
+
+#[derive(Args)]
+pub struct SchemaArgs {
+    /// Which output to describe
+    #[arg(value_enum, default_value = "metadata")]
+    pub kind: SchemaKind,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum SchemaKind {
+    /// The metadata file written alongside split files
+    Metadata,
+    /// The report written by `analyze --json-output`
+    Analysis,
+}
+
+#[derive(Args, Clone)]
+pub struct ParserArgs {
+    /// Custom page break marker, matched literally as a whole line
+    #[arg(long, value_name = "PATTERN", env = "MDSPLIT_PAGE_MARKER")]
+    pub page_marker: Option<String>,
+
+    /// Regex page marker that may span lines, matched against a window of joined lines
+    #[arg(long, value_name = "REGEX", env = "MDSPLIT_MULTILINE_MARKER")]
+    pub multiline_marker: Option<String>,
+
+    /// Lines per window for --multiline-marker (1-16)
+    #[arg(long, value_name = "N", env = "MDSPLIT_MARKER_WINDOW", requires = "multiline_marker")]
+    pub marker_window: Option<usize>,
+
+    /// Heading levels that break pages, e.g. 1 for H1 only or 1,2,3
+    #[arg(
+        long,
+        value_name = "LEVELS",
+        env = "MDSPLIT_HEADING_BREAK_LEVELS",
+        value_delimiter = ',',
+        default_value = "1,2"
+    )]
+    pub heading_break_levels: Vec<usize>,
+
+    /// Deepest heading level that breaks pages: 3 breaks on H1, H2 and H3
+    #[arg(
+        long,
+        value_name = "N",
+        env = "MDSPLIT_HEADING_LEVEL",
+        value_parser = clap::value_parser!(u8).range(1..=6),
+        conflicts_with = "heading_break_levels"
+    )]
+    pub heading_level: Option<u8>,
+
+    /// How page boundaries are detected
+    #[arg(long, value_enum, env = "MDSPLIT_SPLIT_ON", default_value = "auto")]
+    pub split_on: SplitOn,
+
+    /// Maximum gap between consecutive printed page numbers (with --split-on bare-numbers)
+    #[arg(long, value_name = "N")]
+    pub page_number_delta: Option<usize>,
+
+    /// Break pages at runs of at least N consecutive blank lines (overrides --split-on)
+    #[arg(long, value_name = "N", env = "MDSPLIT_BLANK_LINES")]
+    pub blank_lines: Option<usize>,
+
+    /// Never break pages inside a multi-line raw HTML block; breaks move to after it closes
+    #[arg(long, env = "MDSPLIT_KEEP_HTML_INTACT")]
+    pub keep_html_intact: bool,
+
+    /// Columns per tab when measuring indentation for indented code and list nesting
+    #[arg(long, value_name = "N", env = "MDSPLIT_TAB_WIDTH", default_value = "4")]
+    pub tab_width: usize,
+
+    /// Start pages at exactly these 1-based line numbers, e.g. 10,25,40 (overrides --split-on)
+    #[arg(long, value_name = "LINES", value_delimiter = ',', conflicts_with = "blank_lines")]
+    pub breaks: Option<Vec<usize>>,
+
+    /// Drop the blank-line runs between pages from the output (with --blank-lines)
+    #[arg(long, requires = "blank_lines")]
+    pub collapse_blank_lines: bool,
+
+    /// Leave notebook cell delimiter lines out of the output (with --split-on jupyter-cells)
+    #[arg(long)]
+    pub strip_cell_delimiters: bool,
+
+    /// Stop merging small pages into one once it would exceed N lines
+    #[arg(long, value_name = "N", env = "MDSPLIT_MAX_MERGED_LINES")]
+    pub max_merged_lines: Option<usize>,
+
+    /// Only split on explicit page markers (e.g. "# Page 3") when at least N are found
+    #[arg(long, value_name = "N", env = "MDSPLIT_MIN_PAGE_MARKERS", default_value_t = 1)]
+    pub min_page_markers: usize,
+
+    /// How to handle content before the first page break
+    #[arg(long, value_enum, env = "MDSPLIT_LEADING_REGION", default_value = "prepend")]
+    pub leading_region: LeadingRegionArg,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum SplitOn {
+    /// Page markers, falling back to rules, page-break comments and headers
+    Auto,
+    /// Lines holding only a printed page number (OCR'd scans)
+    BareNumbers,
+    /// Top-level entries of the document's table of contents
+    Toc,
+    /// Notebook cell delimiters in Jupyter-exported markdown
+    JupyterCells,
+    /// Lines holding only bold text, used as headings in poorly formatted documents
+    Bold,
+}
+
+/// Parses an inclusive page range such as `3-7` (or a single page, `5`).
+pub fn parse_page_range(value: &str) -> Result<(usize, usize), String> {
+    let parse = |part: &str| {
+        part.trim()
+            .parse::<usize>()
+            .map_err(|_| format!("invalid page range '{}' (expected e.g. 3-7)", value))
+    };
+
+    match value.split_once('-') {
+        Some((first, last)) => Ok((parse(first)?, parse(last)?)),
+        None => parse(value).map(|page| (page, page)),
+    }
+}
+
+/// Parses a positive request rate such as `5` or `0.5` (per second). Rates so small
+/// that the interval between requests is not a representable duration are rejected.
+pub fn parse_rate(value: &str) -> Result<f64, String> {
+    match value.trim().parse::<f64>() {
+        Ok(rate) if rate.is_finite() && rate > 0.0 && Duration::try_from_secs_f64(1.0 / rate).is_ok() => Ok(rate),
+        _ => Err(format!("invalid rate '{}' (expected a positive number per second)", value)),
+    }
+}
+
+/// Parses a byte size such as `512`, `40k` or `2M` (binary multiples).
+pub fn parse_size(value: &str) -> Result<usize, String> {
+    let value = value.trim();
+    let (digits, multiplier) = match value.char_indices().last() {
+        Some((idx, 'k' | 'K')) => (&value[..idx], 1024),
+        Some((idx, 'm' | 'M')) => (&value[..idx], 1024 * 1024),
+        Some((idx, 'g' | 'G')) => (&value[..idx], 1024 * 1024 * 1024),
+        _ => (value, 1),
+    };
+
+    digits
+        .trim()
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(|| format!("invalid size '{}' (expected e.g. 512, 40k, 2M)", value))
+}
+
+/// Parses a source name, which must be a bare file name.
+fn parse_source_name(value: &str) -> Result<String, String> {
+    if value.trim().is_empty() || value.contains(['/', '\\']) || value == "." || value == ".." {
+        return Err(format!("invalid source name '{}' (expected a file name without path separators)", value));
+    }
+    Ok(value.to_string())
+}
+
+/// Parses an `--extract-from` selector.
+fn parse_selector(value: &str) -> Result<ContentSelector, String> {
+    ContentFetcher::parse_selector(value).map_err(|e| e.to_string())
+}
+
+/// Parses a duration such as `500ms`, `90s`, `15m` or `2h`; bare numbers are seconds.
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let split_at = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+    let (digits, unit) = value.split_at(split_at);
+    let invalid = || format!("invalid duration '{}' (expected e.g. 90s, 15m, 2h)", value);
+    let amount: u64 = digits.parse().map_err(|_| invalid())?;
+
+    let seconds = match unit.trim() {
+        "ms" => return Ok(Duration::from_millis(amount)),
+        "" | "s" => Some(amount),
+        "m" => amount.checked_mul(60),
+        "h" => amount.checked_mul(60 * 60),
+        _ => return Err(invalid()),
+    };
+    seconds.map(Duration::from_secs).ok_or_else(invalid)
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ManifestModeArg {
+    /// Start a new manifest with this run's sources
+    Overwrite,
+    /// Keep entries of sources not split in this run
+    Merge,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum LineEndingArg {
+    /// The source's line ending
+    Auto,
+    /// Unix line endings (\n)
+    Lf,
+    /// Windows line endings (\r\n)
+    Crlf,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum RemainderArg {
+    /// Full-size splits and a smaller last one
+    Separate,
+    /// One extra page each for the first splits
+    DistributeEarly,
+    /// One extra page each for the last splits
+    DistributeLate,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum OrderBy {
+    /// Position in the source document
+    Document,
+    /// Largest split first
+    Size,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// One markdown file per split
+    Files,
+    /// `<name>_data_uris.json` listing each split as a `data:text/markdown;base64,...` URI
+    DataUri,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum BalanceBy {
+    /// Page size in bytes
+    Bytes,
+    /// Words per page
+    Words,
+    /// Lines per page
+    Lines,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum TokenModelArg {
+    /// Word and punctuation pieces priced like a BPE vocabulary
+    Bpe,
+    /// Four characters per token
+    Chars,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum SplitLimitArg {
+    /// Fail for that source
+    Error,
+    /// Use fewer, larger splits
+    Coarsen,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum LeadingRegionArg {
+    /// Keep it as page 1
+    Prepend,
+    /// Drop it
+    Discard,
+    /// Fold it into the first page
+    MergeIntoFirst,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Mutex, MutexGuard};
+
+    /// Parsing `Cli` reads MDSPLIT_* variables, so every test that parses it holds
+    /// this lock to keep out of `test_env_defaults_below_flags`
+    static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+    fn env_lock() -> MutexGuard<'static, ()> {
+        ENV_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    #[test]
+    fn test_parse_rate_rejects_unrepresentable_intervals() {
+        assert_eq!(parse_rate("0.5"), Ok(0.5));
+        assert!(parse_rate("0").is_err());
+        assert!(parse_rate("1e-30").is_err());
+    }
+
+    #[test]
+    fn test_parse_size_units() {
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("40k"), Ok(40 * 1024));
+        assert_eq!(parse_size("2M"), Ok(2 * 1024 * 1024));
+        assert!(parse_size("lots").is_err());
+        assert!(parse_size("18014398509481984k").is_err());
+    }
+
+    #[test]
+    fn test_heading_level_range() {
+        let _env = env_lock();
+        let parse = |level: &str| Cli::try_parse_from(["md-split", "split", "a.md", "--heading-level", level]);
+        match parse("3").unwrap().command {
+            Commands::Split(args) => assert_eq!(args.parsing.heading_level, Some(3)),
+            _ => panic!("expected the split command"),
+        }
+        assert!(parse("0").is_err());
+        assert!(parse("7").is_err());
+        assert!(Cli::try_parse_from([
+            "md-split", "split", "a.md", "--heading-level", "3", "--heading-break-levels", "1"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn test_concurrency_at_least_one() {
+        let _env = env_lock();
+        let parse = |n: &str| Cli::try_parse_from(["md-split", "split", "a.md", "--concurrency", n]);
+        match parse("8").unwrap().command {
+            Commands::Split(args) => assert_eq!(args.concurrency, 8),
+            _ => panic!("expected the split command"),
+        }
+        assert!(parse("0").is_err());
+    }
+
+    #[test]
+    fn test_source_name_is_a_file_name() {
+        let _env = env_lock();
+        let parse = |flag: &str, name: &str| Cli::try_parse_from(["md-split", "split", "-", flag, name]);
+        match parse("--source-name", "report.md").unwrap().command {
+            Commands::Split(args) => assert_eq!(args.source_name.as_deref(), Some("report.md")),
+            _ => panic!("expected the split command"),
+        }
+        assert!(parse("--source-name", "docs/report.md").is_err());
+        assert!(parse("--source-name", "docs\\report.md").is_err());
+        assert!(parse("--source-name", "..").is_err());
+        assert!(parse("--source-name", "").is_err());
+    }
+
+    #[test]
+    fn test_parse_duration_units() {
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("15m"), Ok(Duration::from_secs(15 * 60)));
+        assert_eq!(parse_duration("2h"), Ok(Duration::from_secs(2 * 60 * 60)));
+        assert!(parse_duration("soon").is_err());
+        assert!(parse_duration("5d").is_err());
+        assert!(parse_duration("99999999999999999h").is_err());
+    }
+
+    #[test]
+    fn test_env_defaults_below_flags() {
+        let _env = env_lock();
+        let vars = [
+            ("MDSPLIT_SPLITS", "7"),
+            ("MDSPLIT_OUTPUT", "/tmp/mdsplit-env"),
+            ("MDSPLIT_PAGE_MARKER", "<!-- page -->"),
+            (SOURCES_ENV, "a.md  b.md"),
+        ];
+        for (key, value) in vars {
+            std::env::set_var(key, value);
+        }
+
+        let mut cli = Cli::try_parse_from(["md-split", "split"]).unwrap();
+        cli.resolve_env_sources().unwrap();
+        assert_eq!(cli.output, PathBuf::from("/tmp/mdsplit-env"));
+        match &cli.command {
+            Commands::Split(args) => {
+                assert_eq!(args.layout.splits, 7);
+                assert_eq!(args.sources, vec!["a.md", "b.md"]);
+                assert_eq!(args.parsing.page_marker.as_deref(), Some("<!-- page -->"));
+            }
+            _ => panic!("expected split command"),
+        }
+
+        // Command-line flags win over the environment
+        let mut cli =
+            Cli::try_parse_from(["md-split", "split", "c.md", "--splits", "3", "-o", "out"]).unwrap();
+        cli.resolve_env_sources().unwrap();
+        assert_eq!(cli.output, PathBuf::from("out"));
+        match &cli.command {
+            Commands::Split(args) => {
+                assert_eq!(args.layout.splits, 3);
+                assert_eq!(args.sources, vec!["c.md"]);
+            }
+            _ => panic!("expected split command"),
+        }
+
+        for (key, _) in vars {
+            std::env::remove_var(key);
+        }
+
+        let mut cli = Cli::try_parse_from(["md-split", "split"]).unwrap();
+        assert!(cli.resolve_env_sources().is_err());
+    }
+
+    #[test]
+    fn test_extract_page_selection() {
+        let _env = env_lock();
+        assert_eq!(parse_page_range("3-7"), Ok((3, 7)));
+        assert_eq!(parse_page_range("5"), Ok((5, 5)));
+        assert!(parse_page_range("3-").is_err());
+
+        let cli = Cli::try_parse_from(["md-split", "extract", "doc.md", "--pages", "2-4", "--out", "pages.md"]).unwrap();
+        match cli.command {
+            Commands::Extract(args) => {
+                assert_eq!(args.pages, Some((2, 4)));
+                assert_eq!(args.out, Some(PathBuf::from("pages.md")));
+            }
+            _ => panic!("expected extract command"),
+        }
+
+        assert!(Cli::try_parse_from(["md-split", "extract", "doc.md"]).is_err());
+        assert!(Cli::try_parse_from(["md-split", "extract", "doc.md", "--page", "1", "--pages", "1-2"]).is_err());
+    }
+
+    #[test]
+    fn test_inline_content_replaces_sources() {
+        let _env = env_lock();
+        let mut cli = Cli::try_parse_from(["md-split", "split", "--content", "# A\n\nText"]).unwrap();
+        cli.resolve_env_sources().unwrap();
+        match &cli.command {
+            Commands::Split(args) => {
+                assert_eq!(args.content.as_deref(), Some("# A\n\nText"));
+                assert_eq!(args.output_stem, None);
+                assert!(args.sources.is_empty());
+            }
+            _ => panic!("expected split command"),
+        }
+
+        assert!(Cli::try_parse_from(["md-split", "analyze", "doc.md", "--content", "# A"]).is_err());
+        assert!(Cli::try_parse_from(["md-split", "split", "--output-stem", "notes"]).is_err());
+    }
+
+    #[test]
+    fn test_annotate_only_conflicts_with_split_file_options() {
+        let _env = env_lock();
+        let annotate = ["md-split", "split", "doc.md", "--annotate-only"];
+        assert!(Cli::try_parse_from(annotate).is_ok());
+        for extra in [["--extract-code", "code"], ["--filename-template", "{index}.md"], ["--format", "data-uri"]] {
+            assert!(Cli::try_parse_from(annotate.iter().chain(&extra)).is_err(), "{:?}", extra);
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use crate::types::Compression;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -14,6 +15,12 @@ pub enum MarkdownSplitterError {
     #[error("File not found: {path}")]
     FileNotFound { path: String },
     
+    #[error("Standard input can only be read once, but was given as a source {count} times")]
+    StdinReused { count: usize },
+
+    #[error("Symlink cycle detected: {path} points back to {ancestor}")]
+    SymlinkCycle { path: String, ancestor: String },
+    
     #[error("Invalid markdown content: {reason}")]
     InvalidMarkdown { reason: String },
     
@@ -26,6 +33,35 @@ pub enum MarkdownSplitterError {
     #[error("Page parsing error: {reason}")]
     PageParsing { reason: String },
     
+    #[error("Invalid page selection: {reason}")]
+    PageSelection { reason: String },
+    
+    #[error("Invalid split metadata: {reason}")]
+    InvalidMetadata { reason: String },
+    
+    #[error("{source_name} is {size} bytes, over the {limit}-byte limit (raise --max-file-size or split the file first)")]
+    FileTooLarge {
+        source_name: String,
+        size: u64,
+        limit: u64,
+    },
+    
+    #[error("Maximum runtime of {budget:?} exceeded")]
+    RuntimeExceeded { budget: std::time::Duration },
+    
+    #[error("Cannot decompress {source_name} ({compression:?}): {reason}")]
+    Decompression {
+        source_name: String,
+        compression: Compression,
+        reason: String,
+    },
+
+    #[error("Timed out after {timeout:?} fetching {url}")]
+    FetchTimeout { url: String, timeout: std::time::Duration },
+
+    #[error("{failed} of {total} sources failed")]
+    SourcesFailed { failed: usize, total: usize },
+
     #[error("HTTP status error: {status}")]
     HttpStatus { status: u16 },
     
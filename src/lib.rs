@@ -5,7 +5,7 @@
 //! 
 //! ## Example Usage
 //! 
-//! ```rust
+//! ```rust,no_run
 //! use markdown_splitter::{ContentFetcher, MarkdownParser, DocumentSplitter, SplitConfig};
 //! use std::path::PathBuf;
 //! 
@@ -25,6 +25,7 @@
 //!         preserve_structure: true,
 //!         include_metadata: true,
 //!         custom_page_marker: None,
+//!         ..SplitConfig::default()
 //!     };
 //!     
 //!     // Split document
@@ -41,15 +42,54 @@ pub mod types;
 
 // Re-export main types and services for easier usage
 pub use error::{MarkdownSplitterError, Result};
-pub use services::{ContentFetcher, DocumentSplitter, MarkdownParser};
+pub use services::{heading_anchor, ContentFetcher, DocumentSplitter, FirstSentence, MarkdownParser, RateLimiter, Summarizer, Tokenizer};
 pub use types::{
-    DocumentMetadata, MarkdownDocument, MarkdownPage, SourceType, 
-    SplitConfig, SplitResult
+    AccessCheck, AnalysisReport, BoilerplateLine, CodeBlockInfo, Compression, ContentSelector, CrossSplitAnchor, DataUriSplit, DewrapOptions, DocumentMetadata, Distribution, FetchOptions, LeadingRegion, LineEnding, LineLocation, ManifestEntry, ManifestMode, MarkdownDocument, OutlineEntry, OverwritePolicy,
+    MarkdownPage, PageInfo, PageMerge, PageSummary, ParserOptions, PathStep, ParsingStats, PlannedSplit, PlanStrategy, RemainderPolicy, RotationConfig, SectionLimit, SectionPart, SourceType, SplitBy,
+    SplitConfig,
+    SplitInfo, SplitLimitPolicy, SplitManifest, SplitMetadata, SplitOrder, SplitPlan, SplitResult, SplitRunReport, SplitStatistics, SplitStrategy, SplitWarning, SourceOutcome, TokenModel,
+    StructureLimits,
+    WarningKind,
 };
 
+use schemars::schema::RootSchema;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::time::Duration;
+
 /// Version information
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 
+/// JSON Schema for the metadata file written alongside split files
+pub fn metadata_schema() -> RootSchema {
+    schemars::schema_for!(SplitMetadata)
+}
+
+/// JSON Schema for the `analyze --json-output` report, keyed by source
+pub fn analysis_schema() -> RootSchema {
+    schemars::schema_for!(BTreeMap<String, AnalysisReport>)
+}
+
+/// Serializes an `analyze` report. Sources are ordered by name and every other object
+/// by field declaration order, so identical input always yields byte-identical JSON.
+pub fn analysis_json(reports: &BTreeMap<String, AnalysisReport>) -> serde_json::Result<String> {
+    serde_json::to_string_pretty(reports)
+}
+
+/// Runs `work` to completion, or cancels it once `budget` has elapsed and returns
+/// [`MarkdownSplitterError::RuntimeExceeded`]. Without a budget the work runs unbounded.
+pub async fn with_time_budget<T>(
+    budget: Option<Duration>,
+    work: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    match budget {
+        Some(budget) => tokio::time::timeout(budget, work)
+            .await
+            .map_err(|_| MarkdownSplitterError::RuntimeExceeded { budget })?,
+        None => work.await,
+    }
+}
+
 /// Library initialization - sets up default configurations
 pub fn init() {
     // Initialize any global state if needed
@@ -59,8 +99,8 @@ pub fn init() {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
     use std::path::PathBuf;
-    use tokio;
 
     #[tokio::test]
     async fn test_basic_workflow() {
@@ -88,6 +128,8 @@ Page 3 content here."#;
             created_at: chrono::Utc::now().to_rfc3339(),
             total_lines: content.lines().count(),
             page_breaks: Vec::new(),
+            origin: "test.md".to_string(),
+            compression: None,
         };
 
         // Parse document
@@ -125,10 +167,3534 @@ Page 3 content here."#;
             preserve_structure: true,
             include_metadata: true,
             custom_page_marker: Some("<!-- PAGE -->".to_string()),
+            ..SplitConfig::default()
         };
 
         assert_eq!(config.splits, 5);
         assert!(config.preserve_structure);
         assert!(config.include_metadata);
     }
-}
\ No newline at end of file
+
+    fn paged_content(pages: usize) -> String {
+        (1..=pages)
+            .map(|n| format!("# Page {}\n\nContent of page {}.\n", n, n))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn test_metadata(filename: &str, content: &str) -> DocumentMetadata {
+        DocumentMetadata {
+            filename: filename.to_string(),
+            source_type: SourceType::LocalFile,
+            created_at: chrono::Utc::now().to_rfc3339(),
+            total_lines: content.lines().count(),
+            page_breaks: Vec::new(),
+            origin: filename.to_string(),
+            compression: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resplit_with_different_split_count() {
+        let first_dir = tempfile::tempdir().unwrap();
+        let second_dir = tempfile::tempdir().unwrap();
+        let content = paged_content(6);
+
+        let parser = MarkdownParser::new(None).unwrap();
+        let document = parser
+            .parse_document(&content, test_metadata("book.md", &content))
+            .unwrap();
+        assert_eq!(document.total_pages, 6);
+
+        let config = SplitConfig {
+            splits: 3,
+            output_dir: first_dir.path().to_path_buf(),
+            preserve_structure: true,
+            include_metadata: true,
+            custom_page_marker: None,
+            ..SplitConfig::default()
+        };
+        let first = DocumentSplitter::split_document(&document, &config).await.unwrap();
+        assert_eq!(first.output_files.len(), 3);
+
+        let resplit_config = SplitConfig {
+            splits: 2,
+            output_dir: second_dir.path().to_path_buf(),
+            ..config
+        };
+        let metadata_path = first.metadata_file.unwrap();
+        let second = DocumentSplitter::resplit(&metadata_path, &parser, &resplit_config)
+            .await
+            .unwrap();
+
+        assert_eq!(second.output_files.len(), 2);
+        assert_eq!(second.actual_pages, 6);
+
+        let reassembled = DocumentSplitter::reassemble_document(&metadata_path, &parser, Some(1_700_000_000))
+            .await
+            .unwrap();
+        assert_eq!(reassembled.source, "book.md");
+        assert_eq!(reassembled.metadata.created_at, "2023-11-14T22:13:20+00:00");
+        for (page, expected) in reassembled.pages.iter().zip(1..) {
+            assert_eq!(page.title.as_deref(), Some(format!("Page {}", expected).as_str()));
+            assert!(!page.content.contains("Split containing pages"));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_metadata_statistics_match_splits() {
+        let output_dir = tempfile::tempdir().unwrap();
+        let content = "# Page 1\none\n# Page 2\none two\n# Page 3\none two three\n# Page 4\none two three four";
+
+        let parser = MarkdownParser::new(None).unwrap();
+        let document = parser
+            .parse_document(content, test_metadata("stats.md", content))
+            .unwrap();
+
+        let config = SplitConfig {
+            splits: 4,
+            output_dir: output_dir.path().to_path_buf(),
+            preserve_structure: false,
+            include_metadata: true,
+            custom_page_marker: None,
+            ..SplitConfig::default()
+        };
+        let result = DocumentSplitter::split_document(&document, &config).await.unwrap();
+
+        let metadata = DocumentSplitter::read_metadata(result.metadata_file.as_ref().unwrap())
+            .await
+            .unwrap();
+        let stats = metadata.statistics;
+
+        assert_eq!(stats.words.min, 4);
+        assert_eq!(stats.words.max, 7);
+        assert_eq!(stats.words.mean, 5.5);
+        assert_eq!(stats.words.median, 5.5);
+        assert_eq!(stats.lines.min, 2);
+        assert_eq!(stats.lines.max, 2);
+
+        let mut sizes: Vec<usize> = result
+            .output_files
+            .iter()
+            .map(|path| std::fs::metadata(path).unwrap().len() as usize)
+            .collect();
+        sizes.sort_unstable();
+        assert_eq!(stats.bytes.min, sizes[0]);
+        assert_eq!(stats.bytes.max, sizes[3]);
+        assert_eq!(stats.bytes.median, (sizes[1] + sizes[2]) as f64 / 2.0);
+        assert_eq!(
+            metadata.split_info.iter().map(|info| info.bytes).sum::<usize>(),
+            sizes.iter().sum::<usize>()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unstructured_splits_reassemble_byte_identical() {
+        let output_dir = tempfile::tempdir().unwrap();
+        let content = format!("{}\n", paged_content(5));
+
+        let parser = MarkdownParser::new(None).unwrap();
+        let document = parser
+            .parse_document(&content, test_metadata("plain.md", &content))
+            .unwrap();
+        assert_eq!(document.total_pages, 5);
+
+        let config = SplitConfig {
+            splits: 3,
+            output_dir: output_dir.path().to_path_buf(),
+            preserve_structure: false,
+            include_metadata: true,
+            custom_page_marker: None,
+            ..SplitConfig::default()
+        };
+        let result = DocumentSplitter::split_document(&document, &config).await.unwrap();
+
+        let combined: String = result
+            .output_files
+            .iter()
+            .map(|path| std::fs::read_to_string(path).unwrap())
+            .collect();
+        assert_eq!(combined, content);
+        assert!(!combined.contains("<!--"));
+
+        let (reassembled, _) =
+            DocumentSplitter::reassemble_splits(result.metadata_file.as_ref().unwrap())
+                .await
+                .unwrap();
+        assert_eq!(reassembled, content);
+    }
+
+    #[test]
+    fn test_bare_number_lines_split_ocr_pages() {
+        let content = "Front matter text\n\n12\n\nText of page twelve.\nThe answer is\n42\nwhich sits mid-paragraph.\n\n13\n\nText of page thirteen.\n\n900\n\nA figure label, not a page.\n\n14\n\nText of page fourteen.";
+
+        let parser = MarkdownParser::with_options(ParserOptions {
+            split_by: SplitBy::BareNumberLines { max_delta: Some(2) },
+            ..ParserOptions::default()
+        })
+        .unwrap();
+        let document = parser
+            .parse_document(content, test_metadata("scan.md", content))
+            .unwrap();
+
+        let numbers: Vec<Option<usize>> =
+            document.pages.iter().map(|p| p.original_number).collect();
+        assert_eq!(numbers, vec![None, Some(12), Some(13), Some(14)]);
+        assert!(document.pages[1].content.contains("42"));
+        assert!(document.pages[2].content.contains("A figure label"));
+        assert_eq!(document.pages[3].number, 4);
+    }
+
+    #[tokio::test]
+    async fn test_csv_index_round_trips_rows() {
+        let output_dir = tempfile::tempdir().unwrap();
+        let content = "# Intro, \"quoted\"\n\nFirst page.\n\n# Page 2\n\nSecond page.\n\n# Page 3\n\nThird page.";
+
+        let parser = MarkdownParser::new(None).unwrap();
+        let document = parser
+            .parse_document(content, test_metadata("guide.md", content))
+            .unwrap();
+
+        let config = SplitConfig {
+            splits: 2,
+            output_dir: output_dir.path().to_path_buf(),
+            preserve_structure: true,
+            include_metadata: false,
+            custom_page_marker: None,
+            ..SplitConfig::default()
+        };
+        let result = DocumentSplitter::split_document(&document, &config).await.unwrap();
+
+        let csv_path = output_dir.path().join("index.csv");
+        DocumentSplitter::write_csv_index(&csv_path, &result.splits).unwrap();
+
+        let mut reader = csv::Reader::from_path(&csv_path).unwrap();
+        assert_eq!(
+            reader.headers().unwrap(),
+            vec!["split_index", "filename", "start_page", "end_page", "bytes", "title"]
+        );
+
+        let rows: Vec<csv::StringRecord> = reader.records().map(|r| r.unwrap()).collect();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(&rows[0][1], "guide_split_1_of_2.md");
+        assert_eq!(&rows[0][2], "1");
+        assert_eq!(&rows[0][3], "2");
+        assert_eq!(&rows[0][5], "Intro, \"quoted\"");
+        assert_eq!(&rows[1][2], "3");
+        assert_eq!(&rows[1][3], "3");
+        assert_eq!(&rows[1][5], "Page 3");
+        assert_eq!(rows[1][4].parse::<usize>().unwrap(), result.splits[1].bytes);
+    }
+
+    #[test]
+    fn test_suggest_split_count_for_target_size() {
+        let content = paged_content(10);
+        let parser = MarkdownParser::new(None).unwrap();
+        let document = parser
+            .parse_document(&content, test_metadata("sized.md", &content))
+            .unwrap();
+
+        let total_bytes: usize = document.pages.iter().map(|p| p.content.len()).sum();
+        assert_eq!(total_bytes, 291);
+
+        assert_eq!(DocumentSplitter::suggest_split_count(&document, 100), 3);
+        assert_eq!(DocumentSplitter::suggest_split_count(&document, 1_000_000), 1);
+        assert_eq!(DocumentSplitter::suggest_split_count(&document, 1), 10);
+    }
+
+    #[tokio::test]
+    async fn test_break_marker_matches_source_line() {
+        let output_dir = tempfile::tempdir().unwrap();
+        let content = "Preface line\n# Page 1\nAlpha\n# page 2  \nBeta\n#  PAGE 3\nGamma";
+        let lines: Vec<&str> = content.lines().collect();
+
+        let parser = MarkdownParser::new(None).unwrap();
+        let document = parser
+            .parse_document(content, test_metadata("markers.md", content))
+            .unwrap();
+        assert_eq!(document.total_pages, 4);
+
+        assert_eq!(document.pages[0].break_marker, None);
+        for page in &document.pages[1..] {
+            assert_eq!(page.break_marker.as_deref(), Some(lines[page.start_line]));
+        }
+        assert_eq!(document.pages[2].break_marker.as_deref(), Some("# page 2  "));
+
+        let config = SplitConfig {
+            splits: 2,
+            output_dir: output_dir.path().to_path_buf(),
+            preserve_structure: true,
+            include_metadata: true,
+            custom_page_marker: None,
+            ..SplitConfig::default()
+        };
+        let result = DocumentSplitter::split_document(&document, &config).await.unwrap();
+        let metadata = DocumentSplitter::read_metadata(result.metadata_file.as_ref().unwrap())
+            .await
+            .unwrap();
+
+        let recorded: Vec<Option<String>> = metadata
+            .split_info
+            .iter()
+            .flat_map(|info| info.pages.iter().map(|p| p.break_marker.clone()))
+            .collect();
+        let expected: Vec<Option<String>> =
+            document.pages.iter().map(|p| p.break_marker.clone()).collect();
+        assert_eq!(recorded, expected);
+    }
+
+    fn markdownish_line() -> impl Strategy<Value = String> {
+        prop_oneof![
+            Just(String::new()),
+            Just("---".to_string()),
+            Just("***".to_string()),
+            Just("```".to_string()),
+            Just("<!-- page break -->".to_string()),
+            Just("\\newpage".to_string()),
+            "# Page [0-9]{1,4}",
+            "\\(?[Pp]age [0-9]{1,3}\\)?",
+            "#{1,7} .{0,20}",
+            "[0-9]{1,8}",
+            "[\\-*_#=> ]{0,12}",
+            ".{0,40}",
+            "x{200,2000}",
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn prop_parsing_never_panics(
+            lines in prop::collection::vec(markdownish_line(), 0..60),
+            crlf in any::<bool>(),
+            marker in prop::option::of(".{0,12}"),
+        ) {
+            let content = lines.join(if crlf { "\r\n" } else { "\n" });
+
+            for split_by in [
+                SplitBy::Auto,
+                SplitBy::BareNumberLines { max_delta: Some(2) },
+                SplitBy::DocumentToc,
+                SplitBy::BlankLineRun(2),
+                SplitBy::JupyterCells,
+            ] {
+                let parser = MarkdownParser::with_options(ParserOptions {
+                    custom_page_marker: marker.clone(),
+                    split_by,
+                    ..ParserOptions::default()
+                })
+                .unwrap();
+
+                match parser.parse_str(&content, "fuzz.md") {
+                    Ok(document) => {
+                        prop_assert_eq!(document.total_pages, document.pages.len());
+                        prop_assert!(document.total_pages > 0);
+                        for page in &document.pages {
+                            prop_assert!(page.start_line <= page.end_line);
+                        }
+                        let _ = parser.get_parsing_stats(&document);
+                    }
+                    Err(e) => {
+                        let is_parsing_error = matches!(e, MarkdownSplitterError::PageParsing { .. });
+                        prop_assert!(is_parsing_error, "unexpected error: {}", e);
+                    }
+                }
+            }
+        }
+
+        #[test]
+        fn prop_split_info_never_panics(total_pages in 0usize..500, splits in 0usize..50) {
+            let (_, ranges) = DocumentSplitter::calculate_split_info(total_pages, splits);
+            for (start, end) in ranges {
+                prop_assert!(start <= end && end <= total_pages);
+            }
+        }
+    }
+
+    #[test]
+    fn test_leading_region_handling() {
+        let content = "Exported by PDF tool\nConfidential\n# Page 5\nFifth page.\n# Page 6\nSixth page.";
+
+        let parse = |leading_region| {
+            MarkdownParser::with_options(ParserOptions {
+                leading_region,
+                ..ParserOptions::default()
+            })
+            .unwrap()
+            .parse_str(content, "export.md")
+            .unwrap()
+        };
+
+        let prepended = parse(LeadingRegion::Prepend);
+        assert_eq!(prepended.total_pages, 3);
+        assert_eq!(prepended.pages[0].title, None);
+        assert!(prepended.pages[0].content.starts_with("Exported by PDF tool"));
+
+        let discarded = parse(LeadingRegion::Discard);
+        assert_eq!(discarded.total_pages, 2);
+        assert_eq!(discarded.pages[0].title.as_deref(), Some("Page 5"));
+        assert_eq!(discarded.pages[0].start_line, 2);
+        assert!(!discarded.pages.iter().any(|p| p.content.contains("Confidential")));
+
+        let merged = parse(LeadingRegion::MergeIntoFirst);
+        assert_eq!(merged.total_pages, 2);
+        assert_eq!(merged.pages[0].start_line, 0);
+        assert_eq!(merged.pages[0].title.as_deref(), Some("Page 5"));
+        assert!(merged.pages[0].content.starts_with("Exported by PDF tool"));
+        assert!(merged.pages[0].content.ends_with("Fifth page."));
+    }
+
+    #[tokio::test]
+    async fn test_shards_distribute_files_evenly() {
+        let output_dir = tempfile::tempdir().unwrap();
+        let content = paged_content(6);
+
+        let parser = MarkdownParser::new(None).unwrap();
+        let document = parser.parse_str(&content, "sharded.md").unwrap();
+
+        let config = SplitConfig {
+            splits: 6,
+            output_dir: output_dir.path().to_path_buf(),
+            shard_dirs: Some(3),
+            ..SplitConfig::default()
+        };
+        let result = DocumentSplitter::split_document(&document, &config).await.unwrap();
+
+        for shard in 0..3 {
+            let shard_dir = output_dir.path().join(format!("shard-{}", shard));
+            let files: Vec<_> = std::fs::read_dir(&shard_dir).unwrap().collect();
+            assert_eq!(files.len(), 2, "shard-{} should hold two splits", shard);
+        }
+
+        assert!(result.output_files[4].starts_with(output_dir.path().join("shard-1")));
+        let shards: Vec<Option<usize>> = result.splits.iter().map(|s| s.shard).collect();
+        assert_eq!(shards, vec![Some(0), Some(1), Some(2), Some(0), Some(1), Some(2)]);
+
+        let (reassembled, metadata) =
+            DocumentSplitter::reassemble_splits(result.metadata_file.as_ref().unwrap())
+                .await
+                .unwrap();
+        assert_eq!(metadata.split_info[5].shard, Some(2));
+        assert!(reassembled.contains("Content of page 6."));
+    }
+
+    #[tokio::test]
+    async fn test_reconstruct_round_trips_original() {
+        // Headings, rules and a short untitled gap that gets merged into its predecessor
+        let content = "# Intro\n\nWelcome.\n\n---\n\nGap text\n\n## Usage\n\nRun it.\n\n---\n\n## Notes\n\nDone.\n";
+
+        for preserve_structure in [true, false] {
+            let output_dir = tempfile::tempdir().unwrap();
+            let parser = MarkdownParser::new(None).unwrap();
+            let document = parser.parse_str(content, "roundtrip.md").unwrap();
+
+            let config = SplitConfig {
+                splits: 2,
+                output_dir: output_dir.path().to_path_buf(),
+                preserve_structure,
+                ..SplitConfig::default()
+            };
+            let result = DocumentSplitter::split_document(&document, &config).await.unwrap();
+
+            let rebuilt = DocumentSplitter::reconstruct(
+                result.metadata_file.as_ref().unwrap(),
+                output_dir.path(),
+            )
+            .await
+            .unwrap();
+            assert_eq!(rebuilt, content);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reconstruct_rejects_discarded_leading_region() {
+        let output_dir = tempfile::tempdir().unwrap();
+        let content = "Boilerplate\n# Page 1\nOne\n# Page 2\nTwo\n";
+
+        let parser = MarkdownParser::with_options(ParserOptions {
+            leading_region: LeadingRegion::Discard,
+            ..ParserOptions::default()
+        })
+        .unwrap();
+        let document = parser.parse_str(content, "lossy.md").unwrap();
+
+        let config = SplitConfig {
+            splits: 2,
+            output_dir: output_dir.path().to_path_buf(),
+            ..SplitConfig::default()
+        };
+        let result = DocumentSplitter::split_document(&document, &config).await.unwrap();
+
+        let rebuilt =
+            DocumentSplitter::reconstruct(result.metadata_file.as_ref().unwrap(), output_dir.path())
+                .await;
+        assert!(matches!(rebuilt, Err(MarkdownSplitterError::InvalidMetadata { .. })));
+    }
+
+    #[test]
+    fn test_document_toc_drives_page_breaks() {
+        let content = "# Manual\n\n## Contents\n\n- [Getting Started](#getting-started)\n  - [Install](#install)\n- [Usage & Tips](#usage--tips)\n- [Missing](#missing)\n\n## Getting Started\n\nIntro.\n\n### Install\n\nSteps.\n\n## Usage & Tips\n\nMore.\n\n## Appendix\n\nNot in TOC.";
+
+        let parser = MarkdownParser::with_options(ParserOptions {
+            split_by: SplitBy::DocumentToc,
+            ..ParserOptions::default()
+        })
+        .unwrap();
+        let document = parser.parse_str(content, "manual.md").unwrap();
+
+        let titles: Vec<Option<&str>> =
+            document.pages.iter().map(|p| p.title.as_deref()).collect();
+        assert_eq!(titles, vec![Some("Manual"), Some("Getting Started"), Some("Usage & Tips")]);
+        assert!(document.pages[1].content.contains("### Install"));
+        assert!(document.pages[2].content.contains("## Appendix"));
+
+        assert_eq!(heading_anchor("Usage & Tips"), "usage--tips");
+
+        // Without a TOC the parser falls back to pattern detection
+        let fallback = parser.parse_str("# One\n\nText\n\n# Two\n\nText", "plain.md").unwrap();
+        assert_eq!(fallback.total_pages, 2);
+    }
+
+    #[tokio::test]
+    async fn test_min_split_bytes_merges_small_trailing_split() {
+        let output_dir = tempfile::tempdir().unwrap();
+        let parser = MarkdownParser::new(None).unwrap();
+        let document = parser.parse_str(&paged_content(5), "tail.md").unwrap();
+        assert_eq!(document.total_pages, 5);
+
+        // Three splits of two pages leave a single-page leftover below the floor
+        let config = SplitConfig {
+            splits: 3,
+            output_dir: output_dir.path().to_path_buf(),
+            min_split_bytes: Some(40),
+            ..SplitConfig::default()
+        };
+        let result = DocumentSplitter::split_document(&document, &config).await.unwrap();
+
+        assert_eq!(result.split_number, 2);
+        assert_eq!(result.actual_pages, 5);
+        let page_ranges: Vec<(usize, usize)> =
+            result.splits.iter().map(|s| (s.start_page, s.end_page)).collect();
+        assert_eq!(page_ranges, vec![(1, 2), (3, 5)]);
+        assert!(result.output_files[1].ends_with("tail_split_2_of_2.md"));
+        assert!(!output_dir.path().join("tail_split_3_of_3.md").exists());
+    }
+
+    #[tokio::test]
+    async fn test_heading_context_carries_ancestor_headings() {
+        let output_dir = tempfile::tempdir().unwrap();
+        let filler = "More text.\n".repeat(12);
+        let content = format!(
+            "# Chapter 1\n\nIntro.\n\n## Section 2\n\nText.\n<!-- cut -->\n### Detail A\n\nDetails.\n<!-- cut -->\n{}",
+            filler
+        );
+
+        let parser = MarkdownParser::new(Some("<!-- cut -->")).unwrap();
+        let document = parser.parse_str(&content, "book.md").unwrap();
+        assert_eq!(document.total_pages, 3);
+
+        let config = SplitConfig {
+            splits: 3,
+            output_dir: output_dir.path().to_path_buf(),
+            include_heading_context: true,
+            ..SplitConfig::default()
+        };
+        let result = DocumentSplitter::split_document(&document, &config).await.unwrap();
+
+        let headers: Vec<&str> = result.splits.iter().map(|s| s.header.as_str()).collect();
+        assert!(!headers[0].contains("# Chapter 1"));
+        assert!(headers[1].ends_with("-->\n\n# Chapter 1\n## Section 2\n\n"));
+        assert!(headers[2].ends_with("# Chapter 1\n## Section 2\n### Detail A\n\n"));
+
+        let second = std::fs::read_to_string(&result.output_files[1]).unwrap();
+        assert!(second.contains("# Chapter 1\n## Section 2\n\n<!-- cut -->\n### Detail A"));
+
+        // The context is part of the header, so reassembly does not duplicate it
+        let (reassembled, _) =
+            DocumentSplitter::reassemble_splits(result.metadata_file.as_ref().unwrap())
+                .await
+                .unwrap();
+        assert_eq!(reassembled.matches("# Chapter 1").count(), 1);
+    }
+
+    #[test]
+    fn test_schemas_cover_key_fields() {
+        let metadata: serde_json::Value =
+            serde_json::from_str(&serde_json::to_string(&metadata_schema()).unwrap()).unwrap();
+        let properties = &metadata["properties"];
+        for field in ["source", "total_splits", "split_info", "statistics", "document_metadata"] {
+            assert!(properties.get(field).is_some(), "metadata schema lacks {}", field);
+        }
+        assert!(metadata["definitions"]["SplitInfo"]["properties"]["pages"].is_object());
+
+        let analysis: serde_json::Value =
+            serde_json::from_str(&serde_json::to_string(&analysis_schema()).unwrap()).unwrap();
+        let definitions = &analysis["definitions"];
+        assert!(definitions["AnalysisReport"]["properties"]["stats"].is_object());
+        assert!(definitions["MarkdownPage"]["properties"]["content"].is_object());
+        assert!(definitions["ParsingStats"]["properties"]["avg_lines_per_page"].is_object());
+    }
+
+    #[test]
+    fn test_blank_line_runs_break_pages() {
+        let content = "# Intro\n\nFirst.\n\n\n\nSecond part.\n\n\nStill second.\n\n\n\n\nThird.";
+
+        let parser = MarkdownParser::with_options(ParserOptions {
+            split_by: SplitBy::BlankLineRun(3),
+            ..ParserOptions::default()
+        })
+        .unwrap();
+        let document = parser.parse_str(content, "gaps.md").unwrap();
+
+        // Three- and four-blank gaps break, the two-blank gap does not
+        assert_eq!(document.total_pages, 3);
+        assert!(document.pages[1].content.starts_with("Second part."));
+        assert!(document.pages[1].content.contains("Still second."));
+        assert_eq!(document.pages[2].content, "Third.");
+        assert_eq!(document.pages[0].content, "# Intro\n\nFirst.\n\n\n");
+
+        let collapsing = MarkdownParser::with_options(ParserOptions {
+            split_by: SplitBy::BlankLineRun(3),
+            collapse_blank_runs: true,
+            ..ParserOptions::default()
+        })
+        .unwrap();
+        let document = collapsing.parse_str(content, "gaps.md").unwrap();
+        assert_eq!(document.pages[0].content, "# Intro\n\nFirst.");
+        assert_eq!(document.pages[1].content, "Second part.\n\n\nStill second.");
+    }
+
+    #[test]
+    fn test_max_merged_lines_caps_small_page_merging() {
+        let tiny_pages = "---\nA short note.\n\n".repeat(30);
+        let content = format!("# Notes\n\nIntro.\n\n{}", tiny_pages);
+
+        let uncapped = MarkdownParser::new(None).unwrap();
+        let document = uncapped.parse_str(&content, "notes.md").unwrap();
+        assert_eq!(document.total_pages, 1);
+
+        let capped = MarkdownParser::with_options(ParserOptions {
+            max_merged_lines: Some(10),
+            ..ParserOptions::default()
+        })
+        .unwrap();
+        let document = capped.parse_str(&content, "notes.md").unwrap();
+
+        assert!(document.total_pages > 1);
+        for page in &document.pages {
+            assert!(page.end_line - page.start_line <= 10);
+        }
+        assert_eq!(document.pages.last().unwrap().end_line, document.metadata.total_lines);
+    }
+
+    #[test]
+    fn test_jupyter_cells_become_pages() {
+        let content = "# %% [markdown]\n# Loading data\n\nRead the CSV.\n# %%\nimport pandas as pd\n# %% [markdown]\nDone.";
+
+        let parser = MarkdownParser::with_options(ParserOptions {
+            split_by: SplitBy::JupyterCells,
+            ..ParserOptions::default()
+        })
+        .unwrap();
+        let document = parser.parse_str(content, "notebook.md").unwrap();
+
+        assert_eq!(document.total_pages, 3);
+        assert_eq!(document.pages[0].title.as_deref(), Some("Loading data"));
+        assert_eq!(document.pages[1].title, None);
+        assert_eq!(document.pages[1].content, "# %%\nimport pandas as pd");
+        assert_eq!(document.pages[2].break_marker.as_deref(), Some("# %% [markdown]"));
+
+        let stripping = MarkdownParser::with_options(ParserOptions {
+            split_by: SplitBy::JupyterCells,
+            strip_cell_delimiters: true,
+            ..ParserOptions::default()
+        })
+        .unwrap();
+        let document = stripping.parse_str(content, "notebook.md").unwrap();
+        let contents: Vec<&str> = document.pages.iter().map(|p| p.content.as_str()).collect();
+        assert_eq!(contents, vec!["# Loading data\n\nRead the CSV.", "import pandas as pd", "Done."]);
+    }
+
+    #[tokio::test]
+    async fn test_max_file_size_rejects_large_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("big.md");
+        std::fs::write(&path, paged_content(3)).unwrap();
+        let source = path.to_str().unwrap();
+
+        let options = FetchOptions {
+            max_bytes: Some(16),
+            ..FetchOptions::default()
+        };
+        let result = ContentFetcher::fetch_content_with(source, &options).await;
+        match result {
+            Err(MarkdownSplitterError::FileTooLarge { size, limit, .. }) => {
+                assert_eq!(limit, 16);
+                assert_eq!(size, std::fs::metadata(&path).unwrap().len());
+            }
+            other => panic!("expected FileTooLarge, got {:?}", other.map(|(_, m)| m.filename)),
+        }
+
+        let options = FetchOptions {
+            max_bytes: Some(1024),
+            ..FetchOptions::default()
+        };
+        assert!(ContentFetcher::fetch_content_with(source, &options).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_chunk_ids_stable_across_runs() {
+        let parser = MarkdownParser::new(None).unwrap();
+
+        async fn chunk_ids(document: &MarkdownDocument, source_id: Option<&str>) -> Vec<String> {
+            let output_dir = tempfile::tempdir().unwrap();
+            let config = SplitConfig {
+                splits: 3,
+                output_dir: output_dir.path().to_path_buf(),
+                source_id: source_id.map(str::to_string),
+                chunk_id_in_header: true,
+                ..SplitConfig::default()
+            };
+            let result = DocumentSplitter::split_document(document, &config).await.unwrap();
+
+            let first = std::fs::read_to_string(&result.output_files[0]).unwrap();
+            assert!(first.contains(&format!("<!-- chunk-id: {} -->", result.splits[0].chunk_id)));
+            result.splits.into_iter().map(|s| s.chunk_id).collect()
+        }
+
+        let document = parser.parse_str(&paged_content(6), "guide.md").unwrap();
+        let first_run = chunk_ids(&document, None).await;
+        assert_eq!(first_run.len(), 3);
+        assert!(first_run.iter().all(|id| id.len() == 16));
+        assert_eq!(chunk_ids(&document, None).await, first_run);
+
+        // Editing a page only changes the ID of the chunk holding it
+        let edited = paged_content(6).replace("Content of page 6.", "Revised page 6.");
+        let edited = parser.parse_str(&edited, "guide.md").unwrap();
+        let edited_run = chunk_ids(&edited, None).await;
+        assert_eq!(edited_run[..2], first_run[..2]);
+        assert_ne!(edited_run[2], first_run[2]);
+
+        // A logical source name keeps IDs independent of the file name
+        let moved = parser.parse_str(&paged_content(6), "moved.md").unwrap();
+        assert_ne!(chunk_ids(&moved, None).await, first_run);
+        assert_eq!(
+            chunk_ids(&moved, Some("docs/guide")).await,
+            chunk_ids(&document, Some("docs/guide")).await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_wrap_width_reflows_paragraphs_only() {
+        let output_dir = tempfile::tempdir().unwrap();
+        let paragraph = "word ".repeat(40);
+        let code_line = format!("let long_line = \"{}\";", "x".repeat(100));
+        let content = format!(
+            "# Title\n\n{}\n\n```rust\n{}\n```\n\n- a list item that stays on a single line even though it is longer than forty columns",
+            paragraph.trim(),
+            code_line
+        );
+
+        let parser = MarkdownParser::new(None).unwrap();
+        let document = parser.parse_str(&content, "wrap.md").unwrap();
+        let config = SplitConfig {
+            splits: 1,
+            output_dir: output_dir.path().to_path_buf(),
+            wrap_width: Some(40),
+            ..SplitConfig::default()
+        };
+        let result = DocumentSplitter::split_document(&document, &config).await.unwrap();
+        let written = std::fs::read_to_string(&result.output_files[0]).unwrap();
+
+        let prose: Vec<&str> = written.lines().filter(|line| line.starts_with("word")).collect();
+        assert_eq!(prose.len(), 5);
+        assert!(prose.iter().all(|line| line.len() <= 40));
+        assert!(written.contains(&format!("```rust\n{}\n```", code_line)));
+        assert!(written.contains("- a list item that stays on a single line even though"));
+    }
+
+    #[test]
+    fn test_extract_page_range() {
+        let parser = MarkdownParser::new(None).unwrap();
+        let document = parser.parse_str(&paged_content(8), "pages.md").unwrap();
+
+        let single = DocumentSplitter::extract_page_range(&document, 5, 5).unwrap();
+        assert_eq!(single, document.pages[4].content);
+        assert!(single.starts_with("# Page 5"));
+
+        let range = DocumentSplitter::extract_page_range(&document, 3, 7).unwrap();
+        assert!(range.starts_with("# Page 3"));
+        assert!(range.contains("# Page 7"));
+        assert!(!range.contains("# Page 8"));
+        assert_eq!(range.matches("\n\n---\n\n").count(), 4);
+
+        for (first, last) in [(0, 2), (4, 3), (8, 9)] {
+            let result = DocumentSplitter::extract_page_range(&document, first, last);
+            assert!(matches!(result, Err(MarkdownSplitterError::PageSelection { .. })));
+        }
+    }
+
+    #[cfg(feature = "lang-detect")]
+    #[test]
+    fn test_detects_english_documents() {
+        let content = "# Getting Started\n\nThis guide explains how to install the tool, configure the \
+            output directory and split a long document into several smaller files that are \
+            easier to review. Each section below walks through one of the common workflows.";
+
+        let parser = MarkdownParser::new(None).unwrap();
+        let document = parser.parse_str(content, "guide.md").unwrap();
+        assert_eq!(document.detected_language.as_deref(), Some("eng"));
+    }
+
+    #[tokio::test]
+    async fn test_max_splits_per_source_coarsens() {
+        let output_dir = tempfile::tempdir().unwrap();
+        let parser = MarkdownParser::new(None).unwrap();
+        let document = parser.parse_str(&paged_content(100), "huge.md").unwrap();
+        assert_eq!(document.total_pages, 100);
+
+        let config = SplitConfig {
+            splits: 100,
+            output_dir: output_dir.path().to_path_buf(),
+            max_splits_per_source: Some(10),
+            ..SplitConfig::default()
+        };
+        let result = DocumentSplitter::split_document(&document, &config).await.unwrap();
+
+        assert_eq!(result.split_number, 10);
+        assert_eq!(result.pages_per_split, 10);
+        assert_eq!(result.actual_pages, 100);
+        assert!(result.output_files[9].ends_with("huge_split_10_of_10.md"));
+
+        let strict = SplitConfig {
+            split_limit_policy: SplitLimitPolicy::Error,
+            ..config
+        };
+        let result = DocumentSplitter::split_document(&document, &strict).await;
+        assert!(matches!(result, Err(MarkdownSplitterError::SplitConfig { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_balanced_split_uses_cost_function() {
+        let output_dir = tempfile::tempdir().unwrap();
+        let content = (1..=6)
+            .map(|n| {
+                let body = if n <= 2 { "```\nlet x = 1;\n```" } else { "Some prose here." };
+                format!("# Page {}\n\n{}\n", n, body)
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let parser = MarkdownParser::new(None).unwrap();
+        let document = parser.parse_str(&content, "review.md").unwrap();
+        assert_eq!(document.total_pages, 6);
+
+        let config = SplitConfig {
+            splits: 2,
+            output_dir: output_dir.path().to_path_buf(),
+            ..SplitConfig::default()
+        };
+        let ranges = |result: &SplitResult| -> Vec<(usize, usize)> {
+            result.splits.iter().map(|s| (s.start_page, s.end_page)).collect()
+        };
+
+        let even = DocumentSplitter::split_document(&document, &config).await.unwrap();
+        assert_eq!(ranges(&even), vec![(1, 3), (4, 6)]);
+
+        // Code pages count double, so the two code pages weigh as much as the four prose pages
+        let code_heavy = |page: &MarkdownPage| if page.content.contains("```") { 2 } else { 1 };
+        let balanced = DocumentSplitter::split_document_balanced(&document, &config, code_heavy)
+            .await
+            .unwrap();
+        assert_eq!(ranges(&balanced), vec![(1, 2), (3, 6)]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_directory_sources_survive_symlink_loops() {
+        let dir = tempfile::tempdir().unwrap();
+        let docs = dir.path().join("docs");
+        std::fs::create_dir_all(docs.join("nested")).unwrap();
+        std::fs::write(docs.join("a.md"), "# A").unwrap();
+        std::fs::write(docs.join("nested/b.markdown"), "# B").unwrap();
+        std::fs::write(docs.join("notes.txt"), "skip").unwrap();
+        std::os::unix::fs::symlink(&docs, docs.join("nested/loop")).unwrap();
+        std::os::unix::fs::symlink(docs.join("a.md"), docs.join("alias.md")).unwrap();
+
+        let sources = vec![docs.display().to_string()];
+        let expanded = ContentFetcher::expand_sources(&sources, false).unwrap();
+        let names: Vec<&str> = expanded
+            .iter()
+            .map(|path| path.rsplit('/').next().unwrap())
+            .collect();
+        assert_eq!(names, vec!["a.md", "b.markdown"]);
+
+        let followed = ContentFetcher::expand_sources(&sources, true);
+        assert!(matches!(followed, Err(MarkdownSplitterError::SymlinkCycle { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_order_by_size_numbers_largest_first() {
+        let output_dir = tempfile::tempdir().unwrap();
+        let content = [2, 1, 6, 3]
+            .iter()
+            .enumerate()
+            .map(|(idx, lines)| format!("# Page {}\n\n{}", idx + 1, "Filler text.\n".repeat(*lines)))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let parser = MarkdownParser::new(None).unwrap();
+        let document = parser.parse_str(&content, "sized.md").unwrap();
+        assert_eq!(document.total_pages, 4);
+
+        let config = SplitConfig {
+            splits: 4,
+            output_dir: output_dir.path().to_path_buf(),
+            order_by: SplitOrder::Size,
+            ..SplitConfig::default()
+        };
+        let result = DocumentSplitter::split_document(&document, &config).await.unwrap();
+
+        // Splits stay in document order, numbered by size
+        let numbering: Vec<(usize, usize)> = result
+            .splits
+            .iter()
+            .map(|s| (s.document_order, s.split_number))
+            .collect();
+        assert_eq!(numbering, vec![(1, 3), (2, 4), (3, 1), (4, 2)]);
+        assert_eq!(result.splits[2].filename, "sized_split_1_of_4.md");
+        assert_eq!(result.splits[2].start_page, 3);
+
+        let (reassembled, _) =
+            DocumentSplitter::reassemble_splits(result.metadata_file.as_ref().unwrap())
+                .await
+                .unwrap();
+        let page_order: Vec<usize> = ["# Page 1", "# Page 2", "# Page 3", "# Page 4"]
+            .iter()
+            .map(|title| reassembled.find(title).unwrap())
+            .collect();
+        assert!(page_order.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[tokio::test]
+    async fn test_rotating_append_rolls_over() {
+        let output_dir = tempfile::tempdir().unwrap();
+        let parser = MarkdownParser::new(None).unwrap();
+        let config = RotationConfig {
+            output_dir: output_dir.path().to_path_buf(),
+            base_name: "log".to_string(),
+            max_bytes: 100,
+        };
+
+        let first = parser.parse_str(&paged_content(2), "first.md").unwrap();
+        let written = DocumentSplitter::append_to_rotating(&first, &config).await.unwrap();
+        assert_eq!(written, vec![output_dir.path().join("log_0001.md")]);
+
+        // The second run continues the existing tail file, then rolls over
+        let second = parser.parse_str(&paged_content(3), "second.md").unwrap();
+        let written = DocumentSplitter::append_to_rotating(&second, &config).await.unwrap();
+        assert_eq!(
+            written,
+            vec![output_dir.path().join("log_0001.md"), output_dir.path().join("log_0002.md")]
+        );
+
+        let tail = std::fs::read_to_string(output_dir.path().join("log_0001.md")).unwrap();
+        let rolled = std::fs::read_to_string(output_dir.path().join("log_0002.md")).unwrap();
+        assert!(tail.len() <= 100 && rolled.len() <= 100);
+        assert!(tail.starts_with("# Page 1") && tail.contains("# Page 2"));
+        assert_eq!(tail.matches("# Page 1").count(), 2);
+        assert!(rolled.starts_with("# Page 2") && rolled.contains("# Page 3"));
+    }
+
+    #[tokio::test]
+    async fn test_cross_split_anchors_reported_and_fixed() {
+        let content = "# Intro\n\nSee [setup](#setup) and [intro](#intro).\n\n---\n\n# Setup\n\nInstall it.\n";
+        let parser = MarkdownParser::new(None).unwrap();
+        let document = parser.parse_str(content, "guide.md").unwrap();
+        assert_eq!(document.total_pages, 2);
+
+        let output_dir = tempfile::tempdir().unwrap();
+        let mut config = SplitConfig {
+            splits: 2,
+            output_dir: output_dir.path().to_path_buf(),
+            ..SplitConfig::default()
+        };
+        let result = DocumentSplitter::split_document(&document, &config).await.unwrap();
+
+        // Only the link whose heading moved to the second split is reported
+        assert_eq!(
+            result.cross_split_anchors,
+            vec![CrossSplitAnchor {
+                split_number: 1,
+                anchor: "setup".to_string(),
+                target_split_number: 2,
+                target_file: "guide_split_2_of_2.md".to_string(),
+                rewritten: false,
+            }]
+        );
+        let first = std::fs::read_to_string(&result.output_files[0]).unwrap();
+        assert!(first.contains("[setup](#setup)"));
+
+        config.fix_cross_split_anchors = true;
+        let result = DocumentSplitter::split_document(&document, &config).await.unwrap();
+        assert!(result.cross_split_anchors[0].rewritten);
+        let first = std::fs::read_to_string(&result.output_files[0]).unwrap();
+        assert!(first.contains("[setup](guide_split_2_of_2.md#setup)"));
+        assert!(first.contains("[intro](#intro)"));
+    }
+
+    /// Serves `body` over HTTP to every connection on a local port, returning its base URL
+    async fn serve_markdown(body: &'static str) -> String {
+        serve_markdown_after(body, Duration::ZERO).await
+    }
+
+    /// Like [`serve_markdown`], waiting `delay` before each response
+    async fn serve_markdown_after(body: &'static str, delay: Duration) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = [0u8; 1024];
+                let _ = stream.read(&mut request).await;
+                tokio::time::sleep(delay).await;
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+
+        format!("http://{}", address)
+    }
+
+    #[tokio::test]
+    async fn test_splits_record_origin_of_mixed_sources() {
+        let input_dir = tempfile::tempdir().unwrap();
+        let local_path = input_dir.path().join("local.md");
+        std::fs::write(&local_path, "# Local\n\nFrom disk.\n").unwrap();
+        let local_source = local_path.display().to_string();
+        let url_source = format!("{}/remote.md", serve_markdown("# Remote\n\nFrom the web.\n").await);
+
+        let output_dir = tempfile::tempdir().unwrap();
+        let config = SplitConfig {
+            splits: 1,
+            output_dir: output_dir.path().to_path_buf(),
+            tag_origin: true,
+            ..SplitConfig::default()
+        };
+        let parser = MarkdownParser::new(None).unwrap();
+
+        let expected = [
+            (local_source.as_str(), SourceType::LocalFile, "LocalFile"),
+            (url_source.as_str(), SourceType::Url, "Url"),
+        ];
+        for (source, source_type, label) in expected {
+            let (content, metadata) = ContentFetcher::fetch_content(source).await.unwrap();
+            let document = parser.parse_document(&content, metadata).unwrap();
+            let result = DocumentSplitter::split_document(&document, &config).await.unwrap();
+
+            let info = &result.splits[0];
+            assert_eq!(info.source_type, Some(source_type));
+            assert_eq!(info.origin, source);
+
+            let written = std::fs::read_to_string(&result.output_files[0]).unwrap();
+            assert!(written.contains(&format!("<!-- origin: {} {} -->", label, source)));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_data_uri_list_decodes_to_split_content() {
+        use base64::Engine;
+
+        let parser = MarkdownParser::new(None).unwrap();
+        let document = parser.parse_str(&paged_content(4), "embed.md").unwrap();
+
+        let output_dir = tempfile::tempdir().unwrap();
+        let config = SplitConfig {
+            splits: 2,
+            output_dir: output_dir.path().to_path_buf(),
+            ..SplitConfig::default()
+        };
+        let list_path = DocumentSplitter::write_data_uri_list(&document, &config, None)
+            .await
+            .unwrap();
+        assert_eq!(list_path, output_dir.path().join("embed_data_uris.json"));
+
+        let entries: Vec<DataUriSplit> =
+            serde_json::from_str(&std::fs::read_to_string(&list_path).unwrap()).unwrap();
+        assert_eq!(entries.len(), 2);
+        // No split files are written alongside the list
+        assert!(!output_dir.path().join("embed_split_1_of_2.md").exists());
+
+        // Each URI carries exactly what the split file would have held
+        let split_dir = tempfile::tempdir().unwrap();
+        let split_config = SplitConfig {
+            output_dir: split_dir.path().to_path_buf(),
+            ..config
+        };
+        let result = DocumentSplitter::split_document(&document, &split_config).await.unwrap();
+        for (entry, path) in entries.iter().zip(&result.output_files) {
+            let encoded = entry.data_uri.strip_prefix("data:text/markdown;base64,").unwrap();
+            let decoded = base64::engine::general_purpose::STANDARD.decode(encoded).unwrap();
+            assert_eq!(String::from_utf8(decoded).unwrap(), std::fs::read_to_string(path).unwrap());
+            assert_eq!(entry.bytes, std::fs::metadata(path).unwrap().len() as usize);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_extract_code_blocks_by_language() {
+        let content = "# Samples\n\nIn Rust:\n\n```rust\nfn main() {}\n```\n\n---\n\n# More\n\nIn Python:\n\n~~~python\nprint(\"hi\")\n~~~\n\nDone.\n";
+        let parser = MarkdownParser::new(None).unwrap();
+        let document = parser.parse_str(content, "samples.md").unwrap();
+
+        let output_dir = tempfile::tempdir().unwrap();
+        let code_dir = output_dir.path().join("code");
+        let config = SplitConfig {
+            splits: 1,
+            output_dir: output_dir.path().to_path_buf(),
+            extract_code_dir: Some(code_dir.clone()),
+            strip_code_blocks: true,
+            ..SplitConfig::default()
+        };
+        let result = DocumentSplitter::split_document(&document, &config).await.unwrap();
+
+        assert_eq!(std::fs::read_to_string(code_dir.join("samples_001.rs")).unwrap(), "fn main() {}\n");
+        assert_eq!(std::fs::read_to_string(code_dir.join("samples_002.py")).unwrap(), "print(\"hi\")\n");
+
+        let lines: Vec<&str> = content.lines().collect();
+        let blocks = &result.code_blocks;
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0].language.as_deref(), Some("rust"));
+        assert_eq!(lines[blocks[0].start_line], "```rust");
+        assert_eq!(lines[blocks[1].end_line - 1], "~~~");
+        assert_eq!(blocks[1].page, 2);
+
+        let metadata: SplitMetadata = serde_json::from_str(
+            &std::fs::read_to_string(result.metadata_file.as_ref().unwrap()).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(&metadata.code_blocks, blocks);
+
+        // The prose keeps its text but not the code
+        let prose = std::fs::read_to_string(&result.output_files[0]).unwrap();
+        assert!(prose.contains("In Python:") && prose.contains("Done."));
+        assert!(!prose.contains("fn main") && !prose.contains("print("));
+
+        // Another source sharing the code directory keeps its own files
+        let other = parser.parse_str(content, "other.md").unwrap();
+        let other_config = SplitConfig {
+            output_dir: output_dir.path().join("other"),
+            ..config
+        };
+        DocumentSplitter::split_document(&other, &other_config).await.unwrap();
+        assert!(code_dir.join("samples_001.rs").exists() && code_dir.join("other_001.rs").exists());
+    }
+
+    #[test]
+    fn test_stray_page_marker_falls_back_to_rules() {
+        let sections: Vec<String> = (1..=6)
+            .map(|n| format!("Section {} text.\n{}", n, "Filler line.\n".repeat(12)))
+            .collect();
+        let content = format!("# Page 1\n\n{}", sections.join("\n\n---\n\n"));
+
+        // By default the single marker wins and the rules are ignored
+        let parser = MarkdownParser::new(None).unwrap();
+        assert_eq!(parser.parse_str(&content, "rules.md").unwrap().total_pages, 1);
+
+        let parser = MarkdownParser::with_options(ParserOptions {
+            min_explicit_markers: 2,
+            ..ParserOptions::default()
+        })
+        .unwrap();
+        let document = parser.parse_str(&content, "rules.md").unwrap();
+        assert_eq!(document.total_pages, 6);
+        assert!(document.pages[5].content.contains("Section 6 text."));
+    }
+
+    #[tokio::test]
+    async fn test_repeated_runs_produce_identical_json() {
+        let content = paged_content(6);
+        let metadata = test_metadata("stable.md", &content);
+        let parser = MarkdownParser::new(None).unwrap();
+
+        let analyze = || {
+            let mut reports = BTreeMap::new();
+            for source in ["b.md", "a.md", "c.md"] {
+                let report = parser.analyze(&content, metadata.clone()).unwrap();
+                reports.insert(source.to_string(), report);
+            }
+            analysis_json(&reports).unwrap()
+        };
+        let first = analyze();
+        assert_eq!(first, analyze());
+        assert!(first.find("\"a.md\"").unwrap() < first.find("\"b.md\"").unwrap());
+
+        let document = parser.parse_document(&content, metadata).unwrap();
+        let mut metadata_files = Vec::new();
+        for _ in 0..2 {
+            let output_dir = tempfile::tempdir().unwrap();
+            let config = SplitConfig {
+                splits: 3,
+                output_dir: output_dir.path().to_path_buf(),
+                ..SplitConfig::default()
+            };
+            let result = DocumentSplitter::split_document(&document, &config).await.unwrap();
+            let json = std::fs::read_to_string(result.metadata_file.unwrap()).unwrap();
+            // Split paths embed the temporary directory
+            metadata_files.push(json.replace(&output_dir.path().display().to_string(), "OUT"));
+        }
+        assert_eq!(metadata_files[0], metadata_files[1]);
+    }
+
+    #[test]
+    fn test_form_feed_pages() {
+        let content = "Report header\nFirst page text.\n\x0CSecond page text.\nMore.\n\x0C\n# Appendix\nThird page.\n";
+        let parser = MarkdownParser::new(None).unwrap();
+        let document = parser.parse_str(content, "dump.txt").unwrap();
+
+        assert_eq!(document.total_pages, 3);
+        assert_eq!(document.pages[1].content, "Second page text.\nMore.");
+        assert_eq!(document.pages[2].title.as_deref(), Some("Appendix"));
+        assert!(document.pages.iter().all(|page| !page.content.contains('\x0C')));
+    }
+
+    #[test]
+    fn test_page_callback_sees_every_final_page() {
+        let content = paged_content(5);
+        let parser = MarkdownParser::new(None).unwrap();
+
+        let mut seen = Vec::new();
+        let document = parser
+            .parse_document_with_callback(&content, test_metadata("stream.md", &content), |page| {
+                seen.push(page.number)
+            })
+            .unwrap();
+
+        assert_eq!(seen.len(), document.total_pages);
+        assert_eq!(seen, (1..=document.total_pages).collect::<Vec<_>>());
+
+        // Pages are handed over with the small pages merged into them
+        let content = "# One
+
+Intro.
+
+---
+
+Tail of one.
+
+---
+
+# Two
+
+Body.
+";
+        let mut seen = Vec::new();
+        let document = parser
+            .parse_document_with_callback(content, test_metadata("merged.md", content), |page| {
+                seen.push((page.number, page.content.clone()))
+            })
+            .unwrap();
+        let pages: Vec<_> = document.pages.iter().map(|page| (page.number, page.content.clone())).collect();
+        assert_eq!(document.total_pages, 2);
+        assert_eq!(seen, pages);
+    }
+
+    #[tokio::test]
+    async fn test_time_budget_cancels_slow_source() {
+        let input_dir = tempfile::tempdir().unwrap();
+        let fast_path = input_dir.path().join("fast.md");
+        std::fs::write(&fast_path, paged_content(3)).unwrap();
+        let fast = fast_path.display().to_string();
+        let slow = format!(
+            "{}/slow.md",
+            serve_markdown_after("# Slow\n\nEventually.\n", Duration::from_secs(10)).await
+        );
+
+        let output_dir = tempfile::tempdir().unwrap();
+        let config = SplitConfig {
+            splits: 1,
+            output_dir: output_dir.path().to_path_buf(),
+            ..SplitConfig::default()
+        };
+        let parser = MarkdownParser::new(None).unwrap();
+
+        let mut completed = Vec::new();
+        let batch = async {
+            for source in [&fast, &slow] {
+                let (content, metadata) = ContentFetcher::fetch_content(source).await?;
+                let document = parser.parse_document(&content, metadata)?;
+                DocumentSplitter::split_document(&document, &config).await?;
+                completed.push(source.clone());
+            }
+            Ok(())
+        };
+        let started = std::time::Instant::now();
+        let outcome = with_time_budget(Some(Duration::from_millis(500)), batch).await;
+
+        assert!(matches!(outcome, Err(MarkdownSplitterError::RuntimeExceeded { .. })));
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(completed, vec![fast]);
+        assert!(output_dir.path().join("fast_split_1_of_1.md").exists());
+
+        // Without a budget the same work simply runs
+        assert_eq!(with_time_budget(None, async { Ok(7) }).await.unwrap(), 7);
+    }
+
+    #[tokio::test]
+    async fn test_strip_repeated_boilerplate() {
+        let bodies = ["Alpha", "Bravo", "Charlie", "Delta", "Echo"];
+        let content = (1..=5)
+            .map(|n| {
+                format!(
+                    "# Page {}\n\n{} body text.\n\nACME Corp  Confidential - page {} of 5\n",
+                    n,
+                    bodies[n - 1],
+                    n
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        let parser = MarkdownParser::new(None).unwrap();
+        let document = parser.parse_str(&content, "export.md").unwrap();
+        assert_eq!(document.total_pages, 5);
+
+        let output_dir = tempfile::tempdir().unwrap();
+        let mut config = SplitConfig {
+            splits: 1,
+            output_dir: output_dir.path().to_path_buf(),
+            ..SplitConfig::default()
+        };
+        let result = DocumentSplitter::split_document(&document, &config).await.unwrap();
+        assert!(result.removed_boilerplate.is_empty());
+        let kept = std::fs::read_to_string(&result.output_files[0]).unwrap();
+        assert_eq!(kept.matches("Confidential").count(), 5);
+
+        config.strip_repeated_boilerplate = true;
+        let result = DocumentSplitter::split_document(&document, &config).await.unwrap();
+        assert_eq!(
+            result.removed_boilerplate,
+            vec![BoilerplateLine {
+                text: "ACME Corp  Confidential - page 1 of 5".to_string(),
+                pages: 5,
+            }]
+        );
+
+        let stripped = std::fs::read_to_string(&result.output_files[0]).unwrap();
+        assert!(!stripped.contains("Confidential"));
+        // Page markers and body text are not boilerplate
+        assert!(stripped.contains("# Page 3") && stripped.contains("Charlie body text."));
+    }
+
+    #[tokio::test]
+    async fn test_part_headings_open_each_split() {
+        let content = "# Intro\n\nWelcome text.\n\n---\n\nUntitled middle page.\nStill untitled.\nMore lines.\nAnd more.\nEven more.\nAnother.\nYet another.\nNearly there.\nAlmost.\nLast one.\nDone.\n\n---\n\n# Usage\n\nRun it.\n";
+        let parser = MarkdownParser::new(None).unwrap();
+        let document = parser.parse_str(content, "book.md").unwrap();
+        assert_eq!(document.total_pages, 3);
+
+        let output_dir = tempfile::tempdir().unwrap();
+        let config = SplitConfig {
+            splits: 3,
+            output_dir: output_dir.path().to_path_buf(),
+            add_part_headings: true,
+            ..SplitConfig::default()
+        };
+        let result = DocumentSplitter::split_document(&document, &config).await.unwrap();
+
+        let expected = ["# Part 1: Intro\n", "# Part 2\n", "# Part 3: Usage\n"];
+        for (path, heading) in result.output_files.iter().zip(expected) {
+            let written = std::fs::read_to_string(path).unwrap();
+            let body = written.split_once("-->\n\n").unwrap().1;
+            assert!(body.starts_with(heading), "{}", written);
+            assert_eq!(written.matches("# Part").count(), 1);
+        }
+    }
+
+    #[test]
+    fn test_user_patterns_are_guarded() {
+        use crate::services::parser::MarkdownParser as Parser;
+
+        // Compiling this would take gigabytes; it is refused up front
+        let reason = Parser::compile_user_pattern("(?:(?:(?:a{100}){100}){100})").unwrap_err();
+        assert!(reason.contains("too complex"), "{}", reason);
+
+        let deeply_nested = format!("{}a{}", "(".repeat(100), ")".repeat(100));
+        assert!(Parser::compile_user_pattern(&deeply_nested).is_err());
+
+        // Patterns that backtrack catastrophically elsewhere run in linear time here
+        let pattern = Parser::compile_user_pattern("(a+)+$").unwrap();
+        let line = format!("{}b", "a".repeat(50_000));
+        let started = std::time::Instant::now();
+        assert!(!pattern.is_match(&line));
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        // Page markers are taken literally, so regex syntax in them is harmless
+        let parser = MarkdownParser::new(Some("(a+)+$")).unwrap();
+        let content = format!("{}\n(a+)+$\n{}", "Intro text.\n".repeat(12), "Body text.\n".repeat(12));
+        assert_eq!(parser.parse_str(&content, "marked.md").unwrap().total_pages, 2);
+
+        // ... but an absurdly long one is still rejected with a parsing error
+        let huge_marker = "x".repeat(2_000_000);
+        assert!(matches!(
+            MarkdownParser::new(Some(&huge_marker)),
+            Err(MarkdownSplitterError::PageParsing { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_extract_markdown_from_wrapper_files() {
+        let input_dir = tempfile::tempdir().unwrap();
+        let markdown = format!("# Overview\n\n{}\n---\n\n# Endpoints\n\n{}", "About the API.\n".repeat(12), "GET /pets\n".repeat(12));
+        let json_path = input_dir.path().join("api.json");
+        std::fs::write(
+            &json_path,
+            serde_json::json!({ "info": { "title": "Pets", "description": markdown } }).to_string(),
+        )
+        .unwrap();
+
+        let options = FetchOptions {
+            extract_from: Some(ContentFetcher::parse_selector("$.info.description").unwrap()),
+            ..FetchOptions::default()
+        };
+        let (content, metadata) = ContentFetcher::fetch_content_with(json_path.to_str().unwrap(), &options)
+            .await
+            .unwrap();
+        assert_eq!(content, markdown);
+        assert_eq!(metadata.total_lines, markdown.lines().count());
+
+        let document = MarkdownParser::new(None).unwrap().parse_document(&content, metadata).unwrap();
+        let output_dir = tempfile::tempdir().unwrap();
+        let config = SplitConfig {
+            splits: 2,
+            output_dir: output_dir.path().to_path_buf(),
+            ..SplitConfig::default()
+        };
+        let result = DocumentSplitter::split_document(&document, &config).await.unwrap();
+        assert_eq!(result.splits.len(), 2);
+        assert_eq!(result.splits[1].title.as_deref(), Some("Endpoints"));
+
+        // YAML wrappers, wildcards and regex capture groups
+        let yaml_path = input_dir.path().join("docs.yaml");
+        std::fs::write(&yaml_path, "items:\n  - body: \"# One\"\n  - body: \"# Two\"\n").unwrap();
+        let options = FetchOptions {
+            extract_from: Some(ContentFetcher::parse_selector("$.items[*].body").unwrap()),
+            ..FetchOptions::default()
+        };
+        let (content, _) = ContentFetcher::fetch_content_with(yaml_path.to_str().unwrap(), &options)
+            .await
+            .unwrap();
+        assert_eq!(content, "# One\n\n# Two");
+
+        let text_path = input_dir.path().join("notes.txt");
+        std::fs::write(&text_path, "noise\n<<<MD\n# Kept\nMD>>>\nnoise").unwrap();
+        let options = FetchOptions {
+            extract_from: Some(ContentFetcher::parse_selector(r"(?s)<<<MD\n(.*?)\nMD>>>").unwrap()),
+            ..FetchOptions::default()
+        };
+        let (content, _) = ContentFetcher::fetch_content_with(text_path.to_str().unwrap(), &options)
+            .await
+            .unwrap();
+        assert_eq!(content, "# Kept");
+
+        assert!(ContentFetcher::parse_selector("$.items[x]").is_err());
+        let options = FetchOptions {
+            extract_from: Some(ContentFetcher::parse_selector("$.info.missing").unwrap()),
+            ..FetchOptions::default()
+        };
+        assert!(ContentFetcher::fetch_content_with(json_path.to_str().unwrap(), &options).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_explain_merges_reports_mapping() {
+        let long = "Body line.\n".repeat(12);
+        // Raw pages: 1 intro, 2 titled, 3 short untitled, 4 short untitled, 5 long untitled
+        let content = format!(
+            "{long}# Usage\n{long}\n---\nA short gap.\n\n---\nAnother gap.\n\n---\n{long}",
+            long = long
+        );
+        let parser = MarkdownParser::new(None).unwrap();
+
+        let raw_pages = parser.parse_raw_pages(&content).unwrap();
+        assert_eq!(raw_pages.len(), 5);
+        let document = parser.parse_str(&content, "merge.md").unwrap();
+        assert_eq!(document.total_pages, 3);
+
+        let merges = parser.explain_merges(&content).unwrap();
+        let mapping: Vec<(usize, usize, usize)> = merges
+            .iter()
+            .map(|merge| (merge.raw_page, merge.into_page, merge.lines))
+            .collect();
+        assert_eq!(mapping, vec![(3, 2, 3), (4, 2, 3)]);
+        assert!(merges[0].reason.contains("at most 10"));
+        assert_eq!(document.merges, merges);
+
+        // Library callers get the merges as split warnings
+        let output_dir = tempfile::tempdir().unwrap();
+        let config = SplitConfig {
+            splits: 1,
+            output_dir: output_dir.path().to_path_buf(),
+            ..SplitConfig::default()
+        };
+        let result = DocumentSplitter::split_document(&document, &config).await.unwrap();
+        let merged: Vec<Option<usize>> = result
+            .warnings
+            .iter()
+            .filter(|warning| warning.kind == WarningKind::MergedPage)
+            .map(|warning| warning.page)
+            .collect();
+        assert_eq!(merged, vec![Some(2), Some(2)]);
+
+        // Explicit split modes never merge
+        let parser = MarkdownParser::with_options(ParserOptions {
+            split_by: SplitBy::BlankLineRun(2),
+            ..ParserOptions::default()
+        })
+        .unwrap();
+        assert!(parser.explain_merges(&content).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_page_summary_omits_content() {
+        let content = paged_content(3);
+        let parser = MarkdownParser::new(None).unwrap();
+        let document = parser
+            .parse_document(&content, test_metadata("summary.md", &content))
+            .unwrap();
+
+        let json = serde_json::to_value(document.summary()).unwrap();
+        let pages = json.as_array().unwrap();
+        assert_eq!(pages.len(), document.pages.len());
+        for (summary, page) in pages.iter().zip(&document.pages) {
+            assert!(summary.get("content").is_none());
+            assert_eq!(summary["number"], page.number);
+            assert_eq!(summary["title"], serde_json::json!(page.title));
+            assert_eq!(summary["start_line"], page.start_line);
+            assert_eq!(summary["end_line"], page.end_line);
+        }
+    }
+
+    /// Serve empty responses: `/NNN` answers HEAD with status NNN, `/no-head`
+    /// refuses HEAD and answers a ranged GET with 206
+    async fn serve_statuses() -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = [0u8; 1024];
+                let read = stream.read(&mut request).await.unwrap_or(0);
+                let request = String::from_utf8_lossy(&request[..read]).to_string();
+                let mut words = request.split_whitespace();
+                let (method, path) = (words.next().unwrap_or(""), words.next().unwrap_or(""));
+                let status = match (method, path) {
+                    ("HEAD", "/no-head") => 405,
+                    ("GET", "/no-head") if request.to_lowercase().contains("range: bytes=0-0") => 206,
+                    ("HEAD", path) => path.trim_start_matches('/').parse().unwrap_or(404),
+                    _ => 400,
+                };
+                let response =
+                    format!("HTTP/1.1 {} Status\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status);
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+
+        format!("http://{}", address)
+    }
+
+    #[tokio::test]
+    async fn test_check_access_reports_head_statuses() {
+        let base = serve_statuses().await;
+
+        let expected = [
+            ("/200", Some("HEAD"), Some(200), true),
+            ("/404", Some("HEAD"), Some(404), false),
+            ("/500", Some("HEAD"), Some(500), false),
+            ("/no-head", Some("GET"), Some(206), true),
+        ];
+        for (path, method, status, reachable) in expected {
+            let check = ContentFetcher::check_access(&format!("{}{}", base, path), &FetchOptions::default()).await;
+            assert_eq!(check.method.as_deref(), method, "{}", path);
+            assert_eq!(check.status, status, "{}", path);
+            assert_eq!(check.reachable, reachable, "{}", path);
+        }
+
+        let missing = ContentFetcher::check_access("/definitely/not/here.md", &FetchOptions::default()).await;
+        assert!(!missing.reachable);
+        assert!(missing.method.is_none() && missing.error.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_comment_metadata_block() {
+        let content = format!(
+            "<!-- author: Jane Doe -->\n<!-- status: draft -->\n\n# Intro\n\nOpening.\n\n{}",
+            paged_content(3)
+        );
+        let parser = MarkdownParser::new(None).unwrap();
+        let document = parser
+            .parse_document(&content, test_metadata("notes.md", &content))
+            .unwrap();
+
+        assert_eq!(document.comment_metadata.len(), 2);
+        assert_eq!(document.comment_metadata["author"], "Jane Doe");
+        assert_eq!(document.comment_metadata["status"], "draft");
+        assert_eq!(document.total_pages, 4);
+        assert_eq!(document.pages[0].start_line, 0);
+        assert_eq!(document.pages[0].title.as_deref(), Some("Intro"));
+        assert!(document.pages[0].content.starts_with("<!-- author: Jane Doe -->"));
+
+        let output_dir = tempfile::tempdir().unwrap();
+        let config = SplitConfig {
+            splits: 2,
+            output_dir: output_dir.path().to_path_buf(),
+            propagate_comment_metadata: true,
+            ..SplitConfig::default()
+        };
+        let result = DocumentSplitter::split_document(&document, &config).await.unwrap();
+        let block = "<!-- author: Jane Doe -->\n<!-- status: draft -->\n";
+
+        let first = std::fs::read_to_string(&result.splits[0].path).unwrap();
+        assert_eq!(first.matches(block).count(), 1);
+        let second = std::fs::read_to_string(&result.splits[1].path).unwrap();
+        assert!(second.contains(block));
+
+        let rebuilt = DocumentSplitter::reconstruct(result.metadata_file.as_ref().unwrap(), output_dir.path())
+            .await
+            .unwrap();
+        assert_eq!(rebuilt, content);
+    }
+
+    #[tokio::test]
+    async fn test_section_split_divides_oversized_sections() {
+        let subsections: String = (1..=5)
+            .map(|n| format!("## Step {}\n\nDetails of step {}.\n\n", n, n))
+            .collect();
+        let content = format!(
+            "# Intro\n\nShort opening.\n\n# Guide\n\nOverview.\n\n{}# Appendix\n\nNotes.\n",
+            subsections
+        );
+        let parser = MarkdownParser::new(None).unwrap();
+        let document = parser.parse_str(&content, "manual.md").unwrap();
+        assert_eq!(document.total_pages, 8);
+
+        let output_dir = tempfile::tempdir().unwrap();
+        let config = SplitConfig {
+            output_dir: output_dir.path().to_path_buf(),
+            section_limit: Some(SectionLimit::Pages(2)),
+            ..SplitConfig::default()
+        };
+        let result = DocumentSplitter::split_document(&document, &config).await.unwrap();
+
+        let layout: Vec<(&str, usize, usize)> = result
+            .splits
+            .iter()
+            .map(|split| (split.filename.as_str(), split.start_page, split.end_page))
+            .collect();
+        assert_eq!(
+            layout,
+            vec![
+                ("manual_sec01-part01.md", 1, 1),
+                ("manual_sec02-part01.md", 2, 3),
+                ("manual_sec02-part02.md", 4, 5),
+                ("manual_sec02-part03.md", 6, 7),
+                ("manual_sec03-part01.md", 8, 8),
+            ]
+        );
+
+        let guide = result.splits[2].section.as_ref().unwrap();
+        assert_eq!((guide.section, guide.part, guide.parts), (2, 2, 3));
+        assert_eq!(guide.title.as_deref(), Some("Guide"));
+
+        let metadata = DocumentSplitter::read_metadata(result.metadata_file.as_ref().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(metadata.split_info[4].section.as_ref().unwrap().title.as_deref(), Some("Appendix"));
+    }
+
+    #[tokio::test]
+    async fn test_warnings_report_lists_problems() {
+        let content = format!(
+            "# Big\n\n{}\n# Small A\n\nA.\n\n# Small B\n\nB.\n\n# Small C\n\nSee [Big](#big).\n",
+            "A long paragraph line.\n".repeat(40)
+        );
+        let parser = MarkdownParser::new(None).unwrap();
+        let document = parser.parse_str(&content, "uneven.md").unwrap();
+
+        let output_dir = tempfile::tempdir().unwrap();
+        let config = SplitConfig {
+            splits: 2,
+            output_dir: output_dir.path().to_path_buf(),
+            ..SplitConfig::default()
+        };
+        let result = DocumentSplitter::split_document(&document, &config).await.unwrap();
+
+        let report_path = output_dir.path().join("warnings.json");
+        DocumentSplitter::write_warnings_report(&report_path, &result.warnings, 0).await.unwrap();
+        let report: Vec<SplitWarning> =
+            serde_json::from_str(&std::fs::read_to_string(&report_path).unwrap()).unwrap();
+
+        let kinds: Vec<WarningKind> = report.iter().map(|warning| warning.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![WarningKind::OversizedPage, WarningKind::Imbalance, WarningKind::CrossSplitAnchor]
+        );
+        assert_eq!(report[0].page, Some(1));
+        assert_eq!(report[2].split_number, Some(2));
+        assert!(report.iter().all(|warning| warning.source == "uneven.md"));
+    }
+
+    #[tokio::test]
+    async fn test_split_str_inline_content() {
+        let output_dir = tempfile::tempdir().unwrap();
+        let config = SplitConfig {
+            splits: 3,
+            output_dir: output_dir.path().to_path_buf(),
+            ..SplitConfig::default()
+        };
+        let parser = MarkdownParser::new(None).unwrap();
+
+        let result = DocumentSplitter::split_str(&paged_content(6), "inline.md", &parser, &config)
+            .await
+            .unwrap();
+
+        assert_eq!(result.output_files.len(), 3);
+        assert!(result.output_files.iter().all(|path| path.exists()));
+        assert_eq!(result.splits[0].filename, "inline_split_1_of_3.md");
+    }
+
+    #[tokio::test]
+    async fn test_source_name_renames_outputs() {
+        let input_dir = tempfile::tempdir().unwrap();
+        let source = input_dir.path().join("scan-0042.md");
+        std::fs::write(&source, paged_content(4)).unwrap();
+        let options = FetchOptions {
+            source_name: Some("report.md".to_string()),
+            ..FetchOptions::default()
+        };
+        let (content, metadata) = ContentFetcher::fetch_content_with(source.to_str().unwrap(), &options)
+            .await
+            .unwrap();
+        assert_eq!(metadata.filename, "report.md");
+
+        let output_dir = tempfile::tempdir().unwrap();
+        let config = SplitConfig {
+            splits: 2,
+            output_dir: output_dir.path().to_path_buf(),
+            include_metadata: true,
+            ..SplitConfig::default()
+        };
+        let document = MarkdownParser::new(None).unwrap().parse_document(&content, metadata).unwrap();
+        let result = DocumentSplitter::split_document(&document, &config).await.unwrap();
+        assert_eq!(
+            result.output_files,
+            [output_dir.path().join("report_split_1_of_2.md"), output_dir.path().join("report_split_2_of_2.md")]
+        );
+        assert_eq!(result.metadata_file, Some(output_dir.path().join("report_metadata.json")));
+    }
+
+    #[tokio::test]
+    async fn test_remainder_policies() {
+        let content = paged_content(10);
+        let parser = MarkdownParser::new(None).unwrap();
+        let document = parser.parse_str(&content, "ten.md").unwrap();
+        assert_eq!(document.total_pages, 10);
+
+        let expected = [
+            (RemainderPolicy::Separate, vec![4, 4, 2]),
+            (RemainderPolicy::DistributeEarly, vec![4, 3, 3]),
+            (RemainderPolicy::DistributeLate, vec![3, 3, 4]),
+        ];
+        for (remainder_policy, sizes) in expected {
+            let output_dir = tempfile::tempdir().unwrap();
+            let config = SplitConfig {
+                splits: 3,
+                output_dir: output_dir.path().to_path_buf(),
+                remainder_policy,
+                ..SplitConfig::default()
+            };
+            let result = DocumentSplitter::split_document(&document, &config).await.unwrap();
+
+            let actual: Vec<usize> = result.splits.iter().map(|split| split.pages.len()).collect();
+            assert_eq!(actual, sizes, "{:?}", remainder_policy);
+
+            // The preview plans the same ranges
+            let ranges: Vec<(usize, usize)> = result.splits.iter().map(|s| (s.start_page, s.end_page)).collect();
+            assert_eq!(DocumentSplitter::calculate_strategy_split_info(&document, &config).unwrap(), ranges);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_per_page_frontmatter() {
+        let content = "# Guide\n\nIntro.\n\n---\nsection: install\nowner: ops\n---\n\nRun the installer.\n\n---\nsection: usage\n---\n\nCall it.\n\n---\n\nPlain rule gap.\n";
+        let parser = MarkdownParser::new(None).unwrap();
+        let document = parser.parse_str(content, "frontmatter.md").unwrap();
+
+        assert_eq!(document.total_pages, 3);
+        assert!(document.pages[0].page_frontmatter.is_none());
+
+        let install = document.pages[1].page_frontmatter.as_ref().unwrap();
+        assert_eq!(install["section"], "install");
+        assert_eq!(install["owner"], "ops");
+        assert!(document.pages[1].content.contains("Run the installer."));
+
+        // The short page after the plain rule is merged; the frontmatter page is not
+        let usage = document.pages[2].page_frontmatter.as_ref().unwrap();
+        assert_eq!(usage["section"], "usage");
+        assert!(document.pages[2].content.ends_with("Plain rule gap."));
+
+        let output_dir = tempfile::tempdir().unwrap();
+        let config = SplitConfig {
+            splits: 3,
+            output_dir: output_dir.path().to_path_buf(),
+            ..SplitConfig::default()
+        };
+        let result = DocumentSplitter::split_document(&document, &config).await.unwrap();
+        let metadata = DocumentSplitter::read_metadata(result.metadata_file.as_ref().unwrap())
+            .await
+            .unwrap();
+        let recorded = metadata.split_info[1].pages[0].frontmatter.as_ref().unwrap();
+        assert_eq!(recorded["owner"], "ops");
+
+        // Entries are written in key order, so repeated runs write identical metadata
+        let written = std::fs::read_to_string(result.metadata_file.as_ref().unwrap()).unwrap();
+        assert!(written.find("\"owner\": \"ops\"").unwrap() < written.find("\"section\": \"install\"").unwrap());
+    }
+
+    #[test]
+    fn test_structure_limits() {
+        let parser = MarkdownParser::new(None).unwrap();
+        let titled = parser.parse_str(&paged_content(3), "titled.md").unwrap();
+        let untitled = parser
+            .parse_str("Opening text without a heading.\n\n# Page 2\n\nMore.\n", "untitled.md")
+            .unwrap();
+        let check = |document, limits: StructureLimits| MarkdownParser::check_structure(document, &limits);
+
+        assert!(check(&titled, StructureLimits::default()).is_empty());
+
+        let min_pages = |min| StructureLimits { min_pages: Some(min), ..StructureLimits::default() };
+        assert!(check(&titled, min_pages(3)).is_empty());
+        assert_eq!(check(&titled, min_pages(4)), vec!["3 pages found, at least 4 required"]);
+
+        let max_bytes = |max| StructureLimits { max_page_bytes: Some(max), ..StructureLimits::default() };
+        let largest = titled.pages.iter().map(|page| page.content.len()).max().unwrap();
+        assert!(check(&titled, max_bytes(largest)).is_empty());
+        let oversized = check(&titled, max_bytes(largest - 1));
+        assert!(!oversized.is_empty());
+        assert!(oversized[0].ends_with(&format!("is {} bytes, over the limit of {}", largest, largest - 1)));
+
+        let require_titles = StructureLimits { require_titles: true, ..StructureLimits::default() };
+        assert!(check(&titled, require_titles.clone()).is_empty());
+        assert_eq!(check(&untitled, require_titles), vec!["Pages without a title: 1"]);
+    }
+
+    #[tokio::test]
+    async fn test_split_to_tempdir_cleans_up_on_drop() {
+        let parser = MarkdownParser::new(None).unwrap();
+        let document = parser.parse_str(&paged_content(4), "temp.md").unwrap();
+        let config = SplitConfig {
+            splits: 2,
+            ..SplitConfig::default()
+        };
+
+        let (result, temp_dir) = DocumentSplitter::split_to_tempdir(&document, &config).await.unwrap();
+        let dir = temp_dir.path().to_path_buf();
+        assert_eq!(result.output_files.len(), 2);
+        assert!(result.output_files.iter().all(|path| path.starts_with(&dir) && path.exists()));
+        assert!(result.metadata_file.as_ref().unwrap().exists());
+
+        drop(temp_dir);
+        assert!(!dir.exists());
+        assert!(result.output_files.iter().all(|path| !path.exists()));
+    }
+
+    #[test]
+    fn test_multiline_page_marker() {
+        let body = |name: &str| format!("{} text.\n", name).repeat(12);
+        let content = format!(
+            "{}\n                12\n\n{}\n                13\n\n{}",
+            body("First"),
+            body("Second"),
+            body("Third")
+        );
+        let parser = MarkdownParser::with_options(ParserOptions {
+            multiline_marker: Some(r"^\s*$\n^\s+\d+\s*$\n^\s*$".to_string()),
+            ..ParserOptions::default()
+        })
+        .unwrap();
+
+        let document = parser.parse_str(&content, "scanned.md").unwrap();
+        assert_eq!(document.metadata.page_breaks, vec![0, 12, 27, 42]);
+        assert_eq!(document.total_pages, 3);
+        assert!(document.pages[1].content.contains("Second text."));
+        assert!(!document.pages[1].content.contains("First text."));
+
+        // A single number line is not the three-line marker
+        let numbered = body("Only").replacen("Only text.\n", "Only text.\n  7\n", 1);
+        let plain = parser.parse_str(&numbered, "plain.md").unwrap();
+        assert_eq!(plain.total_pages, 1);
+
+        let too_wide = ParserOptions {
+            multiline_marker: Some("x".to_string()),
+            marker_window: Some(100),
+            ..ParserOptions::default()
+        };
+        assert!(MarkdownParser::with_options(too_wide).is_err());
+    }
+
+    #[test]
+    fn test_heading_break_levels() {
+        let content = "# One\n\nIntro.\n\n## Two\n\nMiddle.\n\n### Three\n\nDetail.\n\n# Four\n\nEnd.\n";
+        let titles = |levels: Option<Vec<usize>>| {
+            let parser = MarkdownParser::with_options(ParserOptions {
+                heading_break_levels: levels,
+                ..ParserOptions::default()
+            })
+            .unwrap();
+            let document = parser.parse_str(content, "levels.md").unwrap();
+            document.pages.iter().map(|page| page.title.clone().unwrap()).collect::<Vec<_>>()
+        };
+
+        assert_eq!(titles(None), vec!["One", "Two", "Four"]);
+        assert_eq!(titles(Some(vec![1])), vec!["One", "Four"]);
+        assert_eq!(titles(Some(vec![1, 2, 3])), vec!["One", "Two", "Three", "Four"]);
+
+        let invalid = ParserOptions {
+            heading_break_levels: Some(vec![7]),
+            ..ParserOptions::default()
+        };
+        assert!(MarkdownParser::with_options(invalid).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_summarizer_output_in_splits() {
+        struct PageCount;
+        impl Summarizer for PageCount {
+            fn summarize(&self, text: &str) -> String {
+                format!("{} headings", text.matches("# Page").count())
+            }
+        }
+
+        let parser = MarkdownParser::new(None).unwrap();
+        let document = parser.parse_str(&paged_content(4), "summary.md").unwrap();
+        let output_dir = tempfile::tempdir().unwrap();
+        let config = SplitConfig {
+            splits: 2,
+            output_dir: output_dir.path().to_path_buf(),
+            summarizer: Some(std::sync::Arc::new(PageCount)),
+            ..SplitConfig::default()
+        };
+        let result = DocumentSplitter::split_document(&document, &config).await.unwrap();
+
+        let first = std::fs::read_to_string(&result.splits[0].path).unwrap();
+        assert!(first.contains("<!-- summary: 2 headings -->"));
+        assert_eq!(result.splits[1].summary.as_deref(), Some("2 headings"));
+
+        let rebuilt = DocumentSplitter::reconstruct(result.metadata_file.as_ref().unwrap(), output_dir.path())
+            .await
+            .unwrap();
+        assert_eq!(rebuilt, paged_content(4));
+
+        let text = "# Intro\n\n---\n\n```\ncode();\n```\nThe tool splits files. It also merges them.\n";
+        assert_eq!(FirstSentence.summarize(text), "The tool splits files.");
+        let tilde = "~~~\nlet ratio = 0.5; ignored\n~~~\nAfter the block. More.\n";
+        assert_eq!(FirstSentence.summarize(tilde), "After the block.");
+    }
+
+    #[test]
+    fn test_dewrap_rejoins_wrapped_prose() {
+        let content = "# Intro\nThis paragraph was wrapped\nby a PDF conver-\nsion tool.\n\n- a list item\nthat wraps\n- another item\n\n```\nkeep\nthese lines\n```\n12\nNext page text\ncontinues here.\n";
+
+        let dewrapped = ContentFetcher::dewrap(content, &DewrapOptions::default());
+        assert_eq!(
+            dewrapped,
+            "# Intro\nThis paragraph was wrapped by a PDF conversion tool.\n\n- a list item\nthat wraps\n- another item\n\n```\nkeep\nthese lines\n```\n12\nNext page text continues here.\n"
+        );
+
+        let options = DewrapOptions {
+            preserve_lists: false,
+            ..DewrapOptions::default()
+        };
+        assert!(ContentFetcher::dewrap(content, &options).contains("- a list item that wraps\n- another item"));
+    }
+
+    #[tokio::test]
+    async fn test_split_plan_matches_execution() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let content = paged_content(7);
+        let document = MarkdownParser::new(None)
+            .unwrap()
+            .parse_document(&content, test_metadata("plan.md", &content))
+            .unwrap();
+
+        let even = SplitConfig {
+            splits: 3,
+            output_dir: temp_dir.path().join("even"),
+            ..SplitConfig::default()
+        };
+        let balanced = SplitConfig {
+            output_dir: temp_dir.path().join("balanced"),
+            ..even.clone()
+        };
+        let sections = SplitConfig {
+            section_limit: Some(SectionLimit::Pages(2)),
+            output_dir: temp_dir.path().join("sections"),
+            ..even.clone()
+        };
+        let bytes: &dyn Fn(&MarkdownPage) -> usize = &DocumentSplitter::page_bytes;
+
+        for (config, cost_fn, strategy) in [
+            (&even, None, PlanStrategy::EvenPages),
+            (&balanced, Some(bytes), PlanStrategy::Balanced),
+            (&sections, None, PlanStrategy::Sections),
+        ] {
+            let plan = DocumentSplitter::plan(&document, config, cost_fn).unwrap();
+            assert_eq!(plan.strategy, strategy);
+            assert_eq!(plan.total_pages, document.total_pages);
+            assert!(!std::fs::exists(&config.output_dir).unwrap());
+
+            let result = match cost_fn {
+                Some(cost_fn) => DocumentSplitter::split_document_balanced(&document, config, cost_fn).await,
+                None => DocumentSplitter::split_document(&document, config).await,
+            }
+            .unwrap();
+            assert_eq!(plan.files.len(), result.splits.len());
+            for (planned, split) in plan.files.iter().zip(&result.splits) {
+                assert_eq!(planned.filename, split.filename);
+                assert_eq!((planned.start_page, planned.end_page), (split.start_page, split.end_page));
+                assert_eq!(planned.bytes, std::fs::metadata(&split.path).unwrap().len() as usize);
+                assert_eq!(planned.estimated_tokens, planned.bytes.div_ceil(4));
+            }
+        }
+
+        let plan = DocumentSplitter::plan(&document, &even, None).unwrap();
+        let json: serde_json::Value = serde_json::to_value(&plan).unwrap();
+        assert_eq!(json["source"], "plan.md");
+        assert_eq!(json["strategy"], "even_pages");
+        assert_eq!(json["files"][0]["filename"], plan.files[0].filename);
+        assert_eq!(json["files"][0]["start_page"], 1);
+        assert!(json["files"][2]["estimated_tokens"].as_u64().unwrap() > 0);
+        let round_trip: SplitPlan = serde_json::from_value(json).unwrap();
+        assert_eq!(round_trip, plan);
+    }
+
+    #[test]
+    fn test_bold_lines_split_into_titled_pages() {
+        let content = "Preamble text.\n\n**Introduction**\nThis report has **two** parts.\n**Not a heading**\n\n__Findings__\nMostly good.\n\n```\n\n**Inside code**\n```\n\n**Next Steps**  \nShip it.";
+        let parser = MarkdownParser::with_options(ParserOptions {
+            split_by: SplitBy::BoldLines,
+            ..ParserOptions::default()
+        })
+        .unwrap();
+        let document = parser.parse_document(content, test_metadata("report.md", content)).unwrap();
+
+        let titles: Vec<Option<&str>> = document.pages.iter().map(|page| page.title.as_deref()).collect();
+        assert_eq!(titles, vec![None, Some("Introduction"), Some("Findings"), Some("Next Steps")]);
+        assert_eq!(document.pages[1].start_line, 2);
+        assert!(document.pages[1].content.contains("**Not a heading**"));
+        assert!(document.pages[2].content.contains("**Inside code**"));
+    }
+
+    #[tokio::test]
+    async fn test_manifest_merge_accumulates_runs() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let manifest_path = temp_dir.path().join("manifest.json");
+        let parser = MarkdownParser::new(None).unwrap();
+
+        let mut runs = Vec::new();
+        for name in ["monday.md", "tuesday.md"] {
+            let content = paged_content(4);
+            let document = parser.parse_document(&content, test_metadata(name, &content)).unwrap();
+            let config = SplitConfig {
+                splits: 2,
+                output_dir: temp_dir.path().join(name),
+                ..SplitConfig::default()
+            };
+            runs.push(DocumentSplitter::split_document(&document, &config).await.unwrap().splits);
+        }
+
+        DocumentSplitter::write_manifest(&manifest_path, &runs[0], ManifestMode::Merge, None).await.unwrap();
+        let manifest = DocumentSplitter::write_manifest(&manifest_path, &runs[1], ManifestMode::Merge, None).await.unwrap();
+        let written: SplitManifest = serde_json::from_str(&std::fs::read_to_string(&manifest_path).unwrap()).unwrap();
+        assert_eq!(written, manifest);
+        assert_eq!(manifest.sources.keys().collect::<Vec<_>>(), vec!["monday.md", "tuesday.md"]);
+        assert_eq!(manifest.sources["monday.md"].files.len(), 2);
+        assert_eq!(
+            manifest.sources["tuesday.md"].bytes,
+            runs[1].iter().map(|info| info.bytes).sum::<usize>()
+        );
+        assert!(!temp_dir.path().join("manifest.json.lock").exists());
+
+        let manifest = DocumentSplitter::write_manifest(&manifest_path, &runs[1], ManifestMode::Overwrite, None).await.unwrap();
+        assert_eq!(manifest.sources.keys().collect::<Vec<_>>(), vec!["tuesday.md"]);
+
+        // A lock left by another run blocks the update instead of losing its entries
+        std::fs::write(temp_dir.path().join("manifest.json.lock"), "").unwrap();
+        assert!(DocumentSplitter::write_manifest(&manifest_path, &runs[0], ManifestMode::Merge, None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_paces_url_fetches() {
+        let base = serve_markdown("# Paced\n\nOne request at a time.\n").await;
+        let options = FetchOptions {
+            rate_limit: Some(std::sync::Arc::new(RateLimiter::new(20.0))),
+            ..FetchOptions::default()
+        };
+
+        // The first fetch goes out at once, each further one waits 50ms for its slot
+        let started = std::time::Instant::now();
+        let fetches: Vec<_> = (0..4)
+            .map(|n| {
+                let options = options.clone();
+                let url = format!("{}/doc{}.md", base, n);
+                tokio::spawn(async move { ContentFetcher::fetch_content_with(&url, &options).await })
+            })
+            .collect();
+        for fetch in fetches {
+            assert!(fetch.await.unwrap().is_ok());
+        }
+        assert!(started.elapsed() >= Duration::from_millis(150));
+
+        // A rate too small for its interval to be a Duration still paces, without panicking
+        let slow = RateLimiter::new(1e-30);
+        slow.acquire().await;
+        assert!(tokio::time::timeout(Duration::from_millis(20), slow.acquire()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_split_metadata_lists_heading_outline() {
+        let output_dir = tempfile::tempdir().unwrap();
+        let content = "# Page 1\n\n## Install\nRun it.\n\n```sh\n## not a heading\n```\n\n## Configure\nSet it.\n\nReference\n=========\nAll of it.\n\n# Page 2\n\nDone.";
+        let document = MarkdownParser::new(None)
+            .unwrap()
+            .parse_document(content, test_metadata("outline.md", content))
+            .unwrap();
+        let config = SplitConfig {
+            splits: 2,
+            output_dir: output_dir.path().to_path_buf(),
+            include_metadata: true,
+            ..SplitConfig::default()
+        };
+        let result = DocumentSplitter::split_document(&document, &config).await.unwrap();
+
+        let metadata: SplitMetadata =
+            serde_json::from_str(&std::fs::read_to_string(result.metadata_file.unwrap()).unwrap()).unwrap();
+        let outline: Vec<(usize, &str, usize)> = metadata.split_info[0]
+            .outline
+            .iter()
+            .map(|entry| (entry.level, entry.text.as_str(), entry.line))
+            .collect();
+        assert_eq!(
+            outline,
+            vec![(1, "Page 1", 0), (2, "Install", 2), (2, "Configure", 9), (1, "Reference", 12)]
+        );
+        assert_eq!(metadata.split_info[1].outline[0].text, "Page 2");
+
+        // Comments in TOML front matter are not headings; lines count from the source
+        let content = "+++\n# build settings\ntitle = \"Guide\"\n+++\n\nIntro\n-----\n\nText.";
+        let document = MarkdownParser::new(None).unwrap().parse_str(content, "toml.md").unwrap();
+        let headings: Vec<(usize, &str, usize)> = document
+            .headings
+            .iter()
+            .map(|entry| (entry.level, entry.text.as_str(), entry.line))
+            .collect();
+        assert_eq!(headings, vec![(2, "Intro", 5)]);
+    }
+
+    #[tokio::test]
+    async fn test_overwrite_policies_with_existing_file() {
+        let content = paged_content(4);
+        let document = MarkdownParser::new(None)
+            .unwrap()
+            .parse_document(&content, test_metadata("existing.md", &content))
+            .unwrap();
+
+        for policy in [OverwritePolicy::Overwrite, OverwritePolicy::Error, OverwritePolicy::Skip] {
+            let output_dir = tempfile::tempdir().unwrap();
+            let existing = output_dir.path().join("existing_split_1_of_2.md");
+            std::fs::write(&existing, "keep me").unwrap();
+            let config = SplitConfig {
+                splits: 2,
+                output_dir: output_dir.path().to_path_buf(),
+                overwrite_policy: policy,
+                ..SplitConfig::default()
+            };
+
+            let result = DocumentSplitter::split_document(&document, &config).await;
+            let kept = std::fs::read_to_string(&existing).unwrap() == "keep me";
+            match policy {
+                OverwritePolicy::Overwrite => {
+                    assert!(result.unwrap().skipped_files.is_empty());
+                    assert!(!kept);
+                }
+                OverwritePolicy::Error => {
+                    let error = result.unwrap_err().to_string();
+                    assert!(error.contains("existing_split_1_of_2.md already exists"), "{}", error);
+                    assert!(kept);
+                }
+                OverwritePolicy::Skip => {
+                    let result = result.unwrap();
+                    assert_eq!(result.skipped_files, vec![existing.clone()]);
+                    assert_eq!(result.output_files.len(), 2);
+                    assert!(output_dir.path().join("existing_split_2_of_2.md").exists());
+                    assert!(kept);
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_overwrite_policies_cover_metadata_and_toc() {
+        let content = paged_content(4);
+        let document = MarkdownParser::new(None)
+            .unwrap()
+            .parse_document(&content, test_metadata("existing.md", &content))
+            .unwrap();
+
+        for policy in [OverwritePolicy::Error, OverwritePolicy::Skip] {
+            let output_dir = tempfile::tempdir().unwrap();
+            let metadata = output_dir.path().join("existing_metadata.json");
+            let toc = output_dir.path().join("existing_TOC.md");
+            std::fs::write(&metadata, "keep me").unwrap();
+            std::fs::write(&toc, "keep me").unwrap();
+            let config = SplitConfig {
+                splits: 2,
+                output_dir: output_dir.path().to_path_buf(),
+                generate_toc: true,
+                overwrite_policy: policy,
+                ..SplitConfig::default()
+            };
+
+            let result = DocumentSplitter::split_document(&document, &config).await;
+            assert_eq!(std::fs::read_to_string(&metadata).unwrap(), "keep me");
+            assert_eq!(std::fs::read_to_string(&toc).unwrap(), "keep me");
+            let first_split = output_dir.path().join("existing_split_1_of_2.md");
+            match policy {
+                // Nothing is written once any target exists
+                OverwritePolicy::Error => {
+                    let error = result.unwrap_err().to_string();
+                    assert!(error.contains("existing_metadata.json already exists"), "{}", error);
+                    assert!(!first_split.exists());
+                }
+                _ => {
+                    assert!(result.unwrap().skipped_files.is_empty());
+                    assert!(first_split.exists());
+                }
+            }
+        }
+
+        // Extracted code files follow the same policy
+        let content = "# Page 1\n\n```rust\nfn main() {}\n```\n\n# Page 2\n\nText.";
+        let document = MarkdownParser::new(None).unwrap().parse_str(content, "existing.md").unwrap();
+        for policy in [OverwritePolicy::Error, OverwritePolicy::Skip] {
+            let output_dir = tempfile::tempdir().unwrap();
+            let code_dir = output_dir.path().join("code");
+            std::fs::create_dir(&code_dir).unwrap();
+            let code_file = code_dir.join("existing_001.rs");
+            std::fs::write(&code_file, "keep me").unwrap();
+            let config = SplitConfig {
+                splits: 2,
+                output_dir: output_dir.path().to_path_buf(),
+                extract_code_dir: Some(code_dir),
+                overwrite_policy: policy,
+                ..SplitConfig::default()
+            };
+
+            let result = DocumentSplitter::split_document(&document, &config).await;
+            assert_eq!(std::fs::read_to_string(&code_file).unwrap(), "keep me");
+            let first_split = output_dir.path().join("existing_split_1_of_2.md");
+            match policy {
+                OverwritePolicy::Error => {
+                    assert!(result.unwrap_err().to_string().contains("existing_001.rs already exists"));
+                    assert!(!first_split.exists());
+                }
+                _ => assert_eq!(result.unwrap().code_blocks.len(), 1),
+            }
+        }
+    }
+
+    #[test]
+    fn test_explicit_line_breaks() {
+        // Line 2 would be a rule break and line 6 a heading break under detection
+        let content = "intro\n---\nstill intro\nfirst page\nmore\n# Not a break\nsecond page\nend";
+        let parser = MarkdownParser::with_options(ParserOptions {
+            split_by: SplitBy::Lines(vec![4, 7]),
+            ..ParserOptions::default()
+        })
+        .unwrap();
+        let document = parser.parse_document(content, test_metadata("lines.md", content)).unwrap();
+
+        let spans: Vec<(usize, usize)> = document.pages.iter().map(|page| (page.start_line, page.end_line)).collect();
+        assert_eq!(spans, vec![(0, 3), (3, 6), (6, 8)]);
+        assert!(document.pages[1].content.starts_with("first page"));
+
+        let past_end = MarkdownParser::with_options(ParserOptions {
+            split_by: SplitBy::Lines(vec![4, 9]),
+            ..ParserOptions::default()
+        })
+        .unwrap();
+        assert!(past_end.parse_document(content, test_metadata("lines.md", content)).is_err());
+
+        for unsorted in [vec![7, 4], vec![0, 4], vec![4, 4]] {
+            assert!(MarkdownParser::with_options(ParserOptions {
+                split_by: SplitBy::Lines(unsorted),
+                ..ParserOptions::default()
+            })
+            .is_err());
+        }
+    }
+
+    #[test]
+    fn test_latex_breaks_ignored_inside_fences() {
+        let content = format!(
+            "Intro text.\n\n```latex\n\\section{{A}}\n\\newpage\n\\section{{B}}\n```\n\n$$\n\\pagebreak\n$$\n\nMore prose.\n\\newpage\n{}",
+            "Second page.\n".repeat(12)
+        );
+        let content = content.as_str();
+        let parser = MarkdownParser::new(None).unwrap();
+        let document = parser.parse_document(content, test_metadata("latex.md", content)).unwrap();
+
+        assert_eq!(document.total_pages, 2);
+        assert!(document.pages[0].content.contains("\\newpage\n\\section{B}"));
+        assert!(document.pages[0].content.contains("$$\n\\pagebreak\n$$"));
+        assert_eq!(document.pages[1].start_line, 13);
+    }
+
+    #[test]
+    fn test_format_document_is_idempotent() {
+        let content = "\n\n#   Title ##\nIntro line. \nHard break  \nnext\n\n\n\n***\nSetext\n---\n##Not a heading\n## Real\n```md\n#   keep    \n\n\n* * *\n```\n- - -\n\n\n";
+        let parser = MarkdownParser::new(None).unwrap();
+        let document = parser.parse_document(content, test_metadata("messy.md", content)).unwrap();
+
+        let formatted = parser.format_document(&document);
+        assert_eq!(
+            formatted,
+            "# Title\n\nIntro line.\nHard break  \nnext\n\n---\n\nSetext\n---\n##Not a heading\n\n## Real\n\n```md\n#   keep    \n\n\n* * *\n```\n\n---\n"
+        );
+
+        let reparsed = parser.parse_document(&formatted, test_metadata("messy.md", &formatted)).unwrap();
+        assert_eq!(parser.format_document(&reparsed), formatted);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_cache_serves_without_network() {
+        let cache_dir = tempfile::tempdir().unwrap();
+        // Nothing listens on the discard port, so a network fetch would fail
+        let offline_url = "http://127.0.0.1:9/guide.md";
+        std::fs::write(ContentFetcher::cache_path(cache_dir.path(), offline_url), "# Cached\n\nSaved earlier.\n").unwrap();
+        let options = FetchOptions {
+            cache_dir: Some(cache_dir.path().to_path_buf()),
+            ..FetchOptions::default()
+        };
+
+        let (content, metadata) = ContentFetcher::fetch_content_with(offline_url, &options).await.unwrap();
+        assert_eq!(content, "# Cached\n\nSaved earlier.\n");
+        assert_eq!(metadata.source_type, SourceType::Url);
+        assert_eq!(metadata.origin, offline_url);
+        assert!(ContentFetcher::fetch_content(offline_url).await.is_err());
+
+        // A miss goes to the network and, when asked, saves the response for next time
+        let url = format!("{}/fresh.md", serve_markdown("# Fresh\n").await);
+        let options = FetchOptions {
+            write_cache: true,
+            ..options
+        };
+        ContentFetcher::fetch_content_with(&url, &options).await.unwrap();
+        let saved = std::fs::read_to_string(ContentFetcher::cache_path(cache_dir.path(), &url)).unwrap();
+        assert_eq!(saved, "# Fresh\n");
+    }
+
+    #[test]
+    fn test_page_offsets_delimit_source_content() {
+        let parser = MarkdownParser::new(None).unwrap();
+        let content = format!("{}\n", paged_content(3));
+        let document = parser.parse_document(&content, test_metadata("offsets.md", &content)).unwrap();
+        assert_eq!(document.total_pages, 3);
+        for page in &document.pages {
+            assert_eq!(&content[page.start_offset..page.end_offset], page.content);
+        }
+        assert_eq!(document.pages[0].start_offset, 0);
+
+        // CRLF terminators are two bytes wide; the span still lands on the page lines
+        let crlf = content.replace('\n', "\r\n");
+        let document = parser.parse_document(&crlf, test_metadata("offsets.md", &crlf)).unwrap();
+        for page in &document.pages {
+            assert_eq!(crlf[page.start_offset..page.end_offset].replace("\r\n", "\n"), page.content);
+        }
+
+        // A stripped delimiter closing the document leaves an empty cell at its end
+        let parser = MarkdownParser::with_options(ParserOptions {
+            split_by: SplitBy::JupyterCells,
+            strip_cell_delimiters: true,
+            ..ParserOptions::default()
+        })
+        .unwrap();
+        let cells = "# %%\nfoo\n# %%";
+        let document = parser.parse_str(cells, "cells.py").unwrap();
+        for page in &document.pages {
+            assert_eq!(&cells[page.start_offset..page.end_offset], page.content);
+        }
+        assert_eq!(document.pages[0].content, "foo");
+    }
+
+    #[tokio::test]
+    async fn test_bisect_depth_two_writes_tree_named_files() {
+        let output_dir = tempfile::tempdir().unwrap();
+        // One long page up front pulls the first cut forward
+        let content = format!("# Page 1\n\n{}\n{}", "Long text. ".repeat(60), paged_content(9));
+        let document = MarkdownParser::new(None)
+            .unwrap()
+            .parse_document(&content, test_metadata("tree.md", &content))
+            .unwrap();
+        let config = SplitConfig {
+            bisect_depth: Some(2),
+            output_dir: output_dir.path().to_path_buf(),
+            ..SplitConfig::default()
+        };
+        let result = DocumentSplitter::split_document(&document, &config).await.unwrap();
+
+        let names: Vec<&str> = result.splits.iter().map(|info| info.filename.as_str()).collect();
+        assert_eq!(names, vec!["tree_1.1.md", "tree_1.2.md", "tree_2.1.md", "tree_2.2.md"]);
+        let paths: Vec<Option<&str>> = result.splits.iter().map(|info| info.tree_path.as_deref()).collect();
+        assert_eq!(paths, vec![Some("1.1"), Some("1.2"), Some("2.1"), Some("2.2")]);
+
+        // Every page lands in exactly one file, in order
+        assert_eq!(result.splits[0].start_page, 1);
+        assert_eq!(result.splits[3].end_page, document.total_pages);
+        for pair in result.splits.windows(2) {
+            assert_eq!(pair[1].start_page, pair[0].end_page + 1);
+        }
+        // The long first page gets a file of its own, balancing the rest
+        assert_eq!((result.splits[0].start_page, result.splits[0].end_page), (1, 1));
+
+        let too_deep = SplitConfig {
+            bisect_depth: Some(4),
+            ..config
+        };
+        assert!(DocumentSplitter::split_document(&document, &too_deep).await.is_err());
+    }
+
+    #[test]
+    fn test_keep_html_intact_moves_breaks_past_block() {
+        let filler = "Some text.\n".repeat(11);
+        let content = format!(
+            "{filler}\n---\n{filler}<table>\n  <tr><td>one</td></tr>\n---\n  <tr><td>two</td></tr>\n</table>\n{filler}"
+        );
+        let parse = |keep_html_intact| {
+            let parser = MarkdownParser::with_options(ParserOptions {
+                keep_html_intact,
+                ..ParserOptions::default()
+            })
+            .unwrap();
+            parser.parse_document(&content, test_metadata("table.md", &content)).unwrap()
+        };
+
+        let split_table = parse(false);
+        assert!(split_table.pages.iter().any(|page| page.content.starts_with("---\n  <tr><td>two")));
+
+        let document = parse(true);
+        let starts: Vec<usize> = document.pages.iter().map(|page| page.start_line).collect();
+        assert_eq!(starts, vec![0, 12, 29]);
+        assert!(document.pages[1].content.contains("<table>"));
+        assert!(document.pages[1].content.ends_with("</table>"));
+    }
+
+    #[test]
+    fn test_locate_line_maps_to_split() {
+        let content = paged_content(6);
+        let document = MarkdownParser::new(None)
+            .unwrap()
+            .parse_document(&content, test_metadata("locate.md", &content))
+            .unwrap();
+        let config = SplitConfig {
+            splits: 3,
+            ..SplitConfig::default()
+        };
+
+        let page = &document.pages[3];
+        let line = page.start_line + 1;
+        let location = DocumentSplitter::locate_line(&document, &config, None, line).unwrap();
+        assert_eq!(location.line, line);
+        assert_eq!(location.page, page.number);
+        assert_eq!(location.split_number, 2);
+        assert_eq!(location.filename, "locate_split_2_of_3.md");
+
+        let first = DocumentSplitter::locate_line(&document, &config, None, 1).unwrap();
+        assert_eq!((first.page, first.split_number), (1, 1));
+
+        let total_lines = document.metadata.total_lines;
+        assert!(DocumentSplitter::locate_line(&document, &config, None, 0).is_err());
+        assert!(DocumentSplitter::locate_line(&document, &config, None, total_lines + 1).is_err());
+    }
+
+    #[test]
+    fn test_locate_line_follows_title_filter() {
+        let content = paged_content(6);
+        let document = MarkdownParser::new(None)
+            .unwrap()
+            .parse_document(&content, test_metadata("locate.md", &content))
+            .unwrap();
+        let config = SplitConfig {
+            splits: 3,
+            title_filter: Some("Page [456]".to_string()),
+            ..SplitConfig::default()
+        };
+
+        // Page 5 is the second page left after the filter
+        let page = &document.pages[4];
+        let location = DocumentSplitter::locate_line(&document, &config, None, page.start_line + 1).unwrap();
+        assert_eq!(location.page, 5);
+        assert_eq!(location.split_number, 2);
+
+        assert!(DocumentSplitter::locate_line(&document, &config, None, 1).is_err());
+    }
+
+    #[test]
+    fn test_locate_line_finds_interleaved_groups() {
+        let page = |category: &str, body: &str| format!("---\ncategory: {}\n---\n\n{}\n", category, body);
+        let content = [page("api", "Endpoints."), page("guide", "Install."), page("api", "Errors.")].join("\n");
+        let document = MarkdownParser::new(None)
+            .unwrap()
+            .parse_document(&content, test_metadata("h.md", &content))
+            .unwrap();
+        assert_eq!(document.total_pages, 3);
+        let config = SplitConfig {
+            group_by_field: Some("category".to_string()),
+            ..SplitConfig::default()
+        };
+
+        // The api file spans pages 1-3 but holds only pages 1 and 3
+        let files: Vec<(String, usize)> = document
+            .pages
+            .iter()
+            .map(|page| {
+                let location = DocumentSplitter::locate_line(&document, &config, None, page.start_line + 1).unwrap();
+                (location.filename, location.page)
+            })
+            .collect();
+        assert_eq!(
+            files,
+            vec![("h_api.md".to_string(), 1), ("h_guide.md".to_string(), 2), ("h_api.md".to_string(), 3)]
+        );
+    }
+
+    #[test]
+    fn test_tab_width_decides_indented_code() {
+        let content = "# Intro\n\nSample output:\n\n\t# Page 2\n\t---\n\nMore text.\n";
+        let parse = |tab_width| {
+            MarkdownParser::with_options(ParserOptions {
+                tab_width,
+                // Keep the short pages apart so the breaks stay visible
+                max_merged_lines: Some(1),
+                ..ParserOptions::default()
+            })
+            .unwrap()
+            .parse_document(content, test_metadata("tabs.md", content))
+            .unwrap()
+        };
+
+        // A tab reaches column 4: the marker and rule are code
+        let code = parse(4);
+        assert_eq!(code.pages.len(), 1);
+        assert_eq!(code.pages[0].content, content.trim_end());
+
+        // A tab reaches column 2: they are ordinary lines again
+        let text = parse(2);
+        let starts: Vec<usize> = text.pages.iter().map(|page| page.start_line).collect();
+        assert_eq!(starts, vec![0, 4]);
+    }
+
+    #[tokio::test]
+    async fn test_reverse_order_numbers_back_to_front() {
+        let output_dir = tempfile::tempdir().unwrap();
+        let content = paged_content(6);
+        let document = MarkdownParser::new(None)
+            .unwrap()
+            .parse_document(&content, test_metadata("log.md", &content))
+            .unwrap();
+        let config = SplitConfig {
+            splits: 3,
+            output_dir: output_dir.path().to_path_buf(),
+            reverse_order: true,
+            ..SplitConfig::default()
+        };
+        let result = DocumentSplitter::split_document(&document, &config).await.unwrap();
+
+        let numbering: Vec<(usize, usize)> = result
+            .splits
+            .iter()
+            .map(|s| (s.document_order, s.split_number))
+            .collect();
+        assert_eq!(numbering, vec![(1, 3), (2, 2), (3, 1)]);
+        assert_eq!(result.splits[2].filename, "log_split_1_of_3.md");
+        assert_eq!((result.splits[2].start_page, result.splits[2].end_page), (5, 6));
+
+        // Pages inside a file keep their order
+        let last = std::fs::read_to_string(output_dir.path().join("log_split_1_of_3.md")).unwrap();
+        assert!(last.find("# Page 5").unwrap() < last.find("# Page 6").unwrap());
+
+        let rebuilt = DocumentSplitter::reconstruct(result.metadata_file.as_ref().unwrap(), output_dir.path())
+            .await
+            .unwrap();
+        assert_eq!(rebuilt, content);
+    }
+
+    #[tokio::test]
+    async fn test_title_filter_keeps_matching_pages() {
+        let output_dir = tempfile::tempdir().unwrap();
+        let content = [
+            "# Chapter 1\n\nOpening.\n",
+            "# Appendix A\n\nTables.\n",
+            "# Chapter 2\n\nMiddle.\n",
+            "# Chapter 3\n\nEnding.\n",
+            "# Appendix B\n\nIndex.\n",
+        ]
+        .join("\n");
+        let document = MarkdownParser::new(None)
+            .unwrap()
+            .parse_document(&content, test_metadata("book.md", &content))
+            .unwrap();
+        assert_eq!(document.total_pages, 5);
+
+        let config = SplitConfig {
+            splits: 3,
+            output_dir: output_dir.path().to_path_buf(),
+            title_filter: Some("^Chapter".to_string()),
+            ..SplitConfig::default()
+        };
+        let result = DocumentSplitter::split_document(&document, &config).await.unwrap();
+
+        assert_eq!(result.actual_pages, 3);
+        let pages: Vec<usize> = result
+            .splits
+            .iter()
+            .flat_map(|split| split.pages.iter().map(|page| page.number))
+            .collect();
+        assert_eq!(pages, vec![1, 2, 3]);
+        for (file, chapter) in result.output_files.iter().zip(["Chapter 1", "Chapter 2", "Chapter 3"]) {
+            let written = std::fs::read_to_string(file).unwrap();
+            assert!(written.contains(chapter) && !written.contains("Appendix"));
+        }
+
+        let no_match = SplitConfig {
+            title_filter: Some("^Part".to_string()),
+            ..config.clone()
+        };
+        assert!(DocumentSplitter::plan(&document, &no_match, None).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_source_date_epoch_makes_metadata_reproducible() {
+        let output_dir = tempfile::tempdir().unwrap();
+        let content = paged_content(4);
+        let config = SplitConfig {
+            splits: 2,
+            output_dir: output_dir.path().to_path_buf(),
+            source_date_epoch: Some(1_700_000_000),
+            ..SplitConfig::default()
+        };
+
+        let mut runs = Vec::new();
+        for _ in 0..2 {
+            // Each parse stamps the document with the current time
+            let document = MarkdownParser::new(None)
+                .unwrap()
+                .parse_document(&content, test_metadata("stable.md", &content))
+                .unwrap();
+            let result = DocumentSplitter::split_document(&document, &config).await.unwrap();
+            runs.push(std::fs::read(result.metadata_file.unwrap()).unwrap());
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+
+        assert_eq!(runs[0], runs[1]);
+        let metadata: SplitMetadata = serde_json::from_slice(&runs[0]).unwrap();
+        assert_eq!(metadata.document_metadata.created_at, "2023-11-14T22:13:20+00:00");
+        assert!(DocumentSplitter::timestamp(Some(i64::MAX)).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_sitemap_lists_split_urls() {
+        let output_dir = tempfile::tempdir().unwrap();
+        let content = paged_content(4);
+        let document = MarkdownParser::new(None)
+            .unwrap()
+            .parse_document(&content, test_metadata("q&a notes.md", &content))
+            .unwrap();
+        let config = SplitConfig {
+            splits: 2,
+            output_dir: output_dir.path().to_path_buf(),
+            shard_dirs: Some(2),
+            ..SplitConfig::default()
+        };
+        let result = DocumentSplitter::split_document(&document, &config).await.unwrap();
+
+        let sitemap_path = output_dir.path().join("sitemap.xml");
+        DocumentSplitter::write_sitemap(&sitemap_path, "https://example.com/docs", output_dir.path(), &result.splits)
+            .unwrap();
+        let sitemap = std::fs::read_to_string(&sitemap_path).unwrap();
+
+        assert!(sitemap.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>"));
+        assert_eq!(sitemap.matches("<loc>").count(), 2);
+        for info in &result.splits {
+            let shard = info.shard.unwrap();
+            let number = info.split_number;
+            let loc = format!(
+                "<loc>https://example.com/docs/shard-{}/q&amp;a%20notes_split_{}_of_2.md</loc>",
+                shard, number
+            );
+            assert!(sitemap.contains(&loc), "{} missing from {}", loc, sitemap);
+        }
+
+        let bad_base = DocumentSplitter::write_sitemap(&sitemap_path, "not a url", output_dir.path(), &result.splits);
+        assert!(bad_base.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_group_by_field_writes_one_file_per_value() {
+        let output_dir = tempfile::tempdir().unwrap();
+        // Each page opens with a `---` block, except the untitled introduction
+        let page = |category: &str, body: &str| format!("---\ncategory: {}\n---\n\n{}\n", category, body);
+        let content = [
+            "# Handbook\n\nIntroduction.\n".to_string(),
+            page("api", "Endpoints."),
+            page("Getting Started", "Install."),
+            page("api", "Errors."),
+            page("Getting Started", "Configure."),
+        ]
+        .join("\n");
+        let document = MarkdownParser::new(None)
+            .unwrap()
+            .parse_document(&content, test_metadata("handbook.md", &content))
+            .unwrap();
+        assert_eq!(document.total_pages, 5);
+
+        let config = SplitConfig {
+            output_dir: output_dir.path().to_path_buf(),
+            group_by_field: Some("category".to_string()),
+            ..SplitConfig::default()
+        };
+        let result = DocumentSplitter::split_document(&document, &config).await.unwrap();
+
+        let files: Vec<(&str, Option<&str>, Vec<usize>)> = result
+            .splits
+            .iter()
+            .map(|split| {
+                let pages = split.pages.iter().map(|page| page.number).collect();
+                (split.filename.as_str(), split.group.as_deref(), pages)
+            })
+            .collect();
+        assert_eq!(
+            files,
+            vec![
+                ("handbook_ungrouped.md", Some("ungrouped"), vec![1]),
+                ("handbook_api.md", Some("api"), vec![2, 4]),
+                ("handbook_getting-started.md", Some("getting-started"), vec![3, 5]),
+            ]
+        );
+
+        let api = std::fs::read_to_string(output_dir.path().join("handbook_api.md")).unwrap();
+        assert!(api.find("Endpoints.").unwrap() < api.find("Errors.").unwrap());
+        assert!(!api.contains("Install."));
+    }
+
+    #[tokio::test]
+    async fn test_max_words_packs_whole_pages() {
+        let output_dir = tempfile::tempdir().unwrap();
+        // "# Page N" adds three words to each page
+        let content = [10, 10, 50, 5, 5]
+            .iter()
+            .enumerate()
+            .map(|(idx, words)| format!("# Page {}\n\n{}\n", idx + 1, "word ".repeat(*words).trim_end()))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let document = MarkdownParser::new(None)
+            .unwrap()
+            .parse_document(&content, test_metadata("words.md", &content))
+            .unwrap();
+        let config = SplitConfig {
+            strategy: SplitStrategy::MaxWords(30),
+            output_dir: output_dir.path().to_path_buf(),
+            ..SplitConfig::default()
+        };
+        let result = DocumentSplitter::split_document(&document, &config).await.unwrap();
+
+        // The 53-word page is over budget and stands alone
+        let ranges: Vec<(usize, usize)> = result.splits.iter().map(|s| (s.start_page, s.end_page)).collect();
+        assert_eq!(ranges, vec![(1, 2), (3, 3), (4, 5)]);
+        assert_eq!(result.splits[0].filename, "words_split_1_of_3.md");
+        assert_eq!(
+            DocumentSplitter::calculate_strategy_split_info(&document, &config).unwrap(),
+            ranges
+        );
+
+        let plan = DocumentSplitter::plan(&document, &config, None).unwrap();
+        assert_eq!(plan.strategy, PlanStrategy::Packed);
+
+        let zero = SplitConfig {
+            strategy: SplitStrategy::MaxWords(0),
+            ..config.clone()
+        };
+        assert!(DocumentSplitter::plan(&document, &zero, None).is_err());
+
+        // Modes that choose their own files would drop the budget, so they are rejected
+        let tokens = SplitStrategy::MaxTokens { limit: 200, model: TokenModel::Chars };
+        for strategy in [config.strategy, tokens] {
+            let with_sections = SplitConfig {
+                strategy,
+                section_limit: Some(SectionLimit::Pages(2)),
+                ..config.clone()
+            };
+            let with_bisect = SplitConfig {
+                strategy,
+                bisect_depth: Some(1),
+                ..config.clone()
+            };
+            assert!(DocumentSplitter::plan(&document, &with_sections, None).is_err());
+            assert!(DocumentSplitter::plan(&document, &with_bisect, None).is_err());
+        }
+    }
+
+    #[test]
+    fn test_tokenizer_estimates() {
+        let chars = Tokenizer::new(TokenModel::Chars);
+        assert_eq!(chars.count(""), 0);
+        assert_eq!(chars.count("abcdefghi"), 3);
+
+        let bpe = Tokenizer::new(TokenModel::Bpe);
+        // " the", " because" and " document" are single tokens; "##" is one
+        assert_eq!(bpe.count("## the because document"), 4);
+        // "tokenizer" is nine letters, three tokens; "12345" is two digit groups
+        assert_eq!(bpe.count("tokenizer 12345"), 5);
+        assert_eq!(bpe.count("\n\n"), 1);
+    }
+
+    #[tokio::test]
+    async fn test_max_tokens_counts_headers() {
+        let output_dir = tempfile::tempdir().unwrap();
+        let content = paged_content(6);
+        let document = MarkdownParser::new(None)
+            .unwrap()
+            .parse_document(&content, test_metadata("tokens.md", &content))
+            .unwrap();
+        let model = TokenModel::Chars;
+        let config = SplitConfig {
+            strategy: SplitStrategy::MaxTokens { limit: 40, model },
+            output_dir: output_dir.path().to_path_buf(),
+            ..SplitConfig::default()
+        };
+        let result = DocumentSplitter::split_document(&document, &config).await.unwrap();
+
+        // Every written file, header comment included, stays within the limit
+        let tokenizer = Tokenizer::new(model);
+        assert!(result.splits.len() > 1);
+        for file in &result.output_files {
+            let written = std::fs::read_to_string(file).unwrap();
+            assert!(written.contains("Split containing pages"));
+            assert!(tokenizer.count(&written) <= 40, "{} tokens in {}", tokenizer.count(&written), file.display());
+        }
+        let page_tokens: usize =
+            document.pages.iter().map(|page| DocumentSplitter::page_tokens(page, &tokenizer)).sum();
+        assert!(page_tokens <= 40 * result.splits.len());
+
+        // Summaries, part headings and heading context count against the limit too
+        let nested = "# Guide\n\nThe guide starts here.\n\n## Setup\n\nInstall the tool first.\n\n---\n\n\
+            Then configure it.\n\n---\n\nAnd run it once.\n\n---\n\nCheck the output.\n";
+        let nested_document = MarkdownParser::new(None)
+            .unwrap()
+            .parse_document(nested, test_metadata("nested.md", nested))
+            .unwrap();
+        let decorated_dir = tempfile::tempdir().unwrap();
+        let decorated = SplitConfig {
+            strategy: SplitStrategy::MaxTokens { limit: 60, model },
+            output_dir: decorated_dir.path().to_path_buf(),
+            summarizer: Some(std::sync::Arc::new(FirstSentence)),
+            add_part_headings: true,
+            include_heading_context: true,
+            ..SplitConfig::default()
+        };
+        let result = DocumentSplitter::split_document(&nested_document, &decorated).await.unwrap();
+        assert!(result.splits.len() > 1);
+        for file in &result.output_files {
+            let written = std::fs::read_to_string(file).unwrap();
+            assert!(written.contains("# Part "));
+            assert!(tokenizer.count(&written) <= 60, "{} tokens in {}", tokenizer.count(&written), file.display());
+        }
+
+        // A header alone larger than the budget cannot work
+        let tiny = SplitConfig {
+            strategy: SplitStrategy::MaxTokens { limit: 5, model },
+            ..config.clone()
+        };
+        assert!(DocumentSplitter::plan(&document, &tiny, None).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_stdin_source() {
+        let content = paged_content(2);
+        let (read, metadata) =
+            ContentFetcher::fetch_from_reader(content.as_bytes(), "-", &FetchOptions::default())
+                .await
+                .unwrap();
+        assert_eq!(read, content);
+        assert_eq!(metadata.filename, "stdin.md");
+        assert_eq!(metadata.source_type, SourceType::Stdin);
+        assert_eq!(metadata.origin, "-");
+
+        let options = FetchOptions {
+            max_bytes: Some(16),
+            ..FetchOptions::default()
+        };
+        let result = ContentFetcher::fetch_from_reader(content.as_bytes(), "-", &options).await;
+        assert!(matches!(result, Err(MarkdownSplitterError::FileTooLarge { .. })));
+
+        let validated = ContentFetcher::validate_sources(&["-".to_string()]).await.unwrap();
+        assert_eq!(validated, vec!["-".to_string()]);
+
+        let twice = ContentFetcher::validate_sources(&["-".to_string(), "/dev/stdin".to_string()]).await;
+        assert!(matches!(twice, Err(MarkdownSplitterError::StdinReused { count: 2 })));
+    }
+
+    #[tokio::test]
+    async fn test_url_size_limit_applies_while_streaming() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // No Content-Length and a body that never ends
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = [0u8; 1024];
+                let _ = stream.read(&mut request).await;
+                let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nConnection: close\r\n\r\n").await;
+                while stream.write_all(&[b'a'; 1024]).await.is_ok() {}
+            }
+        });
+
+        let options = FetchOptions {
+            max_bytes: Some(64 * 1024),
+            ..FetchOptions::default()
+        };
+        let source = format!("http://{}/endless.md", address);
+        let result = tokio::time::timeout(
+            Duration::from_secs(10),
+            ContentFetcher::fetch_content_with(&source, &options),
+        )
+        .await
+        .expect("the size limit should stop the download");
+        assert!(matches!(result, Err(MarkdownSplitterError::FileTooLarge { .. })));
+    }
+
+    /// Serves `body`, answering the first `failures` requests with 503
+    async fn serve_flaky(body: &'static str, failures: usize) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut served = 0;
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = [0u8; 1024];
+                let _ = stream.read(&mut request).await;
+                let (status, body) = if served < failures { ("503 Unavailable", "") } else { ("200 OK", body) };
+                served += 1;
+                let response = format!(
+                    "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+
+        format!("http://{}", address)
+    }
+
+    #[tokio::test]
+    async fn test_fetch_retries_and_timeout() {
+        let body = "# Flaky\n\nEventually served.\n";
+        let options = FetchOptions {
+            retries: 2,
+            retry_backoff: Duration::from_millis(10),
+            ..FetchOptions::default()
+        };
+        let url = format!("{}/flaky.md", serve_flaky(body, 2).await);
+        let (content, _) = ContentFetcher::fetch_content_with(&url, &options).await.unwrap();
+        assert_eq!(content, body);
+
+        let url = format!("{}/flaky.md", serve_flaky(body, 3).await);
+        let result = ContentFetcher::fetch_content_with(&url, &options).await;
+        assert!(matches!(result, Err(MarkdownSplitterError::HttpStatus { status: 503 })));
+
+        let url = format!("{}/slow.md", serve_markdown_after(body, Duration::from_secs(5)).await);
+        let options = FetchOptions {
+            timeout: Some(Duration::from_millis(100)),
+            ..FetchOptions::default()
+        };
+        match ContentFetcher::fetch_content_with(&url, &options).await {
+            Err(MarkdownSplitterError::FetchTimeout { url: timed_out, timeout }) => {
+                assert_eq!(timed_out, url);
+                assert_eq!(timeout, Duration::from_millis(100));
+            }
+            other => panic!("expected FetchTimeout, got {:?}", other.map(|(_, m)| m.filename)),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_front_matter_repeated_in_splits() {
+        let front_matter = "---\ntitle: Release notes\n# Authors, in order\ntags:\n  - news\n---";
+        let content = format!("{}\n\n# Intro\n\nOpening.\n\n{}", front_matter, paged_content(3));
+        let parser = MarkdownParser::new(None).unwrap();
+        let document = parser.parse_str(&content, "notes.md").unwrap();
+
+        assert_eq!(document.front_matter.as_deref(), Some(front_matter));
+        assert_eq!(document.total_pages, 4);
+        assert_eq!(document.pages[0].title.as_deref(), Some("Intro"));
+        assert!(document.pages[0].content.starts_with(front_matter));
+
+        let output_dir = tempfile::tempdir().unwrap();
+        let config = SplitConfig {
+            splits: 2,
+            output_dir: output_dir.path().to_path_buf(),
+            repeat_front_matter: true,
+            ..SplitConfig::default()
+        };
+        let result = DocumentSplitter::split_document(&document, &config).await.unwrap();
+
+        let first = std::fs::read_to_string(&result.splits[0].path).unwrap();
+        assert_eq!(first.matches(front_matter).count(), 1);
+        let second = std::fs::read_to_string(&result.splits[1].path).unwrap();
+        assert!(second.starts_with(front_matter));
+
+        let rebuilt = DocumentSplitter::reconstruct(result.metadata_file.as_ref().unwrap(), output_dir.path())
+            .await
+            .unwrap();
+        assert_eq!(rebuilt, content);
+
+        let toml = "+++\ntitle = \"Notes\"\n+++\n\n# Intro\n\nOpening.\n";
+        let document = parser.parse_str(toml, "notes.md").unwrap();
+        assert_eq!(document.front_matter.as_deref(), Some("+++\ntitle = \"Notes\"\n+++"));
+        assert_eq!(document.total_pages, 1);
+    }
+
+    #[test]
+    fn test_code_fences_hide_page_breaks() {
+        let content = "```sh\n# Setup comment\n```\n\nPreamble text.\n\n# Install\n\nRun this:\n\n```bash\n---\n# Page 5\n## Heading\n```\n\n  ~~~\n  ---\n  # Page 6\n  ~~~\n\n# Usage\n\nUse it.\n";
+        let parser = MarkdownParser::with_options(ParserOptions {
+            max_merged_lines: Some(1),
+            ..ParserOptions::default()
+        })
+        .unwrap();
+        let document = parser.parse_str(content, "guide.md").unwrap();
+
+        let titles: Vec<_> = document.pages.iter().map(|page| page.title.as_deref()).collect();
+        assert_eq!(titles, vec![None, Some("Install"), Some("Usage")]);
+        assert!(document.pages[1].content.contains("# Page 5\n## Heading"));
+    }
+
+    #[tokio::test]
+    async fn test_toc_file_links_splits() {
+        let content = "Preamble without a heading.\n\n# Input/Output #1\n\nBody.\n\n# Use [cat] here\n\nMore.\n";
+        let parser = MarkdownParser::with_options(ParserOptions {
+            max_merged_lines: Some(1),
+            ..ParserOptions::default()
+        })
+        .unwrap();
+        let document = parser.parse_str(content, "my notes.md").unwrap();
+        assert_eq!(document.total_pages, 3);
+
+        let output_dir = tempfile::tempdir().unwrap();
+        let config = SplitConfig {
+            splits: 3,
+            output_dir: output_dir.path().to_path_buf(),
+            generate_toc: true,
+            ..SplitConfig::default()
+        };
+        let result = DocumentSplitter::split_document(&document, &config).await.unwrap();
+        let toc_file = result.toc_file.unwrap();
+        assert_eq!(toc_file, output_dir.path().join("my notes_TOC.md"));
+
+        let toc = std::fs::read_to_string(toc_file).unwrap();
+        let expected = [
+            "1. [Untitled split 1](my%20notes_split_1_of_3.md) - `my notes_split_1_of_3.md`, page 1",
+            "2. [Input/Output \\#1](my%20notes_split_2_of_3.md) - `my notes_split_2_of_3.md`, page 2",
+            "3. [Use \\[cat\\] here](my%20notes_split_3_of_3.md) - `my notes_split_3_of_3.md`, page 3",
+        ];
+        for line in expected {
+            assert!(toc.contains(line), "missing {:?} in\n{}", line, toc);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_balanced_strategy_evens_line_counts() {
+        // Ten pages of 4, 4, 4, 4, 4, 4, 12, 12, 12 and 12 lines
+        let content: String = (1..=10)
+            .map(|n| {
+                let body_lines = if n > 6 { 10 } else { 2 };
+                let body: String = (1..=body_lines).map(|line| format!("Line {} of page {}.\n", line, n)).collect();
+                format!("# Page {}\n\n{}", n, body)
+            })
+            .collect();
+        let parser = MarkdownParser::with_options(ParserOptions {
+            max_merged_lines: Some(1),
+            ..ParserOptions::default()
+        })
+        .unwrap();
+        let document = parser.parse_str(&content, "uneven.md").unwrap();
+        assert_eq!(document.total_pages, 10);
+
+        let split = |strategy| {
+            let output_dir = tempfile::tempdir().unwrap();
+            let config = SplitConfig {
+                splits: 3,
+                strategy,
+                output_dir: output_dir.path().to_path_buf(),
+                preserve_structure: false,
+                include_metadata: false,
+                ..SplitConfig::default()
+            };
+            let document = document.clone();
+            async move { DocumentSplitter::split_document(&document, &config).await.unwrap() }
+        };
+
+        let even = split(SplitStrategy::Count).await;
+        let page_counts: Vec<_> = even.splits.iter().map(|info| info.pages.len()).collect();
+        assert_eq!(page_counts, vec![4, 4, 2]);
+
+        let balanced = split(SplitStrategy::Balanced).await;
+        assert_eq!(balanced.per_split_lines.len(), 3);
+        let spread = |lines: &[usize]| lines.iter().max().unwrap() - lines.iter().min().unwrap();
+        assert!(spread(&balanced.per_split_lines) < spread(&even.per_split_lines));
+        assert_eq!(
+            balanced.per_split_lines,
+            balanced.splits.iter().map(|info| info.lines).collect::<Vec<_>>()
+        );
+
+        // Whole pages, in document order
+        let pages: Vec<usize> = balanced
+            .splits
+            .iter()
+            .flat_map(|info| info.pages.iter().map(|page| page.number))
+            .collect();
+        assert_eq!(pages, (1..=10).collect::<Vec<_>>());
+    }
+
+    /// Serves `body` as is, with a `Content-Encoding` header when given
+    async fn serve_bytes(body: Vec<u8>, content_encoding: Option<&'static str>) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = [0u8; 1024];
+                let _ = stream.read(&mut request).await;
+                let encoding = content_encoding
+                    .map(|encoding| format!("Content-Encoding: {}\r\n", encoding))
+                    .unwrap_or_default();
+                let head = format!(
+                    "HTTP/1.1 200 OK\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n",
+                    encoding,
+                    body.len()
+                );
+                let _ = stream.write_all(head.as_bytes()).await;
+                let _ = stream.write_all(&body).await;
+            }
+        });
+
+        format!("http://{}", address)
+    }
+
+    #[tokio::test]
+    async fn test_compressed_sources_are_decompressed() {
+        use std::io::Write;
+
+        let markdown = paged_content(3);
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(markdown.as_bytes()).unwrap();
+        let gzip = gzip.finish().unwrap();
+        let mut brotli = Vec::new();
+        {
+            let mut writer = brotli::CompressorWriter::new(&mut brotli, 4096, 5, 22);
+            writer.write_all(markdown.as_bytes()).unwrap();
+        }
+
+        let options = FetchOptions::default();
+        let url = format!("{}/guide.md.gz", serve_bytes(gzip.clone(), None).await);
+        let (content, metadata) = ContentFetcher::fetch_content_with(&url, &options).await.unwrap();
+        assert_eq!(content, markdown);
+        assert_eq!(metadata.filename, "guide.md");
+        assert_eq!(metadata.compression, Some(Compression::Gzip));
+
+        let url = format!("{}/notes.md", serve_bytes(brotli.clone(), Some("br")).await);
+        let (content, metadata) = ContentFetcher::fetch_content_with(&url, &options).await.unwrap();
+        assert_eq!(content, markdown);
+        assert_eq!(metadata.filename, "notes.md");
+        assert_eq!(metadata.compression, Some(Compression::Brotli));
+
+        let truncated = gzip[..gzip.len() / 2].to_vec();
+        let url = format!("{}/guide.md", serve_bytes(truncated, Some("gzip")).await);
+        let result = ContentFetcher::fetch_content_with(&url, &options).await;
+        assert!(matches!(
+            result,
+            Err(MarkdownSplitterError::Decompression { compression: Compression::Gzip, .. })
+        ));
+
+        let truncated = &brotli[..brotli.len() / 2];
+        let result = ContentFetcher::decompress("notes.md.br", truncated, Compression::Brotli, &options);
+        assert!(matches!(
+            result,
+            Err(MarkdownSplitterError::Decompression { compression: Compression::Brotli, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_writes_nothing() {
+        let content = paged_content(6);
+        let parser = MarkdownParser::new(None).unwrap();
+        let document = parser.parse_str(&content, "plan.md").unwrap();
+
+        let root = tempfile::tempdir().unwrap();
+        let config = SplitConfig {
+            splits: 3,
+            output_dir: root.path().join("out"),
+            shard_dirs: Some(2),
+            generate_toc: true,
+            extract_code_dir: Some(root.path().join("code")),
+            ..SplitConfig::default()
+        };
+        let dry_config = SplitConfig {
+            dry_run: true,
+            ..config.clone()
+        };
+
+        let planned = DocumentSplitter::split_document(&document, &dry_config).await.unwrap();
+        assert!(std::fs::read_dir(root.path()).unwrap().next().is_none());
+        assert_eq!(planned.split_number, 3);
+        assert!(planned.metadata_file.is_some() && planned.toc_file.is_some());
+
+        let written = DocumentSplitter::split_document(&document, &config).await.unwrap();
+        assert_eq!(planned.output_files, written.output_files);
+        assert_eq!(planned.per_split_lines, written.per_split_lines);
+        let sizes = |result: &SplitResult| result.splits.iter().map(|info| info.bytes).collect::<Vec<_>>();
+        assert_eq!(sizes(&planned), sizes(&written));
+        assert!(written.output_files.iter().all(|path| path.exists()));
+    }
+
+    #[tokio::test]
+    async fn test_filename_template() {
+        let content = paged_content(4);
+        let parser = MarkdownParser::new(None).unwrap();
+        let document = parser.parse_str(&content, "guide.md").unwrap();
+
+        let root = tempfile::tempdir().unwrap();
+        let config = SplitConfig {
+            splits: 2,
+            output_dir: root.path().to_path_buf(),
+            filename_template: Some("{base}-{index:03}-of-{total}-{title}.md".to_string()),
+            ..SplitConfig::default()
+        };
+        let result = DocumentSplitter::split_document(&document, &config).await.unwrap();
+        let names: Vec<_> = result.splits.iter().map(|info| info.filename.as_str()).collect();
+        assert_eq!(names, ["guide-001-of-2-page-1.md", "guide-002-of-2-page-3.md"]);
+        assert!(result.output_files.iter().all(|path| path.exists()));
+
+        let unknown = SplitConfig {
+            filename_template: Some("{base}-{chapter}.md".to_string()),
+            ..config.clone()
+        };
+        let err = DocumentSplitter::split_document(&document, &unknown).await.unwrap_err();
+        assert!(matches!(err, MarkdownSplitterError::SplitConfig { ref reason } if reason.contains("{chapter}")));
+
+        let duplicate = SplitConfig {
+            filename_template: Some("{base}.md".to_string()),
+            ..config
+        };
+        let err = DocumentSplitter::split_document(&document, &duplicate).await.unwrap_err();
+        assert!(matches!(err, MarkdownSplitterError::SplitConfig { ref reason } if reason.contains("guide.md")));
+    }
+
+    #[tokio::test]
+    async fn test_split_run_report() {
+        let content = paged_content(4);
+        let parser = MarkdownParser::new(None).unwrap();
+        let document = parser.parse_str(&content, "run.md").unwrap();
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let config = SplitConfig {
+            splits: 2,
+            output_dir: temp_dir.path().join("out"),
+            ..SplitConfig::default()
+        };
+        let result = DocumentSplitter::split_document(&document, &config).await.unwrap();
+
+        let mut report = SplitRunReport::default();
+        report.record("run.md", Duration::from_millis(12), Ok(Some(result)));
+        report.record("missing.md", Duration::from_millis(3), Err("File not found: missing.md".to_string()));
+        assert_eq!((report.files_written, report.total_pages, report.failed), (2, 4, 1));
+
+        let report_path = temp_dir.path().join("report.json");
+        DocumentSplitter::write_run_report(&report_path, &report).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&report_path).unwrap()).unwrap();
+        assert_eq!(json["files_written"], 2);
+        assert_eq!(json["sources"][0]["elapsed_ms"], 12);
+        assert_eq!(json["sources"][0]["result"]["splits"].as_array().unwrap().len(), 2);
+        assert!(json["sources"][0]["error"].is_null());
+        assert_eq!(json["sources"][1]["error"], "File not found: missing.md");
+        assert!(json["sources"][1]["result"].is_null());
+
+        // A dry run lists the planned files but writes none
+        let dry_config = SplitConfig {
+            output_dir: temp_dir.path().join("dry"),
+            dry_run: true,
+            ..config
+        };
+        let result = DocumentSplitter::split_document(&document, &dry_config).await.unwrap();
+        let mut report = SplitRunReport {
+            dry_run: true,
+            ..SplitRunReport::default()
+        };
+        report.record("run.md", Duration::from_millis(1), Ok(Some(result)));
+        assert_eq!((report.files_written, report.total_pages), (0, 4));
+        assert!(!temp_dir.path().join("dry").exists());
+    }
+
+    #[tokio::test]
+    async fn test_overlap_pages_repeat_previous_split() {
+        let content = paged_content(6);
+        let parser = MarkdownParser::new(None).unwrap();
+        let document = parser.parse_str(&content, "overlap.md").unwrap();
+
+        for preserve_structure in [true, false] {
+            let output_dir = tempfile::tempdir().unwrap();
+            let config = SplitConfig {
+                splits: 3,
+                output_dir: output_dir.path().to_path_buf(),
+                preserve_structure,
+                overlap_pages: 1,
+                ..SplitConfig::default()
+            };
+            let result = DocumentSplitter::split_document(&document, &config).await.unwrap();
+            assert_eq!(result.splits[0].overlap_pages, Vec::<usize>::new());
+            assert_eq!(result.splits[1].overlap_pages, [2]);
+            assert_eq!((result.splits[1].start_page, result.splits[1].end_page), (3, 4));
+
+            let second = std::fs::read_to_string(&result.output_files[1]).unwrap();
+            assert!(second.contains("Content of page 2.") && second.contains("Content of page 3."));
+            assert_eq!(second.contains("<!-- overlap from split 1 -->"), preserve_structure);
+
+            // Overlapped pages are not duplicated when the document is rebuilt
+            let metadata_file = result.metadata_file.as_ref().unwrap();
+            let rebuilt = DocumentSplitter::reconstruct(metadata_file, output_dir.path()).await.unwrap();
+            assert_eq!(rebuilt, content);
+        }
+
+        assert_eq!(
+            DocumentSplitter::calculate_overlapping_split_info(6, 3, 1).1,
+            [(1, 2), (2, 4), (4, 6)]
+        );
+
+        let too_wide = SplitConfig {
+            splits: 3,
+            overlap_pages: 2,
+            ..SplitConfig::default()
+        };
+        let err = DocumentSplitter::split_document(&document, &too_wide).await.unwrap_err();
+        assert!(matches!(err, MarkdownSplitterError::SplitConfig { .. }));
+
+        // Repeated pages are not part of the planned budget
+        let budgeted = SplitConfig {
+            strategy: SplitStrategy::MaxWords(70),
+            overlap_pages: 1,
+            ..SplitConfig::default()
+        };
+        let err = DocumentSplitter::split_document(&document, &budgeted).await.unwrap_err();
+        assert!(matches!(err, MarkdownSplitterError::SplitConfig { .. }));
+    }
+
+    #[test]
+    fn test_setext_headings_break_pages() {
+        let content = "Intro text.\n\nFirst Chapter\n=============\n\nBody one.\n\nSection\n-------\n\nBody two.\n\n---\n\nAfter the rule.\n";
+        let parser = MarkdownParser::with_options(ParserOptions {
+            max_merged_lines: Some(1),
+            ..ParserOptions::default()
+        })
+        .unwrap();
+        let document = parser.parse_str(content, "setext.md").unwrap();
+
+        // Breaks land on the heading text, never on its underline; a lone "---" stays a rule
+        let starts: Vec<usize> = document.pages.iter().map(|page| page.start_line).collect();
+        assert_eq!(starts, vec![0, 2, 7, 12]);
+        let titles: Vec<Option<&str>> = document.pages.iter().map(|page| page.title.as_deref()).collect();
+        assert_eq!(titles, vec![None, Some("First Chapter"), Some("Section"), None]);
+
+        // H2 underlines only break pages when level 2 does
+        let h1_only = MarkdownParser::with_options(ParserOptions {
+            max_merged_lines: Some(1),
+            heading_break_levels: Some(vec![1]),
+            ..ParserOptions::default()
+        })
+        .unwrap();
+        let document = h1_only.parse_str(content, "setext.md").unwrap();
+        let starts: Vec<usize> = document.pages.iter().map(|page| page.start_line).collect();
+        assert_eq!(starts, vec![0, 2, 12]);
+    }
+
+    #[tokio::test]
+    async fn test_annotate_only_marks_split_boundaries() {
+        // "# Page N" would itself be a page marker when re-parsing
+        let content = (1..=6)
+            .map(|n| format!("# Chapter {}\n\nText of chapter {}.\n", n, n))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let parser = MarkdownParser::with_options(ParserOptions {
+            max_merged_lines: Some(1),
+            ..ParserOptions::default()
+        })
+        .unwrap();
+        let document = parser.parse_str(&content, "marked.md").unwrap();
+
+        let output_dir = tempfile::tempdir().unwrap();
+        let config = SplitConfig {
+            splits: 3,
+            output_dir: output_dir.path().to_path_buf(),
+            annotate_only: true,
+            ..SplitConfig::default()
+        };
+        let result = DocumentSplitter::split_document(&document, &config).await.unwrap();
+        assert_eq!(result.output_files, [output_dir.path().join("marked_annotated.md")]);
+        assert!(result.splits.is_empty() && result.metadata_file.is_none());
+        assert_eq!(std::fs::read_dir(output_dir.path()).unwrap().count(), 1);
+
+        let annotated = std::fs::read_to_string(&result.output_files[0]).unwrap();
+        assert!(annotated.contains("<!-- SPLIT 2 -->\n# Chapter 3") && annotated.contains("<!-- SPLIT 3 -->\n# Chapter 5"));
+        let unmarked: Vec<&str> = annotated.lines().filter(|line| !line.starts_with("<!-- SPLIT")).collect();
+        assert_eq!(unmarked.join("\n") + "\n", content);
+
+        // Splitting on the custom marker finds the same boundaries again
+        let marked = SplitConfig {
+            custom_page_marker: Some("<!-- cut -->".to_string()),
+            overwrite_policy: OverwritePolicy::Overwrite,
+            ..config
+        };
+        let result = DocumentSplitter::split_document(&document, &marked).await.unwrap();
+        let annotated = std::fs::read_to_string(&result.output_files[0]).unwrap();
+        let reparsed = MarkdownParser::new(Some("<!-- cut -->")).unwrap().parse_str(&annotated, "marked.md").unwrap();
+        let titles: Vec<Option<&str>> = reparsed.pages.iter().map(|page| page.title.as_deref()).collect();
+        assert_eq!(titles, [Some("Chapter 1"), Some("Chapter 3"), Some("Chapter 5")]);
+
+        // Options that only shape split files are rejected rather than ignored
+        let with_code = SplitConfig {
+            extract_code_dir: Some(output_dir.path().join("code")),
+            ..marked.clone()
+        };
+        assert!(DocumentSplitter::split_document(&document, &with_code).await.is_err());
+        let with_template = SplitConfig {
+            filename_template: Some("{base}-{index}.md".to_string()),
+            ..marked.clone()
+        };
+        assert!(DocumentSplitter::split_document(&document, &with_template).await.is_err());
+        assert!(DocumentSplitter::write_data_uri_list(&document, &marked, None).await.is_err());
+    }
+
+    #[test]
+    fn test_page_byte_len_and_word_count() {
+        let content = "# Café\n\nhéllo wörld 日本\n\n# Plain\n\none two three four\n";
+        let parser = MarkdownParser::with_options(ParserOptions {
+            max_merged_lines: Some(1),
+            ..ParserOptions::default()
+        })
+        .unwrap();
+        let document = parser.parse_str(content, "sizes.md").unwrap();
+        assert_eq!(document.pages.len(), 2);
+
+        // Multibyte characters count by their UTF-8 width, not as one each
+        let first = &document.pages[0];
+        assert_eq!(first.byte_len, first.content.len());
+        // é and ö take two bytes each, 日 and 本 three
+        assert_eq!(first.byte_len, first.content.chars().count() + 7);
+        assert_eq!(first.word_count, 5);
+
+        let second = &document.pages[1];
+        assert_eq!(second.byte_len, second.content.len());
+        assert_eq!(second.byte_len, second.content.chars().count());
+        assert_eq!(second.word_count, 6);
+
+        let stats = parser.get_parsing_stats(&document);
+        assert_eq!(stats.page_bytes.min, first.byte_len.min(second.byte_len));
+        assert_eq!(stats.page_bytes.max, first.byte_len.max(second.byte_len));
+        assert_eq!(stats.page_words.min, 5);
+        assert_eq!(stats.page_words.max, 6);
+        assert_eq!(stats.page_words.mean, 5.5);
+
+        // Merged pages add up their sizes, including the joining newline
+        let merging = MarkdownParser::new(None).unwrap();
+        let merged = merging.parse_str("# Long\n\na\nb\nc\nd\ne\nf\n\n---\n\nshort\n", "merged.md").unwrap();
+        assert_eq!(merged.pages.len(), 1);
+        for page in &merged.pages {
+            assert_eq!(page.byte_len, page.content.len());
+            assert_eq!(page.word_count, page.content.split_whitespace().count());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_split_files_keep_line_ending_and_trailing_newline() {
+        assert_eq!(LineEnding::detect("a\nb\n"), LineEnding::Lf);
+        assert_eq!(LineEnding::detect("a\r\nb\r\nc\n"), LineEnding::Crlf);
+        assert_eq!(LineEnding::detect("no line break"), LineEnding::Lf);
+
+        let content = "# One\r\n\r\nFirst.\r\n\r\n# Two\r\n\r\nSecond.\r\n";
+        let parser = MarkdownParser::with_options(ParserOptions {
+            max_merged_lines: Some(1),
+            ..ParserOptions::default()
+        })
+        .unwrap();
+        let document = parser.parse_str(content, "windows.md").unwrap();
+        assert_eq!(document.line_ending, LineEnding::Crlf);
+
+        let output_dir = tempfile::tempdir().unwrap();
+        let config = SplitConfig {
+            splits: 2,
+            output_dir: output_dir.path().to_path_buf(),
+            ..SplitConfig::default()
+        };
+        let result = DocumentSplitter::split_document(&document, &config).await.unwrap();
+        for split in &result.splits {
+            let written = std::fs::read_to_string(&split.path).unwrap();
+            assert_eq!(written.matches('\n').count(), written.matches("\r\n").count());
+            // Recorded sizes count the two-byte line breaks
+            assert_eq!(split.bytes, written.len());
+        }
+        let last = std::fs::read_to_string(&result.splits[1].path).unwrap();
+        assert!(last.ends_with("Second.\r\n"));
+
+        let rebuilt = DocumentSplitter::reconstruct(result.metadata_file.as_ref().unwrap(), output_dir.path())
+            .await
+            .unwrap();
+        assert_eq!(rebuilt, content);
+        let reassembled = DocumentSplitter::reassemble_document(result.metadata_file.as_ref().unwrap(), &parser, None)
+            .await
+            .unwrap();
+        assert_eq!(reassembled.line_ending, LineEnding::Crlf);
+
+        // A forced line ending wins over the source's
+        let lf_dir = tempfile::tempdir().unwrap();
+        let config = SplitConfig {
+            output_dir: lf_dir.path().to_path_buf(),
+            line_ending: LineEnding::Lf,
+            ..config
+        };
+        let result = DocumentSplitter::split_document(&document, &config).await.unwrap();
+        for split in &result.splits {
+            let written = std::fs::read_to_string(&split.path).unwrap();
+            assert!(!written.contains('\r'));
+            assert_eq!(split.bytes, written.len());
+        }
+        let rebuilt = DocumentSplitter::reconstruct(result.metadata_file.as_ref().unwrap(), lf_dir.path())
+            .await
+            .unwrap();
+        assert_eq!(rebuilt, content.replace("\r\n", "\n"));
+    }
+}
@@ -1,53 +1,393 @@
 mod cli;
-mod error;
-mod services;
-mod types;
 
 use anyhow::Context;
 use clap::Parser;
-use cli::{AnalyzeArgs, Cli, Commands, SplitArgs, ValidateArgs};
-use error::{MarkdownSplitterError, Result};
-use services::{ContentFetcher, DocumentSplitter, MarkdownParser};
-use std::collections::HashMap;
-use std::path::PathBuf;
-use tracing::{error, info, Level};
-use tracing_subscriber;
-use types::SplitConfig;
+use cli::{
+    AnalyzeArgs, AppendArgs, BalanceBy, Cli, Commands, ExtractArgs, FormatArgs, LineEndingArg, LocateArgs, OrderBy, LeadingRegionArg, ManifestModeArg, RemainderArg, OutputFormat, ParserArgs, ResplitArgs, SchemaArgs, SchemaKind,
+    SplitArgs, SplitLayoutArgs, SplitLimitArg, SplitOn, TokenModelArg, ValidateArgs,
+};
+use markdown_splitter::{
+    AccessCheck, AnalysisReport, ContentFetcher, DewrapOptions, DocumentMetadata, FirstSentence, Summarizer, FetchOptions, DocumentSplitter, LeadingRegion, LineEnding, ManifestMode, MarkdownDocument,
+    MarkdownPage, MarkdownParser, MarkdownSplitterError, OverwritePolicy, ParserOptions, PlanStrategy, RateLimiter, Result, RemainderPolicy, RotationConfig, SectionLimit, SplitBy,
+    SplitConfig, SplitRunReport, StructureLimits,
+    SplitLimitPolicy, SplitOrder, SplitResult, SplitStrategy, TokenModel, Tokenizer,
+};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tracing::{error, info, info_span, warn, Instrument, Level};
+
+/// File name stem for --content when --output-stem is not given
+const INLINE_STEM: &str = "inline";
+
+/// Exit status when --max-runtime runs out, as used by timeout(1)
+const TIMEOUT_EXIT_CODE: i32 = 124;
+
+/// Exit status when a URL request runs past --timeout, as used by curl
+const FETCH_TIMEOUT_EXIT_CODE: i32 = 28;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let cli = Cli::parse();
+    let mut cli = Cli::parse();
+    if let Err(e) = cli.resolve_env_sources() {
+        e.exit();
+    }
 
-    // Initialize logging
+    // Initialize logging; keep stdout clean for commands that print their result there
     let log_level = if cli.verbose { Level::DEBUG } else { Level::INFO };
-    tracing_subscriber::fmt()
+    let subscriber = tracing_subscriber::fmt()
         .with_max_level(log_level)
-        .with_target(false)
-        .init();
+        .with_target(false);
+    if matches!(cli.command, Commands::Extract(_) | Commands::Schema(_) | Commands::Format(_)) {
+        subscriber.with_writer(std::io::stderr).init();
+    } else {
+        subscriber.init();
+    }
 
     let result = match &cli.command {
         Commands::Split(args) => handle_split_command(args, &cli.output).await,
         Commands::Analyze(args) => handle_analyze_command(args).await,
         Commands::Validate(args) => handle_validate_command(args).await,
+        Commands::Resplit(args) => handle_resplit_command(args, &cli.output).await,
+        Commands::Schema(args) => handle_schema_command(args),
+        Commands::Append(args) => handle_append_command(args, &cli.output).await,
+        Commands::Extract(args) => handle_extract_command(args).await,
+        Commands::Locate(args) => handle_locate_command(args).await,
+        Commands::Format(args) => handle_format_command(args).await,
     };
 
     if let Err(e) = result {
         error!("Operation failed: {}", e);
-        std::process::exit(1);
+        let code = match e {
+            MarkdownSplitterError::RuntimeExceeded { .. } => TIMEOUT_EXIT_CODE,
+            MarkdownSplitterError::FetchTimeout { .. } => FETCH_TIMEOUT_EXIT_CODE,
+            _ => 1,
+        };
+        std::process::exit(code);
     }
 
     Ok(())
 }
 
-async fn handle_split_command(args: &SplitArgs, output_dir: &PathBuf) -> Result<()> {
+async fn handle_split_command(args: &SplitArgs, output_dir: &Path) -> Result<()> {
     info!("Starting split operation with {} sources", args.sources.len());
 
-    // Validate sources first
-    let validated_sources = ContentFetcher::validate_sources(&args.sources).await?;
+    // Expand directories, then validate sources
+    let sources = ContentFetcher::expand_sources(&args.sources, args.follow_symlinks)?;
+    let validated_sources = ContentFetcher::validate_sources(&sources).await?;
     info!("Validated {} sources", validated_sources.len());
+    if args.source_name.is_some() && validated_sources.len() > 1 {
+        return Err(MarkdownSplitterError::SplitConfig {
+            reason: format!(
+                "--source-name names a single source, but {} were given",
+                validated_sources.len()
+            ),
+        });
+    }
+
+    check_output_directory(output_dir, args.force)?;
+
+    let config = SplitConfig {
+        output_dir: output_dir.to_path_buf(),
+        preserve_structure: args.preserve_structure,
+        include_metadata: args.include_metadata,
+        custom_page_marker: args.parsing.page_marker.clone(),
+        include_heading_context: args.heading_context,
+        source_id: args.source_id.clone(),
+        chunk_id_in_header: args.chunk_id_header,
+        wrap_width: args.wrap,
+        source_date_epoch: args.source_date_epoch,
+        generate_toc: args.toc,
+        dry_run: args.dry_run,
+        annotate_only: args.annotate_only,
+        line_ending: match args.line_ending {
+            LineEndingArg::Auto => LineEnding::Auto,
+            LineEndingArg::Lf => LineEnding::Lf,
+            LineEndingArg::Crlf => LineEnding::Crlf,
+        },
+        fix_cross_split_anchors: args.fix_cross_split_anchors,
+        tag_origin: args.tag_origin,
+        write_retries: args.write_retries,
+        extract_code_dir: args.extract_code.clone(),
+        strip_code_blocks: args.drop_code,
+        strip_repeated_boilerplate: args.strip_boilerplate,
+        propagate_comment_metadata: args.propagate_comment_metadata,
+        repeat_front_matter: args.repeat_front_matter,
+        add_part_headings: args.part_headings,
+        part_heading_template: args.part_heading_template.clone(),
+        summarizer: args
+            .summaries
+            .then(|| Arc::new(FirstSentence) as Arc<dyn Summarizer>),
+        overwrite_policy: overwrite_policy(args.force),
+        ..layout_config(&args.layout)
+    };
+
+    let parser = Arc::new(MarkdownParser::with_options(parser_options(&args.parsing))?);
+    let fetch_options = FetchOptions {
+        max_bytes: args.max_file_size.map(|size| size as u64),
+        extract_from: args.extract_from.clone(),
+        dewrap: args.dewrap.then(|| DewrapOptions {
+            preserve_lists: !args.dewrap_lists,
+            ..DewrapOptions::default()
+        }),
+        rate_limit: args.rate_limit.map(|rate| Arc::new(RateLimiter::new(rate))),
+        cache_dir: args.fetch_cache_dir.clone(),
+        write_cache: args.fetch_cache_write,
+        timeout: args.timeout,
+        retries: args.retries,
+        source_name: args.source_name.clone(),
+        ..FetchOptions::default()
+    };
+    let mut all_splits = Vec::new();
+    let mut all_warnings = Vec::new();
+    let mut completed = Vec::new();
+    let mut failures = Vec::new();
+    let mut report = SplitRunReport {
+        dry_run: args.dry_run,
+        ..SplitRunReport::default()
+    };
+
+    // --content comes first and is never given together with sources
+    let inline_source = args.content.as_ref().map(|_| inline_filename(args.output_stem.as_deref()));
+    let sources: Vec<&str> = inline_source
+        .iter()
+        .chain(validated_sources.iter())
+        .map(String::as_str)
+        .collect();
+    let offset = sources.len() - validated_sources.len();
+    let mut results: Vec<Option<(Duration, Result<Option<SplitResult>>)>> = sources.iter().map(|_| None).collect();
+
+    let batch = async {
+        if let Some(content) = &args.content {
+            let started = Instant::now();
+            let content = &inline_content(content, &fetch_options);
+            let result = async {
+                let document = parser.parse_str(content, sources[0])?;
+                split_parsed(&document, args, &config).await
+            }
+            .await;
+            if result.is_ok() {
+                completed.push(sources[0]);
+            }
+            results[0] = Some((started.elapsed(), result));
+        }
+
+        // Split up to --concurrency sources at once; every log line of a source carries
+        // its name, and results are kept in source order whatever order they finish in
+        let shared = Arc::new((args.clone(), config.clone(), fetch_options.clone()));
+        let permits = Arc::new(Semaphore::new(args.concurrency as usize));
+        let total = validated_sources.len();
+        let mut tasks = JoinSet::new();
+        for (idx, source) in validated_sources.iter().enumerate() {
+            let (shared, parser, permits, source) = (shared.clone(), parser.clone(), permits.clone(), source.clone());
+            let span = info_span!("source", name = %source);
+            tasks.spawn(
+                async move {
+                    let _permit = permits.acquire_owned().await.expect("semaphore is never closed");
+                    let (args, config, fetch_options) = &*shared;
+                    info!("Processing source {}/{}: {}", idx + 1, total, source);
+                    let started = Instant::now();
+                    let result = split_source(&source, args, config, &parser, fetch_options).await;
+                    (idx, started.elapsed(), result)
+                }
+                .instrument(span),
+            );
+        }
+
+        while let Some(joined) = tasks.join_next().await {
+            let (idx, elapsed, result) = joined.map_err(|e| MarkdownSplitterError::Io(e.into()))?;
+            if result.is_ok() {
+                completed.push(sources[offset + idx]);
+            }
+            results[offset + idx] = Some((elapsed, result));
+        }
+        Ok(())
+    };
+
+    // Cancel whatever is in flight once the budget runs out, and say what got done
+    let outcome = markdown_splitter::with_time_budget(args.max_runtime, batch).await;
+    if let Err(MarkdownSplitterError::RuntimeExceeded { budget }) = &outcome {
+        warn!(
+            "Stopped after {:?} with {} of {} sources completed",
+            budget,
+            completed.len(),
+            validated_sources.len()
+        );
+        for source in &completed {
+            warn!("  completed: {}", source);
+        }
+    }
+
+    // Sources cut off by an abort are reported as failed with its reason
+    let stopped = outcome.as_ref().err().map(|e| e.to_string());
+    for (&source, result) in sources.iter().zip(results) {
+        match result {
+            Some((elapsed, Ok(result))) => {
+                if let Some(result) = &result {
+                    all_splits.extend(result.splits.iter().cloned());
+                    all_warnings.extend(result.warnings.iter().cloned());
+                }
+                report.record(source, elapsed, Ok(result));
+            }
+            Some((elapsed, Err(e))) => {
+                report.record(source, elapsed, Err(e.to_string()));
+                failures.push((source, e));
+            }
+            None => {
+                if let Some(reason) = &stopped {
+                    report.record(source, Duration::ZERO, Err(reason.clone()));
+                }
+            }
+        }
+    }
+
+    if let Some(report_path) = &args.report {
+        DocumentSplitter::write_run_report(report_path, &report)?;
+        println!(
+            "Report of {} sources ({} files, {} failed) written to {}",
+            report.sources.len(),
+            report.files_written,
+            report.failed,
+            report_path.display()
+        );
+    }
+    outcome?;
+
+    if args.dry_run {
+        println!("Dry run: nothing was written");
+        return split_failures(failures, sources.len());
+    }
+
+    if let Some(csv_path) = &args.csv_index {
+        DocumentSplitter::write_csv_index(csv_path, &all_splits)?;
+    }
+
+    if let (Some(sitemap_path), Some(base_url)) = (&args.sitemap, &args.base_url) {
+        DocumentSplitter::write_sitemap(sitemap_path, base_url, output_dir, &all_splits)?;
+        println!("Sitemap of {} files written to {}", all_splits.len(), sitemap_path.display());
+    }
+
+    if let Some(manifest_path) = &args.manifest {
+        let mode = match args.manifest_mode {
+            ManifestModeArg::Overwrite => ManifestMode::Overwrite,
+            ManifestModeArg::Merge => ManifestMode::Merge,
+        };
+        let manifest = DocumentSplitter::write_manifest(manifest_path, &all_splits, mode, args.source_date_epoch).await?;
+        println!("{} sources recorded in {}", manifest.sources.len(), manifest_path.display());
+    }
+
+    if let Some(report_path) = &args.warnings_report {
+        DocumentSplitter::write_warnings_report(report_path, &all_warnings, args.write_retries).await?;
+        println!("{} warnings written to {}", all_warnings.len(), report_path.display());
+    }
+
+    split_failures(failures, sources.len())?;
+    info!("Split operation completed successfully!");
+    Ok(())
+}
+
+/// Reports every source that failed to split. A lone failure is returned as is so its
+/// exit code survives; several are summed up in `SourcesFailed`.
+fn split_failures(mut failures: Vec<(&str, MarkdownSplitterError)>, total: usize) -> Result<()> {
+    if failures.len() <= 1 {
+        return failures.pop().map_or(Ok(()), |(_, e)| Err(e));
+    }
+    for (source, e) in &failures {
+        error!("Failed to split {}: {}", source, e);
+    }
+    Err(MarkdownSplitterError::SourcesFailed {
+        failed: failures.len(),
+        total,
+    })
+}
+
+async fn handle_resplit_command(args: &ResplitArgs, output_dir: &Path) -> Result<()> {
+    info!("Re-splitting from metadata: {}", args.metadata.display());
 
+    check_output_directory(output_dir, args.force)?;
+
+    let config = SplitConfig {
+        splits: args.splits,
+        output_dir: output_dir.to_path_buf(),
+        preserve_structure: args.preserve_structure,
+        include_metadata: args.include_metadata,
+        custom_page_marker: args.parsing.page_marker.clone(),
+        shard_dirs: args.shards,
+        min_split_bytes: args.min_split_bytes,
+        include_heading_context: args.heading_context,
+        source_id: args.source_id.clone(),
+        chunk_id_in_header: args.chunk_id_header,
+        wrap_width: args.wrap,
+        source_date_epoch: args.source_date_epoch,
+        overwrite_policy: overwrite_policy(args.force),
+        ..SplitConfig::default()
+    };
+
+    let parser = MarkdownParser::with_options(parser_options(&args.parsing))?;
+    let document = DocumentSplitter::reassemble_document(&args.metadata, &parser, args.source_date_epoch).await?;
+    split_and_report(&document, &config, None).await?;
+
+    info!("Re-split operation completed successfully!");
+    Ok(())
+}
+
+/// Apply the same preprocessing to --content that fetched sources get
+fn inline_content(content: &str, fetch_options: &FetchOptions) -> String {
+    match &fetch_options.dewrap {
+        Some(options) => ContentFetcher::dewrap(content, options),
+        None => content.to_string(),
+    }
+}
+
+/// Logical file name for markdown given with --content
+fn inline_filename(stem: Option<&str>) -> String {
+    format!("{}.md", stem.unwrap_or(INLINE_STEM))
+}
+
+fn parser_options(args: &ParserArgs) -> ParserOptions {
+    let split_by = match (&args.breaks, args.blank_lines, args.split_on) {
+        (Some(lines), _, _) => SplitBy::Lines(lines.clone()),
+        (None, Some(min_run), _) => SplitBy::BlankLineRun(min_run),
+        (None, None, SplitOn::Auto) => SplitBy::Auto,
+        (None, None, SplitOn::BareNumbers) => SplitBy::BareNumberLines {
+            max_delta: args.page_number_delta,
+        },
+        (None, None, SplitOn::Toc) => SplitBy::DocumentToc,
+        (None, None, SplitOn::JupyterCells) => SplitBy::JupyterCells,
+        (None, None, SplitOn::Bold) => SplitBy::BoldLines,
+    };
+
+    let leading_region = match args.leading_region {
+        LeadingRegionArg::Prepend => LeadingRegion::Prepend,
+        LeadingRegionArg::Discard => LeadingRegion::Discard,
+        LeadingRegionArg::MergeIntoFirst => LeadingRegion::MergeIntoFirst,
+    };
+
+    ParserOptions {
+        custom_page_marker: args.page_marker.clone(),
+        split_by,
+        leading_region,
+        collapse_blank_runs: args.collapse_blank_lines,
+        max_merged_lines: args.max_merged_lines,
+        strip_cell_delimiters: args.strip_cell_delimiters,
+        min_explicit_markers: args.min_page_markers,
+        multiline_marker: args.multiline_marker.clone(),
+        marker_window: args.marker_window,
+        heading_break_levels: Some(match args.heading_level {
+            Some(deepest) => (1..=deepest as usize).collect(),
+            None => args.heading_break_levels.clone(),
+        }),
+        keep_html_intact: args.keep_html_intact,
+        tab_width: args.tab_width,
+    }
+}
+
+fn check_output_directory(output_dir: &Path, force: bool) -> Result<()> {
     // Check if output directory exists and handle force flag
-    if output_dir.exists() && !args.force {
+    if output_dir.exists() && !force {
         let entries = std::fs::read_dir(output_dir)
             .map_err(|e| MarkdownSplitterError::OutputDirectory {
                 reason: format!("Cannot read output directory: {}", e),
@@ -60,138 +400,289 @@ async fn handle_split_command(args: &SplitArgs, output_dir: &PathBuf) -> Result<
         }
     }
 
-    let config = SplitConfig {
-        splits: args.splits,
-        output_dir: output_dir.clone(),
-        preserve_structure: args.preserve_structure,
-        include_metadata: args.include_metadata,
-        custom_page_marker: args.page_marker.clone(),
-    };
+    Ok(())
+}
 
-    let parser = MarkdownParser::new(config.custom_page_marker.as_deref())?;
+fn split_strategy(max_words: Option<usize>, max_tokens: Option<usize>, model: TokenModelArg) -> SplitStrategy {
+    match (max_words, max_tokens) {
+        (Some(limit), _) => SplitStrategy::MaxWords(limit),
+        (None, Some(limit)) => SplitStrategy::MaxTokens {
+            limit,
+            model: token_model(model),
+        },
+        (None, None) => SplitStrategy::Count,
+    }
+}
 
-    for (idx, source) in validated_sources.iter().enumerate() {
-        info!("Processing source {}/{}: {}", idx + 1, validated_sources.len(), source);
+fn token_model(model: TokenModelArg) -> TokenModel {
+    match model {
+        TokenModelArg::Bpe => TokenModel::Bpe,
+        TokenModelArg::Chars => TokenModel::Chars,
+    }
+}
 
-        // Fetch content
-        let (content, metadata) = ContentFetcher::fetch_content(source).await?;
-        
-        // Parse document
-        let document = parser.parse_document(&content, metadata)?;
-        
-        // Calculate split information
-        let (pages_per_split, split_ranges) = DocumentSplitter::calculate_split_info(
-            document.total_pages, 
-            config.splits
-        );
+fn remainder_policy(remainder: RemainderArg) -> RemainderPolicy {
+    match remainder {
+        RemainderArg::Separate => RemainderPolicy::Separate,
+        RemainderArg::DistributeEarly => RemainderPolicy::DistributeEarly,
+        RemainderArg::DistributeLate => RemainderPolicy::DistributeLate,
+    }
+}
 
+/// The part of the split configuration that decides which split each page lands in
+/// and what the split files are called
+fn layout_config(args: &SplitLayoutArgs) -> SplitConfig {
+    SplitConfig {
+        splits: args.splits,
+        strategy: match args.balance {
+            Some(BalanceBy::Lines) => SplitStrategy::Balanced,
+            _ => split_strategy(args.max_words, args.max_tokens, args.token_model),
+        },
+        shard_dirs: args.shards,
+        min_split_bytes: args.min_split_bytes,
+        max_splits_per_source: args.max_splits_per_source,
+        split_limit_policy: match args.on_split_limit {
+            SplitLimitArg::Error => SplitLimitPolicy::Error,
+            SplitLimitArg::Coarsen => SplitLimitPolicy::Coarsen,
+        },
+        order_by: match args.order_by {
+            OrderBy::Document => SplitOrder::Document,
+            OrderBy::Size => SplitOrder::Size,
+        },
+        reverse_order: args.reverse,
+        title_filter: args.title_filter.clone(),
+        keep_untitled_pages: args.keep_untitled,
+        group_by_field: args.group_by_field.clone(),
+        filename_template: args.filename_template.clone(),
+        overlap_pages: args.overlap_pages,
+        remainder_policy: remainder_policy(args.remainder),
+        section_limit: match (args.section_max_pages, args.section_max_bytes) {
+            (Some(pages), _) => Some(SectionLimit::Pages(pages)),
+            (None, Some(bytes)) => Some(SectionLimit::Bytes(bytes)),
+            (None, None) => None,
+        },
+        bisect_depth: args.bisect_depth,
+        ..SplitConfig::default()
+    }
+}
+
+/// The configuration for one document, with the split count chosen from its size
+/// under --auto-splits
+fn document_config(document: &MarkdownDocument, config: &SplitConfig, args: &SplitLayoutArgs) -> SplitConfig {
+    let mut config = config.clone();
+    if args.auto_splits {
+        config.splits = DocumentSplitter::suggest_split_count(document, args.target_size);
         info!(
-            "Document '{}' has {} pages, will create {} splits with ~{} pages each",
-            document.source, document.total_pages, config.splits, pages_per_split
+            "Auto-selected {} splits for a target size of {} bytes",
+            config.splits, args.target_size
         );
+    }
+    config
+}
 
-        // Print split preview
-        for (split_idx, (start, end)) in split_ranges.iter().enumerate() {
-            info!("  Split {}: Pages {}-{}", split_idx + 1, start, end);
-        }
+/// Page cost for --balance bytes/words; line balancing is a split strategy instead
+fn balance_cost(balance: Option<BalanceBy>) -> Option<fn(&MarkdownPage) -> usize> {
+    match balance {
+        Some(BalanceBy::Bytes) => Some(DocumentSplitter::page_bytes),
+        Some(BalanceBy::Words) => Some(DocumentSplitter::page_words),
+        Some(BalanceBy::Lines) | None => None,
+    }
+}
 
-        // Perform the split
-        let split_result = DocumentSplitter::split_document(&document, &config).await?;
+/// Existing split files are only replaced with --force
+fn overwrite_policy(force: bool) -> OverwritePolicy {
+    if force {
+        OverwritePolicy::Overwrite
+    } else {
+        OverwritePolicy::Error
+    }
+}
 
-        // Report results
-        info!(
-            "Successfully created {} split files for '{}':",
-            split_result.output_files.len(), 
-            document.source
-        );
+/// Fetches, parses and splits one source
+async fn split_source(
+    source: &str,
+    args: &SplitArgs,
+    config: &SplitConfig,
+    parser: &MarkdownParser,
+    fetch_options: &FetchOptions,
+) -> Result<Option<SplitResult>> {
+    // Fetch content
+    let (content, metadata) = ContentFetcher::fetch_content_with(source, fetch_options).await?;
+
+    // Parse document
+    let document = parser.parse_document(&content, metadata)?;
+    split_parsed(&document, args, config).await
+}
+
+/// Splits a parsed document per the split arguments. There is no `SplitResult` for
+/// `--format data-uri`, which writes a list of data URIs instead of split files.
+async fn split_parsed(
+    document: &MarkdownDocument,
+    args: &SplitArgs,
+    config: &SplitConfig,
+) -> Result<Option<SplitResult>> {
+    let config = document_config(document, config, &args.layout);
+
+    if args.format == OutputFormat::DataUri {
+        let cost_fn = balance_cost(args.layout.balance);
+        let list_path = DocumentSplitter::write_data_uri_list(
+            document,
+            &config,
+            cost_fn.as_ref().map(|f| f as &dyn Fn(&MarkdownPage) -> usize),
+        )
+        .await?;
+        info!("  - {} (data URIs)", list_path.display());
+        return Ok(None);
+    }
+
+    Ok(Some(split_and_report(document, &config, args.layout.balance).await?))
+}
 
-        for output_file in &split_result.output_files {
-            info!("  - {}", output_file.display());
+async fn split_and_report(
+    document: &MarkdownDocument,
+    config: &SplitConfig,
+    balance: Option<BalanceBy>,
+) -> Result<SplitResult> {
+    // Preview the files exactly as the split will plan them
+    let cost_fn = balance_cost(balance);
+    let plan = DocumentSplitter::plan(
+        document,
+        config,
+        cost_fn.as_ref().map(|f| f as &dyn Fn(&MarkdownPage) -> usize),
+    )?;
+    let layout = match plan.strategy {
+        PlanStrategy::EvenPages => {
+            format!("with ~{} pages each", plan.total_pages.div_ceil(plan.files.len().max(1)))
+        }
+        PlanStrategy::Balanced => "with balanced page ranges".to_string(),
+        PlanStrategy::Sections => "along H1 sections".to_string(),
+        PlanStrategy::Bisect => format!("by bisecting to depth {}", config.bisect_depth.unwrap_or_default()),
+        PlanStrategy::Packed => match config.strategy {
+            SplitStrategy::MaxTokens { limit, .. } => format!("of at most {} tokens each", limit),
+            SplitStrategy::MaxWords(limit) => format!("of at most {} words each", limit),
+            _ => "packed to a budget".to_string(),
+        },
+        PlanStrategy::Group => {
+            format!("one per '{}' value", config.group_by_field.as_deref().unwrap_or_default())
         }
+    };
+    info!(
+        "Document '{}' has {} pages, will create {} splits {}",
+        document.source, plan.total_pages, plan.files.len(), layout
+    );
+    for file in &plan.files {
+        match plan.strategy {
+            // Grouped files gather their pages from all over the document
+            PlanStrategy::Group => info!("  Split {}: {}", file.split_number, file.filename),
+            _ => info!("  Split {}: Pages {}-{}", file.split_number, file.start_page, file.end_page),
+        }
+    }
 
-        if let Some(metadata_file) = &split_result.metadata_file {
-            info!("  - {} (metadata)", metadata_file.display());
+    // Perform the split
+    let split_result = match cost_fn {
+        Some(cost_fn) => DocumentSplitter::split_document_balanced(document, config, cost_fn).await?,
+        None => DocumentSplitter::split_document(document, config).await?,
+    };
+
+    if config.annotate_only {
+        let markers = split_result.split_number.saturating_sub(1);
+        let annotated = split_result.output_files[0].display();
+        if config.dry_run {
+            println!("{} would be annotated with {} split markers in {}", document.source, markers, annotated);
+        } else {
+            info!("Annotated '{}' with {} split markers: {}", document.source, markers, annotated);
         }
+        return Ok(split_result);
     }
 
-    info!("Split operation completed successfully!");
-    Ok(())
+    if config.dry_run {
+        println!("{} would be split into {} files:", document.source, split_result.output_files.len());
+        for info in &split_result.splits {
+            println!(
+                "  {} (pages {}-{}, {} bytes)",
+                Path::new(&info.path).display(),
+                info.start_page,
+                info.end_page,
+                info.bytes
+            );
+        }
+        for (path, label) in [(&split_result.metadata_file, "metadata"), (&split_result.toc_file, "table of contents")] {
+            if let Some(path) = path {
+                println!("  {} ({})", path.display(), label);
+            }
+        }
+        return Ok(split_result);
+    }
+
+    // Report results
+    info!(
+        "Successfully created {} split files for '{}':",
+        split_result.output_files.len(), 
+        document.source
+    );
+
+    for output_file in &split_result.output_files {
+        info!("  - {}", output_file.display());
+    }
+
+    if let Some(metadata_file) = &split_result.metadata_file {
+        info!("  - {} (metadata)", metadata_file.display());
+    }
+    if let Some(toc_file) = &split_result.toc_file {
+        info!("  - {} (table of contents)", toc_file.display());
+    }
+
+    Ok(split_result)
 }
 
 async fn handle_analyze_command(args: &AnalyzeArgs) -> Result<()> {
     info!("Starting analysis of {} sources", args.sources.len());
 
-    let validated_sources = ContentFetcher::validate_sources(&args.sources).await?;
-    let parser = MarkdownParser::new(args.page_marker.as_deref())?;
+    let sources = ContentFetcher::expand_sources(&args.sources, args.follow_symlinks)?;
+    let validated_sources = ContentFetcher::validate_sources(&sources).await?;
+    let parser = MarkdownParser::with_options(parser_options(&args.parsing))?;
+    let fetch_options = FetchOptions {
+        max_bytes: args.max_file_size.map(|size| size as u64),
+        extract_from: args.extract_from.clone(),
+        dewrap: args.dewrap.then(|| DewrapOptions {
+            preserve_lists: !args.dewrap_lists,
+            ..DewrapOptions::default()
+        }),
+        rate_limit: args.rate_limit.map(|rate| Arc::new(RateLimiter::new(rate))),
+        cache_dir: args.fetch_cache_dir.clone(),
+        write_cache: args.fetch_cache_write,
+        timeout: args.timeout,
+        retries: args.retries,
+        ..FetchOptions::default()
+    };
     
-    let mut all_analyses = HashMap::new();
+    let mut all_analyses = BTreeMap::new();
+    let limits = StructureLimits {
+        min_pages: args.min_pages,
+        max_page_bytes: args.max_page_bytes,
+        require_titles: args.require_titles,
+    };
+
+    if let Some(content) = &args.content {
+        let content = &inline_content(content, &fetch_options);
+        let filename = inline_filename(args.output_stem.as_deref());
+        let metadata = MarkdownParser::str_metadata(content, &filename);
+        all_analyses.insert(filename, analyze_content(args, &parser, &limits, content, metadata)?);
+    }
 
     for source in validated_sources {
         info!("Analyzing: {}", source);
 
-        let (content, metadata) = ContentFetcher::fetch_content(&source).await?;
-        let document = parser.parse_document(&content, metadata)?;
-        let stats = parser.get_parsing_stats(&document);
-
-        // Print analysis to console
-        println!("\n=== Analysis for '{}' ===", document.source);
-        println!("Source type: {:?}", document.metadata.source_type);
-        println!("Total pages: {}", document.total_pages);
-        println!("Total lines: {}", document.metadata.total_lines);
-        println!("Page breaks found: {}", document.metadata.page_breaks.len());
-        
-        if let Some(avg_lines) = stats.get("avg_lines_per_page") {
-            println!("Average lines per page: {:.1}", avg_lines.as_f64().unwrap_or(0.0));
-        }
-        
-        if let Some(titled_pages) = stats.get("pages_with_titles") {
-            println!("Pages with titles: {}", titled_pages.as_u64().unwrap_or(0));
-        }
-
-        if args.detailed {
-            println!("\nPage Details:");
-            for page in &document.pages {
-                let title_info = page.title.as_ref()
-                    .map(|t| format!(" ({})", t))
-                    .unwrap_or_default();
-                println!(
-                    "  Page {}: Lines {}-{} ({} lines){}",
-                    page.number,
-                    page.start_line + 1,
-                    page.end_line,
-                    page.end_line - page.start_line,
-                    title_info
-                );
-            }
-        }
-
-        // Calculate potential splits
-        println!("\nPotential Split Scenarios:");
-        for splits in [2, 3, 5, 10] {
-            if splits <= document.total_pages {
-                let (pages_per_split, ranges) = DocumentSplitter::calculate_split_info(
-                    document.total_pages, 
-                    splits
-                );
-                println!("  {} splits: ~{} pages per split", splits, pages_per_split);
-                if args.detailed {
-                    for (idx, (start, end)) in ranges.iter().enumerate() {
-                        println!("    Split {}: Pages {}-{}", idx + 1, start, end);
-                    }
-                }
-            }
-        }
+        let (content, metadata) = ContentFetcher::fetch_content_with(&source, &fetch_options).await?;
+        let report = analyze_content(args, &parser, &limits, &content, metadata)?;
 
         // Store for JSON output
-        all_analyses.insert(source.clone(), serde_json::json!({
-            "document": document,
-            "stats": stats
-        }));
+        all_analyses.insert(source.clone(), report);
     }
 
     // Write JSON output if requested
     if let Some(json_path) = &args.json_output {
-        let json_content = serde_json::to_string_pretty(&all_analyses)
+        let json_content = markdown_splitter::analysis_json(&all_analyses)
             .context("Failed to serialize analysis results")?;
         
         tokio::fs::write(json_path, json_content).await
@@ -200,33 +691,168 @@ async fn handle_analyze_command(args: &AnalyzeArgs) -> Result<()> {
         info!("Analysis results written to: {}", json_path.display());
     }
 
+    let failing: Vec<_> = all_analyses.iter().filter(|(_, report)| !report.violations.is_empty()).collect();
+    if !failing.is_empty() {
+        println!("\n=== Structure Check Failed ===");
+        for (source, report) in &failing {
+            println!("{}:", source);
+            for violation in &report.violations {
+                println!("  - {}", violation);
+            }
+        }
+        return Err(MarkdownSplitterError::InvalidMarkdown {
+            reason: format!("{} sources failed the structure check", failing.len()),
+        });
+    }
+
     Ok(())
 }
 
+/// Analyzes one document and prints the report to the console
+fn analyze_content(
+    args: &AnalyzeArgs,
+    parser: &MarkdownParser,
+    limits: &StructureLimits,
+    content: &str,
+    metadata: DocumentMetadata,
+) -> Result<AnalysisReport> {
+    let mut report = parser.analyze(content, metadata)?;
+    if args.explain_merges {
+        report.merges = parser.explain_merges(content)?;
+    }
+    report.violations = MarkdownParser::check_structure(&report.document, limits);
+    let (document, stats) = (&report.document, &report.stats);
+
+    // Print analysis to console
+    println!("\n=== Analysis for '{}' ===", document.source);
+    println!("Source type: {:?}", document.metadata.source_type);
+    println!("Total pages: {}", document.total_pages);
+    println!("Total lines: {}", document.metadata.total_lines);
+    println!("Page breaks found: {}", document.metadata.page_breaks.len());
+    
+    println!("Average lines per page: {:.1}", stats.avg_lines_per_page);
+    println!("Pages with titles: {}", stats.pages_with_titles);
+    println!(
+        "Page size: {}-{} bytes (average {:.0}), {}-{} words (average {:.0})",
+        stats.page_bytes.min,
+        stats.page_bytes.max,
+        stats.page_bytes.mean,
+        stats.page_words.min,
+        stats.page_words.max,
+        stats.page_words.mean
+    );
+    if let Some(language) = &document.detected_language {
+        println!("Detected language: {}", language);
+    }
+
+    if args.detailed {
+        println!("\nPage Details:");
+        let tokenizer = Tokenizer::new(token_model(args.token_model));
+        for page in &document.pages {
+            let title_info = page.title.as_ref()
+                .map(|t| format!(" ({})", t))
+                .unwrap_or_default();
+            println!(
+                "  Page {}: Lines {}-{} ({} lines, {} bytes, {} words, ~{} tokens){}",
+                page.number,
+                page.start_line + 1,
+                page.end_line,
+                page.end_line - page.start_line,
+                page.byte_len,
+                page.word_count,
+                DocumentSplitter::page_tokens(page, &tokenizer),
+                title_info
+            );
+        }
+    }
+
+    if args.explain_merges {
+        println!("\nMerged Pages:");
+        if report.merges.is_empty() {
+            println!("  None");
+        }
+        for merge in &report.merges {
+            println!(
+                "  Raw page {} -> page {}: {}",
+                merge.raw_page, merge.into_page, merge.reason
+            );
+        }
+    }
+
+    // Calculate potential splits
+    println!("\nPotential Split Scenarios:");
+    for splits in [2, 3, 5, 10] {
+        if splits <= document.total_pages {
+            let (pages_per_split, ranges) = DocumentSplitter::calculate_overlapping_split_info(
+                document.total_pages,
+                splits,
+                args.overlap_pages,
+            );
+            println!("  {} splits: ~{} pages per split", splits, pages_per_split);
+            if args.detailed {
+                for (idx, (start, end)) in ranges.iter().enumerate() {
+                    println!("    Split {}: Pages {}-{}", idx + 1, start, end);
+                }
+            }
+        }
+    }
+    let budgets = [
+        args.max_words.map(|limit| (SplitStrategy::MaxWords(limit), format!("{} words", limit))),
+        args.max_tokens.map(|limit| {
+            let strategy = split_strategy(None, Some(limit), args.token_model);
+            (strategy, format!("{} tokens", limit))
+        }),
+    ];
+    for (strategy, budget) in budgets.into_iter().flatten() {
+        let config = SplitConfig {
+            strategy,
+            overlap_pages: args.overlap_pages,
+            ..SplitConfig::default()
+        };
+        let ranges = DocumentSplitter::calculate_strategy_split_info(document, &config)?;
+        println!("  at most {}: {} splits", budget, ranges.len());
+        if args.detailed {
+            for (idx, (start, end)) in ranges.iter().enumerate() {
+                println!("    Split {}: Pages {}-{}", idx + 1, start, end);
+            }
+        }
+    }
+
+    Ok(report)
+}
+
 async fn handle_validate_command(args: &ValidateArgs) -> Result<()> {
     info!("Validating {} sources", args.sources.len());
 
     let mut valid_sources = Vec::new();
     let mut invalid_sources = Vec::new();
+    let mut access_checks = Vec::new();
+    let fetch_options = FetchOptions {
+        timeout: args.timeout,
+        retries: args.retries,
+        ..FetchOptions::default()
+    };
 
     for source in &args.sources {
-        match ContentFetcher::validate_sources(&[source.clone()]).await {
+        match ContentFetcher::validate_sources(std::slice::from_ref(source)).await {
             Ok(_) => {
                 info!("✓ Valid: {}", source);
                 valid_sources.push(source);
                 
                 if args.check_access {
-                    // Try to actually fetch a small portion of the content
-                    match ContentFetcher::fetch_content(source).await {
-                        Ok((content, _)) => {
-                            let lines = content.lines().count();
-                            info!("  Accessible, {} lines found", lines);
-                        }
-                        Err(e) => {
-                            error!("  Cannot access content: {}", e);
-                            invalid_sources.push((source, format!("Access error: {}", e)));
-                        }
+                    let check = ContentFetcher::check_access(source, &fetch_options).await;
+                    if check.reachable {
+                        info!("  Accessible{}", access_detail(&check));
+                    } else {
+                        let reason = match (&check.error, check.status) {
+                            (Some(e), _) => e.clone(),
+                            (None, Some(status)) => format!("HTTP status {}", status),
+                            (None, None) => "unreachable".to_string(),
+                        };
+                        error!("  Cannot access content: {}", reason);
+                        invalid_sources.push((source, format!("Access error: {}", reason)));
                     }
+                    access_checks.push(check);
                 }
             }
             Err(e) => {
@@ -238,6 +864,23 @@ async fn handle_validate_command(args: &ValidateArgs) -> Result<()> {
 
     println!("\n=== Validation Summary ===");
     println!("Valid sources: {}/{}", valid_sources.len(), args.sources.len());
+
+    if !access_checks.is_empty() {
+        println!("Access checks:");
+        for check in &access_checks {
+            let status = check.status.map_or("-".to_string(), |status| status.to_string());
+            let mark = if check.reachable { "✓" } else { "✗" };
+            println!("  {} {:>3} {}{}", mark, status, check.source, access_detail(check));
+        }
+    }
+
+    if let Some(json_path) = &args.json_output {
+        let json_content = serde_json::to_string_pretty(&access_checks)
+            .context("Failed to serialize access checks")?;
+        tokio::fs::write(json_path, json_content).await
+            .context("Failed to write JSON access check file")?;
+        info!("Access checks written to: {}", json_path.display());
+    }
     
     if !invalid_sources.is_empty() {
         println!("Invalid sources:");
@@ -252,4 +895,101 @@ async fn handle_validate_command(args: &ValidateArgs) -> Result<()> {
 
     println!("All sources are valid!");
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Method note for an access check, e.g. " (HEAD)"
+fn access_detail(check: &AccessCheck) -> String {
+    check.method.as_ref().map_or(String::new(), |method| format!(" ({})", method))
+}
+
+fn handle_schema_command(args: &SchemaArgs) -> Result<()> {
+    let schema = match args.kind {
+        SchemaKind::Metadata => markdown_splitter::metadata_schema(),
+        SchemaKind::Analysis => markdown_splitter::analysis_schema(),
+    };
+
+    let json = serde_json::to_string_pretty(&schema).context("Failed to serialize schema")?;
+    println!("{}", json);
+    Ok(())
+}
+
+async fn handle_extract_command(args: &ExtractArgs) -> Result<()> {
+    let (first, last) = args.pages.or(args.page.map(|page| (page, page))).unwrap_or((1, 1));
+
+    let (content, metadata) = ContentFetcher::fetch_content(&args.source).await?;
+    let parser = MarkdownParser::with_options(parser_options(&args.parsing))?;
+    let document = parser.parse_document(&content, metadata)?;
+    let extracted = DocumentSplitter::extract_page_range(&document, first, last)?;
+
+    match &args.out {
+        Some(path) => {
+            tokio::fs::write(path, format!("{}\n", extracted)).await?;
+            info!("Pages {}-{} written to: {}", first, last, path.display());
+        }
+        None => println!("{}", extracted),
+    }
+
+    Ok(())
+}
+
+async fn handle_locate_command(args: &LocateArgs) -> Result<()> {
+    let (content, metadata) = ContentFetcher::fetch_content(&args.source).await?;
+    let parser = MarkdownParser::with_options(parser_options(&args.parsing))?;
+    let document = parser.parse_document(&content, metadata)?;
+    let config = SplitConfig {
+        custom_page_marker: args.parsing.page_marker.clone(),
+        ..layout_config(&args.layout)
+    };
+    let config = document_config(&document, &config, &args.layout);
+    let cost_fn = balance_cost(args.layout.balance);
+
+    let location = DocumentSplitter::locate_line(
+        &document,
+        &config,
+        cost_fn.as_ref().map(|f| f as &dyn Fn(&MarkdownPage) -> usize),
+        args.line,
+    )?;
+    println!(
+        "Line {}: page {}, split {} ({})",
+        location.line, location.page, location.split_number, location.filename
+    );
+    Ok(())
+}
+
+async fn handle_format_command(args: &FormatArgs) -> Result<()> {
+    let (content, metadata) = ContentFetcher::fetch_content(&args.source).await?;
+    let parser = MarkdownParser::with_options(parser_options(&args.parsing))?;
+    let document = parser.parse_document(&content, metadata)?;
+    let formatted = parser.format_document(&document);
+
+    match &args.out {
+        Some(path) => {
+            tokio::fs::write(path, &formatted).await?;
+            info!("Formatted document written to: {}", path.display());
+        }
+        None => print!("{}", formatted),
+    }
+
+    Ok(())
+}
+
+async fn handle_append_command(args: &AppendArgs, output_dir: &Path) -> Result<()> {
+    let validated_sources = ContentFetcher::validate_sources(&args.sources).await?;
+    let parser = MarkdownParser::with_options(parser_options(&args.parsing))?;
+    let config = RotationConfig {
+        output_dir: output_dir.to_path_buf(),
+        base_name: args.name.clone(),
+        max_bytes: args.max_size,
+    };
+
+    for source in &validated_sources {
+        let (content, metadata) = ContentFetcher::fetch_content(source).await?;
+        let document = parser.parse_document(&content, metadata)?;
+
+        for path in DocumentSplitter::append_to_rotating(&document, &config).await? {
+            info!("  - {}", path.display());
+        }
+    }
+
+    Ok(())
+}
@@ -1,26 +1,320 @@
 use crate::error::{MarkdownSplitterError, Result};
-use crate::types::{DocumentMetadata, SourceType};
-use std::path::Path;
+use crate::services::MarkdownParser;
+use crate::types::{
+    AccessCheck, Compression, ContentSelector, DewrapOptions, DocumentMetadata, FetchOptions, PathStep, SourceType,
+};
+use flate2::read::MultiGzDecoder;
+use percent_encoding::percent_decode_str;
+use sha2::{Digest, Sha256};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use tokio::fs;
-use tracing::{info, warn};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+use tracing::{debug, info, warn};
 use url::Url;
+use walkdir::WalkDir;
+
+/// File extensions picked up when a directory is given as a source
+const MARKDOWN_EXTENSIONS: [&str; 2] = ["md", "markdown"];
+
+/// Sources read from standard input instead of a file or URL
+const STDIN_SOURCES: [&str; 2] = ["-", "/dev/stdin"];
+
+/// Filename given to documents read from standard input
+const STDIN_FILENAME: &str = "stdin.md";
+
+/// HEAD statuses meaning the server does not support HEAD rather than that the
+/// resource is missing; the check retries with a ranged GET
+const HEAD_UNSUPPORTED: [u16; 2] = [405, 501];
+
+/// URL path suffixes of compressed sources, used when the response has no
+/// `Content-Encoding`
+const COMPRESSED_EXTENSIONS: [(&str, Compression); 2] = [(".gz", Compression::Gzip), (".br", Compression::Brotli)];
+
+/// Internal buffer of the brotli decoder
+const BROTLI_BUFFER_SIZE: usize = 4096;
+
+/// Wait before the first retry of a failed URL request when none is configured
+const DEFAULT_RETRY_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Longest wait between two rate-limited requests
+const MAX_RATE_INTERVAL: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+/// Token bucket holding a single token: requests go out at most once per interval,
+/// and callers wait for their turn in the order they asked
+#[derive(Debug)]
+pub struct RateLimiter {
+    interval: Duration,
+    next_slot: Mutex<Option<Instant>>,
+}
+
+impl RateLimiter {
+    /// Allows `per_second` requests per second, which may be fractional (0.5 is one
+    /// request every two seconds). Intervals are capped at a year, so tiny rates
+    /// cannot overflow the schedule.
+    pub fn new(per_second: f64) -> Self {
+        Self {
+            interval: Duration::try_from_secs_f64(1.0 / per_second)
+                .unwrap_or(MAX_RATE_INTERVAL)
+                .min(MAX_RATE_INTERVAL),
+            next_slot: Mutex::new(None),
+        }
+    }
+
+    /// Waits until the next request may be sent
+    pub async fn acquire(&self) {
+        let slot = {
+            let mut next_slot = self.next_slot.lock().await;
+            let now = Instant::now();
+            let slot = next_slot.map_or(now, |next| next.max(now));
+            *next_slot = Some(slot + self.interval);
+            slot
+        };
+        tokio::time::sleep_until(slot).await;
+    }
+}
 
 pub struct ContentFetcher;
 
 impl ContentFetcher {
     pub async fn fetch_content(source: &str) -> Result<(String, DocumentMetadata)> {
-        if Self::is_url(source) {
-            Self::fetch_from_url(source).await
+        Self::fetch_content_with(source, &FetchOptions::default()).await
+    }
+
+    pub async fn fetch_content_with(
+        source: &str,
+        options: &FetchOptions,
+    ) -> Result<(String, DocumentMetadata)> {
+        let (content, mut metadata) = if Self::is_stdin(source) {
+            Self::fetch_from_reader(tokio::io::stdin(), source, options).await?
+        } else if Self::is_url(source) {
+            Self::fetch_from_url(source, options).await?
         } else {
-            Self::fetch_from_file(source).await
+            Self::fetch_from_file(source, options).await?
+        };
+        if let Some(name) = &options.source_name {
+            metadata.filename = name.clone();
         }
+
+        let content = match &options.extract_from {
+            Some(selector) => Self::extract_embedded(&content, selector).map_err(|reason| {
+                MarkdownSplitterError::InvalidMarkdown {
+                    reason: format!("Cannot extract markdown from {}: {}", source, reason),
+                }
+            })?,
+            None => content,
+        };
+
+        let content = match &options.dewrap {
+            Some(dewrap) => Self::dewrap(&content, dewrap),
+            None => content,
+        };
+        metadata.total_lines = content.lines().count();
+        Ok((content, metadata))
+    }
+
+    /// Joins hard-wrapped lines (as left by PDF and OCR conversions) back into
+    /// paragraphs: a line without terminal punctuation is joined with the next one,
+    /// unless either is blank or structural (heading, list item, rule, table row,
+    /// page number, fenced code) as configured in `options`.
+    pub fn dewrap(content: &str, options: &DewrapOptions) -> String {
+        let lines: Vec<&str> = content.lines().collect();
+        let mut is_fence = vec![false; lines.len()];
+        let mut fenced = vec![false; lines.len()];
+        for block in MarkdownParser::fenced_blocks(&lines) {
+            is_fence[block.start] = true;
+            is_fence[block.end - 1] = true;
+            fenced[block].fill(true);
+        }
+        let mut output: Vec<String> = Vec::new();
+        // Whether the last output line may take the next line onto its end
+        let mut open = false;
+        let mut in_list_item = false;
+
+        for (line_idx, line) in lines.into_iter().enumerate() {
+            let trimmed = line.trim();
+            let is_fence = is_fence[line_idx];
+            if options.preserve_code && fenced[line_idx] {
+                output.push(line.to_string());
+                open = false;
+                continue;
+            }
+
+            let is_heading = trimmed.starts_with('#');
+            let is_list_item = Self::is_list_item(trimmed);
+            let structural = trimmed.is_empty()
+                || is_fence
+                || trimmed.starts_with('|')
+                || trimmed.starts_with('<')
+                || trimmed.starts_with('>')
+                || trimmed.chars().all(|c| matches!(c, '-' | '*' | '_' | '=' | ' '))
+                || Self::is_page_number_line(trimmed)
+                || (options.preserve_headings && is_heading);
+
+            // Headings and list items always start a line of their own
+            match output.last_mut() {
+                Some(previous) if open && !structural && !is_list_item && !is_heading => {
+                    // Rejoin a word hyphenated across the break
+                    if previous.ends_with('-') && trimmed.starts_with(|c: char| c.is_lowercase()) {
+                        previous.pop();
+                    } else {
+                        previous.push(' ');
+                    }
+                    previous.push_str(trimmed);
+                }
+                _ => {
+                    output.push(line.to_string());
+                    in_list_item = is_list_item;
+                }
+            }
+
+            let last = output.last().map(|line| line.trim_end()).unwrap_or_default();
+            let hard_break = line.ends_with("  ") || last.ends_with('\\');
+            let ends_sentence = last
+                .trim_end_matches(['"', '\'', ')', ']', '*', '_', '”', '’'])
+                .ends_with(['.', '!', '?', ':', ';']);
+            open = !(structural || hard_break || ends_sentence || (options.preserve_lists && in_list_item));
+        }
+
+        let mut dewrapped = output.join("\n");
+        if content.ends_with('\n') {
+            dewrapped.push('\n');
+        }
+        dewrapped
+    }
+
+    fn is_list_item(trimmed: &str) -> bool {
+        let bullet = ["- ", "* ", "+ "].iter().any(|marker| trimmed.starts_with(marker));
+        let digits = trimmed.chars().take_while(char::is_ascii_digit).count();
+        let numbered = digits > 0 && (trimmed[digits..].starts_with(". ") || trimmed[digits..].starts_with(") "));
+        bullet || numbered
+    }
+
+    /// "12", "Page 12" or "(Page 12)" on a line of its own
+    fn is_page_number_line(trimmed: &str) -> bool {
+        let inner = trimmed.trim_start_matches('(').trim_end_matches(')');
+        let number = match inner.get(..5) {
+            Some(prefix) if prefix.eq_ignore_ascii_case("page ") => inner[5..].trim_start(),
+            _ => inner,
+        };
+        !number.is_empty() && number.chars().all(|c| c.is_ascii_digit())
+    }
+
+    /// Parses an `--extract-from` selector: `$`-rooted paths select from JSON or YAML,
+    /// anything else is a regex
+    pub fn parse_selector(selector: &str) -> Result<ContentSelector> {
+        let invalid = |reason: String| MarkdownSplitterError::InvalidMarkdown {
+            reason: format!("Invalid content selector '{}': {}", selector, reason),
+        };
+
+        let Some(mut rest) = selector.strip_prefix('$') else {
+            return MarkdownParser::compile_user_pattern(selector)
+                .map(ContentSelector::Pattern)
+                .map_err(invalid);
+        };
+
+        let mut steps = Vec::new();
+        while !rest.is_empty() {
+            if let Some(after_dot) = rest.strip_prefix('.') {
+                let end = after_dot.find(['.', '[']).unwrap_or(after_dot.len());
+                let step = match &after_dot[..end] {
+                    "" => return Err(invalid("empty key".to_string())),
+                    "*" => PathStep::Wildcard,
+                    key => PathStep::Key(key.to_string()),
+                };
+                steps.push(step);
+                rest = &after_dot[end..];
+            } else if let Some(after_bracket) = rest.strip_prefix('[') {
+                let end = after_bracket
+                    .find(']')
+                    .ok_or_else(|| invalid("unclosed '['".to_string()))?;
+                let inner = after_bracket[..end].trim();
+                let quoted = inner
+                    .strip_prefix('\'')
+                    .and_then(|s| s.strip_suffix('\''))
+                    .or_else(|| inner.strip_prefix('"').and_then(|s| s.strip_suffix('"')));
+                let step = match (inner, quoted) {
+                    (_, Some(key)) => PathStep::Key(key.to_string()),
+                    ("*", None) => PathStep::Wildcard,
+                    (index, None) => PathStep::Index(
+                        index.parse().map_err(|_| invalid(format!("bad index '{}'", index)))?,
+                    ),
+                };
+                steps.push(step);
+                rest = &after_bracket[end + 1..];
+            } else {
+                return Err(invalid(format!("unexpected '{}'", rest)));
+            }
+        }
+
+        Ok(ContentSelector::Path(steps))
+    }
+
+    /// The markdown selected from a wrapper document; several selections are joined by
+    /// a blank line
+    fn extract_embedded(content: &str, selector: &ContentSelector) -> std::result::Result<String, String> {
+        let parts: Vec<String> = match selector {
+            ContentSelector::Pattern(pattern) => pattern
+                .captures_iter(content)
+                .filter_map(|captures| captures.get(1).or_else(|| captures.get(0)))
+                .map(|m| m.as_str().to_string())
+                .collect(),
+            ContentSelector::Path(steps) => {
+                // YAML covers JSON too, but JSON's own parser gives clearer errors
+                let document: serde_json::Value = match serde_json::from_str(content) {
+                    Ok(value) => value,
+                    Err(json_error) => serde_yaml::from_str(content).map_err(|yaml_error| {
+                        format!("neither JSON ({}) nor YAML ({})", json_error, yaml_error)
+                    })?,
+                };
+
+                let mut selected = vec![&document];
+                for step in steps {
+                    selected = selected
+                        .into_iter()
+                        .flat_map(|value| -> Vec<&serde_json::Value> {
+                            match (step, value) {
+                                (PathStep::Key(key), value) => value.get(key).into_iter().collect(),
+                                (PathStep::Index(index), value) => value.get(index).into_iter().collect(),
+                                (PathStep::Wildcard, serde_json::Value::Array(items)) => items.iter().collect(),
+                                (PathStep::Wildcard, serde_json::Value::Object(map)) => map.values().collect(),
+                                (PathStep::Wildcard, _) => Vec::new(),
+                            }
+                        })
+                        .collect();
+                }
+
+                selected
+                    .into_iter()
+                    .map(|value| match value {
+                        serde_json::Value::String(text) => Ok(text.clone()),
+                        other => Err(format!("selected value is not a string: {}", other)),
+                    })
+                    .collect::<std::result::Result<_, _>>()?
+            }
+        };
+
+        if parts.is_empty() {
+            return Err("the selector matched nothing".to_string());
+        }
+        Ok(parts.join("\n\n"))
     }
 
     pub async fn fetch_multiple(sources: &[String]) -> Result<Vec<(String, DocumentMetadata)>> {
+        Self::fetch_multiple_with(sources, &FetchOptions::default()).await
+    }
+
+    pub async fn fetch_multiple_with(
+        sources: &[String],
+        options: &FetchOptions,
+    ) -> Result<Vec<(String, DocumentMetadata)>> {
         let mut results = Vec::new();
         
         for source in sources {
-            match Self::fetch_content(source).await {
+            match Self::fetch_content_with(source, options).await {
                 Ok(content) => {
                     info!("Successfully fetched content from: {}", source);
                     results.push(content);
@@ -35,21 +329,38 @@ impl ContentFetcher {
         Ok(results)
     }
 
-    async fn fetch_from_url(url: &str) -> Result<(String, DocumentMetadata)> {
+    async fn fetch_from_url(url: &str, options: &FetchOptions) -> Result<(String, DocumentMetadata)> {
         info!("Fetching content from URL: {}", url);
         
         let parsed_url = Url::parse(url)?;
-        let client = reqwest::Client::new();
-        let response = client.get(url).send().await?;
-        
-        if !response.status().is_success() {
-            return Err(MarkdownSplitterError::HttpStatus {
-                status: response.status().as_u16(),
-            });
+        let cache_path = options.cache_dir.as_deref().map(|dir| Self::cache_path(dir, url));
+        let (content, compression) = match &cache_path {
+            Some(path) if fs::try_exists(path).await? => {
+                debug!("Serving {} from cache file {}", url, path.display());
+                // The cache holds decompressed content; only the extension tells how it arrived
+                let content = fs::read_to_string(path).await?;
+                Self::check_size(url, content.len() as u64, options)?;
+                (content, Self::path_compression(parsed_url.path()))
+            }
+            _ => {
+                let (content, compression) = Self::download(url, &parsed_url, options).await?;
+                if let Some(path) = cache_path.as_deref().filter(|_| options.write_cache) {
+                    if let Some(dir) = path.parent() {
+                        fs::create_dir_all(dir).await?;
+                    }
+                    fs::write(path, &content).await?;
+                    debug!("Saved {} to cache file {}", url, path.display());
+                }
+                (content, compression)
+            }
+        };
+        let mut filename = Self::extract_filename_from_url(&parsed_url);
+        if compression.is_some() {
+            // "guide.md.gz" names the document "guide.md"
+            if let Some((extension, _)) = COMPRESSED_EXTENSIONS.iter().find(|(ext, _)| filename.ends_with(ext)) {
+                filename.truncate(filename.len() - extension.len());
+            }
         }
-        
-        let content = response.text().await?;
-        let filename = Self::extract_filename_from_url(&parsed_url);
         let total_lines = content.lines().count();
         
         let metadata = DocumentMetadata {
@@ -58,12 +369,179 @@ impl ContentFetcher {
             created_at: chrono::Utc::now().to_rfc3339(),
             total_lines,
             page_breaks: Vec::new(), // Will be populated by parser
+            origin: url.to_string(),
+            compression,
         };
         
         Ok((content, metadata))
     }
 
-    async fn fetch_from_file(file_path: &str) -> Result<(String, DocumentMetadata)> {
+    /// Downloads `url`, decompressing gzip and brotli bodies named by the
+    /// `Content-Encoding` header or, failing that, the URL's extension
+    async fn download(url: &str, parsed_url: &Url, options: &FetchOptions) -> Result<(String, Option<Compression>)> {
+        let response = Self::send_with_retries(url, options, |client| {
+            client.get(url).header(reqwest::header::ACCEPT_ENCODING, "gzip, br")
+        })
+        .await?;
+
+        if !response.status().is_success() {
+            return Err(MarkdownSplitterError::HttpStatus {
+                status: response.status().as_u16(),
+            });
+        }
+
+        // Reject early when the server announces the size, and otherwise while the body arrives
+        if let Some(size) = response.content_length() {
+            Self::check_size(url, size, options)?;
+        }
+
+        let encoding = response
+            .headers()
+            .get(reqwest::header::CONTENT_ENCODING)
+            .and_then(|value| value.to_str().ok())
+            .and_then(Self::encoding_compression);
+        let compression = encoding.or_else(|| Self::path_compression(parsed_url.path()));
+
+        let body = Self::read_body(url, response, options).await?;
+        let content = match compression {
+            Some(compression) => {
+                debug!("Decompressing {} ({:?}, {} bytes)", url, compression, body.len());
+                Self::decompress(url, &body, compression, options)?
+            }
+            None => String::from_utf8_lossy(&body).into_owned(),
+        };
+        Ok((content, compression))
+    }
+
+    /// Reads a response body chunk by chunk, failing as soon as it grows past
+    /// `options.max_bytes` rather than after buffering all of it
+    async fn read_body(url: &str, mut response: reqwest::Response, options: &FetchOptions) -> Result<Vec<u8>> {
+        let mut body = Vec::new();
+        while let Some(chunk) = response.chunk().await.map_err(|e| Self::request_error(url, options, e))? {
+            body.extend_from_slice(&chunk);
+            Self::check_size(url, body.len() as u64, options)?;
+        }
+        Ok(body)
+    }
+
+    fn encoding_compression(encoding: &str) -> Option<Compression> {
+        match encoding.trim().to_ascii_lowercase().as_str() {
+            "gzip" | "x-gzip" => Some(Compression::Gzip),
+            "br" => Some(Compression::Brotli),
+            _ => None,
+        }
+    }
+
+    fn path_compression(path: &str) -> Option<Compression> {
+        let path = path.to_ascii_lowercase();
+        COMPRESSED_EXTENSIONS
+            .iter()
+            .find(|(extension, _)| path.ends_with(extension))
+            .map(|(_, compression)| *compression)
+    }
+
+    /// Decompresses a body and decodes it as UTF-8. Reading stops one byte past the
+    /// size limit, so a small body cannot expand into unbounded memory.
+    pub(crate) fn decompress(
+        source: &str,
+        body: &[u8],
+        compression: Compression,
+        options: &FetchOptions,
+    ) -> Result<String> {
+        let decoder: Box<dyn Read + '_> = match compression {
+            Compression::Gzip => Box::new(MultiGzDecoder::new(body)),
+            Compression::Brotli => Box::new(brotli::Decompressor::new(body, BROTLI_BUFFER_SIZE)),
+        };
+        let limit = options.max_bytes.map_or(u64::MAX, |limit| limit.saturating_add(1));
+        let mut decoded = Vec::new();
+        decoder
+            .take(limit)
+            .read_to_end(&mut decoded)
+            .map_err(|e| MarkdownSplitterError::Decompression {
+                source_name: source.to_string(),
+                compression,
+                reason: e.to_string(),
+            })?;
+        Self::check_size(source, decoded.len() as u64, options)?;
+
+        String::from_utf8(decoded).map_err(|e| MarkdownSplitterError::InvalidMarkdown {
+            reason: format!("{} is not UTF-8 after decompression: {}", source, e),
+        })
+    }
+
+    /// Sends the request built by `request`, retrying connection errors, timeouts and
+    /// 5xx statuses up to `options.retries` times with exponential backoff. The last
+    /// response is returned whatever its status; the caller decides what it means.
+    async fn send_with_retries(
+        url: &str,
+        options: &FetchOptions,
+        request: impl Fn(&reqwest::Client) -> reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response> {
+        let mut builder = reqwest::Client::builder();
+        if let Some(timeout) = options.timeout {
+            builder = builder.timeout(timeout);
+        }
+        let client = builder.build()?;
+        let mut backoff = if options.retry_backoff.is_zero() {
+            DEFAULT_RETRY_BACKOFF
+        } else {
+            options.retry_backoff
+        };
+
+        let mut attempt = 0;
+        loop {
+            if let Some(rate_limit) = &options.rate_limit {
+                rate_limit.acquire().await;
+            }
+            let result = request(&client).send().await;
+            let retryable = match &result {
+                Ok(response) => response.status().is_server_error(),
+                Err(e) => e.is_connect() || e.is_timeout(),
+            };
+            if !retryable || attempt >= options.retries {
+                return result.map_err(|e| Self::request_error(url, options, e));
+            }
+
+            attempt += 1;
+            match &result {
+                Ok(response) => warn!(
+                    "{} answered {}, retry {}/{} in {:?}",
+                    url,
+                    response.status(),
+                    attempt,
+                    options.retries,
+                    backoff
+                ),
+                Err(e) => warn!("{} failed ({}), retry {}/{} in {:?}", url, e, attempt, options.retries, backoff),
+            }
+            tokio::time::sleep(backoff).await;
+            backoff = backoff.saturating_mul(2);
+        }
+    }
+
+    /// Reports a timed-out request as such, so callers can tell it from other failures
+    fn request_error(url: &str, options: &FetchOptions, error: reqwest::Error) -> MarkdownSplitterError {
+        match options.timeout {
+            Some(timeout) if error.is_timeout() => MarkdownSplitterError::FetchTimeout {
+                url: url.to_string(),
+                timeout,
+            },
+            _ => MarkdownSplitterError::Http(error),
+        }
+    }
+
+    /// File holding the saved response for `url` in a fetch cache directory, named by
+    /// the SHA-256 of the URL
+    pub fn cache_path(cache_dir: &Path, url: &str) -> PathBuf {
+        let digest = Sha256::digest(url.as_bytes());
+        let name: String = digest.iter().map(|byte| format!("{:02x}", byte)).collect();
+        cache_dir.join(format!("{}.md", name))
+    }
+
+    async fn fetch_from_file(
+        file_path: &str,
+        options: &FetchOptions,
+    ) -> Result<(String, DocumentMetadata)> {
         info!("Reading file: {}", file_path);
         
         let path = Path::new(file_path);
@@ -74,6 +552,10 @@ impl ContentFetcher {
             });
         }
         
+        // Check the size before reading anything into memory
+        let size = fs::metadata(path).await?.len();
+        Self::check_size(file_path, size, options)?;
+
         let content = fs::read_to_string(path).await?;
         let filename = path
             .file_name()
@@ -89,28 +571,196 @@ impl ContentFetcher {
             created_at: chrono::Utc::now().to_rfc3339(),
             total_lines,
             page_breaks: Vec::new(), // Will be populated by parser
+            origin: file_path.to_string(),
+            compression: None,
         };
         
         Ok((content, metadata))
     }
 
+    /// Reads a whole document from `reader`, standard input in practice; reading stops
+    /// one byte past the size limit so an endless pipe cannot exhaust memory
+    pub(crate) async fn fetch_from_reader<R: AsyncRead + Unpin>(
+        reader: R,
+        source: &str,
+        options: &FetchOptions,
+    ) -> Result<(String, DocumentMetadata)> {
+        info!("Reading standard input");
+
+        let limit = options.max_bytes.map_or(u64::MAX, |limit| limit.saturating_add(1));
+        let mut content = String::new();
+        reader.take(limit).read_to_string(&mut content).await?;
+        Self::check_size(source, content.len() as u64, options)?;
+
+        let metadata = DocumentMetadata {
+            filename: STDIN_FILENAME.to_string(),
+            source_type: SourceType::Stdin,
+            created_at: chrono::Utc::now().to_rfc3339(),
+            total_lines: content.lines().count(),
+            page_breaks: Vec::new(),
+            origin: source.to_string(),
+            compression: None,
+        };
+
+        Ok((content, metadata))
+    }
+
+    fn check_size(source: &str, size: u64, options: &FetchOptions) -> Result<()> {
+        match options.max_bytes {
+            Some(limit) if size > limit => Err(MarkdownSplitterError::FileTooLarge {
+                source_name: source.to_string(),
+                size,
+                limit,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    fn is_stdin(source: &str) -> bool {
+        STDIN_SOURCES.contains(&source)
+    }
+
     fn is_url(source: &str) -> bool {
         source.starts_with("http://") || source.starts_with("https://")
     }
 
     fn extract_filename_from_url(url: &Url) -> String {
-        url.path_segments()
-            .and_then(|segments| segments.last())
-            .and_then(|name| if name.is_empty() { None } else { Some(name) })
-            .unwrap_or("downloaded.md")
-            .to_string()
+        // An explicit `filename`/`file` query parameter names the document better than the path
+        let from_query = url
+            .query_pairs()
+            .filter(|(key, _)| key == "filename" || key == "file")
+            .find_map(|(_, value)| Self::sanitize_filename(&value));
+
+        from_query
+            .or_else(|| {
+                // The path excludes the query string; skip the empty segment of a trailing slash
+                url.path_segments()?
+                    .rev()
+                    .filter(|segment| !segment.is_empty())
+                    .find_map(|segment| {
+                        Self::sanitize_filename(&percent_decode_str(segment).decode_utf8_lossy())
+                    })
+            })
+            .unwrap_or_else(|| "downloaded.md".to_string())
+    }
+
+    /// Keeps the last path component of a decoded name, if anything usable remains
+    fn sanitize_filename(name: &str) -> Option<String> {
+        let name = name.rsplit(['/', '\\']).next()?.trim();
+        (!name.is_empty() && name != "." && name != "..").then(|| name.to_string())
+    }
+
+    /// Replaces directory sources with the markdown files found under them, in path order.
+    ///
+    /// Symlinks inside directories are skipped unless `follow_symlinks` is set; when they
+    /// are followed, a link leading back to one of its ancestors is reported as an error
+    /// instead of being walked forever.
+    pub fn expand_sources(sources: &[String], follow_symlinks: bool) -> Result<Vec<String>> {
+        let mut expanded = Vec::new();
+
+        for source in sources {
+            if Self::is_stdin(source) || Self::is_url(source) || !Path::new(source).is_dir() {
+                expanded.push(source.clone());
+                continue;
+            }
+
+            let mut found = Vec::new();
+            for entry in WalkDir::new(source).follow_links(follow_symlinks).sort_by_file_name() {
+                let entry = entry.map_err(|e| match e.loop_ancestor() {
+                    Some(ancestor) => MarkdownSplitterError::SymlinkCycle {
+                        path: e.path().map(|p| p.display().to_string()).unwrap_or_default(),
+                        ancestor: ancestor.display().to_string(),
+                    },
+                    None => MarkdownSplitterError::Io(e.into()),
+                })?;
+
+                if entry.path_is_symlink() && !follow_symlinks {
+                    debug!("Skipping symlink {}", entry.path().display());
+                    continue;
+                }
+
+                let is_markdown = entry
+                    .path()
+                    .extension()
+                    .and_then(|ext| ext.to_str())
+                    .is_some_and(|ext| MARKDOWN_EXTENSIONS.contains(&ext.to_lowercase().as_str()));
+                if entry.file_type().is_file() && is_markdown {
+                    found.push(entry.path().display().to_string());
+                }
+            }
+
+            info!("Found {} markdown files under {}", found.len(), source);
+            expanded.extend(found);
+        }
+
+        Ok(expanded)
+    }
+
+    /// Check that a source can be read without downloading it: a HEAD request for
+    /// URLs (a one-byte ranged GET when HEAD is refused), an open for local files.
+    /// Requests follow the timeout and retries in `options`.
+    pub async fn check_access(source: &str, options: &FetchOptions) -> AccessCheck {
+        let mut check = AccessCheck {
+            source: source.to_string(),
+            method: None,
+            status: None,
+            reachable: false,
+            error: None,
+        };
+
+        if Self::is_stdin(source) {
+            // Nothing to probe without consuming the input
+            check.reachable = true;
+            return check;
+        }
+
+        if !Self::is_url(source) {
+            match fs::File::open(source).await {
+                Ok(_) => check.reachable = true,
+                Err(e) => check.error = Some(e.to_string()),
+            }
+            return check;
+        }
+
+        let mut result = Self::send_with_retries(source, options, |client| client.head(source))
+            .await
+            .map(|response| ("HEAD", response));
+        if let Ok((_, response)) = &result {
+            if HEAD_UNSUPPORTED.contains(&response.status().as_u16()) {
+                debug!("HEAD not supported by {}, retrying with a ranged GET", source);
+                result = Self::send_with_retries(source, options, |client| {
+                    client.get(source).header(reqwest::header::RANGE, "bytes=0-0")
+                })
+                .await
+                .map(|response| ("GET", response));
+            }
+        }
+
+        match result {
+            Ok((method, response)) => {
+                check.method = Some(method.to_string());
+                check.status = Some(response.status().as_u16());
+                check.reachable = response.status().is_success();
+            }
+            Err(e) => check.error = Some(e.to_string()),
+        }
+        check
     }
 
+    /// Checks URLs parse and files exist; standard input (`-`) is accepted as is, but
+    /// only once, since a second read would find it empty
     pub async fn validate_sources(sources: &[String]) -> Result<Vec<String>> {
         let mut validated = Vec::new();
+
+        let stdin_count = sources.iter().filter(|source| Self::is_stdin(source)).count();
+        if stdin_count > 1 {
+            return Err(MarkdownSplitterError::StdinReused { count: stdin_count });
+        }
         
         for source in sources {
-            if Self::is_url(source) {
+            if Self::is_stdin(source) {
+                validated.push(source.clone());
+            } else if Self::is_url(source) {
                 // Validate URL format
                 Url::parse(source)?;
                 validated.push(source.clone());
@@ -132,4 +782,34 @@ impl ContentFetcher {
 }
 
 // Add chrono dependency to Cargo.toml
-// chrono = { version = "0.4", features = ["serde"] }
\ No newline at end of file
+// chrono = { version = "0.4", features = ["serde"] }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filename(url: &str) -> String {
+        ContentFetcher::extract_filename_from_url(&Url::parse(url).unwrap())
+    }
+
+    #[test]
+    fn test_filename_from_query_parameter() {
+        assert_eq!(filename("https://host/docs?file=guide.md&v=2"), "guide.md");
+        assert_eq!(filename("https://host/get?v=2&filename=notes%20v2.md"), "notes v2.md");
+        assert_eq!(filename("https://host/get?filename=a%2Fb%2Fc.md"), "c.md");
+        assert_eq!(filename("https://host/guide.md?v=2"), "guide.md");
+    }
+
+    #[test]
+    fn test_filename_percent_decoded() {
+        assert_eq!(filename("https://host/docs/User%20Guide.md"), "User Guide.md");
+        assert_eq!(filename("https://host/docs/caf%C3%A9.md"), "café.md");
+    }
+
+    #[test]
+    fn test_filename_with_trailing_slash() {
+        assert_eq!(filename("https://host/docs/guide/"), "guide");
+        assert_eq!(filename("https://host/"), "downloaded.md");
+        assert_eq!(filename("https://host"), "downloaded.md");
+    }
+}
@@ -1,7 +1,11 @@
 pub mod fetcher;
 pub mod parser;
 pub mod splitter;
+pub mod summarizer;
+pub mod tokenizer;
 
-pub use fetcher::ContentFetcher;
-pub use parser::MarkdownParser;
-pub use splitter::DocumentSplitter;
\ No newline at end of file
+pub use fetcher::{ContentFetcher, RateLimiter};
+pub use parser::{heading_anchor, MarkdownParser};
+pub use splitter::DocumentSplitter;
+pub use summarizer::{FirstSentence, Summarizer};
+pub use tokenizer::Tokenizer;
@@ -1,61 +1,263 @@
 use crate::error::{MarkdownSplitterError, Result};
-use crate::types::{DocumentMetadata, MarkdownDocument, MarkdownPage};
-use regex::Regex;
-use std::collections::HashMap;
-use tracing::{debug, info};
+use crate::types::{
+    AnalysisReport, DocumentMetadata, Distribution, LineEnding, PageMerge, LeadingRegion, MarkdownDocument, MarkdownPage, OutlineEntry, ParserOptions, ParsingStats,
+    SourceType, SplitBy, StructureLimits,
+};
+use regex::{Regex, RegexBuilder};
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Range;
+use tracing::{debug, info, warn};
+
+/// Number of built-in explicit page marker patterns at the start of the pattern list
+const PAGE_MARKER_PATTERNS: usize = 4;
+
+/// Block-level HTML tags whose raw blocks `keep_html_intact` protects
+const HTML_BLOCK_TAGS: [&str; 22] = [
+    "address", "article", "aside", "blockquote", "details", "dialog", "div", "dl", "fieldset", "figure",
+    "footer", "form", "header", "nav", "ol", "pre", "script", "section", "style", "table", "textarea", "ul",
+];
+
+/// Lines opening and closing blocks in which LaTeX page breaks are content
+const BLOCK_FENCES: [&str; 3] = ["```", "~~~", "$$"];
+
+/// Lines opening and closing a front matter block: YAML and TOML
+const FRONT_MATTER_FENCES: [&str; 2] = ["---", "+++"];
+
+/// Columns of indentation that turn a line into an indented code block
+const CODE_BLOCK_INDENT: usize = 4;
+
+/// Tab stop used when `ParserOptions::tab_width` is 0
+const DEFAULT_TAB_WIDTH: usize = 4;
+
+/// Upper bound on the compiled size of user-supplied patterns
+const USER_PATTERN_SIZE_LIMIT: usize = 1 << 20;
+
+/// Maximum group/repetition nesting depth of user-supplied patterns
+const USER_PATTERN_NEST_LIMIT: u32 = 32;
+
+/// Heading levels that break pages unless `ParserOptions::heading_break_levels` says otherwise
+const DEFAULT_HEADING_BREAK_LEVELS: [usize; 2] = [1, 2];
+
+/// Default number of lines joined for multi-line marker matching
+const DEFAULT_MARKER_WINDOW: usize = 3;
+
+/// Largest multi-line marker window; every line start costs one match over the window
+const MAX_MARKER_WINDOW: usize = 16;
+
+/// Untitled pages of at most this many lines are merged into the page before them
+const MERGE_MAX_LINES: usize = 10;
+
+/// ASCII form feed, the classic page-break character of plain-text dumps
+const FORM_FEED: char = '\x0C';
+
+/// Characters from the start of a document used for language detection
+#[cfg(feature = "lang-detect")]
+const LANGUAGE_SAMPLE_CHARS: usize = 4096;
+
+/// How far into a document a table of contents may start
+const TOC_SEARCH_LINES: usize = 100;
+
+/// Builds the GitHub-style anchor for a heading: lowercase, punctuation removed,
+/// spaces turned into hyphens.
+pub fn heading_anchor(text: &str) -> String {
+    text.trim()
+        .to_lowercase()
+        .chars()
+        .filter_map(|c| match c {
+            ' ' => Some('-'),
+            '-' | '_' => Some(c),
+            c if c.is_alphanumeric() => Some(c),
+            _ => None,
+        })
+        .collect()
+}
 
 pub struct MarkdownParser {
     page_break_patterns: Vec<Regex>,
+    /// Patterns before this index are explicit page markers, the rest are fallbacks
+    page_marker_count: usize,
+    /// Multi-line marker and the number of lines it is matched against
+    multiline_marker: Option<(Regex, usize)>,
     title_pattern: Regex,
+    toc_entry_pattern: Regex,
+    cell_delimiter_pattern: Regex,
+    comment_metadata_pattern: Regex,
+    frontmatter_entry_pattern: Regex,
+    bold_line_pattern: Regex,
+    latex_break_pattern: Regex,
+    atx_heading_pattern: Regex,
+    setext_underline_pattern: Regex,
+    thematic_break_pattern: Regex,
+    html_block_pattern: Regex,
+    html_tag_pattern: Regex,
+    list_item_pattern: Regex,
+    options: ParserOptions,
 }
 
 impl MarkdownParser {
     pub fn new(custom_page_marker: Option<&str>) -> Result<Self> {
+        Self::with_options(ParserOptions {
+            custom_page_marker: custom_page_marker.map(str::to_string),
+            ..ParserOptions::default()
+        })
+    }
+
+    pub fn with_options(options: ParserOptions) -> Result<Self> {
         let mut patterns = vec![
             // Document-specific page marker format has highest priority
             Regex::new(r"(?m)^---\s*\n#\s+Page\s+\d+").unwrap(), // "---\n# Page 68" format
+            Regex::new(r"\x0C").unwrap(), // Form feed anywhere on the line
             // Alternative single-line page markers
             Regex::new(r"(?im)^\s*#\s+page\s+\d+\s*$").unwrap(), // "# Page 123"
             Regex::new(r"(?im)^\s*\(?page\s+\d+\)?\s*$").unwrap(), // "Page 123" or "(Page 123)"
             // Common page break patterns
             Regex::new(r"(?m)^---+\s*$").unwrap(), // Horizontal rules
             Regex::new(r"(?m)^<!--\s*page\s*break?\s*-->").unwrap(), // HTML comments
-            Regex::new(r"(?m)^\s*\\pagebreak\s*$").unwrap(), // LaTeX style
-            Regex::new(r"(?m)^\s*\\newpage\s*$").unwrap(), // LaTeX newpage
-            // Only major headers as page breaks (H1 and H2), not all headers
-            Regex::new(r"(?m)^#{1,2}\s+.*$").unwrap(),
         ];
+        // LaTeX "\pagebreak" and "\newpage", only honored outside fenced blocks
+        let latex_break_pattern = Regex::new(r"^\s*\\(?:pagebreak|newpage)\s*$").unwrap();
 
-        // Add custom page marker if provided
-        if let Some(marker) = custom_page_marker {
-            let custom_pattern = Regex::new(&format!(r"(?m)^{}\s*$", regex::escape(marker)))
-                .map_err(|e| MarkdownSplitterError::PageParsing {
-                    reason: format!("Invalid custom page marker regex: {}", e),
+        // Only major headers as page breaks (H1 and H2 unless configured), not all headers
+        let heading_levels = options
+            .heading_break_levels
+            .as_deref()
+            .unwrap_or(&DEFAULT_HEADING_BREAK_LEVELS);
+        if let Some(level) = heading_levels.iter().find(|level| !(1..=6).contains(*level)) {
+            return Err(MarkdownSplitterError::PageParsing {
+                reason: format!("Heading break level {} is not between 1 and 6", level),
+            });
+        }
+        if !heading_levels.is_empty() {
+            let hashes: Vec<String> = heading_levels.iter().map(|&level| "#".repeat(level)).collect();
+            patterns.push(Regex::new(&format!(r"(?m)^(?:{})\s+.*$", hashes.join("|"))).unwrap());
+        }
+
+        if let SplitBy::Lines(numbers) = &options.split_by {
+            if numbers.first() == Some(&0) || numbers.windows(2).any(|pair| pair[0] >= pair[1]) {
+                return Err(MarkdownSplitterError::PageParsing {
+                    reason: "Break line numbers must be 1-based and strictly increasing".to_string(),
+                });
+            }
+        }
+
+        let mut page_marker_count = PAGE_MARKER_PATTERNS;
+
+        // Add custom page marker if provided; it is matched literally, as a whole line
+        if let Some(marker) = options.custom_page_marker.as_deref() {
+            let custom_pattern = Self::compile_user_pattern(&format!(r"(?m)^{}\s*$", regex::escape(marker)))
+                .map_err(|reason| MarkdownSplitterError::PageParsing {
+                    reason: format!("Invalid custom page marker regex: {}", reason),
                 })?;
             patterns.insert(0, custom_pattern); // Give priority to custom marker
+            page_marker_count += 1;
         }
 
+        let multiline_marker = match options.multiline_marker.as_deref() {
+            Some(marker) => {
+                let window = options.marker_window.unwrap_or(DEFAULT_MARKER_WINDOW);
+                if !(1..=MAX_MARKER_WINDOW).contains(&window) {
+                    return Err(MarkdownSplitterError::PageParsing {
+                        reason: format!("Marker window must be between 1 and {} lines", MAX_MARKER_WINDOW),
+                    });
+                }
+                let pattern = Self::compile_user_pattern(&format!(r"(?m)\A(?:{})", marker)).map_err(|reason| {
+                    MarkdownSplitterError::PageParsing {
+                        reason: format!("Invalid multi-line page marker regex: {}", reason),
+                    }
+                })?;
+                Some((pattern, window))
+            }
+            None => None,
+        };
+
         let title_pattern = Regex::new(r"(?m)^(#{1,6})\s+(.+)$").unwrap();
+        // "- [Text](#anchor)", "* [Text](#anchor)" or "1. [Text](#anchor)"
+        let toc_entry_pattern =
+            Regex::new(r"^(\s*)(?:[-*+]|\d+[.)])\s+\[[^\]]+\]\(#([^)\s]+)\)").unwrap();
+        // "# %%", "# %% [markdown]" or "<!-- #region some-id -->"
+        let cell_delimiter_pattern =
+            Regex::new(r"^\s*(?:#\s*%%.*|<!--\s*#region\b.*-->)\s*$").unwrap();
+        // "<!-- author: Jane Doe -->"
+        let comment_metadata_pattern = Regex::new(r"^\s*<!--\s*([\w.-]+)\s*:\s*(.*?)\s*-->\s*$").unwrap();
+        // "section: Installation"
+        let frontmatter_entry_pattern = Regex::new(r"^\s*([\w.-]+)\s*:\s*(.*?)\s*$").unwrap();
+        // "##   Title ##", with optional closing hashes
+        let atx_heading_pattern = Regex::new(r"^ {0,3}(#{1,6})[ \t]+(.*?)(?:[ \t]+#+)?[ \t]*$").unwrap();
+        // "=====" (H1) or "-----" (H2) right under a line of text
+        let setext_underline_pattern = Regex::new(r"^ {0,3}(?:(=+)|-+)[ \t]*$").unwrap();
+        // "***", "- - -" or "___"
+        let thematic_break_pattern = Regex::new(r"^ {0,3}(?:(?:\*[ \t]*){3,}|(?:-[ \t]*){3,}|(?:_[ \t]*){3,})$").unwrap();
+        // "<div class=...>" opening a line
+        let html_block_pattern = Regex::new(r"^ {0,3}<([A-Za-z][A-Za-z0-9-]*)(?:[\s/>]|$)").unwrap();
+        // "<div" or "</div" anywhere on a line, for matching an HTML block's closing tag
+        let html_tag_pattern = Regex::new(r"<(/?)([A-Za-z][A-Za-z0-9-]*)\b").unwrap();
+        // "**Section Title**" or "__Section Title__" alone on a line
+        let bold_line_pattern = Regex::new(r"^\s*(?:\*\*([^*]+)\*\*|__([^_]+)__)\s*$").unwrap();
+        // "- item", "* item" or "1. item"
+        let list_item_pattern = Regex::new(r"^\s*(?:[-*+]|\d+[.)])(?:\s|$)").unwrap();
 
         Ok(Self {
             page_break_patterns: patterns,
+            page_marker_count,
+            multiline_marker,
             title_pattern,
+            toc_entry_pattern,
+            cell_delimiter_pattern,
+            comment_metadata_pattern,
+            frontmatter_entry_pattern,
+            bold_line_pattern,
+            latex_break_pattern,
+            atx_heading_pattern,
+            setext_underline_pattern,
+            thematic_break_pattern,
+            html_block_pattern,
+            html_tag_pattern,
+            list_item_pattern,
+            options,
         })
     }
 
     pub fn parse_document(
+        &self,
+        content: &str,
+        metadata: DocumentMetadata,
+    ) -> Result<MarkdownDocument> {
+        self.parse_document_with_callback(content, metadata, |_| {})
+    }
+
+    /// Like [`Self::parse_document`], calling `on_page` with each page as soon as it is
+    /// final, in document order: a page is handed over once the next page is known not to
+    /// be merged into it, before the rest of the document is merged.
+    pub fn parse_document_with_callback(
         &self,
         content: &str,
         mut metadata: DocumentMetadata,
+        mut on_page: impl FnMut(&MarkdownPage),
     ) -> Result<MarkdownDocument> {
         info!("Parsing markdown document: {}", metadata.filename);
 
         let lines: Vec<&str> = content.lines().collect();
+        if let SplitBy::Lines(numbers) = &self.options.split_by {
+            if let Some(number) = numbers.iter().find(|&&number| number > lines.len()) {
+                return Err(MarkdownSplitterError::PageParsing {
+                    reason: format!(
+                        "Break at line {} is past the end of {} ({} lines)",
+                        number,
+                        metadata.filename,
+                        lines.len()
+                    ),
+                });
+            }
+        }
         let page_breaks = self.find_page_breaks(&lines);
         
         metadata.page_breaks = page_breaks.clone();
 
-        let pages = self.extract_pages(&lines, &page_breaks)?;
+        let (comment_metadata, _) = self.comment_metadata(&lines);
+        let (front_matter, _) = Self::front_matter(&lines);
+        let headings = self.headings(&lines);
+        let mut merges = Vec::new();
+        let pages = self.extract_pages(content, &lines, &page_breaks, &mut merges, &mut on_page)?;
         let total_pages = pages.len();
 
         debug!("Found {} pages in document", total_pages);
@@ -65,49 +267,240 @@ impl MarkdownParser {
             total_pages,
             pages,
             metadata,
+            trailing_newline: content.ends_with('\n'),
+            line_ending: LineEnding::detect(content),
+            detected_language: Self::detect_language(content),
+            comment_metadata,
+            front_matter,
+            merges,
+            headings,
         })
     }
 
-    fn find_page_breaks(&self, lines: &[&str]) -> Vec<usize> {
-        let mut breaks = vec![0]; // Always start with line 0
+    /// ATX and setext headings outside code blocks and front matter, in document order.
+    /// A setext heading starts at the first line of the paragraph it underlines.
+    fn headings(&self, lines: &[&str]) -> Vec<OutlineEntry> {
+        let fenced = Self::fenced_lines(lines);
+        let indented = self.indented_code_lines(lines, &fenced);
+        let code = |idx: usize| fenced[idx] || indented[idx];
+        let (_, front_matter_end) = Self::front_matter(lines);
+        let mut headings = Vec::new();
+
+        for line_idx in front_matter_end..lines.len() {
+            if code(line_idx) {
+                continue;
+            }
+            if let Some(captures) = self.atx_heading_pattern.captures(lines[line_idx]) {
+                headings.push(OutlineEntry {
+                    level: captures[1].len(),
+                    text: captures[2].trim().to_string(),
+                    line: line_idx,
+                });
+                continue;
+            }
+            if line_idx == front_matter_end || code(line_idx - 1) {
+                continue;
+            }
+            let Some(level) = self.setext_level(lines[line_idx - 1], lines[line_idx]) else {
+                continue;
+            };
+            // The heading text is the whole paragraph above the underline
+            let mut start = line_idx - 1;
+            while start > front_matter_end && !code(start - 1) && self.is_paragraph_text(lines[start - 1]) {
+                start -= 1;
+            }
+            let text: Vec<&str> = lines[start..line_idx].iter().map(|line| line.trim()).collect();
+            headings.push(OutlineEntry {
+                level,
+                text: text.join(" "),
+                line: start,
+            });
+        }
+
+        headings
+    }
+
+    /// Reads the YAML (`---`) or TOML (`+++`) front matter block opening the document.
+    /// Returns the block, delimiters included, and the line after it and any blank
+    /// lines following it (0 when the document does not open with front matter).
+    fn front_matter(lines: &[&str]) -> (Option<String>, usize) {
+        let Some(close) = Self::fenced_block_close(lines, &FRONT_MATTER_FENCES) else {
+            return (None, 0);
+        };
+
+        let mut end = close + 1;
+        let block = lines[..end].join("\n");
+        while lines.get(end).is_some_and(|line| line.trim().is_empty()) {
+            end += 1;
+        }
+        (Some(block), end)
+    }
+
+    /// Reads the `<!-- key: value -->` block at the top of the document. Returns the
+    /// entries and the line after the block and any blank lines following it (0 when
+    /// the document does not open with such a block).
+    fn comment_metadata(&self, lines: &[&str]) -> (BTreeMap<String, String>, usize) {
+        let mut entries = BTreeMap::new();
+        let mut end = 0;
 
-        // First, try to find explicit page markers (highest priority)
-        let page_marker_patterns = [
-            &self.page_break_patterns[0], // "---\n# Page 68" format
-            &self.page_break_patterns[1], // "# Page 123"
-            &self.page_break_patterns[2], // "Page 123" or "(Page 123)"
-        ];
-        
-        let mut found_page_markers = false;
-        
         for (line_idx, line) in lines.iter().enumerate() {
-            for pattern in &page_marker_patterns {
-                if pattern.is_match(line) {
-                    found_page_markers = true;
-                    // Avoid duplicate consecutive breaks
-                    if breaks.last() != Some(&line_idx) {
-                        breaks.push(line_idx);
+            if let Some(captures) = self.comment_metadata_pattern.captures(line) {
+                entries.insert(captures[1].to_string(), captures[2].to_string());
+                end = line_idx + 1;
+            } else if !line.trim().is_empty() {
+                break;
+            }
+        }
+
+        if entries.is_empty() {
+            return (entries, 0);
+        }
+        while lines.get(end).is_some_and(|line| line.trim().is_empty()) {
+            end += 1;
+        }
+        (entries, end)
+    }
+
+    /// Detects the primary language from a sample at the start of the content
+    #[cfg(feature = "lang-detect")]
+    fn detect_language(content: &str) -> Option<String> {
+        let sample_end = content
+            .char_indices()
+            .nth(LANGUAGE_SAMPLE_CHARS)
+            .map_or(content.len(), |(idx, _)| idx);
+
+        whatlang::detect(&content[..sample_end])
+            .filter(|info| info.is_reliable())
+            .map(|info| info.lang().code().to_string())
+    }
+
+    #[cfg(not(feature = "lang-detect"))]
+    fn detect_language(_content: &str) -> Option<String> {
+        None
+    }
+
+    /// Compiles a user-supplied regex. The `regex` crate matches in linear time, so the
+    /// remaining risk is a pattern whose compiled form explodes (`(a{1000}){1000}`); those
+    /// are rejected by capping the program size and nesting depth.
+    pub(crate) fn compile_user_pattern(pattern: &str) -> std::result::Result<Regex, String> {
+        RegexBuilder::new(pattern)
+            .size_limit(USER_PATTERN_SIZE_LIMIT)
+            .dfa_size_limit(USER_PATTERN_SIZE_LIMIT)
+            .nest_limit(USER_PATTERN_NEST_LIMIT)
+            .build()
+            .map_err(|e| match e {
+                regex::Error::CompiledTooBig(limit) => format!(
+                    "pattern is too complex (compiles to more than {} bytes)",
+                    limit
+                ),
+                e => e.to_string(),
+            })
+    }
+
+    /// Parses in-memory content under the given logical filename.
+    pub fn parse_str(&self, content: &str, filename: &str) -> Result<MarkdownDocument> {
+        self.parse_document(content, Self::str_metadata(content, filename))
+    }
+
+    /// Metadata for in-memory content, as used by [`Self::parse_str`]
+    pub fn str_metadata(content: &str, filename: &str) -> DocumentMetadata {
+        DocumentMetadata {
+            filename: filename.to_string(),
+            source_type: SourceType::LocalFile,
+            created_at: chrono::Utc::now().to_rfc3339(),
+            total_lines: content.lines().count(),
+            page_breaks: Vec::new(),
+            origin: filename.to_string(),
+            compression: None,
+        }
+    }
+
+    /// Index of the line closing the block that `lines[0]` opens with one of `fences`
+    fn fenced_block_close(lines: &[&str], fences: &[&str]) -> Option<usize> {
+        let fence = lines.first().map(|line| line.trim_end()).filter(|line| fences.contains(line))?;
+        lines.iter().skip(1).position(|line| line.trim_end() == fence).map(|close| close + 1)
+    }
+
+    /// Parses a `---` delimited block of `key: value` lines starting at `lines[0]`.
+    /// Returns the entries and the index of the closing `---` line.
+    fn frontmatter_block(&self, lines: &[&str]) -> Option<(BTreeMap<String, String>, usize)> {
+        let close = Self::fenced_block_close(lines, &["---"])?;
+
+        let mut entries = BTreeMap::new();
+        for line in &lines[1..close] {
+            let captures = self.frontmatter_entry_pattern.captures(line)?;
+            entries.insert(captures[1].to_string(), captures[2].to_string());
+        }
+        (!entries.is_empty()).then_some((entries, close))
+    }
+
+    fn find_page_breaks(&self, lines: &[&str]) -> Vec<usize> {
+        let mut breaks = match &self.options.split_by {
+            SplitBy::Auto => self.find_pattern_breaks(lines),
+            SplitBy::BareNumberLines { max_delta } => Self::find_bare_number_breaks(lines, *max_delta),
+            SplitBy::BlankLineRun(min_run) => Self::find_blank_run_breaks(lines, *min_run),
+            SplitBy::JupyterCells => self.find_cell_breaks(lines),
+            SplitBy::BoldLines => self.find_bold_line_breaks(lines),
+            SplitBy::Lines(numbers) => numbers.iter().map(|number| number - 1).collect(),
+            SplitBy::DocumentToc => self.find_toc_breaks(lines).unwrap_or_else(|| {
+                warn!("Could not resolve a table of contents, falling back to pattern detection");
+                self.find_pattern_breaks(lines)
+            }),
+        };
+
+        // Explicit line numbers are taken as given
+        if !matches!(self.options.split_by, SplitBy::Lines(_)) {
+            // A frontmatter block's closing rule and entries stay with the page it opens
+            let mut line_idx = 0;
+            while line_idx < lines.len() {
+                match self.frontmatter_block(&lines[line_idx..]) {
+                    Some((_, close)) => {
+                        breaks.retain(|&brk| brk <= line_idx || brk > line_idx + close);
+                        line_idx += close + 1;
                     }
-                    break;
+                    None => line_idx += 1,
                 }
             }
-        }
-        
-        // If no explicit page markers found, fall back to other patterns
-        if !found_page_markers {
-            for (line_idx, line) in lines.iter().enumerate() {
-                for pattern in &self.page_break_patterns[3..] { // Skip the page marker patterns
-                    if pattern.is_match(line) {
-                        // Avoid duplicate consecutive breaks
-                        if breaks.last() != Some(&line_idx) {
-                            breaks.push(line_idx);
-                        }
-                        break; // Found a match, no need to check other patterns
+
+            if self.options.keep_html_intact {
+                for block in self.html_blocks(lines) {
+                    for brk in breaks.iter_mut().filter(|brk| block.start < **brk && **brk < block.end) {
+                        debug!("Moving break on line {} past the HTML block ending on line {}", *brk + 1, block.end);
+                        *brk = block.end;
                     }
                 }
+                breaks.retain(|&brk| brk < lines.len());
+                breaks.dedup();
+            }
+
+            // Front matter never breaks pages, whatever it contains; it belongs to the first page
+            let (_, front_matter_end) = Self::front_matter(lines);
+            breaks.retain(|&line_idx| line_idx >= front_matter_end);
+            if front_matter_end > 0 && breaks.first() == Some(&front_matter_end) {
+                breaks[0] = 0;
+            }
+
+            // The comment metadata block never breaks pages; it belongs to the page after it
+            let (_, metadata_end) = self.comment_metadata(lines);
+            breaks.retain(|&line_idx| line_idx >= metadata_end);
+            if metadata_end > 0 && breaks.first() == Some(&metadata_end) {
+                breaks[0] = 0;
             }
         }
 
+        // Content before the first detected break forms the leading region
+        match breaks.first() {
+            Some(&first) if first > 0 => match self.options.leading_region {
+                LeadingRegion::Prepend => breaks.insert(0, 0),
+                LeadingRegion::MergeIntoFirst => breaks[0] = 0,
+                LeadingRegion::Discard => {
+                    debug!("Discarding {} lines before the first page break", first);
+                }
+            },
+            Some(_) => {}
+            None => breaks.push(0),
+        }
+
         // Ensure we end with the last line
         if breaks.last() != Some(&lines.len()) {
             breaks.push(lines.len());
@@ -116,8 +509,486 @@ impl MarkdownParser {
         breaks
     }
 
-    fn extract_pages(&self, lines: &[&str], page_breaks: &[usize]) -> Result<Vec<MarkdownPage>> {
+    /// Lines where a multi-line marker starts. The pattern runs once per line over that
+    /// line and the `window - 1` after it joined with `\n`; the lines a match covers are
+    /// skipped so one marker yields one break.
+    fn find_window_breaks(lines: &[&str], pattern: &Regex, window: usize) -> Vec<usize> {
+        let mut breaks = Vec::new();
+        let mut line_idx = 0;
+
+        while line_idx < lines.len() {
+            let end = std::cmp::min(line_idx + window, lines.len());
+            let joined = lines[line_idx..end].join("\n");
+            match pattern.find(&joined) {
+                Some(found) => {
+                    breaks.push(line_idx);
+                    let covered = joined[..found.end()].trim_end_matches('\n').matches('\n').count() + 1;
+                    line_idx += covered;
+                }
+                None => line_idx += 1,
+            }
+        }
+
+        breaks
+    }
+
+    /// Returns the lines where pages start, without the implicit first page.
+    fn find_pattern_breaks(&self, lines: &[&str]) -> Vec<usize> {
+        let mut breaks = Vec::new();
+
+        // First, try to find explicit page markers (highest priority): the custom marker,
+        // "---\n# Page 68", "# Page 123" and "Page 123" / "(Page 123)"
+        let page_marker_patterns = &self.page_break_patterns[..self.page_marker_count];
+
+        let fenced = Self::fenced_lines(lines);
+        let indented = self.indented_code_lines(lines, &fenced);
+
+        for (line_idx, line) in lines.iter().enumerate() {
+            // Markers inside a fenced or indented code block are sample text
+            if fenced[line_idx] || indented[line_idx] {
+                continue;
+            }
+            if page_marker_patterns.iter().any(|pattern| pattern.is_match(line)) {
+                breaks.push(line_idx);
+            }
+        }
+
+        if let Some((pattern, window)) = &self.multiline_marker {
+            breaks.extend(
+                Self::find_window_breaks(lines, pattern, *window)
+                    .into_iter()
+                    .filter(|&line_idx| !fenced[line_idx]),
+            );
+            breaks.sort_unstable();
+            breaks.dedup();
+        }
+
+        // With too few explicit page markers (a stray "# Page 1"), fall back to other patterns
+        if breaks.len() < self.options.min_explicit_markers.max(1) {
+            if !breaks.is_empty() {
+                debug!(
+                    "Only {} explicit page markers found (need {}), using fallback patterns",
+                    breaks.len(),
+                    self.options.min_explicit_markers
+                );
+                breaks.clear();
+            }
+            let fallback_patterns = &self.page_break_patterns[self.page_marker_count..];
+            let heading_levels = self
+                .options
+                .heading_break_levels
+                .as_deref()
+                .unwrap_or(&DEFAULT_HEADING_BREAK_LEVELS);
+            let mut underlines = Vec::new();
+            for line_idx in 1..lines.len() {
+                let code = |idx: usize| fenced[idx] || indented[idx];
+                if code(line_idx) || code(line_idx - 1) {
+                    continue;
+                }
+                let Some(level) = self.setext_level(lines[line_idx - 1], lines[line_idx]) else {
+                    continue;
+                };
+                underlines.push(line_idx);
+                if heading_levels.contains(&level) {
+                    // The heading text is the whole paragraph above the underline
+                    let mut start = line_idx - 1;
+                    while start > 0 && !code(start - 1) && self.is_paragraph_text(lines[start - 1]) {
+                        start -= 1;
+                    }
+                    breaks.push(start);
+                }
+            }
+
+            for (line_idx, line) in lines.iter().enumerate() {
+                // Rules, headings and "\newpage" inside code blocks or $$ math are content,
+                // and so is the "---" underlining a setext heading
+                if fenced[line_idx] || indented[line_idx] || underlines.contains(&line_idx) {
+                    continue;
+                }
+                let latex_break = self.latex_break_pattern.is_match(line);
+                if latex_break || fallback_patterns.iter().any(|pattern| pattern.is_match(line)) {
+                    breaks.push(line_idx);
+                }
+            }
+            breaks.sort_unstable();
+            breaks.dedup();
+        }
+
+        breaks
+    }
+
+    /// Breaks at each notebook cell delimiter; documents without any fall back to patterns
+    fn find_cell_breaks(&self, lines: &[&str]) -> Vec<usize> {
+        let breaks: Vec<usize> = lines
+            .iter()
+            .enumerate()
+            .filter(|(_, line)| self.cell_delimiter_pattern.is_match(line))
+            .map(|(line_idx, _)| line_idx)
+            .collect();
+
+        if breaks.is_empty() {
+            warn!("No notebook cell delimiters found, falling back to pattern detection");
+            return self.find_pattern_breaks(lines);
+        }
+
+        breaks
+    }
+
+    /// Line ranges of multi-line raw HTML blocks outside fenced code: a block-level tag
+    /// up to the line where it is closed again (counting nested tags of the same name),
+    /// or a comment up to its `-->`. Unclosed blocks run to the end of the document.
+    fn html_blocks(&self, lines: &[&str]) -> Vec<Range<usize>> {
+        let fenced = Self::fenced_lines(lines);
+        let mut blocks = Vec::new();
+        let mut line_idx = 0;
+
+        while line_idx < lines.len() {
+            let start = line_idx;
+            line_idx += 1;
+            if fenced[start] {
+                continue;
+            }
+            let line = lines[start];
+
+            let end = if line.trim_start().starts_with("<!--") {
+                let rest = &line[line.find("<!--").unwrap_or(0) + 4..];
+                if rest.contains("-->") {
+                    continue;
+                }
+                lines[start + 1..].iter().position(|line| line.contains("-->"))
+            } else if let Some(captures) = self.html_block_pattern.captures(line) {
+                let tag = captures[1].to_ascii_lowercase();
+                if !HTML_BLOCK_TAGS.contains(&tag.as_str()) {
+                    continue;
+                }
+                let depth = |line: &str| -> isize {
+                    self.html_tag_pattern
+                        .captures_iter(line)
+                        .filter(|captures| captures[2].eq_ignore_ascii_case(&tag))
+                        .map(|captures| if captures[1].is_empty() { 1 } else { -1 })
+                        .sum()
+                };
+                let mut open = depth(line);
+                if open <= 0 {
+                    continue;
+                }
+                lines[start + 1..].iter().position(|line| {
+                    open += depth(line);
+                    open <= 0
+                })
+            } else {
+                continue;
+            };
+
+            line_idx = end.map_or(lines.len(), |offset| start + 1 + offset + 1);
+            blocks.push(start..line_idx);
+        }
+
+        blocks
+    }
+
+    /// Marks the lines of fenced code blocks and `$$` display math, fences included
+    pub(crate) fn fenced_lines(lines: &[&str]) -> Vec<bool> {
+        let mut fenced = vec![false; lines.len()];
+        for block in Self::fenced_blocks(lines) {
+            fenced[block].fill(true);
+        }
+        fenced
+    }
+
+    /// Line ranges of the fenced code blocks and `$$` display math, fences included. A
+    /// block left open runs to the last line.
+    pub(crate) fn fenced_blocks(lines: &[&str]) -> Vec<Range<usize>> {
+        let mut blocks = Vec::new();
+        let mut open: Option<(usize, &str)> = None;
+        for (line_idx, line) in lines.iter().enumerate() {
+            let trimmed = line.trim();
+            let fence = BLOCK_FENCES.into_iter().find(|fence| trimmed.starts_with(fence));
+            match (open, fence) {
+                (Some((start, current)), Some(fence)) if fence == current => {
+                    blocks.push(start..line_idx + 1);
+                    open = None;
+                }
+                // "$$ x^2 $$" opens and closes on the same line
+                (None, Some("$$")) if trimmed.len() > 2 && trimmed.ends_with("$$") => {
+                    blocks.push(line_idx..line_idx + 1);
+                }
+                (None, Some(fence)) => open = Some((line_idx, fence)),
+                _ => {}
+            }
+        }
+        if let Some((start, _)) = open {
+            blocks.push(start..lines.len());
+        }
+        blocks
+    }
+
+    /// Columns of leading whitespace, with tabs advancing to the next tab stop
+    fn indent_width(&self, line: &str) -> usize {
+        let tab_width = match self.options.tab_width {
+            0 => DEFAULT_TAB_WIDTH,
+            width => width,
+        };
+        line.chars()
+            .take_while(|c| *c == ' ' || *c == '\t')
+            .fold(0, |column, c| if c == '\t' { column + tab_width - column % tab_width } else { column + 1 })
+    }
+
+    /// Marks the lines of indented code blocks: runs of lines indented by at least four
+    /// columns that follow a blank line, outside fenced blocks. Indented lines after a
+    /// list item continue the item instead of starting code.
+    fn indented_code_lines(&self, lines: &[&str], fenced: &[bool]) -> Vec<bool> {
+        let mut code = vec![false; lines.len()];
+        let mut in_list = false;
+
+        for (line_idx, line) in lines.iter().enumerate() {
+            if line.trim().is_empty() || fenced[line_idx] {
+                // Blank lines inside the block belong to it; the block ends at the next
+                // line that is not indented
+                code[line_idx] = !fenced[line_idx] && line_idx > 0 && code[line_idx - 1];
+                continue;
+            }
+
+            let indented = self.indent_width(line) >= CODE_BLOCK_INDENT;
+            let after_blank = line_idx == 0 || lines[line_idx - 1].trim().is_empty();
+            let continues_code = line_idx > 0 && code[line_idx - 1];
+            if indented && !in_list && (after_blank || continues_code) {
+                code[line_idx] = true;
+            } else if !indented {
+                in_list = self.list_item_pattern.is_match(line);
+            }
+        }
+
+        // Trailing blank lines close the block rather than belonging to it
+        for line_idx in (0..lines.len()).rev() {
+            if code[line_idx] && lines[line_idx].trim().is_empty() {
+                let next_code = code.get(line_idx + 1).copied().unwrap_or(false);
+                code[line_idx] = next_code;
+            }
+        }
+
+        code
+    }
+
+    /// Breaks at each bold-only line that starts a paragraph; bold text inside a
+    /// paragraph is emphasis, not a heading. Documents without any fall back to patterns.
+    fn find_bold_line_breaks(&self, lines: &[&str]) -> Vec<usize> {
+        let fenced = Self::fenced_lines(lines);
+        let breaks: Vec<usize> = lines
+            .iter()
+            .enumerate()
+            .filter(|&(line_idx, line)| {
+                let blank_before = line_idx == 0 || lines[line_idx - 1].trim().is_empty();
+                !fenced[line_idx] && blank_before && self.bold_title(line).is_some()
+            })
+            .map(|(line_idx, _)| line_idx)
+            .collect();
+
+        if breaks.is_empty() {
+            warn!("No bold heading lines found, falling back to pattern detection");
+            return self.find_pattern_breaks(lines);
+        }
+
+        breaks
+    }
+
+    /// Inner text of a line holding nothing but a bold span
+    fn bold_title(&self, line: &str) -> Option<String> {
+        let captures = self.bold_line_pattern.captures(line)?;
+        let title = captures.get(1).or_else(|| captures.get(2))?.as_str().trim();
+        (!title.is_empty()).then(|| title.to_string())
+    }
+
+    /// Breaks at the first line following each run of at least `min_run` blank lines
+    fn find_blank_run_breaks(lines: &[&str], min_run: usize) -> Vec<usize> {
+        let mut breaks = Vec::new();
+        let mut run = 0;
+        let mut seen_content = false;
+
+        for (line_idx, line) in lines.iter().enumerate() {
+            if line.trim().is_empty() {
+                run += 1;
+                continue;
+            }
+
+            if seen_content && run >= min_run.max(1) {
+                breaks.push(line_idx);
+            }
+            run = 0;
+            seen_content = true;
+        }
+
+        breaks
+    }
+
+    /// Finds printed page numbers in OCR'd text: lines holding nothing but digits,
+    /// surrounded by blank lines. Each number line starts the page it labels.
+    fn find_bare_number_breaks(lines: &[&str], max_delta: Option<usize>) -> Vec<usize> {
+        let mut breaks = Vec::new();
+        let mut previous_number: Option<usize> = None;
+
+        for (line_idx, line) in lines.iter().enumerate() {
+            let Some(number) = Self::bare_page_number(line) else {
+                continue;
+            };
+
+            // Numbered list items and stray figures sit inside paragraphs, page numbers don't
+            let blank_before = line_idx == 0 || lines[line_idx - 1].trim().is_empty();
+            let blank_after = lines.get(line_idx + 1).is_none_or(|next| next.trim().is_empty());
+            if !blank_before || !blank_after {
+                continue;
+            }
+
+            if let (Some(delta), Some(previous)) = (max_delta, previous_number) {
+                if number <= previous || number - previous > delta {
+                    debug!(
+                        "Ignoring bare number {} on line {} (previous page {})",
+                        number,
+                        line_idx + 1,
+                        previous
+                    );
+                    continue;
+                }
+            }
+
+            previous_number = Some(number);
+            breaks.push(line_idx);
+        }
+
+        breaks
+    }
+
+    /// Uses the top-level entries of a leading table of contents (a list of
+    /// `[text](#anchor)` links) as page breaks, at the headings they point to.
+    fn find_toc_breaks(&self, lines: &[&str]) -> Option<Vec<usize>> {
+        let toc_start = lines
+            .iter()
+            .take(TOC_SEARCH_LINES)
+            .position(|line| self.toc_entry_pattern.is_match(line))?;
+
+        // The TOC runs until the first line that is neither an entry nor blank
+        let mut entries = Vec::new();
+        let mut toc_end = toc_start;
+        for (line_idx, line) in lines.iter().enumerate().skip(toc_start) {
+            if let Some(captures) = self.toc_entry_pattern.captures(line) {
+                entries.push((self.indent_width(&captures[1]), captures[2].to_string()));
+                toc_end = line_idx + 1;
+            } else if !line.trim().is_empty() {
+                break;
+            }
+        }
+
+        let top_level = entries.iter().map(|(indent, _)| *indent).min()?;
+
+        // Anchors are numbered across the whole document, like GitHub does
+        let mut anchor_counts: HashMap<String, usize> = HashMap::new();
+        let mut heading_lines: HashMap<String, usize> = HashMap::new();
+        for (line_idx, line) in lines.iter().enumerate() {
+            let Some(captures) = self.title_pattern.captures(line) else {
+                continue;
+            };
+
+            let base = heading_anchor(&captures[2]);
+            let count = anchor_counts.entry(base.clone()).or_insert(0);
+            let anchor = if *count == 0 { base } else { format!("{}-{}", base, count) };
+            *count += 1;
+
+            if line_idx >= toc_end {
+                heading_lines.entry(anchor).or_insert(line_idx);
+            }
+        }
+
+        let mut breaks: Vec<usize> = entries
+            .iter()
+            .filter(|(indent, _)| *indent == top_level)
+            .filter_map(|(_, anchor)| {
+                let line = heading_lines.get(anchor.to_lowercase().as_str()).copied();
+                if line.is_none() {
+                    warn!("Table of contents entry '#{}' has no matching heading", anchor);
+                }
+                line
+            })
+            .collect();
+
+        breaks.sort_unstable();
+        breaks.dedup();
+
+        if breaks.is_empty() {
+            None
+        } else {
+            debug!("Resolved {} table of contents entries to page breaks", breaks.len());
+            Some(breaks)
+        }
+    }
+
+    fn bare_page_number(line: &str) -> Option<usize> {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.len() > 6 || !trimmed.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        trimmed.parse().ok()
+    }
+
+    fn extract_pages(
+        &self,
+        content: &str,
+        lines: &[&str],
+        page_breaks: &[usize],
+        merges: &mut Vec<PageMerge>,
+        on_page: &mut dyn FnMut(&MarkdownPage),
+    ) -> Result<Vec<MarkdownPage>> {
+        let pages = self.extract_raw_pages(content, lines, page_breaks)?;
+
+        // Explicit split modes produce authoritative pages
+        if self.options.split_by != SplitBy::Auto {
+            pages.iter().for_each(on_page);
+            return Ok(pages);
+        }
+
+        Ok(self.merge_small_pages(pages, merges, on_page))
+    }
+
+    /// Pages as cut at each detected break, before small pages are merged
+    pub fn parse_raw_pages(&self, content: &str) -> Result<Vec<MarkdownPage>> {
+        let lines: Vec<&str> = content.lines().collect();
+        let page_breaks = self.find_page_breaks(&lines);
+        self.extract_raw_pages(content, &lines, &page_breaks)
+    }
+
+    /// Byte offset in `content` where each of its `lines()` starts, whatever the
+    /// line ending (`\n` or `\r\n`)
+    fn line_offsets(content: &str) -> Vec<usize> {
+        let mut offset = 0;
+        content
+            .split_inclusive('\n')
+            .map(|line| {
+                let start = offset;
+                offset += line.len();
+                start
+            })
+            .collect()
+    }
+
+    /// Which raw pages [`Self::parse_document`] folds into the page before them, and why
+    pub fn explain_merges(&self, content: &str) -> Result<Vec<PageMerge>> {
+        if self.options.split_by != SplitBy::Auto {
+            return Ok(Vec::new());
+        }
+
+        let mut merges = Vec::new();
+        self.merge_small_pages(self.parse_raw_pages(content)?, &mut merges, &mut |_| {});
+        Ok(merges)
+    }
+
+    fn extract_raw_pages(
+        &self,
+        source: &str,
+        lines: &[&str],
+        page_breaks: &[usize],
+    ) -> Result<Vec<MarkdownPage>> {
         let mut pages = Vec::new();
+        let line_offsets = Self::line_offsets(source);
+        let (_, front_matter_end) = Self::front_matter(lines);
 
         for (page_idx, window) in page_breaks.windows(2).enumerate() {
             let start_line = window[0];
@@ -127,23 +998,82 @@ impl MarkdownParser {
                 break;
             }
 
-            let actual_end = std::cmp::min(end_line, lines.len());
+            let mut actual_end = std::cmp::min(end_line, lines.len());
+
+            // Leave the separating blank run out of the page; the gap stays visible in the line spans
+            if self.options.collapse_blank_runs
+                && matches!(self.options.split_by, SplitBy::BlankLineRun(_))
+            {
+                while actual_end > start_line + 1 && lines[actual_end - 1].trim().is_empty() {
+                    actual_end -= 1;
+                }
+            }
+
             let page_lines: Vec<&str> = lines[start_line..actual_end].to_vec();
             
             if page_lines.is_empty() {
                 continue;
             }
 
-            let content = page_lines.join("\n");
-            let title = self.extract_title(&page_lines);
-            let line_count = actual_end - start_line;
+            // Cell delimiters like "# %% [markdown]" are not titles
+            let is_cell = self.options.split_by == SplitBy::JupyterCells
+                && self.cell_delimiter_pattern.is_match(page_lines[0]);
+            let body_lines = if is_cell {
+                &page_lines[1..]
+            } else if start_line == 0 {
+                // Front matter lines are not titles either
+                &page_lines[front_matter_end.min(page_lines.len())..]
+            } else {
+                &page_lines[..]
+            };
+            let (content_start, content_lines) = if is_cell && self.options.strip_cell_delimiters {
+                (start_line + 1, body_lines)
+            } else {
+                (start_line, &page_lines[..])
+            };
+
+            let mut content = content_lines.join("\n");
+            let mut title = self.extract_title(body_lines);
+
+            // Form feeds only mark the boundary; keep them out of the page text
+            if content.contains(FORM_FEED) {
+                content = content.replace(FORM_FEED, "");
+                let body = body_lines.join("\n").replace(FORM_FEED, "");
+                title = self.extract_title(&body.lines().collect::<Vec<_>>());
+            }
+            if self.options.split_by == SplitBy::BoldLines {
+                if let Some(bold) = self.bold_title(page_lines[0]) {
+                    title = Some(bold);
+                }
+            }
+            let original_number = match self.options.split_by {
+                SplitBy::BareNumberLines { .. } => Self::bare_page_number(page_lines[0]),
+                _ => None,
+            };
+
+            let opening_line = content_lines.iter().position(|line| !line.trim().is_empty()).unwrap_or(0);
+            // An empty trailing cell whose delimiter is stripped starts past the last line
+            let start_offset = line_offsets.get(content_start).copied().unwrap_or(source.len());
+            let end_offset = match content_lines.last() {
+                Some(last) => line_offsets[actual_end - 1] + last.len(),
+                None => start_offset,
+            };
 
             let page = MarkdownPage {
                 number: page_idx + 1,
+                byte_len: content.len(),
+                word_count: content.split_whitespace().count(),
                 content,
                 title,
-                start_line,
+                start_line: content_start,
                 end_line: actual_end,
+                start_offset,
+                end_offset,
+                original_number,
+                break_marker: (page_idx > 0).then(|| page_lines[0].to_string()),
+                page_frontmatter: self
+                    .frontmatter_block(&content_lines[opening_line..])
+                    .map(|(entries, _)| entries),
             };
 
             pages.push(page);
@@ -155,73 +1085,236 @@ impl MarkdownParser {
             });
         }
 
-        // Merge small pages (likely gaps between real pages) into the previous page
+        Ok(pages)
+    }
+
+    /// Folds small untitled pages into the page before them. A page is final, and passed
+    /// to `on_page`, once the page after it starts a page of its own.
+    fn merge_small_pages(
+        &self,
+        pages: Vec<MarkdownPage>,
+        merges: &mut Vec<PageMerge>,
+        on_page: &mut dyn FnMut(&MarkdownPage),
+    ) -> Vec<MarkdownPage> {
         let mut merged_pages: Vec<MarkdownPage> = Vec::new();
         
-        for page in pages {
+        for mut page in pages {
             let line_count = page.end_line - page.start_line;
             
-            // If this is a small page (≤10 lines) and has no page marker title, merge it with previous
-            if line_count <= 10 && !self.has_page_marker_title(&page.title) && !merged_pages.is_empty() {
+            // Never grow a merged page past the configured cap
+            let within_cap = match (merged_pages.last(), self.options.max_merged_lines) {
+                (Some(prev), Some(max_lines)) => {
+                    prev.end_line - prev.start_line + line_count <= max_lines
+                }
+                _ => true,
+            };
+
+            // If this is a small page (≤ MERGE_MAX_LINES) without any title, merge it with previous.
+            // Titled pages (page markers or headings) and form-fed pages always start a page
+            // of their own.
+            let form_fed = page.break_marker.as_deref().is_some_and(|marker| marker.contains(FORM_FEED));
+            if line_count <= MERGE_MAX_LINES
+                && page.title.is_none()
+                && page.page_frontmatter.is_none()
+                && !form_fed
+                && within_cap
+                && !merged_pages.is_empty()
+            {
                 // Merge with the previous page
                 let prev_idx = merged_pages.len() - 1;
+                merges.push(PageMerge {
+                    raw_page: page.number,
+                    into_page: merged_pages[prev_idx].number,
+                    lines: line_count,
+                    reason: format!(
+                        "{} lines (at most {}) and no title or page marker",
+                        line_count, MERGE_MAX_LINES
+                    ),
+                });
                 
-                // Append content with a separator
-                merged_pages[prev_idx].content.push_str("\n\n");
+                // Append content on the next line, exactly as in the source
+                merged_pages[prev_idx].content.push('\n');
                 merged_pages[prev_idx].content.push_str(&page.content);
                 merged_pages[prev_idx].end_line = page.end_line;
+                merged_pages[prev_idx].end_offset = page.end_offset;
+                merged_pages[prev_idx].byte_len += 1 + page.byte_len;
+                merged_pages[prev_idx].word_count += page.word_count;
             } else {
+                if let Some(prev) = merged_pages.last() {
+                    on_page(prev);
+                }
+                page.number = merged_pages.len() + 1;
                 merged_pages.push(page);
             }
         }
-        
-        // Renumber pages after merging
-        for (idx, page) in merged_pages.iter_mut().enumerate() {
-            page.number = idx + 1;
+        if let Some(last) = merged_pages.last() {
+            on_page(last);
         }
 
-        Ok(merged_pages)
+        merged_pages
     }
 
     fn extract_title(&self, lines: &[&str]) -> Option<String> {
-        for line in lines.iter().take(10) { // Check first 10 lines for title
+        let fenced = Self::fenced_lines(lines);
+        // Check first 10 lines for title; comments in code blocks are not headings
+        for (line_idx, line) in lines.iter().enumerate().take(10).filter(|&(idx, _)| !fenced[idx]) {
             if let Some(captures) = self.title_pattern.captures(line) {
                 if let Some(title) = captures.get(2) {
                     return Some(title.as_str().trim().to_string());
                 }
             }
+            let underline = lines.get(line_idx + 1).filter(|_| !fenced[line_idx + 1]);
+            if underline.is_some_and(|underline| self.setext_level(line, underline).is_some()) {
+                return Some(line.trim().to_string());
+            }
         }
         None
     }
 
-    fn has_page_marker_title(&self, title: &Option<String>) -> bool {
-        if let Some(title_text) = title {
-            // Check if the title looks like a page marker
-            title_text.to_lowercase().contains("page ") && 
-            title_text.chars().any(|c| c.is_ascii_digit())
-        } else {
-            false
+    /// Level of the setext heading formed by `text` and the `underline` below it: 1 for
+    /// `===`, 2 for `---`. A `---` under a blank line, list item or other block is a rule.
+    fn setext_level(&self, text: &str, underline: &str) -> Option<usize> {
+        let captures = self.setext_underline_pattern.captures(underline)?;
+        self.is_paragraph_text(text)
+            .then(|| if captures.get(1).is_some() { 1 } else { 2 })
+    }
+
+    /// Whether a line can be (part of) the text of a setext heading
+    fn is_paragraph_text(&self, line: &str) -> bool {
+        !line.trim().is_empty()
+            && self.indent_width(line) < 4
+            && !line.trim_start().starts_with('>')
+            && !self.atx_heading_pattern.is_match(line)
+            && !self.thematic_break_pattern.is_match(line)
+            && !self.setext_underline_pattern.is_match(line)
+            && !self.list_item_pattern.is_match(line)
+            && !self.html_block_pattern.is_match(line)
+    }
+
+    /// Re-emits the document as one normalized markdown file: ATX headings as `## Title`
+    /// set off by blank lines, thematic breaks as `---`, blank-line runs collapsed to one
+    /// and trailing whitespace dropped (hard breaks keep two spaces). Fenced and indented
+    /// code blocks and frontmatter are copied unchanged. Formatting the result again changes nothing.
+    pub fn format_document(&self, document: &MarkdownDocument) -> String {
+        let text: Vec<&str> = document.pages.iter().map(|page| page.content.as_str()).collect();
+        let text = text.join("\n");
+        let lines: Vec<&str> = text.lines().collect();
+        let fenced = Self::fenced_lines(&lines);
+        let indented = self.indented_code_lines(&lines, &fenced);
+        let mut verbatim_until = 0;
+        let mut output: Vec<String> = Vec::new();
+        // Whether the next non-blank line needs a blank line before it
+        let mut separate_next = false;
+
+        for (line_idx, line) in lines.iter().enumerate() {
+            let trimmed = line.trim_end();
+            let previous_blank = output.last().is_none_or(|previous| previous.is_empty());
+            if let Some((_, close)) = self.frontmatter_block(&lines[line_idx..]).filter(|_| !fenced[line_idx]) {
+                verbatim_until = verbatim_until.max(line_idx + close + 1);
+            }
+
+            let (formatted, separate) = if fenced[line_idx] || indented[line_idx] || line_idx < verbatim_until {
+                (line.to_string(), false)
+            } else if trimmed.is_empty() {
+                if !previous_blank {
+                    output.push(String::new());
+                }
+                continue;
+            } else if let Some(captures) = self.atx_heading_pattern.captures(trimmed) {
+                (format!("{} {}", &captures[1], &captures[2]).trim_end().to_string(), true)
+            } else if self.thematic_break_pattern.is_match(trimmed)
+                // Dashes right under text underline a setext heading
+                && (previous_blank || trimmed.trim_start().chars().any(|c| c != '-'))
+            {
+                ("---".to_string(), true)
+            } else if line.ends_with("  ") {
+                (format!("{}  ", trimmed), false)
+            } else {
+                (trimmed.to_string(), false)
+            };
+
+            if (separate || separate_next) && !previous_blank && !formatted.trim().is_empty() {
+                output.push(String::new());
+            }
+            separate_next = separate;
+            output.push(formatted);
         }
+
+        while output.last().is_some_and(|line| line.is_empty()) {
+            output.pop();
+        }
+        let mut formatted = output.join("\n");
+        formatted.push('\n');
+        formatted
     }
 
-    pub fn get_parsing_stats(&self, document: &MarkdownDocument) -> HashMap<String, serde_json::Value> {
-        let mut stats = HashMap::new();
-        
-        stats.insert("total_pages".to_string(), document.total_pages.into());
-        stats.insert("total_lines".to_string(), document.metadata.total_lines.into());
-        stats.insert("page_breaks".to_string(), document.metadata.page_breaks.len().into());
-        
-        let pages_with_titles = document.pages.iter().filter(|p| p.title.is_some()).count();
-        stats.insert("pages_with_titles".to_string(), pages_with_titles.into());
-        
+    /// Parses the content and gathers its statistics, as reported by `analyze`
+    pub fn analyze(&self, content: &str, metadata: DocumentMetadata) -> Result<AnalysisReport> {
+        let document = self.parse_document(content, metadata)?;
+        let stats = self.get_parsing_stats(&document);
+        Ok(AnalysisReport {
+            document,
+            stats,
+            merges: Vec::new(),
+            violations: Vec::new(),
+        })
+    }
+
+    /// Describes each way the document falls short of `limits`; empty when it meets them all
+    pub fn check_structure(document: &MarkdownDocument, limits: &StructureLimits) -> Vec<String> {
+        let mut violations = Vec::new();
+
+        if let Some(min_pages) = limits.min_pages {
+            if document.total_pages < min_pages {
+                violations.push(format!(
+                    "{} pages found, at least {} required",
+                    document.total_pages, min_pages
+                ));
+            }
+        }
+
+        if let Some(max_bytes) = limits.max_page_bytes {
+            for page in document.pages.iter().filter(|page| page.content.len() > max_bytes) {
+                violations.push(format!(
+                    "Page {} is {} bytes, over the limit of {}",
+                    page.number,
+                    page.content.len(),
+                    max_bytes
+                ));
+            }
+        }
+
+        if limits.require_titles {
+            let untitled: Vec<String> = document
+                .pages
+                .iter()
+                .filter(|page| page.title.is_none())
+                .map(|page| page.number.to_string())
+                .collect();
+            if !untitled.is_empty() {
+                violations.push(format!("Pages without a title: {}", untitled.join(", ")));
+            }
+        }
+
+        violations
+    }
+
+    pub fn get_parsing_stats(&self, document: &MarkdownDocument) -> ParsingStats {
         let avg_lines_per_page = if document.total_pages > 0 {
             document.metadata.total_lines as f64 / document.total_pages as f64
         } else {
             0.0
         };
-        stats.insert("avg_lines_per_page".to_string(), avg_lines_per_page.into());
-        
-        stats
+
+        ParsingStats {
+            total_pages: document.total_pages,
+            total_lines: document.metadata.total_lines,
+            page_breaks: document.metadata.page_breaks.len(),
+            pages_with_titles: document.pages.iter().filter(|p| p.title.is_some()).count(),
+            avg_lines_per_page,
+            page_bytes: Distribution::of(document.pages.iter().map(|p| p.byte_len)),
+            page_words: Distribution::of(document.pages.iter().map(|p| p.word_count)),
+        }
     }
 }
 
@@ -1,254 +1,2996 @@
 use crate::error::{MarkdownSplitterError, Result};
-use crate::types::{MarkdownDocument, MarkdownPage, SplitConfig, SplitResult};
-use serde_json;
-use std::path::PathBuf;
+use crate::services::{heading_anchor, MarkdownParser, Tokenizer};
+use crate::types::{
+    BoilerplateLine, CodeBlockInfo, CrossSplitAnchor, DataUriSplit, DocumentMetadata, Distribution, LineEnding, LineLocation, ManifestEntry, ManifestMode, MarkdownDocument, MarkdownPage, OutlineEntry, OverwritePolicy, PageInfo, PlannedSplit, PlanStrategy, RemainderPolicy, RotationConfig,
+    SectionLimit, SectionPart, SplitConfig,
+    SplitInfo, SplitLimitPolicy, SplitManifest, SplitMetadata, SplitOrder, SplitPlan, SplitResult, SplitRunReport, SplitStatistics, SplitStrategy, SplitWarning, TokenModel,
+    WarningKind,
+};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use regex::Regex;
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::future::Future;
+use std::io::ErrorKind;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+use std::time::Duration;
+use tempfile::TempDir;
 use tokio::fs;
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
+use url::Url;
+
+/// Characters percent-encoded in table of contents link targets: whitespace and
+/// everything that would end the link or start a query or fragment
+const LINK_TARGET: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'(')
+    .add(b')')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'[')
+    .add(b']')
+    .add(b'\\');
+
+/// Separator written between pages when structure is preserved
+const PAGE_SEPARATOR: &str = "\n\n---\n\n";
+
+/// Text identifying the header comment written at the top of each split
+const SPLIT_HEADER_LABEL: &str = "Split containing pages";
+
+/// Number of hash bytes kept in a chunk ID (rendered as twice as many hex digits)
+const CHUNK_ID_BYTES: usize = 8;
+
+/// Prefix of the data URIs produced by [`DocumentSplitter::write_data_uri_list`]
+const DATA_URI_PREFIX: &str = "data:text/markdown;base64,";
+
+/// Lines recurring on more than this share of pages are treated as boilerplate
+const BOILERPLATE_PAGE_SHARE: f64 = 0.7;
+
+/// Documents with fewer pages are too short to tell boilerplate from content
+const BOILERPLATE_MIN_PAGES: usize = 3;
+
+/// Bytes per token assumed for the estimates in a [`SplitPlan`]
+const BYTES_PER_TOKEN: usize = 4;
+
+/// Deepest bisection allowed, i.e. at most 2^10 files
+const MAX_BISECT_DEPTH: usize = 10;
+
+/// Group of pages without the `group_by_field` frontmatter field
+const DEFAULT_GROUP: &str = "ungrouped";
+
+/// `{title}` in a filename template for splits whose pages have no title
+const UNTITLED_SLUG: &str = "untitled";
+
+/// Suffix of the single file written with `SplitConfig::annotate_only`
+const ANNOTATED_SUFFIX: &str = "annotated";
+
+/// Splits are reported as imbalanced when the largest is over this many times the smallest
+const IMBALANCE_RATIO: usize = 2;
+
+/// Attempts to take a manifest's lock file, and the wait between them, before giving up
+const MANIFEST_LOCK_ATTEMPTS: usize = 100;
+const MANIFEST_LOCK_DELAY: Duration = Duration::from_millis(50);
+
+/// Wait before the first retry of a failed write; doubled for each further attempt
+const WRITE_RETRY_DELAY: Duration = Duration::from_millis(50);
+
+/// A `](#anchor)` link to a heading in the same document
+static ANCHOR_LINK: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\]\(#([^)\s]+)\)").unwrap());
+
+/// Lock file held while a manifest is read and rewritten, removed on drop
+struct ManifestLock(PathBuf);
+
+impl Drop for ManifestLock {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// A split file's content: injected header, joined pages and trailing text
+struct RenderedSplit {
+    header: String,
+    body: String,
+    footer: String,
+}
+
+/// Optional lines written above a split's first page: front matter before the
+/// structure comment, the rest after it
+struct SplitHeader<'a> {
+    front_matter: Option<&'a str>,
+    comment_metadata: Option<&'a BTreeMap<String, String>>,
+    summary: Option<String>,
+    part_heading: Option<String>,
+    chunk_id: Option<&'a str>,
+    origin: Option<&'a DocumentMetadata>,
+    heading_context: Vec<String>,
+    /// Trailing pages of the previous split and that split's number
+    overlap: Option<(&'a [MarkdownPage], usize)>,
+}
+
+/// A split rendered in memory, ready to be written
+struct AssembledSplit {
+    path: PathBuf,
+    content: String,
+    info: SplitInfo,
+}
+
+/// All splits of a document, rendered but not yet written
+struct Assembly {
+    splits: Vec<AssembledSplit>,
+    pages_per_split: usize,
+    cross_split_anchors: Vec<CrossSplitAnchor>,
+    boilerplate: Vec<BoilerplateLine>,
+    warnings: Vec<SplitWarning>,
+}
+
+impl RenderedSplit {
+    fn content(&self) -> String {
+        format!("{}{}{}", self.header, self.body, self.footer)
+    }
+}
+
+/// Headings enclosing the current position while walking a document page by page
+#[derive(Default)]
+struct HeadingOutline {
+    stack: Vec<(usize, String)>,
+}
+
+impl HeadingOutline {
+    /// ATX heading level of a line, if it is one
+    fn heading_level(line: &str) -> Option<usize> {
+        let level = line.chars().take_while(|&c| c == '#').count();
+        let rest = &line[level..];
+        ((1..=6).contains(&level) && (rest.is_empty() || rest.starts_with(' '))).then_some(level)
+    }
+
+    /// First non-blank line of a page, looking past a break marker that is not a heading
+    fn opening_line(page: &MarkdownPage) -> Option<&str> {
+        let mut lines = page.content.lines().filter(|line| !line.trim().is_empty());
+        let first_line = lines.next();
+        match (first_line, page.break_marker.as_deref()) {
+            (Some(line), Some(marker)) if line == marker && Self::heading_level(line).is_none() => {
+                lines.next()
+            }
+            _ => first_line,
+        }
+    }
+
+    /// Ancestor headings in effect where `page` starts, outermost first
+    fn context_for(&self, page: &MarkdownPage) -> Vec<String> {
+        // A page opening with its own heading only inherits the levels above it
+        let opening_level = Self::opening_line(page)
+            .and_then(Self::heading_level)
+            .unwrap_or(usize::MAX);
+
+        self.stack
+            .iter()
+            .filter(|(level, _)| *level < opening_level)
+            .map(|(_, line)| line.clone())
+            .collect()
+    }
+
+    /// Moves past `pages` of `document`, returning the headings found on them
+    fn advance(&mut self, document: &MarkdownDocument, pages: &[MarkdownPage]) -> Vec<OutlineEntry> {
+        let mut headings = Vec::new();
+        for page in pages {
+            let page_lines = page.start_line..page.end_line;
+            for heading in document.headings.iter().filter(|heading| page_lines.contains(&heading.line)) {
+                self.stack.retain(|(existing, _)| *existing < heading.level);
+                self.stack.push((heading.level, format!("{} {}", "#".repeat(heading.level), heading.text)));
+                headings.push(heading.clone());
+            }
+        }
+        headings
+    }
+}
 
 pub struct DocumentSplitter;
 
+/// Weight of a page when balancing splits
+type PageCost<'a> = &'a dyn Fn(&MarkdownPage) -> usize;
+
 impl DocumentSplitter {
     pub async fn split_document(
         document: &MarkdownDocument,
         config: &SplitConfig,
     ) -> Result<SplitResult> {
+        Self::split_planned(document, config, None).await
+    }
+
+    /// Splits into a fresh temporary directory instead of `config.output_dir`. The files
+    /// are removed when the returned `TempDir` is dropped, so keep it alive while the
+    /// `SplitResult` paths are in use.
+    pub async fn split_to_tempdir(
+        document: &MarkdownDocument,
+        config: &SplitConfig,
+    ) -> Result<(SplitResult, TempDir)> {
+        let temp_dir = tempfile::Builder::new().prefix("md-split-").tempdir()?;
+        let config = SplitConfig {
+            output_dir: temp_dir.path().to_path_buf(),
+            ..config.clone()
+        };
+        let result = Self::split_document(document, &config).await?;
+        Ok((result, temp_dir))
+    }
+
+    /// Splits so that the summed `cost_fn` of each split's pages is as even as possible,
+    /// instead of giving every split the same number of pages. See [`Self::page_bytes`]
+    /// and [`Self::page_words`] for the built-in costs.
+    pub async fn split_document_balanced(
+        document: &MarkdownDocument,
+        config: &SplitConfig,
+        cost_fn: impl Fn(&MarkdownPage) -> usize,
+    ) -> Result<SplitResult> {
+        Self::split_planned(document, config, Some(&cost_fn)).await
+    }
+
+    /// Parses in-memory content under the given logical filename and splits it.
+    pub async fn split_str(
+        content: &str,
+        filename: &str,
+        parser: &MarkdownParser,
+        config: &SplitConfig,
+    ) -> Result<SplitResult> {
+        let document = parser.parse_str(content, filename)?;
+        Self::split_document(&document, config).await
+    }
+
+    /// Balancing cost: page size in bytes
+    pub fn page_bytes(page: &MarkdownPage) -> usize {
+        page.content.len()
+    }
+
+    /// Balancing cost: number of words on the page
+    pub fn page_words(page: &MarkdownPage) -> usize {
+        page.content.split_whitespace().count()
+    }
+
+    /// Balancing cost: number of lines on the page, as used by `SplitStrategy::Balanced`
+    pub fn page_lines(page: &MarkdownPage) -> usize {
+        page.content.lines().count()
+    }
+
+    /// Plans the splits right away and returns the future that writes them. `cost_fn`
+    /// is only needed for planning, so the future does not hold on to it and stays
+    /// `Send` whatever the cost function is.
+    fn split_planned<'a>(
+        document: &'a MarkdownDocument,
+        config: &'a SplitConfig,
+        cost_fn: Option<PageCost<'_>>,
+    ) -> impl Future<Output = Result<SplitResult>> + 'a {
         info!(
             "Splitting document '{}' into {} splits",
             document.source, config.splits
         );
 
-        // Validate split configuration
-        Self::validate_split_config(document, config)?;
+        let planned = Self::select_pages(document, config).and_then(|document| {
+            let assembly = Self::assemble_splits(document.as_ref(), config, cost_fn)?;
+            Ok((document, assembly))
+        });
+        async move { Self::write_planned(planned?, config).await }
+    }
 
-        // Ensure output directory exists
-        Self::ensure_output_directory(&config.output_dir).await?;
+    async fn write_planned(
+        (document, assembly): (Cow<'_, MarkdownDocument>, Assembly),
+        config: &SplitConfig,
+    ) -> Result<SplitResult> {
+        let document = document.as_ref();
+        if config.annotate_only {
+            return Self::write_annotated(document, config, assembly).await;
+        }
+
+        let metadata_path = config
+            .include_metadata
+            .then(|| Self::generate_metadata_filename(&config.output_dir, &document.source));
+        let toc_path = config
+            .generate_toc
+            .then(|| Self::generate_toc_filename(&config.output_dir, &document.source));
+        let code_blocks = config
+            .extract_code_dir
+            .as_deref()
+            .map(|code_dir| Self::code_blocks(document, code_dir))
+            .unwrap_or_default();
 
-        let pages_per_split = (document.total_pages + config.splits - 1) / config.splits; // Ceiling division
+        // Ensure output directory exists
+        if !config.dry_run {
+            let targets: Vec<&Path> = assembly
+                .splits
+                .iter()
+                .map(|split| split.path.as_path())
+                .chain(metadata_path.as_deref())
+                .chain(toc_path.as_deref())
+                .chain(code_blocks.iter().map(|(path, _, _)| path.as_path()))
+                .collect();
+            Self::check_overwrites(&targets, config).await?;
+            Self::ensure_output_directory(&config.output_dir).await?;
+        }
         let mut output_files = Vec::new();
+        let mut splits = Vec::new();
+        let mut skipped_files = Vec::new();
         let mut actual_pages = 0;
+        let line_ending = config.line_ending.or(document.line_ending);
 
-        // Split the document
-        for split_idx in 0..config.splits {
-            let start_page = split_idx * pages_per_split;
-            let end_page = std::cmp::min(start_page + pages_per_split, document.total_pages);
-
-            if start_page >= document.total_pages {
-                break; // No more pages to split
+        for split in assembly.splits {
+            if !config.dry_run {
+                if let Some(split_dir) = split.path.parent().filter(|_| split.info.shard.is_some()) {
+                    Self::ensure_output_directory(split_dir).await?;
+                }
+                if !Self::write_split_file(&split.path, &split.content, config, line_ending).await? {
+                    skipped_files.push(split.path.clone());
+                }
             }
 
-            let split_pages = &document.pages[start_page..end_page];
-            actual_pages += split_pages.len();
-
-            let output_file = Self::generate_output_filename(
-                &config.output_dir,
-                &document.source,
-                split_idx + 1,
-                config.splits,
-            );
-
-            // Extract base document name for the split marker
-            let base_document_name = std::path::Path::new(&document.source)
-                .file_stem()
-                .and_then(|s| s.to_str())
-                .unwrap_or("document");
-
-            Self::write_split_file(&output_file, split_pages, config, base_document_name).await?;
-            output_files.push(output_file);
-
             debug!(
                 "Created split {} with {} pages (pages {}-{})",
-                split_idx + 1,
-                split_pages.len(),
-                start_page + 1,
-                end_page
+                split.info.split_number,
+                split.info.pages.len(),
+                split.info.start_page,
+                split.info.end_page
             );
+            actual_pages += split.info.pages.len();
+            splits.push(split.info);
+            output_files.push(split.path);
         }
 
-        // Generate metadata file if requested
-        let metadata_file = if config.include_metadata {
-            let metadata_path = Self::generate_metadata_filename(&config.output_dir, &document.source);
-            Self::write_metadata_file(&metadata_path, document, &output_files).await?;
-            Some(metadata_path)
-        } else {
-            None
+        let code_blocks = match &config.extract_code_dir {
+            Some(code_dir) => Self::extract_code_blocks(code_blocks, code_dir, config).await?,
+            None => Vec::new(),
         };
 
+        // Generate metadata file if requested
+        if let Some(metadata_path) = metadata_path.as_deref().filter(|_| !config.dry_run) {
+            Self::write_metadata_file(metadata_path, document, config, &splits, &code_blocks).await?;
+        }
+
+        let per_split_lines = splits.iter().map(|info| info.lines).collect();
+        if let Some(toc_path) = toc_path.as_deref().filter(|_| !config.dry_run) {
+            let toc = Self::toc_markdown(&document.source, &config.output_dir, &splits);
+            if Self::write_output_file(toc_path, toc.as_bytes(), config, "table of contents").await? {
+                info!("Generated table of contents: {}", toc_path.display());
+            }
+        }
+
         let result = SplitResult {
             split_number: output_files.len(),
-            pages_per_split,
+            pages_per_split: assembly.pages_per_split,
             actual_pages,
             output_files,
-            metadata_file,
+            metadata_file: metadata_path,
+            splits,
+            cross_split_anchors: assembly.cross_split_anchors,
+            code_blocks,
+            removed_boilerplate: assembly.boilerplate,
+            warnings: assembly.warnings,
+            skipped_files,
+            toc_file: toc_path,
+            per_split_lines,
         };
 
-        info!(
-            "Successfully split document into {} files with {} total pages",
-            result.split_number, result.actual_pages
-        );
+        if config.dry_run {
+            info!(
+                "Dry run: would split document into {} files with {} total pages",
+                result.split_number, result.actual_pages
+            );
+        } else {
+            info!(
+                "Successfully split document into {} files with {} total pages",
+                result.split_number, result.actual_pages
+            );
+        }
 
         Ok(result)
     }
 
-    fn validate_split_config(document: &MarkdownDocument, config: &SplitConfig) -> Result<()> {
-        if config.splits == 0 {
-            return Err(MarkdownSplitterError::SplitConfig {
-                reason: "Number of splits must be greater than 0".to_string(),
-            });
-        }
+    /// Writes the document once, unchanged but for a marker line in front of the first
+    /// page of every split after the first, so re-parsing it with that marker as
+    /// `custom_page_marker` finds the same boundaries
+    async fn write_annotated(
+        document: &MarkdownDocument,
+        config: &SplitConfig,
+        assembly: Assembly,
+    ) -> Result<SplitResult> {
+        let mut boundaries: Vec<(usize, usize)> = assembly
+            .splits
+            .iter()
+            .map(|split| (split.info.document_order, split.info.start_page))
+            .collect();
+        boundaries.sort_unstable();
 
-        if document.total_pages == 0 {
-            return Err(MarkdownSplitterError::SplitConfig {
-                reason: "Document has no pages to split".to_string(),
-            });
+        let mut annotated = Vec::with_capacity(document.pages.len() + boundaries.len());
+        for page in &document.pages {
+            let boundary = boundaries.iter().skip(1).position(|&(_, start)| start == page.number);
+            if let Some(idx) = boundary {
+                let marker = match &config.custom_page_marker {
+                    Some(marker) => marker.clone(),
+                    None => format!("<!-- SPLIT {} -->", idx + 2),
+                };
+                annotated.push(marker);
+            }
+            annotated.push(page.content.clone());
+        }
+        let mut content = annotated.join("\n");
+        if document.trailing_newline {
+            content.push('\n');
         }
 
-        if config.splits > document.total_pages {
-            return Err(MarkdownSplitterError::SplitConfig {
-                reason: format!(
-                    "Number of splits ({}) cannot exceed total pages ({})",
-                    config.splits, document.total_pages
-                ),
-            });
+        let path = config
+            .output_dir
+            .join(Self::suffixed_filename(&document.source, ANNOTATED_SUFFIX));
+        let mut skipped_files = Vec::new();
+        if config.dry_run {
+            info!("Dry run: would write {} with {} split markers", path.display(), boundaries.len().saturating_sub(1));
+        } else {
+            Self::ensure_output_directory(&config.output_dir).await?;
+            let line_ending = config.line_ending.or(document.line_ending);
+            if !Self::write_split_file(&path, &content, config, line_ending).await? {
+                skipped_files.push(path.clone());
+            }
+            info!("Wrote {} with {} split markers", path.display(), boundaries.len().saturating_sub(1));
         }
 
-        Ok(())
+        Ok(SplitResult {
+            split_number: assembly.splits.len(),
+            pages_per_split: assembly.pages_per_split,
+            actual_pages: document.total_pages,
+            output_files: vec![path],
+            metadata_file: None,
+            splits: Vec::new(),
+            cross_split_anchors: Vec::new(),
+            code_blocks: Vec::new(),
+            removed_boilerplate: Vec::new(),
+            warnings: assembly.warnings,
+            skipped_files,
+            toc_file: None,
+            per_split_lines: Vec::new(),
+        })
     }
 
-    async fn ensure_output_directory(output_dir: &PathBuf) -> Result<()> {
-        if !output_dir.exists() {
-            fs::create_dir_all(output_dir).await.map_err(|e| {
-                MarkdownSplitterError::OutputDirectory {
-                    reason: format!("Failed to create output directory: {}", e),
-                }
-            })?;
-            info!("Created output directory: {}", output_dir.display());
-        }
-        Ok(())
+    /// Encodes each split as a `data:text/markdown;base64,...` URI instead of writing
+    /// split files, and writes the list as `<name>_data_uris.json` in the output directory.
+    /// The splits are planned before the returned future is polled, so it does not hold
+    /// on to `cost_fn`.
+    pub fn write_data_uri_list<'a>(
+        document: &'a MarkdownDocument,
+        config: &'a SplitConfig,
+        cost_fn: Option<&dyn Fn(&MarkdownPage) -> usize>,
+    ) -> impl Future<Output = Result<PathBuf>> + 'a {
+        let annotated = if config.annotate_only {
+            Err(MarkdownSplitterError::SplitConfig {
+                reason: "Annotating in place writes no split files to encode as data URIs".to_string(),
+            })
+        } else {
+            Ok(())
+        };
+        let planned = annotated.and_then(|_| Self::select_pages(document, config)).and_then(|document| {
+            let assembly = Self::assemble_splits(document.as_ref(), config, cost_fn)?;
+            Ok((document, assembly))
+        });
+        async move { Self::write_data_uris(planned?, config).await }
     }
 
-    fn generate_output_filename(
-        output_dir: &PathBuf,
-        source_name: &str,
-        split_number: usize,
-        total_splits: usize,
-    ) -> PathBuf {
-        let base_name = std::path::Path::new(source_name)
-            .file_stem()
-            .and_then(|s| s.to_str())
-            .unwrap_or("document");
+    async fn write_data_uris(
+        (document, assembly): (Cow<'_, MarkdownDocument>, Assembly),
+        config: &SplitConfig,
+    ) -> Result<PathBuf> {
+        let document = document.as_ref();
+        let line_ending = config.line_ending.or(document.line_ending);
+        let entries: Vec<DataUriSplit> = assembly
+            .splits
+            .into_iter()
+            .map(|split| DataUriSplit {
+                data_uri: format!("{}{}", DATA_URI_PREFIX, BASE64.encode(line_ending.apply(&split.content).as_bytes())),
+                split_number: split.info.split_number,
+                document_order: split.info.document_order,
+                filename: split.info.filename,
+                start_page: split.info.start_page,
+                end_page: split.info.end_page,
+                bytes: split.info.bytes,
+                title: split.info.title,
+                chunk_id: split.info.chunk_id,
+            })
+            .collect();
 
-        let filename = format!(
-            "{}_split_{:0width$}_of_{}.md",
-            base_name,
-            split_number,
-            total_splits,
-            width = total_splits.to_string().len()
-        );
+        let list_path = config.output_dir.join(format!("{}_data_uris.json", Self::base_name(&document.source)));
+        if config.dry_run {
+            info!("Dry run: would encode {} splits of '{}' into {}", entries.len(), document.source, list_path.display());
+            return Ok(list_path);
+        }
 
-        output_dir.join(filename)
-    }
+        Self::ensure_output_directory(&config.output_dir).await?;
 
-    fn generate_metadata_filename(output_dir: &PathBuf, source_name: &str) -> PathBuf {
-        let base_name = std::path::Path::new(source_name)
-            .file_stem()
-            .and_then(|s| s.to_str())
-            .unwrap_or("document");
+        let json_content = serde_json::to_string_pretty(&entries).map_err(|e| {
+            MarkdownSplitterError::OutputDirectory {
+                reason: format!("Failed to serialize data URI list: {}", e),
+            }
+        })?;
+        if !Self::write_output_file(&list_path, json_content.as_bytes(), config, "data URI list").await? {
+            return Ok(list_path);
+        }
 
-        let filename = format!("{}_metadata.json", base_name);
-        output_dir.join(filename)
+        info!(
+            "Encoded {} splits of '{}' into {}",
+            entries.len(),
+            document.source,
+            list_path.display()
+        );
+        Ok(list_path)
     }
 
-    async fn write_split_file(
-        output_path: &PathBuf,
-        pages: &[MarkdownPage],
+    /// Describes the files `split_document` (or `split_document_balanced`, given a
+    /// `cost_fn`) would write, without touching the output directory. The plan is built
+    /// from the same rendering pass, so names, page ranges and sizes match the real run.
+    pub fn plan(
+        document: &MarkdownDocument,
         config: &SplitConfig,
-        document_name: &str,
-    ) -> Result<()> {
-        let mut content = String::new();
+        cost_fn: Option<&dyn Fn(&MarkdownPage) -> usize>,
+    ) -> Result<SplitPlan> {
+        let document = Self::select_pages(document, config)?;
+        let document = document.as_ref();
+        let assembly = Self::assemble_splits(document, config, cost_fn)?;
+        let strategy = match (config.bisect_depth, config.section_limit, cost_fn) {
+            _ if config.group_by_field.is_some() => PlanStrategy::Group,
+            (Some(_), _, _) => PlanStrategy::Bisect,
+            _ if matches!(config.strategy, SplitStrategy::MaxWords(_) | SplitStrategy::MaxTokens { .. }) => {
+                PlanStrategy::Packed
+            }
+            (None, Some(_), _) => PlanStrategy::Sections,
+            (None, None, Some(_)) => PlanStrategy::Balanced,
+            (None, None, None) if config.strategy == SplitStrategy::Balanced => PlanStrategy::Balanced,
+            (None, None, None) => PlanStrategy::EvenPages,
+        };
+        let files = assembly
+            .splits
+            .into_iter()
+            .map(|split| PlannedSplit {
+                split_number: split.info.split_number,
+                document_order: split.info.document_order,
+                filename: split.info.filename,
+                start_page: split.info.start_page,
+                end_page: split.info.end_page,
+                bytes: split.info.bytes,
+                words: split.info.words,
+                estimated_tokens: split.info.bytes.div_ceil(BYTES_PER_TOKEN),
+            })
+            .collect();
 
-        // Add header if preserving structure
-        if config.preserve_structure {
-            // Extract clean document name (remove _structured_markdown suffix)
-            let clean_document_name = document_name
-                .strip_suffix("_structured_markdown")
-                .unwrap_or(document_name);
+        Ok(SplitPlan {
+            source: document.source.clone(),
+            strategy,
+            total_pages: document.total_pages,
+            files,
+        })
+    }
 
-            content.push_str(&format!(
-                "<!-- {} Split containing pages {} to {} -->\n\n",
-                clean_document_name,
-                pages.first().map(|p| p.number).unwrap_or(1),
-                pages.last().map(|p| p.number).unwrap_or(1)
-            ));
+    /// Maps a 1-based source line to the page and split file it would be written to
+    pub fn locate_line(
+        document: &MarkdownDocument,
+        config: &SplitConfig,
+        cost_fn: Option<&dyn Fn(&MarkdownPage) -> usize>,
+        line: usize,
+    ) -> Result<LineLocation> {
+        let total_lines = document.metadata.total_lines;
+        if line == 0 || line > total_lines {
+            return Err(MarkdownSplitterError::SplitConfig {
+                reason: format!("Line {} is outside {} (lines 1-{})", line, document.source, total_lines),
+            });
         }
 
-        // Combine page contents
-        for (idx, page) in pages.iter().enumerate() {
-            if idx > 0 && config.preserve_structure {
-                content.push_str("\n\n---\n\n"); // Page separator
-            }
-            content.push_str(&page.content);
+        let page = document
+            .pages
+            .iter()
+            .find(|page| page.start_line < line && line <= page.end_line)
+            .ok_or_else(|| MarkdownSplitterError::SplitConfig {
+                reason: format!("Line {} of {} is not part of any page", line, document.source),
+            })?;
+        let selected = Self::select_pages(document, config)?;
+        if !selected.pages.iter().any(|selected_page| selected_page.start_line == page.start_line) {
+            return Err(MarkdownSplitterError::SplitConfig {
+                reason: format!("Page {} of {} is dropped by the title filter", page.number, document.source),
+            });
         }
+        // Grouped splits reorder pages, so look the page up in each file's own page list;
+        // a page repeated as overlap belongs to the first split holding it
+        let assembly = Self::assemble_splits(selected.as_ref(), config, cost_fn)?;
+        let split = assembly
+            .splits
+            .into_iter()
+            .filter(|split| split.info.pages.iter().any(|info| info.start_line == page.start_line))
+            .min_by_key(|split| split.info.document_order)
+            .ok_or_else(|| MarkdownSplitterError::SplitConfig {
+                reason: format!("Page {} of {} is not in any split", page.number, document.source),
+            })?;
 
-        fs::write(output_path, content).await.map_err(|e| {
-            MarkdownSplitterError::OutputDirectory {
-                reason: format!("Failed to write split file {}: {}", output_path.display(), e),
+        Ok(LineLocation {
+            line,
+            page: page.number,
+            split_number: split.info.split_number,
+            filename: split.info.filename,
+        })
+    }
+
+    /// Applies `config.title_filter`: keeps the pages whose title matches, renumbered
+    /// from 1. Untitled pages are dropped unless `keep_untitled_pages` is set.
+    fn select_pages<'a>(document: &'a MarkdownDocument, config: &SplitConfig) -> Result<Cow<'a, MarkdownDocument>> {
+        let Some(filter) = &config.title_filter else {
+            return Ok(Cow::Borrowed(document));
+        };
+        let pattern = MarkdownParser::compile_user_pattern(filter).map_err(|reason| {
+            MarkdownSplitterError::SplitConfig {
+                reason: format!("Invalid title filter '{}': {}", filter, reason),
             }
         })?;
 
-        Ok(())
+        let mut selected = document.clone();
+        selected.pages.retain(|page| match &page.title {
+            Some(title) => pattern.is_match(title),
+            None => config.keep_untitled_pages,
+        });
+        if selected.pages.is_empty() {
+            return Err(MarkdownSplitterError::SplitConfig {
+                reason: format!("No page of {} has a title matching '{}'", document.source, filter),
+            });
+        }
+
+        for (idx, page) in selected.pages.iter_mut().enumerate() {
+            page.number = idx + 1;
+        }
+        selected.total_pages = selected.pages.len();
+        info!(
+            "Title filter kept {} of {} pages",
+            selected.total_pages, document.total_pages
+        );
+        Ok(Cow::Owned(selected))
     }
 
-    async fn write_metadata_file(
-        metadata_path: &PathBuf,
+    /// Renders every split in memory, along with the path it would be written to
+    fn assemble_splits(
         document: &MarkdownDocument,
-        output_files: &[PathBuf],
-    ) -> Result<()> {
-        let metadata = serde_json::json!({
-            "source": document.source,
-            "total_pages": document.total_pages,
-            "total_splits": output_files.len(),
-            "split_files": output_files.iter().map(|p| p.file_name().unwrap().to_str().unwrap()).collect::<Vec<_>>(),
-            "document_metadata": document.metadata,
-            "split_info": output_files.iter().enumerate().map(|(idx, path)| {
-                serde_json::json!({
-                    "split_number": idx + 1,
-                    "filename": path.file_name().unwrap().to_str().unwrap(),
-                    "path": path.to_str().unwrap()
-                })
-            }).collect::<Vec<_>>()
-        });
+        config: &SplitConfig,
+        cost_fn: Option<PageCost<'_>>,
+    ) -> Result<Assembly> {
+        // Validate split configuration
+        Self::validate_split_config(document, config)?;
 
-        let json_content = serde_json::to_string_pretty(&metadata).map_err(|e| {
-            MarkdownSplitterError::OutputDirectory {
-                reason: format!("Failed to serialize metadata: {}", e),
-            }
-        })?;
+        let line_ending = config.line_ending.or(document.line_ending);
+        let grouped = config
+            .group_by_field
+            .as_deref()
+            .map(|field| Self::group_pages(document, field));
+        let document = grouped.as_ref().map_or(document, |(grouped, _)| grouped);
 
-        fs::write(metadata_path, json_content).await.map_err(|e| {
-            MarkdownSplitterError::OutputDirectory {
-                reason: format!("Failed to write metadata file: {}", e),
+        let mut tree_paths = Vec::new();
+        let mut groups = Vec::new();
+        let (ranges, sections) = match (&grouped, config.bisect_depth, config.section_limit) {
+            (Some((_, group_ranges)), _, _) => {
+                let (ranges, names) = group_ranges.iter().cloned().unzip();
+                groups = names;
+                (ranges, Vec::new())
             }
-        })?;
+            (None, Some(depth), _) => {
+                let (ranges, paths) = Self::bisect_page_ranges(&document.pages, depth).into_iter().unzip();
+                tree_paths = paths;
+                (ranges, Vec::new())
+            }
+            (None, None, Some(limit)) => Self::section_page_ranges(&document.pages, limit).into_iter().unzip(),
+            (None, None, None) => (Self::plan_page_ranges(document, config, cost_fn)?, Vec::new()),
+        };
+        let pages_per_split = ranges.first().map_or(0, |range| range.len());
+        let total_splits = ranges.len();
+        // Each overlap has to come from the previous split alone
+        let smallest = ranges.iter().rev().skip(1).map(|range| range.len()).min().unwrap_or(pages_per_split);
+        if config.overlap_pages > 0 && (config.overlap_pages >= pages_per_split || config.overlap_pages > smallest) {
+            return Err(MarkdownSplitterError::SplitConfig {
+                reason: format!(
+                    "Overlap of {} pages must be smaller than the pages per split ({})",
+                    config.overlap_pages,
+                    pages_per_split.min(smallest + 1)
+                ),
+            });
+        }
+        let mut splits = Vec::new();
+        let mut outline = HeadingOutline::default();
+        let source_id = config.source_id.as_deref().unwrap_or(&document.source);
+        let file_numbers = Self::file_numbers(document, &ranges, config.order_by, config.reverse_order);
+        let anchor_splits = Self::anchor_locations(document, &ranges);
+        let mut cross_split_anchors = Vec::new();
 
-        info!("Generated metadata file: {}", metadata_path.display());
-        Ok(())
-    }
+        let boilerplate = if config.strip_repeated_boilerplate {
+            Self::find_boilerplate(&document.pages)
+        } else {
+            Vec::new()
+        };
+        let boilerplate_keys: HashSet<String> =
+            boilerplate.iter().map(|line| Self::boilerplate_key(&line.text)).collect();
+        for line in &boilerplate {
+            info!("Stripping boilerplate found on {} pages: {}", line.pages, line.text);
+        }
 
-    pub fn calculate_split_info(total_pages: usize, splits: usize) -> (usize, Vec<(usize, usize)>) {
-        let pages_per_split = (total_pages + splits - 1) / splits;
-        let mut split_ranges = Vec::new();
+        // Distribute files round-robin across shard directories if requested
+        let shards: Vec<Option<usize>> = file_numbers
+            .iter()
+            .map(|number| config.shard_dirs.map(|shards| (number - 1) % shards))
+            .collect();
+        let file_names: Vec<String> = if !tree_paths.is_empty() || !groups.is_empty() {
+            tree_paths
+                .iter()
+                .chain(&groups)
+                .map(|suffix| Self::suffixed_filename(&document.source, suffix))
+                .collect()
+        } else if let Some(template) = &config.filename_template {
+            Self::templated_filenames(template, document, &ranges, &file_numbers)?
+        } else if sections.is_empty() {
+            file_numbers
+                .iter()
+                .map(|&number| {
+                    Self::generate_output_filename(Path::new(""), &document.source, number, total_splits)
+                        .to_string_lossy()
+                        .to_string()
+                })
+                .collect()
+        } else {
+            sections
+                .iter()
+                .map(|section| Self::section_filename(&document.source, section))
+                .collect()
+        };
 
-        for split_idx in 0..splits {
-            let start_page = split_idx * pages_per_split;
-            let end_page = std::cmp::min(start_page + pages_per_split, total_pages);
+        // Extract base document name for the split marker
+        let base_document_name = Self::base_name(&document.source);
 
-            if start_page >= total_pages {
-                break;
+        // Split the document
+        for (split_idx, range) in ranges.into_iter().enumerate() {
+            // Ranges are contiguous, so the previous split ends right before this one
+            let overlap = (split_idx > 0 && config.overlap_pages > 0).then(|| {
+                let overlap_pages = &document.pages[range.start - config.overlap_pages..range.start];
+                (overlap_pages, file_numbers[split_idx - 1])
+            });
+            let ends_document = range.end == document.pages.len();
+            let split_pages = &document.pages[range];
+            let file_number = file_numbers[split_idx];
+
+            let shard = shards[split_idx];
+            let split_dir = match shard {
+                Some(shard) => config.output_dir.join(Self::shard_dir_name(shard)),
+                None => config.output_dir.clone(),
+            };
+            let output_file = split_dir.join(&file_names[split_idx]);
+
+            let heading_context = if config.include_heading_context {
+                outline.context_for(&split_pages[0])
+            } else {
+                Vec::new()
+            };
+            let split_outline = outline.advance(document, split_pages);
+
+            let chunk_id = Self::chunk_id(source_id, split_pages);
+            let header = SplitHeader {
+                overlap,
+                ..Self::split_header(document, config, split_pages, split_idx, &chunk_id, heading_context)
+            };
+            let summary = header.summary.clone();
+
+            let mut rendered =
+                Self::render_split(split_pages, config, base_document_name, &header, &boilerplate_keys);
+            // Structured splits have no footer; the last one still ends like the source
+            if ends_document && config.preserve_structure && document.trailing_newline {
+                rendered.footer.push('\n');
             }
 
-            split_ranges.push((start_page + 1, end_page)); // 1-based indexing for display
+            // Anchor links whose heading ended up in another split file
+            for (anchor, target_idx) in Self::cross_split_links(split_pages, split_idx, &anchor_splits) {
+                let target_file = match (shard, shards[target_idx]) {
+                    (Some(from), Some(to)) if from != to => {
+                        format!("../{}/{}", Self::shard_dir_name(to), file_names[target_idx])
+                    }
+                    _ => file_names[target_idx].clone(),
+                };
+
+                if config.fix_cross_split_anchors {
+                    rendered.body = rendered.body.replace(
+                        &format!("](#{})", anchor),
+                        &format!("]({}#{})", target_file, anchor),
+                    );
+                } else {
+                    warn!(
+                        "Link to #{} in split {} points to a heading in {}",
+                        anchor, file_number, target_file
+                    );
+                }
+
+                cross_split_anchors.push(CrossSplitAnchor {
+                    split_number: file_number,
+                    anchor,
+                    target_split_number: file_numbers[target_idx],
+                    target_file,
+                    rewritten: config.fix_cross_split_anchors,
+                });
+            }
+
+            let mut info = Self::split_info(file_number, &output_file, split_pages, &rendered, line_ending);
+            info.document_order = split_idx + 1;
+            info.shard = shard;
+            info.chunk_id = chunk_id;
+            info.source_type = Some(document.metadata.source_type.clone());
+            info.origin = document.metadata.origin.clone();
+            info.section = sections.get(split_idx).cloned();
+            info.tree_path = tree_paths.get(split_idx).cloned();
+            info.group = groups.get(split_idx).cloned();
+            info.overlap_pages = overlap
+                .map(|(pages, _)| pages.iter().map(|page| page.number).collect())
+                .unwrap_or_default();
+            info.summary = summary;
+            info.outline = split_outline;
+            splits.push(AssembledSplit {
+                path: output_file,
+                content: rendered.content(),
+                info,
+            });
         }
 
-        (pages_per_split, split_ranges)
+        let warnings = Self::split_warnings(document, &splits, &cross_split_anchors);
+        Ok(Assembly {
+            splits,
+            pages_per_split,
+            cross_split_anchors,
+            boilerplate,
+            warnings,
+        })
+    }
+
+    /// Oversized pages, imbalanced splits and unrewritten cross-split anchors
+    fn split_warnings(
+        document: &MarkdownDocument,
+        splits: &[AssembledSplit],
+        cross_split_anchors: &[CrossSplitAnchor],
+    ) -> Vec<SplitWarning> {
+        let warning = |kind, split_number, page, message| SplitWarning {
+            kind,
+            source: document.source.clone(),
+            split_number,
+            page,
+            message,
+        };
+        let mut warnings: Vec<SplitWarning> = document
+            .merges
+            .iter()
+            .map(|merge| {
+                warning(
+                    WarningKind::MergedPage,
+                    None,
+                    Some(merge.into_page),
+                    format!(
+                        "Raw page {} was merged into page {}: {}",
+                        merge.raw_page, merge.into_page, merge.reason
+                    ),
+                )
+            })
+            .collect();
+
+        if splits.len() > 1 {
+            let total_bytes: usize = document.pages.iter().map(|page| page.content.len()).sum();
+            let even_share = total_bytes / splits.len();
+            for split in splits {
+                for page in split.info.pages.iter().filter(|page| page.bytes > even_share) {
+                    warnings.push(warning(
+                        WarningKind::OversizedPage,
+                        Some(split.info.split_number),
+                        Some(page.number),
+                        format!(
+                            "Page {} is {} bytes, more than an even share ({} bytes) of the document",
+                            page.number, page.bytes, even_share
+                        ),
+                    ));
+                }
+            }
+
+            let largest = splits.iter().map(|split| split.info.bytes).max().unwrap_or_default();
+            let smallest = splits.iter().map(|split| split.info.bytes).min().unwrap_or_default();
+            if largest > smallest * IMBALANCE_RATIO {
+                warnings.push(warning(
+                    WarningKind::Imbalance,
+                    None,
+                    None,
+                    format!("Largest split is {} bytes, smallest {} bytes", largest, smallest),
+                ));
+            }
+        }
+
+        for anchor in cross_split_anchors.iter().filter(|anchor| !anchor.rewritten) {
+            warnings.push(warning(
+                WarningKind::CrossSplitAnchor,
+                Some(anchor.split_number),
+                None,
+                format!("Link to #{} points to a heading in {}", anchor.anchor, anchor.target_file),
+            ));
+        }
+
+        warnings
+    }
+
+    /// Page index ranges for each split, with an undersized tail folded into its predecessor
+    fn plan_page_ranges(
+        document: &MarkdownDocument,
+        config: &SplitConfig,
+        cost_fn: Option<PageCost<'_>>,
+    ) -> Result<Vec<Range<usize>>> {
+        let lines_cost: PageCost<'_> = &Self::page_lines;
+        let cost_fn = match config.strategy {
+            SplitStrategy::Balanced => cost_fn.or(Some(lines_cost)),
+            _ => cost_fn,
+        };
+        let plan = |splits: usize| match cost_fn {
+            Some(cost_fn) => Self::weighted_page_ranges(&document.pages, splits, cost_fn),
+            None => match config.remainder_policy {
+                RemainderPolicy::Separate => Self::even_page_ranges(document.total_pages, splits),
+                RemainderPolicy::DistributeEarly => Self::distributed_page_ranges(document.total_pages, splits, true),
+                RemainderPolicy::DistributeLate => Self::distributed_page_ranges(document.total_pages, splits, false),
+            },
+        };
+        let mut ranges = match config.strategy {
+            SplitStrategy::Count | SplitStrategy::Balanced => plan(config.splits),
+            SplitStrategy::MaxWords(limit) => Self::packed_page_ranges(&document.pages, limit, &Self::page_words),
+            SplitStrategy::MaxTokens { limit, model } => Self::token_page_ranges(document, config, limit, model)?,
+        };
+
+        if let Some(max_splits) = config.max_splits_per_source {
+            if ranges.len() > max_splits {
+                match config.split_limit_policy {
+                    SplitLimitPolicy::Error => {
+                        return Err(MarkdownSplitterError::SplitConfig {
+                            reason: format!(
+                                "'{}' would produce {} splits, over the limit of {}",
+                                document.source,
+                                ranges.len(),
+                                max_splits
+                            ),
+                        });
+                    }
+                    SplitLimitPolicy::Coarsen => {
+                        debug!(
+                            "Coarsening '{}' from {} to {} splits",
+                            document.source,
+                            ranges.len(),
+                            max_splits
+                        );
+                        ranges = plan(max_splits);
+                    }
+                }
+            }
+        }
+
+        if let Some(min_bytes) = config.min_split_bytes {
+            let range_bytes = |range: &Range<usize>| -> usize {
+                document.pages[range.clone()].iter().map(|p| p.content.len()).sum()
+            };
+            while ranges.len() > 1 && range_bytes(ranges.last().unwrap()) < min_bytes {
+                let tail = ranges.pop().unwrap();
+                let previous = ranges.last_mut().unwrap();
+                debug!(
+                    "Merging undersized split (pages {}-{}) into its predecessor",
+                    tail.start + 1,
+                    tail.end
+                );
+                previous.end = tail.end;
+            }
+        }
+
+        Ok(ranges)
+    }
+
+    /// Packs whole pages in order into ranges whose summed `cost_fn` stays within
+    /// `limit`; a page that alone exceeds the limit gets a range of its own
+    fn packed_page_ranges(pages: &[MarkdownPage], limit: usize, cost_fn: PageCost<'_>) -> Vec<Range<usize>> {
+        let mut ranges = Vec::new();
+        let mut start = 0;
+        let mut used = 0;
+
+        for (idx, page) in pages.iter().enumerate() {
+            let cost = cost_fn(page);
+            if cost > limit {
+                warn!("Page {} alone exceeds the split budget ({} > {})", page.number, cost, limit);
+            }
+            if idx > start && used + cost > limit {
+                ranges.push(start..idx);
+                start = idx;
+                used = 0;
+            }
+            used += cost;
+        }
+
+        if start < pages.len() {
+            ranges.push(start..pages.len());
+        }
+        ranges
+    }
+
+    /// Packs whole pages in order into splits of at most `limit` tokens, each charged
+    /// for its own header: page numbers, summary, part heading and heading context all
+    /// depend on where a split starts and ends
+    fn token_page_ranges(
+        document: &MarkdownDocument,
+        config: &SplitConfig,
+        limit: usize,
+        model: TokenModel,
+    ) -> Result<Vec<Range<usize>>> {
+        let tokenizer = Tokenizer::new(model);
+        // Every page is charged a separator, which slightly overestimates
+        let separator = tokenizer.count(Self::page_separator(config));
+        let pages = &document.pages;
+        let mut outline = HeadingOutline::default();
+        let mut ranges = Vec::new();
+        let mut start = 0;
+        let mut used = 0;
+
+        for (idx, page) in pages.iter().enumerate() {
+            let cost = tokenizer.count(&page.content) + separator;
+            let header = |end: usize, outline: &HeadingOutline| {
+                Self::header_tokens(document, config, &tokenizer, outline, ranges.len(), &pages[start..end])
+            };
+            if idx > start && used + cost + header(idx + 1, &outline) > limit {
+                outline.advance(document, &pages[start..idx]);
+                ranges.push(start..idx);
+                start = idx;
+                used = 0;
+            }
+            if idx == start {
+                let overhead = Self::header_tokens(document, config, &tokenizer, &outline, ranges.len(), &pages[idx..=idx]);
+                if overhead >= limit {
+                    return Err(MarkdownSplitterError::SplitConfig {
+                        reason: format!(
+                            "Token limit {} leaves no room for page {} after its {}-token split header",
+                            limit, page.number, overhead
+                        ),
+                    });
+                }
+                if overhead + cost > limit {
+                    warn!("Page {} alone exceeds the split budget ({} > {})", page.number, overhead + cost, limit);
+                }
+            }
+            used += cost;
+        }
+
+        if start < pages.len() {
+            ranges.push(start..pages.len());
+        }
+        Ok(ranges)
+    }
+
+    /// Tokens taken by everything written around the pages of the `split_idx`-th split
+    /// (in document order) holding `split_pages`, with `outline` at its first page
+    fn header_tokens(
+        document: &MarkdownDocument,
+        config: &SplitConfig,
+        tokenizer: &Tokenizer,
+        outline: &HeadingOutline,
+        split_idx: usize,
+        split_pages: &[MarkdownPage],
+    ) -> usize {
+        let source_id = config.source_id.as_deref().unwrap_or(&document.source);
+        let chunk_id = Self::chunk_id(source_id, split_pages);
+        let heading_context = if config.include_heading_context {
+            outline.context_for(&split_pages[0])
+        } else {
+            Vec::new()
+        };
+        let header = Self::split_header(document, config, split_pages, split_idx, &chunk_id, heading_context);
+        let rendered = Self::render_header(split_pages, config, Self::base_name(&document.source), &header, &HashSet::new());
+        // At most one newline follows the pages
+        tokenizer.count(&rendered) + tokenizer.count("\n")
+    }
+
+    /// The blocks written above the pages of the `split_idx`-th split (in document
+    /// order), all but the overlapped pages of the previous split
+    fn split_header<'a>(
+        document: &'a MarkdownDocument,
+        config: &SplitConfig,
+        split_pages: &[MarkdownPage],
+        split_idx: usize,
+        chunk_id: &'a str,
+        heading_context: Vec<String>,
+    ) -> SplitHeader<'a> {
+        // The first split already carries these blocks in its content
+        let front_matter = document
+            .front_matter
+            .as_deref()
+            .filter(|_| config.repeat_front_matter && split_pages[0].start_line > 0);
+        let comment_metadata = (config.propagate_comment_metadata
+            && !document.comment_metadata.is_empty()
+            && split_pages[0].start_line > 0)
+            .then_some(&document.comment_metadata);
+        let summary = config.summarizer.as_ref().map(|summarizer| {
+            let text: Vec<&str> = split_pages.iter().map(|page| page.content.as_str()).collect();
+            summarizer.summarize(&text.join("\n"))
+        });
+
+        SplitHeader {
+            front_matter,
+            comment_metadata,
+            summary,
+            part_heading: config
+                .add_part_headings
+                .then(|| Self::part_heading(&config.part_heading_template, split_idx + 1, split_pages)),
+            chunk_id: config.chunk_id_in_header.then_some(chunk_id),
+            origin: config.tag_origin.then_some(&document.metadata),
+            heading_context,
+            overlap: None,
+        }
+    }
+
+    /// Reorders the pages so that pages sharing a value of the frontmatter `field` are
+    /// adjacent, groups in order of first appearance and pages in document order within
+    /// a group, and returns the page range of each group with its slug
+    fn group_pages(document: &MarkdownDocument, field: &str) -> (MarkdownDocument, Vec<(Range<usize>, String)>) {
+        let group_of = |page: &MarkdownPage| {
+            let value = page.page_frontmatter.as_ref().and_then(|entries| entries.get(field));
+            value
+                .map(|value| heading_anchor(value))
+                .filter(|slug| !slug.is_empty())
+                .unwrap_or_else(|| DEFAULT_GROUP.to_string())
+        };
+
+        let mut buckets: Vec<(String, Vec<MarkdownPage>)> = Vec::new();
+        for page in &document.pages {
+            let group = group_of(page);
+            match buckets.iter_mut().find(|(name, _)| *name == group) {
+                Some((_, pages)) => pages.push(page.clone()),
+                None => buckets.push((group, vec![page.clone()])),
+            }
+        }
+
+        let mut grouped = document.clone();
+        grouped.pages.clear();
+        let mut ranges = Vec::new();
+        for (group, pages) in buckets {
+            let start = grouped.pages.len();
+            grouped.pages.extend(pages);
+            ranges.push((start..grouped.pages.len(), group));
+        }
+        (grouped, ranges)
+    }
+
+    /// Page ranges from halving the pages by size `depth` times over, each with its
+    /// tree path (`1.2` is the second half of the first half)
+    fn bisect_page_ranges(pages: &[MarkdownPage], depth: usize) -> Vec<(Range<usize>, String)> {
+        let mut level = vec![(0..pages.len(), String::new())];
+        for remaining in (0..depth).rev() {
+            // Each half keeps enough pages to be halved again on the remaining levels
+            let min_pages = 1 << remaining;
+            level = level
+                .into_iter()
+                .flat_map(|(range, path)| {
+                    let total: usize = pages[range.clone()].iter().map(Self::page_bytes).sum();
+                    let before_start: usize = pages[range.start..range.start + min_pages - 1]
+                        .iter()
+                        .map(Self::page_bytes)
+                        .sum();
+                    // Cut where the two halves differ least in size
+                    let cut = (range.start + min_pages..=range.end - min_pages)
+                        .scan(before_start, |before, cut| {
+                            *before += Self::page_bytes(&pages[cut - 1]);
+                            Some((cut, *before))
+                        })
+                        .min_by_key(|&(_, before)| before.abs_diff(total - before))
+                        .map_or(range.end, |(cut, _)| cut);
+                    let child = |n: usize| match path.as_str() {
+                        "" => n.to_string(),
+                        parent => format!("{}.{}", parent, n),
+                    };
+                    [(range.start..cut, child(1)), (cut..range.end, child(2))]
+                })
+                .collect();
+        }
+        level
+    }
+
+    /// Page ranges for section splitting: a new section starts at every page opening
+    /// with an H1, and sections over `limit` are divided into parts
+    fn section_page_ranges(pages: &[MarkdownPage], limit: SectionLimit) -> Vec<(Range<usize>, SectionPart)> {
+        let mut starts: Vec<usize> = pages
+            .iter()
+            .enumerate()
+            .filter(|(_, page)| HeadingOutline::opening_line(page).and_then(HeadingOutline::heading_level) == Some(1))
+            .map(|(idx, _)| idx)
+            .collect();
+        if starts.first() != Some(&0) {
+            starts.insert(0, 0);
+        }
+
+        let mut planned = Vec::new();
+        for (section_idx, &start) in starts.iter().enumerate() {
+            let end = starts.get(section_idx + 1).copied().unwrap_or(pages.len());
+            let section_pages = &pages[start..end];
+            let title = HeadingOutline::opening_line(&section_pages[0])
+                .filter(|line| HeadingOutline::heading_level(line) == Some(1))
+                .map(|line| line.trim_start_matches('#').trim().to_string());
+
+            let parts = match limit {
+                SectionLimit::Pages(max_pages) => {
+                    Self::even_page_ranges(section_pages.len(), section_pages.len().div_ceil(max_pages))
+                }
+                SectionLimit::Bytes(max_bytes) => {
+                    let mut parts: Vec<Range<usize>> = Vec::new();
+                    let mut part_bytes = 0;
+                    for (idx, page) in section_pages.iter().enumerate() {
+                        match parts.last_mut() {
+                            Some(part) if part_bytes + page.content.len() <= max_bytes => {
+                                part.end = idx + 1;
+                                part_bytes += page.content.len();
+                            }
+                            _ => {
+                                parts.push(idx..idx + 1);
+                                part_bytes = page.content.len();
+                            }
+                        }
+                    }
+                    parts
+                }
+            };
+
+            let part_count = parts.len();
+            for (part_idx, part) in parts.into_iter().enumerate() {
+                planned.push((
+                    start + part.start..start + part.end,
+                    SectionPart {
+                        section: section_idx + 1,
+                        part: part_idx + 1,
+                        parts: part_count,
+                        title: title.clone(),
+                    },
+                ));
+            }
+        }
+        planned
+    }
+
+    /// Contiguous partition into `splits` ranges minimizing the largest summed cost
+    fn weighted_page_ranges(
+        pages: &[MarkdownPage],
+        splits: usize,
+        cost_fn: PageCost<'_>,
+    ) -> Vec<Range<usize>> {
+        let costs: Vec<usize> = pages.iter().map(cost_fn).collect();
+        let splits = splits.clamp(1, pages.len().max(1));
+
+        // Count of ranges a greedy pass needs when no range may exceed `cap`
+        let ranges_needed = |cap: usize| {
+            let mut count = 1;
+            let mut current = 0;
+            for &cost in &costs {
+                if current + cost > cap && current > 0 {
+                    count += 1;
+                    current = 0;
+                }
+                current += cost;
+            }
+            count
+        };
+
+        // Binary search the smallest cap that fits in `splits` ranges
+        let mut low = costs.iter().copied().max().unwrap_or(0);
+        let mut high = costs.iter().sum::<usize>().max(low);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if ranges_needed(mid) <= splits {
+                high = mid;
+            } else {
+                low = mid + 1;
+            }
+        }
+
+        // Pack greedily under that cap, opening ranges early if pages would run out
+        let mut ranges = Vec::with_capacity(splits);
+        let mut start = 0;
+        let mut current = 0;
+        for (idx, &cost) in costs.iter().enumerate() {
+            let ranges_left = splits - ranges.len();
+            let pages_left = costs.len() - idx;
+            if idx > start && (current + cost > low || pages_left < ranges_left) {
+                ranges.push(start..idx);
+                start = idx;
+                current = 0;
+            }
+            current += cost;
+        }
+        if start < costs.len() {
+            ranges.push(start..costs.len());
+        }
+
+        ranges
+    }
+
+    /// Lines found on more than [`BOILERPLATE_PAGE_SHARE`] of the pages, compared after
+    /// [`Self::boilerplate_key`] normalization, in order of first appearance
+    fn find_boilerplate(pages: &[MarkdownPage]) -> Vec<BoilerplateLine> {
+        if pages.len() < BOILERPLATE_MIN_PAGES {
+            return Vec::new();
+        }
+
+        let mut first_seen: Vec<(String, String)> = Vec::new();
+        let mut page_counts: HashMap<String, usize> = HashMap::new();
+        for page in pages {
+            // Page markers like "# Page 12" repeat by design
+            let keys: HashSet<String> = page
+                .content
+                .lines()
+                .filter(|line| !line.trim().is_empty() && Some(*line) != page.break_marker.as_deref())
+                .map(|line| {
+                    let key = Self::boilerplate_key(line);
+                    if !page_counts.contains_key(&key) {
+                        first_seen.push((key.clone(), line.trim().to_string()));
+                    }
+                    key
+                })
+                .collect();
+            for key in keys {
+                *page_counts.entry(key).or_insert(0) += 1;
+            }
+        }
+
+        first_seen
+            .into_iter()
+            .filter_map(|(key, text)| {
+                let count = page_counts[&key];
+                (count as f64 > pages.len() as f64 * BOILERPLATE_PAGE_SHARE)
+                    .then_some(BoilerplateLine { text, pages: count })
+            })
+            .collect()
+    }
+
+    /// Normalizes a line so near-identical repeats compare equal: surrounding and repeated
+    /// whitespace is ignored and digit runs (page numbers, dates) become `#`
+    fn boilerplate_key(line: &str) -> String {
+        let mut key = String::new();
+        for word in line.split_whitespace() {
+            if !key.is_empty() {
+                key.push(' ');
+            }
+            let mut in_digits = false;
+            for c in word.chars() {
+                if c.is_ascii_digit() {
+                    if !in_digits {
+                        key.push('#');
+                    }
+                    in_digits = true;
+                } else {
+                    key.push(c);
+                    in_digits = false;
+                }
+            }
+        }
+        key
+    }
+
+    /// Split index holding each heading anchor, numbered like GitHub for repeated headings
+    fn anchor_locations(document: &MarkdownDocument, ranges: &[Range<usize>]) -> HashMap<String, usize> {
+        let mut locations = HashMap::new();
+        let mut anchor_counts: HashMap<String, usize> = HashMap::new();
+
+        for (split_idx, range) in ranges.iter().enumerate() {
+            for page in &document.pages[range.clone()] {
+                let lines: Vec<&str> = page.content.lines().collect();
+                let fenced = MarkdownParser::fenced_lines(&lines);
+                for (line_idx, line) in lines.into_iter().enumerate() {
+                    let Some(level) = HeadingOutline::heading_level(line).filter(|_| !fenced[line_idx]) else {
+                        continue;
+                    };
+
+                    let base = heading_anchor(line[level..].trim().trim_end_matches('#'));
+                    let count = anchor_counts.entry(base.clone()).or_insert(0);
+                    let anchor = if *count == 0 { base } else { format!("{}-{}", base, count) };
+                    *count += 1;
+                    locations.insert(anchor, split_idx);
+                }
+            }
+        }
+
+        locations
+    }
+
+    /// `(anchor, target split index)` for each distinct `](#anchor)` link in the pages
+    /// whose heading lives in a different split
+    fn cross_split_links(
+        pages: &[MarkdownPage],
+        split_idx: usize,
+        anchor_splits: &HashMap<String, usize>,
+    ) -> Vec<(String, usize)> {
+        let mut links: Vec<(String, usize)> = Vec::new();
+
+        for page in pages {
+            for captures in ANCHOR_LINK.captures_iter(&page.content) {
+                let anchor = &captures[1];
+                let Some(&target) = anchor_splits.get(anchor) else {
+                    continue;
+                };
+                if target != split_idx && !links.iter().any(|(existing, _)| existing == anchor) {
+                    links.push((anchor.to_string(), target));
+                }
+            }
+        }
+
+        links
+    }
+
+    /// Number used in each split's file name, by range position in the document,
+    /// counted from the other end when `reverse`
+    fn file_numbers(
+        document: &MarkdownDocument,
+        ranges: &[Range<usize>],
+        order: SplitOrder,
+        reverse: bool,
+    ) -> Vec<usize> {
+        let mut positions: Vec<usize> = (0..ranges.len()).collect();
+
+        if order == SplitOrder::Size {
+            let range_bytes = |idx: &usize| -> usize {
+                document.pages[ranges[*idx].clone()].iter().map(|p| p.content.len()).sum()
+            };
+            // Largest first; ties keep document order
+            positions.sort_by_key(|idx| std::cmp::Reverse(range_bytes(idx)));
+        }
+        if reverse {
+            positions.reverse();
+        }
+
+        let mut numbers = vec![0; ranges.len()];
+        for (rank, idx) in positions.into_iter().enumerate() {
+            numbers[idx] = rank + 1;
+        }
+        numbers
+    }
+
+    fn even_page_ranges(total_pages: usize, splits: usize) -> Vec<Range<usize>> {
+        let pages_per_split = total_pages.div_ceil(splits).max(1);
+        (0..total_pages)
+            .step_by(pages_per_split)
+            .map(|start| start..std::cmp::min(start + pages_per_split, total_pages))
+            .collect()
+    }
+
+    /// Exactly `splits` ranges whose sizes differ by at most one page, the larger ones
+    /// first when `larger_first` and last otherwise
+    fn distributed_page_ranges(total_pages: usize, splits: usize, larger_first: bool) -> Vec<Range<usize>> {
+        let splits = splits.clamp(1, total_pages.max(1));
+        let (base, extra) = (total_pages / splits, total_pages % splits);
+        let mut start = 0;
+
+        (0..splits)
+            .map(|idx| {
+                let larger = if larger_first { idx < extra } else { idx >= splits - extra };
+                let end = start + base + usize::from(larger);
+                let range = start..end;
+                start = end;
+                range
+            })
+            .collect()
+    }
+
+    fn validate_split_config(document: &MarkdownDocument, config: &SplitConfig) -> Result<()> {
+        if config.splits == 0 {
+            return Err(MarkdownSplitterError::SplitConfig {
+                reason: "Number of splits must be greater than 0".to_string(),
+            });
+        }
+
+        if config.max_splits_per_source == Some(0) {
+            return Err(MarkdownSplitterError::SplitConfig {
+                reason: "Maximum splits per source must be greater than 0".to_string(),
+            });
+        }
+
+        if config.shard_dirs == Some(0) {
+            return Err(MarkdownSplitterError::SplitConfig {
+                reason: "Number of shard directories must be greater than 0".to_string(),
+            });
+        }
+
+        if document.total_pages == 0 {
+            return Err(MarkdownSplitterError::SplitConfig {
+                reason: "Document has no pages to split".to_string(),
+            });
+        }
+
+        if matches!(config.section_limit, Some(SectionLimit::Pages(0) | SectionLimit::Bytes(0))) {
+            return Err(MarkdownSplitterError::SplitConfig {
+                reason: "Section size limit must be greater than 0".to_string(),
+            });
+        }
+
+        if let Some(depth) = config.bisect_depth {
+            if !(1..=MAX_BISECT_DEPTH).contains(&depth) {
+                return Err(MarkdownSplitterError::SplitConfig {
+                    reason: format!("Bisection depth must be between 1 and {}", MAX_BISECT_DEPTH),
+                });
+            }
+            if document.total_pages < 1 << depth {
+                return Err(MarkdownSplitterError::SplitConfig {
+                    reason: format!(
+                        "Bisecting to depth {} needs at least {} pages, found {}",
+                        depth,
+                        1 << depth,
+                        document.total_pages
+                    ),
+                });
+            }
+        }
+
+        match config.strategy {
+            SplitStrategy::MaxWords(0) => {
+                return Err(MarkdownSplitterError::SplitConfig {
+                    reason: "Word budget per split must be greater than 0".to_string(),
+                });
+            }
+            SplitStrategy::MaxTokens { limit: 0, .. } => {
+                return Err(MarkdownSplitterError::SplitConfig {
+                    reason: "Token budget per split must be greater than 0".to_string(),
+                });
+            }
+            _ => {}
+        }
+
+        // Sections, bisection and groups decide the files themselves and would ignore the budget
+        if matches!(config.strategy, SplitStrategy::MaxWords(_) | SplitStrategy::MaxTokens { .. })
+            && (config.section_limit.is_some() || config.bisect_depth.is_some() || config.group_by_field.is_some())
+        {
+            return Err(MarkdownSplitterError::SplitConfig {
+                reason: "A word or token budget cannot be combined with sections, bisection or groups".to_string(),
+            });
+        }
+
+        if config.annotate_only && config.group_by_field.is_some() {
+            return Err(MarkdownSplitterError::SplitConfig {
+                reason: "Grouped splits reorder pages and cannot be annotated in place".to_string(),
+            });
+        }
+
+        if config.annotate_only && (config.extract_code_dir.is_some() || config.filename_template.is_some()) {
+            return Err(MarkdownSplitterError::SplitConfig {
+                reason: "Annotating in place writes no split files, so code extraction and filename templates do not apply"
+                    .to_string(),
+            });
+        }
+
+        if config.overlap_pages > 0
+            && (config.section_limit.is_some() || config.bisect_depth.is_some() || config.group_by_field.is_some())
+        {
+            return Err(MarkdownSplitterError::SplitConfig {
+                reason: "Overlapping splits are not supported with sections, bisection or groups".to_string(),
+            });
+        }
+
+        // Budgets are planned on the split's own pages; the repeated ones would exceed them
+        if config.overlap_pages > 0
+            && matches!(config.strategy, SplitStrategy::MaxWords(_) | SplitStrategy::MaxTokens { .. })
+        {
+            return Err(MarkdownSplitterError::SplitConfig {
+                reason: "Overlapping splits are not supported with a word or token budget".to_string(),
+            });
+        }
+
+        if let Some(template) = &config.filename_template {
+            if config.section_limit.is_some() || config.bisect_depth.is_some() || config.group_by_field.is_some() {
+                return Err(MarkdownSplitterError::SplitConfig {
+                    reason: "A filename template only applies to numbered splits, not to sections, bisection or groups"
+                        .to_string(),
+                });
+            }
+            if template.contains(['/', '\\']) {
+                return Err(MarkdownSplitterError::SplitConfig {
+                    reason: format!("Filename template '{}' must not contain path separators", template),
+                });
+            }
+            Self::render_filename_template(template, "document", 1, 1, UNTITLED_SLUG)?;
+        }
+
+        let splits_by_count = matches!(config.strategy, SplitStrategy::Count | SplitStrategy::Balanced)
+            && config.section_limit.is_none()
+            && config.bisect_depth.is_none()
+            && config.group_by_field.is_none();
+        if splits_by_count && config.splits > document.total_pages {
+            return Err(MarkdownSplitterError::SplitConfig {
+                reason: format!(
+                    "Number of splits ({}) cannot exceed total pages ({})",
+                    config.splits, document.total_pages
+                ),
+            });
+        }
+
+        Ok(())
+    }
+
+    async fn ensure_output_directory(output_dir: &Path) -> Result<()> {
+        if !output_dir.exists() {
+            fs::create_dir_all(output_dir).await.map_err(|e| {
+                MarkdownSplitterError::OutputDirectory {
+                    reason: format!("Failed to create output directory: {}", e),
+                }
+            })?;
+            info!("Created output directory: {}", output_dir.display());
+        }
+        Ok(())
+    }
+
+    /// File stem of `source` that output file names start with, "document" when it has none
+    fn base_name(source: &str) -> &str {
+        Path::new(source)
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or("document")
+    }
+
+    fn generate_output_filename(
+        output_dir: &Path,
+        source_name: &str,
+        split_number: usize,
+        total_splits: usize,
+    ) -> PathBuf {
+        let base_name = Self::base_name(source_name);
+
+        let filename = format!(
+            "{}_split_{:0width$}_of_{}.md",
+            base_name,
+            split_number,
+            total_splits,
+            width = total_splits.to_string().len()
+        );
+
+        output_dir.join(filename)
+    }
+
+    /// Renders `config.filename_template` for every numbered split, failing if two
+    /// splits would get the same name
+    fn templated_filenames(
+        template: &str,
+        document: &MarkdownDocument,
+        ranges: &[Range<usize>],
+        file_numbers: &[usize],
+    ) -> Result<Vec<String>> {
+        let base_name = Self::base_name(&document.source);
+
+        let mut seen = HashSet::new();
+        let mut file_names = Vec::with_capacity(ranges.len());
+        for (range, &number) in ranges.iter().zip(file_numbers) {
+            let title = document.pages[range.clone()]
+                .iter()
+                .find_map(|page| page.title.as_deref())
+                .map(heading_anchor)
+                .filter(|slug| !slug.is_empty())
+                .unwrap_or_else(|| UNTITLED_SLUG.to_string());
+            let file_name = Self::render_filename_template(template, base_name, number, ranges.len(), &title)?;
+            if !seen.insert(file_name.clone()) {
+                return Err(MarkdownSplitterError::SplitConfig {
+                    reason: format!(
+                        "Filename template '{}' gives more than one split the name '{}'; add {{index}}",
+                        template, file_name
+                    ),
+                });
+            }
+            file_names.push(file_name);
+        }
+        Ok(file_names)
+    }
+
+    /// Fills the `{base}`, `{index}`, `{index:0N}`, `{total}` and `{title}` placeholders
+    /// of a filename template. Any other placeholder, or an unclosed brace, is an error.
+    fn render_filename_template(
+        template: &str,
+        base_name: &str,
+        index: usize,
+        total: usize,
+        title: &str,
+    ) -> Result<String> {
+        let invalid = |reason: String| MarkdownSplitterError::SplitConfig {
+            reason: format!("Invalid filename template '{}': {}", template, reason),
+        };
+
+        let mut rendered = String::new();
+        let mut rest = template;
+        while let Some(open) = rest.find('{') {
+            rendered.push_str(&rest[..open]);
+            let close = rest[open..]
+                .find('}')
+                .ok_or_else(|| invalid("unclosed '{'".to_string()))?;
+            let placeholder = &rest[open + 1..open + close];
+            match placeholder {
+                "base" => rendered.push_str(base_name),
+                "index" => rendered.push_str(&index.to_string()),
+                "total" => rendered.push_str(&total.to_string()),
+                "title" => rendered.push_str(title),
+                _ => {
+                    let width = placeholder
+                        .strip_prefix("index:0")
+                        .and_then(|width| width.parse::<usize>().ok())
+                        .ok_or_else(|| invalid(format!("unknown placeholder {{{}}}", placeholder)))?;
+                    rendered.push_str(&format!("{:0width$}", index, width = width));
+                }
+            }
+            rest = &rest[open + close + 1..];
+        }
+        rendered.push_str(rest);
+
+        if rendered.trim().is_empty() {
+            return Err(invalid("renders an empty filename".to_string()));
+        }
+        Ok(rendered)
+    }
+
+    fn suffixed_filename(source_name: &str, suffix: &str) -> String {
+        format!("{}_{}.md", Self::base_name(source_name), suffix)
+    }
+
+    fn section_filename(source_name: &str, section: &SectionPart) -> String {
+        format!("{}_sec{:02}-part{:02}.md", Self::base_name(source_name), section.section, section.part)
+    }
+
+    fn generate_metadata_filename(output_dir: &Path, source_name: &str) -> PathBuf {
+        output_dir.join(format!("{}_metadata.json", Self::base_name(source_name)))
+    }
+
+    fn generate_toc_filename(output_dir: &Path, source_name: &str) -> PathBuf {
+        output_dir.join(format!("{}_TOC.md", Self::base_name(source_name)))
+    }
+
+    /// Markdown list linking each split file, in document order, relative to
+    /// `output_dir`. Link text is the split's first page title with markdown
+    /// punctuation escaped, or "Untitled split N"; link targets are percent-encoded.
+    fn toc_markdown(source: &str, output_dir: &Path, splits: &[SplitInfo]) -> String {
+        let mut splits: Vec<&SplitInfo> = splits.iter().collect();
+        splits.sort_by_key(|info| Self::document_position(info));
+
+        let mut toc = format!("# Contents of {}\n\n", Self::markdown_escape(source));
+        for info in splits {
+            let position = Self::document_position(info);
+            let title = match &info.title {
+                Some(title) => Self::markdown_escape(title),
+                None => format!("Untitled split {}", position),
+            };
+            let path = Path::new(&info.path);
+            let relative = path.strip_prefix(output_dir).unwrap_or(Path::new(&info.filename));
+            let target: Vec<String> = relative
+                .components()
+                .map(|part| utf8_percent_encode(&part.as_os_str().to_string_lossy(), LINK_TARGET).to_string())
+                .collect();
+            let pages = if info.start_page == info.end_page {
+                format!("page {}", info.start_page)
+            } else {
+                format!("pages {}-{}", info.start_page, info.end_page)
+            };
+            toc.push_str(&format!(
+                "{}. [{}]({}) - `{}`, {}\n",
+                position,
+                title,
+                target.join("/"),
+                info.filename,
+                pages
+            ));
+        }
+        toc
+    }
+
+    /// Backslash-escapes characters that would otherwise end or format link text
+    fn markdown_escape(text: &str) -> String {
+        let mut escaped = String::with_capacity(text.len());
+        for c in text.chars() {
+            if "\\`*_[]<>#|".contains(c) {
+                escaped.push('\\');
+            }
+            escaped.push(c);
+        }
+        escaped
+    }
+
+    fn render_split(
+        pages: &[MarkdownPage],
+        config: &SplitConfig,
+        document_name: &str,
+        split_header: &SplitHeader<'_>,
+        boilerplate: &HashSet<String>,
+    ) -> RenderedSplit {
+        let header = Self::render_header(pages, config, document_name, split_header, boilerplate);
+
+        // Combine page contents. Without structure, pages are joined exactly as they
+        // appeared in the source so concatenating all splits reproduces the document.
+        let body = Self::render_pages(pages, config, boilerplate);
+
+        let footer = if config.preserve_structure { "" } else { "\n" };
+
+        RenderedSplit {
+            header,
+            body,
+            footer: footer.to_string(),
+        }
+    }
+
+    /// Everything written above the pages of a split
+    fn render_header(
+        pages: &[MarkdownPage],
+        config: &SplitConfig,
+        document_name: &str,
+        split_header: &SplitHeader<'_>,
+        boilerplate: &HashSet<String>,
+    ) -> String {
+        let mut header = String::new();
+
+        // Front matter only counts as such at the very top of the file
+        if let Some(front_matter) = split_header.front_matter {
+            header.push_str(front_matter);
+            header.push_str("\n\n");
+        }
+
+        // Add header if preserving structure
+        if config.preserve_structure {
+            // Extract clean document name (remove _structured_markdown suffix)
+            let clean_document_name = document_name
+                .strip_suffix("_structured_markdown")
+                .unwrap_or(document_name);
+
+            header.push_str(&format!(
+                "<!-- {} {} {} to {} -->\n\n",
+                clean_document_name,
+                SPLIT_HEADER_LABEL,
+                pages.first().map(|p| p.number).unwrap_or(1),
+                pages.last().map(|p| p.number).unwrap_or(1)
+            ));
+        }
+
+        if let Some(comment_metadata) = split_header.comment_metadata {
+            for (key, value) in comment_metadata {
+                header.push_str(&format!("<!-- {}: {} -->\n", key, value));
+            }
+            header.push('\n');
+        }
+
+        if let Some(chunk_id) = split_header.chunk_id {
+            header.push_str(&format!("<!-- chunk-id: {} -->\n\n", chunk_id));
+        }
+
+        if let Some(metadata) = split_header.origin {
+            header.push_str(&format!(
+                "<!-- origin: {:?} {} -->\n\n",
+                metadata.source_type, metadata.origin
+            ));
+        }
+
+        if let Some(summary) = &split_header.summary {
+            // Keep the summary on one line and inside its comment
+            let summary = summary.split_whitespace().collect::<Vec<_>>().join(" ").replace("-->", "-- >");
+            header.push_str(&format!("<!-- summary: {} -->\n\n", summary));
+        }
+
+        if let Some(part_heading) = &split_header.part_heading {
+            header.push_str(&format!("# {}\n\n", part_heading));
+        }
+
+        // Repeat the enclosing headings so a split starting mid-section keeps its place
+        if !split_header.heading_context.is_empty() {
+            header.push_str(&split_header.heading_context.join("\n"));
+            header.push_str("\n\n");
+        }
+
+        // Overlapped pages belong to the header, so reassembling and reconstructing
+        // the document only see each page once
+        if let Some((overlap_pages, from_split)) = split_header.overlap {
+            let overlap = Self::render_pages(overlap_pages, config, boilerplate);
+            if config.preserve_structure {
+                header.push_str(&format!("<!-- overlap from split {} -->\n\n", from_split));
+                header.push_str(&overlap);
+                header.push_str("\n\n<!-- end of overlap -->");
+            } else {
+                header.push_str(&overlap);
+            }
+            header.push_str(Self::page_separator(config));
+        }
+
+        header
+    }
+
+    /// Page contents as written to a split file: code blocks and boilerplate stripped and
+    /// paragraphs wrapped as configured, joined by the page separator
+    fn render_pages(pages: &[MarkdownPage], config: &SplitConfig, boilerplate: &HashSet<String>) -> String {
+        pages
+            .iter()
+            .map(|page| {
+                let mut content = if config.strip_code_blocks {
+                    Self::without_code_blocks(&page.content)
+                } else {
+                    page.content.clone()
+                };
+                if !boilerplate.is_empty() {
+                    content = content
+                        .lines()
+                        .filter(|line| !boilerplate.contains(&Self::boilerplate_key(line)))
+                        .collect::<Vec<_>>()
+                        .join("\n");
+                }
+                match config.wrap_width {
+                    Some(width) => Self::wrap_paragraphs(&content, width),
+                    None => content,
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(Self::page_separator(config))
+    }
+
+    /// Fills `{index}` and `{title}` in a part heading template. The title is the first
+    /// page title in the split; without one, separators left dangling after the
+    /// placeholder (`Part 3: `) are trimmed away.
+    fn part_heading(template: &str, index: usize, pages: &[MarkdownPage]) -> String {
+        let heading = template.replace("{index}", &index.to_string());
+        match pages.iter().find_map(|page| page.title.as_deref()) {
+            Some(title) => heading.replace("{title}", title),
+            None => heading
+                .replace("{title}", "")
+                .trim_end_matches(|c: char| c.is_whitespace() || matches!(c, ':' | '-' | '–' | '—' | '|'))
+                .to_string(),
+        }
+    }
+
+    /// Writes every fenced code block to `<code_dir>/<name>_NNN.<ext>`, numbered in document
+    /// order, with the extension taken from the fence's language. A dry run only lists them.
+    async fn extract_code_blocks(
+        code_blocks: Vec<(PathBuf, String, CodeBlockInfo)>,
+        code_dir: &Path,
+        config: &SplitConfig,
+    ) -> Result<Vec<CodeBlockInfo>> {
+        if !config.dry_run {
+            Self::ensure_output_directory(code_dir).await?;
+        }
+        let mut blocks = Vec::with_capacity(code_blocks.len());
+        for (code_path, code, info) in code_blocks {
+            if !config.dry_run {
+                Self::write_output_file(&code_path, code.as_bytes(), config, "code block").await?;
+            }
+            blocks.push(info);
+        }
+
+        info!("Extracted {} code blocks to {}", blocks.len(), code_dir.display());
+        Ok(blocks)
+    }
+
+    /// Every fenced code block of the document, with the file it is extracted to and its code
+    fn code_blocks(document: &MarkdownDocument, code_dir: &Path) -> Vec<(PathBuf, String, CodeBlockInfo)> {
+        let mut blocks = Vec::new();
+        let base_name = Self::base_name(&document.source);
+
+        for page in &document.pages {
+            let lines: Vec<&str> = page.content.lines().collect();
+
+            for (range, language) in Self::code_block_ranges(&page.content) {
+                let filename = format!(
+                    "{}_{:03}.{}",
+                    base_name,
+                    blocks.len() + 1,
+                    Self::code_extension(language.as_deref())
+                );
+                let code_path = code_dir.join(&filename);
+                let mut code = lines[range.start + 1..range.end - 1].join("\n");
+                code.push('\n');
+
+                let info = CodeBlockInfo {
+                    index: blocks.len() + 1,
+                    language,
+                    filename,
+                    page: page.number,
+                    start_line: page.start_line + range.start,
+                    end_line: page.start_line + range.end,
+                };
+                blocks.push((code_path, code, info));
+            }
+        }
+        blocks
+    }
+
+    /// Line ranges (fences included) and languages of the closed fenced code blocks in `content`
+    fn code_block_ranges(content: &str) -> Vec<(Range<usize>, Option<String>)> {
+        let lines: Vec<&str> = content.lines().collect();
+        MarkdownParser::fenced_blocks(&lines)
+            .into_iter()
+            .filter_map(|range| {
+                let opening = lines[range.start].trim_start();
+                let fence = ['`', '~'].into_iter().find(|&c| opening.starts_with(c))?;
+                // Math blocks are not code, and a block left open has no closing fence
+                let closing = lines[range.end - 1].trim_start();
+                if range.len() < 2 || !closing.starts_with(fence) {
+                    return None;
+                }
+                let language = opening
+                    .trim_start_matches(fence)
+                    .split_whitespace()
+                    .next()
+                    .map(|info| info.trim_matches(['{', '}', '.']).to_lowercase())
+                    .filter(|info| !info.is_empty());
+                Some((range, language))
+            })
+            .collect()
+    }
+
+    /// File extension for a code fence language, `txt` when unknown
+    fn code_extension(language: Option<&str>) -> &'static str {
+        match language.unwrap_or_default() {
+            "rust" | "rs" => "rs",
+            "python" | "py" | "python3" => "py",
+            "javascript" | "js" | "jsx" => "js",
+            "typescript" | "ts" | "tsx" => "ts",
+            "bash" | "sh" | "shell" | "zsh" | "console" => "sh",
+            "c" | "h" => "c",
+            "cpp" | "c++" | "cxx" | "hpp" => "cpp",
+            "csharp" | "cs" | "c#" => "cs",
+            "go" | "golang" => "go",
+            "java" => "java",
+            "kotlin" | "kt" => "kt",
+            "ruby" | "rb" => "rb",
+            "php" => "php",
+            "swift" => "swift",
+            "html" => "html",
+            "css" => "css",
+            "sql" => "sql",
+            "json" => "json",
+            "yaml" | "yml" => "yaml",
+            "toml" => "toml",
+            "xml" => "xml",
+            "markdown" | "md" => "md",
+            _ => "txt",
+        }
+    }
+
+    /// Page content with its fenced code blocks removed
+    fn without_code_blocks(content: &str) -> String {
+        let blocks = Self::code_block_ranges(content);
+        content
+            .lines()
+            .enumerate()
+            .filter(|(idx, _)| !blocks.iter().any(|(range, _)| range.contains(idx)))
+            .map(|(_, line)| line)
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Reflows paragraph text to `width` columns. Code blocks, tables, headings, lists,
+    /// quotes and HTML are kept as they are, as are hard line breaks inside paragraphs.
+    fn wrap_paragraphs(content: &str, width: usize) -> String {
+        let mut output: Vec<String> = Vec::new();
+        let mut paragraph: Vec<&str> = Vec::new();
+        let lines: Vec<&str> = content.lines().collect();
+        let fenced = MarkdownParser::fenced_lines(&lines);
+
+        let flush = |paragraph: &mut Vec<&str>, output: &mut Vec<String>| {
+            if !paragraph.is_empty() {
+                output.extend(Self::wrap_words(&paragraph.join(" "), width));
+                paragraph.clear();
+            }
+        };
+
+        for (line, fenced) in lines.into_iter().zip(fenced) {
+            let trimmed = line.trim_start();
+            let is_prose = !fenced
+                && !trimmed.is_empty()
+                && !line.starts_with("    ")
+                && !line.starts_with('\t')
+                && !Self::is_block_syntax(trimmed);
+            if !is_prose {
+                flush(&mut paragraph, &mut output);
+                output.push(line.to_string());
+                continue;
+            }
+
+            // A hard break ends the text to reflow but keeps its marker
+            if let Some(text) = line.strip_suffix('\\') {
+                paragraph.push(text.trim());
+                flush(&mut paragraph, &mut output);
+                if let Some(last) = output.last_mut() {
+                    last.push('\\');
+                }
+            } else if line.ends_with("  ") {
+                paragraph.push(line.trim());
+                flush(&mut paragraph, &mut output);
+                if let Some(last) = output.last_mut() {
+                    last.push_str("  ");
+                }
+            } else {
+                paragraph.push(line.trim());
+            }
+        }
+        flush(&mut paragraph, &mut output);
+
+        output.join("\n")
+    }
+
+    /// Lines that start a markdown construct other than a plain paragraph
+    fn is_block_syntax(trimmed: &str) -> bool {
+        let list_number = trimmed.chars().take_while(|c| c.is_ascii_digit()).count();
+        let after_number = &trimmed[list_number..];
+
+        trimmed.starts_with('#')
+            || trimmed.starts_with('|')
+            || trimmed.starts_with('>')
+            || trimmed.starts_with('<')
+            || ["- ", "* ", "+ "].iter().any(|bullet| trimmed.starts_with(bullet))
+            || (list_number > 0 && (after_number.starts_with(". ") || after_number.starts_with(") ")))
+            || trimmed.chars().all(|c| matches!(c, '-' | '*' | '_' | '=' | ' '))
+    }
+
+    /// Greedy word wrap; words longer than `width` get a line of their own
+    fn wrap_words(text: &str, width: usize) -> Vec<String> {
+        let mut lines = Vec::new();
+        let mut current = String::new();
+
+        for word in text.split_whitespace() {
+            if !current.is_empty() && current.chars().count() + 1 + word.chars().count() > width {
+                lines.push(std::mem::take(&mut current));
+            }
+            if !current.is_empty() {
+                current.push(' ');
+            }
+            current.push_str(word);
+        }
+        if !current.is_empty() {
+            lines.push(current);
+        }
+
+        lines
+    }
+
+    fn page_separator(config: &SplitConfig) -> &'static str {
+        if config.preserve_structure {
+            PAGE_SEPARATOR
+        } else {
+            "\n"
+        }
+    }
+
+    /// Content-derived ID that stays the same across runs until the split's pages change
+    fn chunk_id(source_id: &str, pages: &[MarkdownPage]) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(source_id.as_bytes());
+        for page in pages {
+            hasher.update([0]);
+            hasher.update(page.content.as_bytes());
+        }
+
+        hasher.finalize()[..CHUNK_ID_BYTES]
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    /// Writes one split file with `line_ending` as `config.overwrite_policy` allows;
+    /// returns false when an existing file was skipped
+    async fn write_split_file(
+        output_path: &Path,
+        content: &str,
+        config: &SplitConfig,
+        line_ending: LineEnding,
+    ) -> Result<bool> {
+        let content = line_ending.apply(content);
+        Self::write_output_file(output_path, content.as_bytes(), config, "split file").await
+    }
+
+    /// Writes one output file, the `what` of error messages, as `config.overwrite_policy`
+    /// allows; returns false when an existing file was kept under `OverwritePolicy::Skip`
+    async fn write_output_file(path: &Path, contents: &[u8], config: &SplitConfig, what: &str) -> Result<bool> {
+        if config.overwrite_policy != OverwritePolicy::Overwrite && fs::try_exists(path).await? {
+            if config.overwrite_policy == OverwritePolicy::Skip {
+                info!("Keeping existing {} {}", what, path.display());
+                return Ok(false);
+            }
+            return Err(MarkdownSplitterError::OutputDirectory {
+                reason: format!("The {} {} already exists", what, path.display()),
+            });
+        }
+
+        Self::with_write_retries(config.write_retries, || fs::write(path, contents))
+            .await
+            .map_err(|e| MarkdownSplitterError::OutputDirectory {
+                reason: format!("Failed to write {} {}: {}", what, path.display(), e),
+            })?;
+
+        Ok(true)
+    }
+
+    /// Under `OverwritePolicy::Error`, fails before anything is written when any of
+    /// `paths` already exists, rather than partway through the run
+    async fn check_overwrites(paths: &[&Path], config: &SplitConfig) -> Result<()> {
+        if config.overwrite_policy != OverwritePolicy::Error {
+            return Ok(());
+        }
+        for path in paths {
+            if fs::try_exists(path).await? {
+                return Err(MarkdownSplitterError::OutputDirectory {
+                    reason: format!("{} already exists", path.display()),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs `write`, retrying up to `retries` more times with exponential backoff while it
+    /// fails with a transient error. Permanent errors such as a full disk or a permission
+    /// problem are returned straight away.
+    async fn with_write_retries<F, Fut>(retries: usize, mut write: F) -> std::io::Result<()>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = std::io::Result<()>>,
+    {
+        let mut delay = WRITE_RETRY_DELAY;
+        let mut attempt = 0;
+
+        loop {
+            match write().await {
+                Err(e) if attempt < retries && Self::is_transient_write_error(&e) => {
+                    attempt += 1;
+                    warn!("Write failed ({}), retrying in {:?} (attempt {} of {})", e, delay, attempt, retries);
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// EAGAIN, EBUSY, EINTR and timeouts, as seen on network filesystems
+    fn is_transient_write_error(error: &std::io::Error) -> bool {
+        matches!(
+            error.kind(),
+            ErrorKind::WouldBlock | ErrorKind::ResourceBusy | ErrorKind::Interrupted | ErrorKind::TimedOut
+        )
+    }
+
+    fn split_info(
+        split_number: usize,
+        path: &Path,
+        pages: &[MarkdownPage],
+        rendered: &RenderedSplit,
+        line_ending: LineEnding,
+    ) -> SplitInfo {
+        let content = rendered.content();
+
+        SplitInfo {
+            split_number,
+            filename: path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or_default()
+                .to_string(),
+            path: path.to_string_lossy().to_string(),
+            // Sized as written, after the line ending conversion
+            bytes: line_ending.apply(&content).len(),
+            lines: content.lines().count(),
+            words: content.split_whitespace().count(),
+            start_page: pages.first().map(|p| p.number).unwrap_or_default(),
+            end_page: pages.last().map(|p| p.number).unwrap_or_default(),
+            title: pages.iter().find_map(|p| p.title.clone()),
+            pages: pages
+                .iter()
+                .map(|p| PageInfo {
+                    number: p.number,
+                    start_line: p.start_line,
+                    end_line: p.end_line,
+                    start_offset: p.start_offset,
+                    end_offset: p.end_offset,
+                    break_marker: p.break_marker.clone(),
+                    bytes: p.content.len(),
+                    frontmatter: p.page_frontmatter.clone(),
+                })
+                .collect(),
+            shard: None,
+            document_order: split_number,
+            chunk_id: String::new(),
+            source_type: None,
+            origin: String::new(),
+            summary: None,
+            section: None,
+            outline: Vec::new(),
+            tree_path: None,
+            group: None,
+            overlap_pages: Vec::new(),
+            header: rendered.header.clone(),
+            footer: rendered.footer.clone(),
+        }
+    }
+
+    fn shard_dir_name(shard: usize) -> String {
+        format!("shard-{}", shard)
+    }
+
+    /// Location of a split file relative to the directory holding its metadata.
+    fn split_file_path(splits_dir: &Path, info: &SplitInfo) -> PathBuf {
+        match info.shard {
+            Some(shard) => splits_dir.join(Self::shard_dir_name(shard)).join(&info.filename),
+            None => splits_dir.join(&info.filename),
+        }
+    }
+
+    /// Writes a CSV index with one row per split file.
+    pub fn write_csv_index(csv_path: &Path, splits: &[SplitInfo]) -> Result<()> {
+        let csv_error = |e: csv::Error| MarkdownSplitterError::OutputDirectory {
+            reason: format!("Failed to write CSV index {}: {}", csv_path.display(), e),
+        };
+
+        let mut writer = csv::Writer::from_path(csv_path).map_err(csv_error)?;
+        writer
+            .write_record(["split_index", "filename", "start_page", "end_page", "bytes", "title"])
+            .map_err(csv_error)?;
+
+        for info in splits {
+            writer
+                .write_record([
+                    info.split_number.to_string(),
+                    info.filename.clone(),
+                    info.start_page.to_string(),
+                    info.end_page.to_string(),
+                    info.bytes.to_string(),
+                    info.title.clone().unwrap_or_default(),
+                ])
+                .map_err(csv_error)?;
+        }
+
+        writer.flush()?;
+        info!("Generated CSV index: {}", csv_path.display());
+        Ok(())
+    }
+
+    /// Writes an XML sitemap listing each split file at `base_url` joined with its path
+    /// relative to `output_dir`, for publishing the splits as a static site.
+    pub fn write_sitemap(
+        sitemap_path: &Path,
+        base_url: &str,
+        output_dir: &Path,
+        splits: &[SplitInfo],
+    ) -> Result<()> {
+        let base = Url::parse(base_url)
+            .ok()
+            .filter(|url| !url.cannot_be_a_base())
+            .ok_or_else(|| MarkdownSplitterError::SplitConfig {
+                reason: format!("Invalid sitemap base URL: {}", base_url),
+            })?;
+
+        let mut xml = String::from(concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n",
+        ));
+        for info in splits {
+            let path = Path::new(&info.path);
+            let relative = path.strip_prefix(output_dir).unwrap_or(Path::new(&info.filename));
+
+            // Each path component becomes one percent-encoded URL segment
+            let mut url = base.clone();
+            if let Ok(mut segments) = url.path_segments_mut() {
+                segments.pop_if_empty();
+                segments.extend(relative.components().map(|part| part.as_os_str().to_string_lossy()));
+            }
+            xml.push_str(&format!("  <url>\n    <loc>{}</loc>\n  </url>\n", Self::xml_escape(url.as_str())));
+        }
+        xml.push_str("</urlset>\n");
+
+        std::fs::write(sitemap_path, xml)?;
+        info!("Wrote sitemap of {} files to {}", splits.len(), sitemap_path.display());
+        Ok(())
+    }
+
+    fn xml_escape(text: &str) -> String {
+        text.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+            .replace('"', "&quot;")
+            .replace('\'', "&apos;")
+    }
+
+    /// Writes the collected warnings as a JSON list.
+    pub async fn write_warnings_report(
+        report_path: &Path,
+        warnings: &[SplitWarning],
+        write_retries: usize,
+    ) -> Result<()> {
+        let json_content = serde_json::to_string_pretty(warnings).map_err(|e| {
+            MarkdownSplitterError::OutputDirectory {
+                reason: format!("Failed to serialize warnings: {}", e),
+            }
+        })?;
+        Self::with_write_retries(write_retries, || fs::write(report_path, &json_content))
+            .await
+            .map_err(|e| MarkdownSplitterError::OutputDirectory {
+                reason: format!("Failed to write warnings report {}: {}", report_path.display(), e),
+            })?;
+        info!("Wrote {} warnings to {}", warnings.len(), report_path.display());
+        Ok(())
+    }
+
+    /// Writes the outcome of a split run as JSON, for `split --report`
+    pub fn write_run_report(report_path: &Path, report: &SplitRunReport) -> Result<()> {
+        let json_content = serde_json::to_string_pretty(report).map_err(|e| {
+            MarkdownSplitterError::OutputDirectory {
+                reason: format!("Failed to serialize run report: {}", e),
+            }
+        })?;
+        std::fs::write(report_path, json_content)?;
+        info!("Wrote the report of {} sources to {}", report.sources.len(), report_path.display());
+        Ok(())
+    }
+
+    /// Records the splits of this run in the manifest at `manifest_path`, one entry per
+    /// source. In `ManifestMode::Merge` entries of sources not split this time are kept.
+    /// A `<manifest>.lock` file keeps concurrent runs from overwriting each other's entries.
+    pub async fn write_manifest(
+        manifest_path: &Path,
+        splits: &[SplitInfo],
+        mode: ManifestMode,
+        source_date_epoch: Option<i64>,
+    ) -> Result<SplitManifest> {
+        let manifest_error = |reason: String| MarkdownSplitterError::OutputDirectory {
+            reason: format!("Failed to update manifest {}: {}", manifest_path.display(), reason),
+        };
+
+        let _lock = Self::lock_manifest(manifest_path).await?;
+        let mut manifest = match mode {
+            ManifestMode::Merge if fs::try_exists(manifest_path).await? => {
+                let existing = fs::read_to_string(manifest_path).await?;
+                serde_json::from_str(&existing).map_err(|e| manifest_error(e.to_string()))?
+            }
+            _ => SplitManifest::default(),
+        };
+
+        let updated_at = Self::timestamp(source_date_epoch)?;
+        let mut entries: Vec<(String, ManifestEntry)> = Vec::new();
+        for info in splits {
+            let index = match entries.iter().position(|(source, _)| *source == info.origin) {
+                Some(index) => index,
+                None => {
+                    let entry = ManifestEntry {
+                        updated_at: updated_at.clone(),
+                        files: Vec::new(),
+                        bytes: 0,
+                    };
+                    entries.push((info.origin.clone(), entry));
+                    entries.len() - 1
+                }
+            };
+            entries[index].1.files.push(info.path.clone());
+            entries[index].1.bytes += info.bytes;
+        }
+        let updated = entries.len();
+        manifest.sources.extend(entries);
+
+        let json_content = serde_json::to_string_pretty(&manifest).map_err(|e| manifest_error(e.to_string()))?;
+        fs::write(manifest_path, json_content).await?;
+        info!(
+            "Recorded {} sources in {} ({} in total)",
+            updated,
+            manifest_path.display(),
+            manifest.sources.len()
+        );
+        Ok(manifest)
+    }
+
+    async fn lock_manifest(manifest_path: &Path) -> Result<ManifestLock> {
+        let mut lock_path = manifest_path.as_os_str().to_owned();
+        lock_path.push(".lock");
+        let lock_path = PathBuf::from(lock_path);
+
+        for _ in 0..MANIFEST_LOCK_ATTEMPTS {
+            match fs::OpenOptions::new().write(true).create_new(true).open(&lock_path).await {
+                Ok(_) => return Ok(ManifestLock(lock_path)),
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => tokio::time::sleep(MANIFEST_LOCK_DELAY).await,
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Err(MarkdownSplitterError::OutputDirectory {
+            reason: format!(
+                "Manifest {} is locked by another run; remove {} if no run is active",
+                manifest_path.display(),
+                lock_path.display()
+            ),
+        })
+    }
+
+    async fn write_metadata_file(
+        metadata_path: &Path,
+        document: &MarkdownDocument,
+        config: &SplitConfig,
+        splits: &[SplitInfo],
+        code_blocks: &[CodeBlockInfo],
+    ) -> Result<()> {
+        let mut document_metadata = document.metadata.clone();
+        if config.source_date_epoch.is_some() {
+            document_metadata.created_at = Self::timestamp(config.source_date_epoch)?;
+        }
+        let metadata = SplitMetadata {
+            source: document.source.clone(),
+            total_pages: document.total_pages,
+            total_splits: splits.len(),
+            split_files: splits.iter().map(|info| info.filename.clone()).collect(),
+            document_metadata,
+            split_info: splits.to_vec(),
+            statistics: Self::calculate_statistics(splits),
+            page_separator: Self::page_separator(config).to_string(),
+            trailing_newline: document.trailing_newline,
+            line_ending: config.line_ending.or(document.line_ending),
+            detected_language: document.detected_language.clone(),
+            code_blocks: code_blocks.to_vec(),
+        };
+
+        let json_content = serde_json::to_string_pretty(&metadata).map_err(|e| {
+            MarkdownSplitterError::OutputDirectory {
+                reason: format!("Failed to serialize metadata: {}", e),
+            }
+        })?;
+
+        if Self::write_output_file(metadata_path, json_content.as_bytes(), config, "metadata file").await? {
+            info!("Generated metadata file: {}", metadata_path.display());
+        }
+        Ok(())
+    }
+
+    /// RFC 3339 time of the run: the current time, or `source_date_epoch` when given
+    /// (the reproducible-builds `SOURCE_DATE_EPOCH` convention)
+    pub fn timestamp(source_date_epoch: Option<i64>) -> Result<String> {
+        let Some(seconds) = source_date_epoch else {
+            return Ok(chrono::Utc::now().to_rfc3339());
+        };
+        chrono::DateTime::from_timestamp(seconds, 0)
+            .map(|time| time.to_rfc3339())
+            .ok_or_else(|| MarkdownSplitterError::SplitConfig {
+                reason: format!("Source date epoch {} is out of range", seconds),
+            })
+    }
+
+    /// Summarizes the size distribution of the given splits.
+    pub fn calculate_statistics(splits: &[SplitInfo]) -> SplitStatistics {
+        let distribution = |metric: fn(&SplitInfo) -> usize| Distribution::of(splits.iter().map(metric));
+
+        SplitStatistics {
+            bytes: distribution(|info| info.bytes),
+            lines: distribution(|info| info.lines),
+            words: distribution(|info| info.words),
+        }
+    }
+
+    pub async fn read_metadata(metadata_path: &Path) -> Result<SplitMetadata> {
+        let json_content = fs::read_to_string(metadata_path).await?;
+
+        serde_json::from_str(&json_content).map_err(|e| MarkdownSplitterError::InvalidMetadata {
+            reason: format!("Failed to parse {}: {}", metadata_path.display(), e),
+        })
+    }
+
+    /// Reassembles a previous split run into a single document, in split order.
+    ///
+    /// Split files are looked up next to the metadata file, so a split directory can be
+    /// moved as a whole. Injected split headers are removed; page separators are kept so
+    /// the original page boundaries survive a re-parse.
+    pub async fn reassemble_splits(metadata_path: &Path) -> Result<(String, SplitMetadata)> {
+        let metadata = Self::read_metadata(metadata_path).await?;
+        let splits_dir = metadata_path.parent().unwrap_or_else(|| Path::new("."));
+
+        let mut split_info = metadata.split_info.clone();
+        split_info.sort_by_key(Self::document_position);
+
+        if split_info.is_empty() {
+            return Err(MarkdownSplitterError::InvalidMetadata {
+                reason: "Metadata does not list any split files".to_string(),
+            });
+        }
+
+        let mut bodies = Vec::new();
+        let mut preserve_structure = false;
+
+        for info in &split_info {
+            let split_path = Self::split_file_path(splits_dir, info);
+            if !split_path.exists() {
+                return Err(MarkdownSplitterError::FileNotFound {
+                    path: split_path.display().to_string(),
+                });
+            }
+
+            let content = fs::read_to_string(&split_path).await?;
+            let content = metadata.line_ending.to_lf(&content);
+            preserve_structure |= Self::strip_split_header(&content).is_some();
+
+            // Prefer the recorded header, which also covers any heading context
+            let body = match content.strip_prefix(info.header.as_str()) {
+                Some(body) if !info.header.is_empty() => Some(body),
+                _ => Self::strip_split_header(&content),
+            };
+            bodies.push(body.unwrap_or(&content).to_string());
+
+            debug!("Reassembled split {} from {}", info.split_number, split_path.display());
+        }
+
+        // Unstructured splits already end with the line terminator of their last page
+        let separator = if preserve_structure { PAGE_SEPARATOR } else { "" };
+        Ok((bodies.join(separator), metadata))
+    }
+
+    /// Reassembles a previous split run and parses it back into a document, stamped
+    /// with the current time or `source_date_epoch` when given.
+    pub async fn reassemble_document(
+        metadata_path: &Path,
+        parser: &MarkdownParser,
+        source_date_epoch: Option<i64>,
+    ) -> Result<MarkdownDocument> {
+        let (content, split_metadata) = Self::reassemble_splits(metadata_path).await?;
+
+        info!(
+            "Reassembled {} split files of '{}'",
+            split_metadata.total_splits, split_metadata.source
+        );
+
+        let metadata = DocumentMetadata {
+            filename: split_metadata.source.clone(),
+            source_type: split_metadata.document_metadata.source_type.clone(),
+            created_at: Self::timestamp(source_date_epoch)?,
+            total_lines: content.lines().count(),
+            page_breaks: Vec::new(),
+            origin: split_metadata.document_metadata.origin.clone(),
+            compression: split_metadata.document_metadata.compression,
+        };
+
+        // The reassembled content has `\n` line breaks; keep the ones the splits were written with
+        let mut document = parser.parse_document(&content, metadata)?;
+        document.line_ending = split_metadata.line_ending.or(document.line_ending);
+        Ok(document)
+    }
+
+    /// Reassembles a previous split run and splits it again with a new configuration.
+    pub async fn resplit(
+        metadata_path: &Path,
+        parser: &MarkdownParser,
+        config: &SplitConfig,
+    ) -> Result<SplitResult> {
+        let document = Self::reassemble_document(metadata_path, parser, config.source_date_epoch).await?;
+        Self::split_document(&document, config).await
+    }
+
+    /// Rebuilds the original document from a metadata file and its split files.
+    ///
+    /// Uses the recorded split headers, page byte lengths and page separator to cut
+    /// each split back into its pages. Fails when the split run was lossy, e.g. when
+    /// leading content was discarded or the split files were edited. The document gets
+    /// the line ending the split files were written with.
+    pub async fn reconstruct(metadata_path: &Path, splits_dir: &Path) -> Result<String> {
+        let metadata = Self::read_metadata(metadata_path).await?;
+        let lossy = |reason: String| MarkdownSplitterError::InvalidMetadata { reason };
+
+        let mut split_info = metadata.split_info.clone();
+        split_info.sort_by_key(Self::document_position);
+
+        let mut pages = Vec::new();
+        for info in &split_info {
+            let split_path = Self::split_file_path(splits_dir, info);
+            let content = fs::read_to_string(&split_path).await?;
+            let content = metadata.line_ending.to_lf(&content);
+
+            let body = content
+                .strip_prefix(info.header.as_str())
+                .and_then(|rest| rest.strip_suffix(info.footer.as_str()))
+                .ok_or_else(|| {
+                    lossy(format!("{} does not match its recorded header", split_path.display()))
+                })?;
+
+            let mut offset = 0;
+            for (idx, page) in info.pages.iter().enumerate() {
+                if idx > 0 {
+                    if !body[offset..].starts_with(metadata.page_separator.as_str()) {
+                        return Err(lossy(format!(
+                            "Missing page separator before page {} in {}",
+                            page.number,
+                            split_path.display()
+                        )));
+                    }
+                    offset += metadata.page_separator.len();
+                }
+
+                let page_content = body
+                    .get(offset..offset + page.bytes)
+                    .ok_or_else(|| lossy(format!("Page {} is truncated", page.number)))?;
+                pages.push((page, page_content.to_string()));
+                offset += page.bytes;
+            }
+
+            if offset != body.len() {
+                return Err(lossy(format!("Unexpected content in {}", split_path.display())));
+            }
+        }
+
+        // Pages must cover every source line, in order, for the result to be exact
+        let mut expected_line = 0;
+        for (page, _) in &pages {
+            if page.start_line != expected_line {
+                return Err(lossy(format!(
+                    "Source lines {} to {} are not part of any split",
+                    expected_line + 1,
+                    page.start_line
+                )));
+            }
+            expected_line = page.end_line;
+        }
+        if expected_line != metadata.document_metadata.total_lines {
+            return Err(lossy("Trailing source lines are not part of any split".to_string()));
+        }
+
+        let mut document = pages
+            .iter()
+            .map(|(_, content)| content.as_str())
+            .collect::<Vec<_>>()
+            .join("\n");
+        if metadata.trailing_newline {
+            document.push('\n');
+        }
+
+        Ok(metadata.line_ending.apply(&document).into_owned())
+    }
+
+    /// Position of a split in the source document; older metadata only has the split number
+    fn document_position(info: &SplitInfo) -> usize {
+        if info.document_order > 0 {
+            info.document_order
+        } else {
+            info.split_number
+        }
+    }
+
+    fn strip_split_header(content: &str) -> Option<&str> {
+        let (first_line, rest) = content.split_once('\n')?;
+        let is_header = first_line.starts_with("<!--")
+            && first_line.ends_with("-->")
+            && first_line.contains(SPLIT_HEADER_LABEL);
+
+        if is_header {
+            Some(rest.strip_prefix('\n').unwrap_or(rest))
+        } else {
+            None
+        }
+    }
+
+    /// Suggests a split count so each split holds roughly `target_bytes` of content.
+    pub fn suggest_split_count(document: &MarkdownDocument, target_bytes: usize) -> usize {
+        let total_bytes: usize = document.pages.iter().map(|p| p.content.len()).sum();
+        let max_splits = document.total_pages.max(1);
+
+        if target_bytes == 0 {
+            return max_splits;
+        }
+
+        total_bytes.div_ceil(target_bytes).clamp(1, max_splits)
+    }
+
+    /// Appends the document's pages to the newest file of a rotating series, starting a
+    /// new file whenever the next page would push the current one past the size limit.
+    ///
+    /// The series is picked up from the files already in the output directory, so
+    /// repeated calls keep filling the same tail file. Returns the files written to.
+    pub async fn append_to_rotating(
+        document: &MarkdownDocument,
+        config: &RotationConfig,
+    ) -> Result<Vec<PathBuf>> {
+        if config.max_bytes == 0 {
+            return Err(MarkdownSplitterError::SplitConfig {
+                reason: "Rotation size must be greater than 0".to_string(),
+            });
+        }
+        Self::ensure_output_directory(&config.output_dir).await?;
+
+        let mut file_number = Self::latest_rotation_number(config).await?.unwrap_or(1);
+        let mut path = Self::rotation_file_path(config, file_number);
+        let mut size = match fs::metadata(&path).await {
+            Ok(metadata) => metadata.len() as usize,
+            Err(_) => 0,
+        };
+
+        let mut pending = String::new();
+        let mut touched = vec![path.clone()];
+
+        for page in &document.pages {
+            let separator = if size == 0 { "" } else { PAGE_SEPARATOR };
+            let added = separator.len() + page.content.len();
+
+            // Oversized pages still get a file of their own
+            if size > 0 && size + added > config.max_bytes {
+                Self::append_to_file(&path, &pending).await?;
+                pending.clear();
+
+                file_number += 1;
+                path = Self::rotation_file_path(config, file_number);
+                size = 0;
+                touched.push(path.clone());
+                debug!("Rolling over to {}", path.display());
+
+                pending.push_str(&page.content);
+                size += page.content.len();
+                continue;
+            }
+
+            pending.push_str(separator);
+            pending.push_str(&page.content);
+            size += added;
+        }
+        Self::append_to_file(&path, &pending).await?;
+
+        info!(
+            "Appended {} pages from '{}' across {} file(s)",
+            document.total_pages,
+            document.source,
+            touched.len()
+        );
+        Ok(touched)
+    }
+
+    fn rotation_file_path(config: &RotationConfig, file_number: usize) -> PathBuf {
+        config
+            .output_dir
+            .join(format!("{}_{:04}.md", config.base_name, file_number))
+    }
+
+    /// Highest file number of the series already present in the output directory
+    async fn latest_rotation_number(config: &RotationConfig) -> Result<Option<usize>> {
+        let prefix = format!("{}_", config.base_name);
+        let mut latest = None;
+
+        let mut entries = fs::read_dir(&config.output_dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name();
+            let number = name
+                .to_str()
+                .and_then(|name| name.strip_prefix(&prefix))
+                .and_then(|rest| rest.strip_suffix(".md"))
+                .filter(|digits| !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit()))
+                .and_then(|digits| digits.parse::<usize>().ok());
+            latest = latest.max(number);
+        }
+
+        Ok(latest)
+    }
+
+    async fn append_to_file(path: &Path, content: &str) -> Result<()> {
+        use tokio::io::AsyncWriteExt;
+
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await
+            .map_err(|e| MarkdownSplitterError::OutputDirectory {
+                reason: format!("Failed to open {}: {}", path.display(), e),
+            })?;
+        file.write_all(content.as_bytes()).await?;
+        // Tokio hands writes to a background task; wait for them before the next step reads
+        file.flush().await?;
+
+        Ok(())
+    }
+
+    /// Content of pages `first..=last` (1-based), joined with the page separator
+    pub fn extract_page_range(
+        document: &MarkdownDocument,
+        first: usize,
+        last: usize,
+    ) -> Result<String> {
+        if first == 0 || first > last || last > document.total_pages {
+            return Err(MarkdownSplitterError::PageSelection {
+                reason: format!(
+                    "pages {}-{} are outside the document's {} pages",
+                    first, last, document.total_pages
+                ),
+            });
+        }
+
+        Ok(document.pages[first - 1..last]
+            .iter()
+            .map(|page| page.content.as_str())
+            .collect::<Vec<_>>()
+            .join(PAGE_SEPARATOR))
+    }
+
+    /// 1-based page ranges `config.strategy` would give each split of the document, for
+    /// previews, planned exactly as `split_document` plans them
+    pub fn calculate_strategy_split_info(
+        document: &MarkdownDocument,
+        config: &SplitConfig,
+    ) -> Result<Vec<(usize, usize)>> {
+        Self::validate_split_config(document, config)?;
+        let ranges = Self::plan_page_ranges(document, config, None)?;
+        Ok(ranges
+            .into_iter()
+            .enumerate()
+            .map(|(idx, range)| match idx {
+                0 => (range.start + 1, range.end),
+                _ => (range.start.saturating_sub(config.overlap_pages) + 1, range.end),
+            })
+            .collect())
+    }
+
+    /// Estimated tokens of one page, for reports
+    pub fn page_tokens(page: &MarkdownPage, tokenizer: &Tokenizer) -> usize {
+        tokenizer.count(&page.content)
+    }
+
+    pub fn calculate_split_info(total_pages: usize, splits: usize) -> (usize, Vec<(usize, usize)>) {
+        Self::calculate_overlapping_split_info(total_pages, splits, 0)
+    }
+
+    /// Like [`Self::calculate_split_info`], with every range after the first starting
+    /// `overlap_pages` earlier to include the pages repeated from the previous split
+    pub fn calculate_overlapping_split_info(
+        total_pages: usize,
+        splits: usize,
+        overlap_pages: usize,
+    ) -> (usize, Vec<(usize, usize)>) {
+        if splits == 0 {
+            return (0, Vec::new());
+        }
+
+        let pages_per_split = total_pages.div_ceil(splits);
+        let mut split_ranges = Vec::new();
+
+        for split_idx in 0..splits {
+            let start_page = split_idx * pages_per_split;
+            let end_page = std::cmp::min(start_page + pages_per_split, total_pages);
+
+            if start_page >= total_pages {
+                break;
+            }
+
+            let start_page = match split_idx {
+                0 => start_page,
+                _ => start_page.saturating_sub(overlap_pages),
+            };
+            split_ranges.push((start_page + 1, end_page)); // 1-based indexing for display
+        }
+
+        (pages_per_split, split_ranges)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[tokio::test]
+    async fn test_transient_write_error_is_retried() {
+        let attempts = Cell::new(0);
+        let result = DocumentSplitter::with_write_retries(2, || {
+            attempts.set(attempts.get() + 1);
+            let outcome = match attempts.get() {
+                1 => Err(std::io::Error::from(ErrorKind::ResourceBusy)),
+                _ => Ok(()),
+            };
+            async move { outcome }
+        })
+        .await;
+
+        assert!(result.is_ok());
+        assert_eq!(attempts.get(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_permanent_write_error_is_not_retried() {
+        let attempts = Cell::new(0);
+        let result = DocumentSplitter::with_write_retries(3, || {
+            attempts.set(attempts.get() + 1);
+            async { Err(std::io::Error::from(ErrorKind::PermissionDenied)) }
+        })
+        .await;
+
+        assert_eq!(result.unwrap_err().kind(), ErrorKind::PermissionDenied);
+        assert_eq!(attempts.get(), 1);
+
+        // Retries run out on an error that never clears
+        attempts.set(0);
+        let result = DocumentSplitter::with_write_retries(1, || {
+            attempts.set(attempts.get() + 1);
+            async { Err(std::io::Error::from(ErrorKind::WouldBlock)) }
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(attempts.get(), 2);
     }
-}
\ No newline at end of file
+}
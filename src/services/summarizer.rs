@@ -0,0 +1,48 @@
+use crate::services::MarkdownParser;
+use std::fmt;
+
+/// Produces the one-line summary written into each split's header
+/// (`SplitConfig::summarizer`).
+pub trait Summarizer: Send + Sync {
+    /// Summarizes the page contents of one split
+    fn summarize(&self, text: &str) -> String;
+}
+
+impl fmt::Debug for dyn Summarizer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Summarizer")
+    }
+}
+
+/// Extractive summary without external services: the first sentence of the first
+/// line of prose, skipping headings, rules, HTML comments and code blocks.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FirstSentence;
+
+impl Summarizer for FirstSentence {
+    fn summarize(&self, text: &str) -> String {
+        let lines: Vec<&str> = text.lines().collect();
+        let fenced = MarkdownParser::fenced_lines(&lines);
+        let line = lines
+            .into_iter()
+            .zip(fenced)
+            .filter(|&(_, fenced)| !fenced)
+            .map(|(line, _)| line.trim())
+            .find(|line| {
+                !line.is_empty()
+                    && !line.starts_with('#')
+                    && !line.starts_with("<!--")
+                    && !line.chars().all(|c| matches!(c, '-' | '*' | '_' | ' '))
+            })
+            .unwrap_or_default();
+
+        // Cut after the first sentence-ending punctuation followed by a space
+        let end = line
+            .char_indices()
+            .find(|&(idx, c)| {
+                matches!(c, '.' | '!' | '?') && line[idx + c.len_utf8()..].starts_with(' ')
+            })
+            .map_or(line.len(), |(idx, c)| idx + c.len_utf8());
+        line[..end].to_string()
+    }
+}
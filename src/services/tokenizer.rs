@@ -0,0 +1,83 @@
+use crate::types::TokenModel;
+use regex::Regex;
+
+/// Characters per token assumed for text without a better estimate
+const CHARS_PER_TOKEN: usize = 4;
+
+/// Punctuation characters merged into one token, as in `##`, `**` or `](`
+const PUNCTUATION_PER_TOKEN: usize = 2;
+
+/// Longer words that cl100k-style vocabularies encode as a single token; words of up
+/// to four letters are a single token anyway
+const COMMON_TOKENS: &[&str] = &[
+    "about", "after", "again", "against", "always", "another", "because", "before", "being", "below",
+    "between", "change", "could", "different", "document", "during", "example", "first", "following",
+    "function", "general", "however", "important", "include", "information", "never", "number",
+    "other", "people", "please", "provide", "return", "section", "should", "since", "something",
+    "still", "string", "system", "table", "their", "there", "these", "thing", "think", "those",
+    "through", "under", "until", "using", "value", "where", "which", "while", "without", "would",
+    "write", "years",
+];
+
+/// Estimates how many tokens a language model needs for a piece of text, without
+/// shipping a real vocabulary.
+///
+/// `TokenModel::Bpe` cuts the text the way tiktoken's pre-tokenizer does (words with
+/// their leading space, digit groups of up to three, punctuation runs, whitespace)
+/// and prices each piece: one token for common words, four characters per token for
+/// other words, one per non-ASCII letter and one per two punctuation characters.
+/// `TokenModel::Chars` is the plain four-characters-per-token rule of thumb.
+#[derive(Debug, Clone)]
+pub struct Tokenizer {
+    model: TokenModel,
+    piece_pattern: Regex,
+}
+
+impl Tokenizer {
+    pub fn new(model: TokenModel) -> Self {
+        Self {
+            model,
+            piece_pattern: Regex::new(r" ?\p{L}+| ?\p{N}{1,3}| ?[^\s\p{L}\p{N}]+|\n+|\s+").unwrap(),
+        }
+    }
+
+    pub fn model(&self) -> TokenModel {
+        self.model
+    }
+
+    /// Estimated token count of `text`
+    pub fn count(&self, text: &str) -> usize {
+        match self.model {
+            TokenModel::Chars => text.chars().count().div_ceil(CHARS_PER_TOKEN),
+            TokenModel::Bpe => self
+                .piece_pattern
+                .find_iter(text)
+                .map(|piece| Self::piece_tokens(piece.as_str()))
+                .sum(),
+        }
+    }
+
+    fn piece_tokens(piece: &str) -> usize {
+        let word = piece.strip_prefix(' ').unwrap_or(piece);
+        let Some(first) = word.chars().next() else {
+            // A lone space
+            return 1;
+        };
+
+        if first.is_alphabetic() {
+            if !word.is_ascii() {
+                return word.chars().count();
+            }
+            let lowercase = word.to_ascii_lowercase();
+            if COMMON_TOKENS.contains(&lowercase.as_str()) {
+                1
+            } else {
+                word.len().div_ceil(CHARS_PER_TOKEN)
+            }
+        } else if first.is_numeric() || first.is_whitespace() {
+            1
+        } else {
+            word.chars().count().div_ceil(PUNCTUATION_PER_TOKEN)
+        }
+    }
+}
@@ -1,52 +1,1025 @@
+use crate::services::{RateLimiter, Summarizer};
+use regex::Regex;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct MarkdownPage {
     pub number: usize,
     pub content: String,
     pub title: Option<String>,
     pub start_line: usize,
     pub end_line: usize,
+    /// Byte offsets of the page in the parsed content: from the start of its first
+    /// line to the end of its last, line terminator excluded
+    #[serde(default)]
+    pub start_offset: usize,
+    #[serde(default)]
+    pub end_offset: usize,
+    /// Page number printed in the source, when the split mode detects one
+    #[serde(default)]
+    pub original_number: Option<usize>,
+    /// Verbatim source line that started this page (`None` for the first page)
+    #[serde(default)]
+    pub break_marker: Option<String>,
+    /// `key: value` pairs of a `---` delimited block opening the page
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub page_frontmatter: Option<BTreeMap<String, String>>,
+    /// Size of `content` in bytes (UTF-8), not characters
+    #[serde(default)]
+    pub byte_len: usize,
+    /// Whitespace-separated words in `content`
+    #[serde(default)]
+    pub word_count: usize,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Content-free projection of a `MarkdownPage` for lightweight listings
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct PageSummary {
+    pub number: usize,
+    pub title: Option<String>,
+    pub start_line: usize,
+    pub end_line: usize,
+    #[serde(default)]
+    pub original_number: Option<usize>,
+    #[serde(default)]
+    pub break_marker: Option<String>,
+}
+
+impl From<&MarkdownPage> for PageSummary {
+    fn from(page: &MarkdownPage) -> Self {
+        Self {
+            number: page.number,
+            title: page.title.clone(),
+            start_line: page.start_line,
+            end_line: page.end_line,
+            original_number: page.original_number,
+            break_marker: page.break_marker.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct MarkdownDocument {
     pub source: String,
     pub total_pages: usize,
     pub pages: Vec<MarkdownPage>,
     pub metadata: DocumentMetadata,
+    /// Whether the source content ended with a line break
+    #[serde(default)]
+    pub trailing_newline: bool,
+    /// Line ending used by most lines of the source, `Lf` or `Crlf`
+    #[serde(default)]
+    pub line_ending: LineEnding,
+    /// ISO 639-3 code of the primary language (requires the `lang-detect` feature)
+    #[serde(default)]
+    pub detected_language: Option<String>,
+    /// `<!-- key: value -->` lines from a comment block at the top of the document
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub comment_metadata: BTreeMap<String, String>,
+    /// YAML (`---`) or TOML (`+++`) front matter opening the document, delimiters included
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub front_matter: Option<String>,
+    /// Small raw pages folded into the page before them (`SplitBy::Auto` only)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub merges: Vec<PageMerge>,
+    /// ATX and setext headings outside code blocks, with their source lines
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub headings: Vec<OutlineEntry>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl MarkdownDocument {
+    /// Page listing without page content, cheap to serialize
+    pub fn summary(&self) -> Vec<PageSummary> {
+        self.pages.iter().map(PageSummary::from).collect()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct DocumentMetadata {
     pub filename: String,
     pub source_type: SourceType,
     pub created_at: String,
     pub total_lines: usize,
     pub page_breaks: Vec<usize>,
+    /// Path or URL the content was fetched from
+    #[serde(default)]
+    pub origin: String,
+    /// Compression the content arrived in, removed before parsing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression: Option<Compression>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Compression of a fetched source, from its `Content-Encoding` or file extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    Gzip,
+    Brotli,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
 pub enum SourceType {
     LocalFile,
     Url,
+    Stdin,
+}
+
+/// How the parser detects page boundaries
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SplitBy {
+    /// Explicit page markers, falling back to rules, page-break comments and headers
+    #[default]
+    Auto,
+    /// Lines holding only a printed page number, as found in OCR'd scans.
+    /// With `max_delta`, a number only counts if it follows the previous one
+    /// by at most that much.
+    BareNumberLines { max_delta: Option<usize> },
+    /// Top-level entries of a leading table of contents (`[text](#anchor)` links),
+    /// breaking at the headings they point to
+    DocumentToc,
+    /// Runs of at least this many consecutive blank lines; the page starts at the
+    /// first line after the run
+    BlankLineRun(usize),
+    /// Notebook cell delimiters (`# %%`, `# %% [markdown]`, `<!-- #region -->`) in
+    /// Jupyter-exported markdown, one page per cell
+    JupyterCells,
+    /// Lines holding nothing but a bold span (`**Title**` or `__Title__`) after a blank
+    /// line, as used for headings in poorly formatted documents; the bold text becomes
+    /// the page title
+    BoldLines,
+    /// Exactly these 1-based line numbers, sorted, each starting a page; detection
+    /// is bypassed entirely
+    Lines(Vec<usize>),
+}
+
+/// What to do with content that precedes the first detected page break
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LeadingRegion {
+    /// Keep it as a page of its own (page 1)
+    #[default]
+    Prepend,
+    /// Drop it from the document
+    Discard,
+    /// Fold it into the first detected page
+    MergeIntoFirst,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ParserOptions {
+    pub custom_page_marker: Option<String>,
+    pub split_by: SplitBy,
+    pub leading_region: LeadingRegion,
+    /// With `SplitBy::BlankLineRun`, drop the blank run that ends each page
+    pub collapse_blank_runs: bool,
+    /// Stop merging small pages into a page once it would exceed this many lines
+    pub max_merged_lines: Option<usize>,
+    /// With `SplitBy::JupyterCells`, leave the cell delimiter lines out of the pages
+    pub strip_cell_delimiters: bool,
+    /// Explicit page markers (`# Page N`, custom marker, ...) needed before they are used
+    /// instead of the fallback patterns; 0 and 1 both mean a single marker is enough
+    pub min_explicit_markers: usize,
+    /// Regex page marker matched against a window of consecutive lines joined with
+    /// `\n`, for markers that span lines; a match must start at the window's first line
+    pub multiline_marker: Option<String>,
+    /// Lines per window for `multiline_marker` (default 3, at most 16)
+    pub marker_window: Option<usize>,
+    /// Heading levels (1-6) that break pages when no explicit markers are found;
+    /// `None` means H1 and H2
+    pub heading_break_levels: Option<Vec<usize>>,
+    /// Move breaks that fall inside a multi-line raw HTML block (`<div>` ... `</div>`,
+    /// `<!--` ... `-->`) to the line after the block closes
+    pub keep_html_intact: bool,
+    /// Columns a tab advances to when measuring indentation (indented code blocks,
+    /// list nesting); 0 means the default of 4
+    pub tab_width: usize,
+}
+
+/// Limits and pre-processing applied while fetching source content
+#[derive(Debug, Clone, Default)]
+pub struct FetchOptions {
+    /// Refuse sources larger than this many bytes
+    pub max_bytes: Option<u64>,
+    /// Pull the markdown out of a wrapper file (JSON, YAML or arbitrary text)
+    pub extract_from: Option<ContentSelector>,
+    /// Rejoin hard-wrapped lines before parsing
+    pub dewrap: Option<DewrapOptions>,
+    /// Pace URL fetches; clones of the options share the limiter
+    pub rate_limit: Option<Arc<RateLimiter>>,
+    /// Serve URL fetches from saved responses in this directory when present
+    pub cache_dir: Option<PathBuf>,
+    /// Save responses fetched from the network into `cache_dir`
+    pub write_cache: bool,
+    /// Give up on a URL request that has not completed after this long
+    pub timeout: Option<Duration>,
+    /// Retry URL requests failing with a connection error, timeout or 5xx status
+    pub retries: u32,
+    /// Wait before the first retry, doubled for each one after; zero means half a second
+    pub retry_backoff: Duration,
+    /// Name the source this in output file names and metadata instead of its file name
+    pub source_name: Option<String>,
+}
+
+/// Which lines dewrapping leaves alone. A line is joined with the next one when it
+/// does not end in terminal punctuation and neither line is blank or structural.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DewrapOptions {
+    /// Keep list items on their own lines instead of pulling continuation lines up
+    pub preserve_lists: bool,
+    /// Never join headings with the line after them
+    pub preserve_headings: bool,
+    /// Leave fenced code blocks untouched
+    pub preserve_code: bool,
+}
+
+impl Default for DewrapOptions {
+    fn default() -> Self {
+        Self {
+            preserve_lists: true,
+            preserve_headings: true,
+            preserve_code: true,
+        }
+    }
+}
+
+/// Where the markdown sits inside a wrapper file
+#[derive(Debug, Clone)]
+pub enum ContentSelector {
+    /// Path into a JSON or YAML document, such as `$.info.description` or
+    /// `$.endpoints[*].docs`; every string it selects is used
+    Path(Vec<PathStep>),
+    /// Every match of the regex; its first capture group when it has one
+    Pattern(Regex),
+}
+
+/// One step of a [`ContentSelector::Path`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathStep {
+    Key(String),
+    Index(usize),
+    /// All elements of an array or values of an object
+    Wildcard,
 }
 
 #[derive(Debug, Clone)]
 pub struct SplitConfig {
     pub splits: usize,
+    /// Fixed file count, or a size budget per file
+    pub strategy: SplitStrategy,
     pub output_dir: PathBuf,
     pub preserve_structure: bool,
     pub include_metadata: bool,
     pub custom_page_marker: Option<String>,
+    /// Distribute split files round-robin across this many `shard-N` subdirectories
+    pub shard_dirs: Option<usize>,
+    /// Merge trailing splits smaller than this many bytes into the preceding split
+    pub min_split_bytes: Option<usize>,
+    /// Repeat the enclosing headings at the top of splits that start below the top level
+    pub include_heading_context: bool,
+    /// Source identifier hashed into chunk IDs (defaults to the document source name),
+    /// so moving or renaming files need not change the IDs
+    pub source_id: Option<String>,
+    /// Write each split's chunk ID into its header comment
+    pub chunk_id_in_header: bool,
+    /// Reflow paragraph text to this many columns when writing splits
+    pub wrap_width: Option<usize>,
+    /// Upper bound on the number of files written for one source
+    pub max_splits_per_source: Option<usize>,
+    /// What to do when a source would exceed `max_splits_per_source`
+    pub split_limit_policy: SplitLimitPolicy,
+    /// How split files are numbered
+    pub order_by: SplitOrder,
+    /// Rewrite `#anchor` links whose heading lands in another split to `file.md#anchor`
+    pub fix_cross_split_anchors: bool,
+    /// Write the source type and origin into each split's header comment
+    pub tag_origin: bool,
+    /// Extra attempts for split and metadata writes failing with a transient error
+    pub write_retries: usize,
+    /// Also write each fenced code block to `<name>_NNN.<ext>` in this directory; the
+    /// source name keeps several sources sharing the directory apart
+    pub extract_code_dir: Option<PathBuf>,
+    /// Leave fenced code blocks out of the split files
+    pub strip_code_blocks: bool,
+    /// Drop lines (running headers, footers) that recur on most pages
+    pub strip_repeated_boilerplate: bool,
+    /// Placement of leftover pages when splitting by page count
+    pub remainder_policy: RemainderPolicy,
+    /// Split on H1 sections instead of into `splits` files, dividing sections that
+    /// exceed the limit into parts named `<name>_secNN-partNN.md`
+    pub section_limit: Option<SectionLimit>,
+    /// Repeat the document's comment metadata block at the top of the splits after the first
+    pub propagate_comment_metadata: bool,
+    /// Repeat the document's front matter at the top of the splits after the first
+    pub repeat_front_matter: bool,
+    /// Open each split with an H1 built from `part_heading_template`
+    pub add_part_headings: bool,
+    /// Part heading text; `{index}` is the split's position in the document and
+    /// `{title}` the first page title in the split
+    pub part_heading_template: String,
+    /// Writes a one-line summary of each split into its header and metadata
+    pub summarizer: Option<Arc<dyn Summarizer>>,
+    /// What to do when a split file already exists
+    pub overwrite_policy: OverwritePolicy,
+    /// Halve the document by size, then each half, this many times over, writing
+    /// `2^depth` files named by their tree path (`<name>_1.2.md`)
+    pub bisect_depth: Option<usize>,
+    /// Number the files back to front, so the last part of the document is file 1;
+    /// pages inside each file keep their order
+    pub reverse_order: bool,
+    /// Only split pages whose title matches this regex; the kept pages are renumbered
+    pub title_filter: Option<String>,
+    /// With `title_filter`, keep pages that have no title instead of dropping them
+    pub keep_untitled_pages: bool,
+    /// Seconds since the Unix epoch written in place of the current time (metadata
+    /// `created_at`, manifest `updated_at`), so repeated runs produce identical files
+    pub source_date_epoch: Option<i64>,
+    /// One file per distinct value of this page frontmatter field, named by the slugified
+    /// value; pages without the field share an `ungrouped` file
+    pub group_by_field: Option<String>,
+    /// Write `<name>_TOC.md` linking each split file with its page range and title
+    pub generate_toc: bool,
+    /// Plan and render everything but write nothing, not even the output directory;
+    /// the `SplitResult` lists the files as if they had been written
+    pub dry_run: bool,
+    /// Names numbered split files from this template instead of
+    /// `<name>_split_NN_of_MM.md`: `{base}` is the source file stem, `{index}` the file
+    /// number (`{index:03}` zero-padded to three digits), `{total}` the file count and
+    /// `{title}` the slug of the split's first page title
+    pub filename_template: Option<String>,
+    /// Repeat the last N pages of the previous split at the top of each split, for
+    /// retrieval where context should not be lost at boundaries. Must be smaller than
+    /// the pages per split.
+    pub overlap_pages: usize,
+    /// Write one `<name>_annotated.md` holding the original pages, with
+    /// `custom_page_marker` (or `<!-- SPLIT n -->`) on its own line where each split
+    /// after the first would start, instead of one file per split. `SplitResult::output_files`
+    /// lists that file and `splits` is empty.
+    pub annotate_only: bool,
+    /// Line ending of the written split files; `Auto` keeps the source's
+    pub line_ending: LineEnding,
+}
+
+/// Default `SplitConfig::part_heading_template`
+pub const DEFAULT_PART_HEADING: &str = "Part {index}: {title}";
+
+impl Default for SplitConfig {
+    fn default() -> Self {
+        Self {
+            splits: 5,
+            strategy: SplitStrategy::Count,
+            output_dir: PathBuf::from("./output"),
+            preserve_structure: true,
+            include_metadata: true,
+            custom_page_marker: None,
+            shard_dirs: None,
+            min_split_bytes: None,
+            include_heading_context: false,
+            source_id: None,
+            chunk_id_in_header: false,
+            wrap_width: None,
+            max_splits_per_source: None,
+            split_limit_policy: SplitLimitPolicy::default(),
+            order_by: SplitOrder::default(),
+            reverse_order: false,
+            title_filter: None,
+            keep_untitled_pages: false,
+            source_date_epoch: None,
+            group_by_field: None,
+            generate_toc: false,
+            dry_run: false,
+            filename_template: None,
+            overlap_pages: 0,
+            annotate_only: false,
+            line_ending: LineEnding::default(),
+            fix_cross_split_anchors: false,
+            tag_origin: false,
+            write_retries: 3,
+            extract_code_dir: None,
+            strip_code_blocks: false,
+            strip_repeated_boilerplate: false,
+            remainder_policy: RemainderPolicy::default(),
+            section_limit: None,
+            propagate_comment_metadata: false,
+            repeat_front_matter: false,
+            add_part_headings: false,
+            part_heading_template: DEFAULT_PART_HEADING.to_string(),
+            summarizer: None,
+            overwrite_policy: OverwritePolicy::default(),
+            bisect_depth: None,
+        }
+    }
+}
+
+/// Order in which split files are numbered
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SplitOrder {
+    /// By position in the source document
+    #[default]
+    Document,
+    /// Largest content first, for handing the biggest files out first
+    Size,
 }
 
+/// Where the leftover pages go when the page count does not divide evenly
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RemainderPolicy {
+    /// Fill every split to the rounded-up size and leave the rest to a smaller last split
+    #[default]
+    Separate,
+    /// One extra page each for the first splits
+    DistributeEarly,
+    /// One extra page each for the last splits
+    DistributeLate,
+}
+
+/// How pages are divided among split files
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SplitStrategy {
+    /// Exactly `SplitConfig::splits` files
+    #[default]
+    Count,
+    /// As many files as needed so none holds more than this many words; whole pages
+    /// are packed in order, and a page over the budget gets a file of its own
+    MaxWords(usize),
+    /// Like `MaxWords`, but counting estimated tokens, including the split's header
+    /// comments and page separators
+    MaxTokens { limit: usize, model: TokenModel },
+    /// Exactly `SplitConfig::splits` files, with whole pages divided in order so the
+    /// files' line counts are as even as possible
+    Balanced,
+}
+
+/// How tokens are estimated (see `Tokenizer`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TokenModel {
+    /// Word and punctuation pieces priced like a cl100k-style BPE vocabulary
+    #[default]
+    Bpe,
+    /// Four characters per token
+    Chars,
+}
+
+/// Cap on the size of one file when splitting by H1 section
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SectionLimit {
+    /// At most this many pages per file
+    Pages(usize),
+    /// At most this many bytes of page content per file (a single larger page still
+    /// gets a file of its own)
+    Bytes(usize),
+}
+
+/// Position of a split within the H1 section it was cut from
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct SectionPart {
+    /// 1-based section number in the document
+    pub section: usize,
+    /// 1-based part number within the section
+    pub part: usize,
+    /// Number of parts the section was divided into
+    pub parts: usize,
+    /// Text of the section's H1 heading
+    pub title: Option<String>,
+}
+
+/// Handling of split files that already exist in the output directory
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OverwritePolicy {
+    /// Replace them
+    #[default]
+    Overwrite,
+    /// Fail the split at the first existing file
+    Error,
+    /// Leave them untouched and list them in `SplitResult::skipped_files`
+    Skip,
+}
+
+/// Line terminator of a document or of written split files
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum LineEnding {
+    /// Whatever the source document uses
+    #[default]
+    Auto,
+    /// `\n`
+    Lf,
+    /// `\r\n`
+    Crlf,
+}
+
+impl LineEnding {
+    /// `Crlf` when at least half of the line breaks in `content` are `\r\n`, otherwise `Lf`
+    pub fn detect(content: &str) -> Self {
+        let breaks = content.matches('\n').count();
+        let crlf = content.matches("\r\n").count();
+        if crlf > 0 && crlf * 2 >= breaks {
+            LineEnding::Crlf
+        } else {
+            LineEnding::Lf
+        }
+    }
+
+    /// This line ending, or `detected` for `Auto`
+    pub fn or(self, detected: LineEnding) -> Self {
+        match self {
+            LineEnding::Auto => detected,
+            ending => ending,
+        }
+    }
+
+    /// `text`, written with `\n` line breaks, converted to this line ending
+    pub fn apply(self, text: &str) -> Cow<'_, str> {
+        match self {
+            LineEnding::Crlf => Cow::Owned(text.replace('\n', "\r\n")),
+            LineEnding::Auto | LineEnding::Lf => Cow::Borrowed(text),
+        }
+    }
+
+    /// `text`, written with this line ending, converted back to `\n` line breaks
+    pub fn to_lf(self, text: &str) -> Cow<'_, str> {
+        match self {
+            LineEnding::Crlf => Cow::Owned(text.replace("\r\n", "\n")),
+            LineEnding::Auto | LineEnding::Lf => Cow::Borrowed(text),
+        }
+    }
+}
+
+/// Handling of sources that would produce more splits than allowed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SplitLimitPolicy {
+    /// Fail the split for that source
+    Error,
+    /// Use fewer, larger splits so the limit is met
+    #[default]
+    Coarsen,
+}
+
+/// Settings for appending pages to a rotating series of files
 #[derive(Debug, Clone)]
+pub struct RotationConfig {
+    pub output_dir: PathBuf,
+    /// Files are named `<base_name>_0001.md`, `<base_name>_0002.md`, ...
+    pub base_name: String,
+    /// Roll over to a new file rather than grow the current one past this size
+    pub max_bytes: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SplitResult {
     pub split_number: usize,
     pub pages_per_split: usize,
     pub actual_pages: usize,
     pub output_files: Vec<PathBuf>,
     pub metadata_file: Option<PathBuf>,
-}
\ No newline at end of file
+    pub splits: Vec<SplitInfo>,
+    /// Anchor links that point at a heading in a different split file
+    pub cross_split_anchors: Vec<CrossSplitAnchor>,
+    /// Code blocks written to `SplitConfig::extract_code_dir`
+    pub code_blocks: Vec<CodeBlockInfo>,
+    /// Recurring lines left out with `SplitConfig::strip_repeated_boilerplate`
+    pub removed_boilerplate: Vec<BoilerplateLine>,
+    /// Problems worth reviewing that did not stop the split
+    pub warnings: Vec<SplitWarning>,
+    /// Existing files left untouched under `OverwritePolicy::Skip`; they are still
+    /// listed in `output_files` and `splits`
+    pub skipped_files: Vec<PathBuf>,
+    /// Table of contents written with `SplitConfig::generate_toc`
+    pub toc_file: Option<PathBuf>,
+    /// Line count of each split file, in `splits` order
+    pub per_split_lines: Vec<usize>,
+}
+
+/// How one source of a split run went
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SourceOutcome {
+    pub source: String,
+    /// Time spent fetching, parsing and splitting the source, in milliseconds
+    pub elapsed_ms: u64,
+    /// Absent when the source failed, or was encoded as data URIs instead of split files
+    pub result: Option<SplitResult>,
+    pub error: Option<String>,
+}
+
+/// Outcome of splitting a batch of sources, in source order, with run totals
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SplitRunReport {
+    pub sources: Vec<SourceOutcome>,
+    /// Split files written across all sources; files kept under `OverwritePolicy::Skip`
+    /// do not count, and nothing does on a dry run
+    pub files_written: usize,
+    /// Pages across all split files
+    pub total_pages: usize,
+    /// Number of sources that failed
+    pub failed: usize,
+    /// Whether the run was a dry run, which writes no files
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+impl SplitRunReport {
+    /// Adds the outcome of the next source and updates the totals
+    pub fn record(&mut self, source: &str, elapsed: Duration, outcome: Result<Option<SplitResult>, String>) {
+        let (result, error) = match outcome {
+            Ok(result) => (result, None),
+            Err(error) => (None, Some(error)),
+        };
+        if let Some(result) = &result {
+            if !self.dry_run {
+                self.files_written += result.output_files.len() - result.skipped_files.len();
+            }
+            self.total_pages += result.actual_pages;
+        }
+        self.failed += usize::from(error.is_some());
+        self.sources.push(SourceOutcome {
+            source: source.to_string(),
+            elapsed_ms: elapsed.as_millis() as u64,
+            result,
+            error,
+        });
+    }
+}
+
+/// Kind of problem recorded in a `SplitWarning`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WarningKind {
+    /// A single page is larger than an even share of the document
+    OversizedPage,
+    /// A small page was folded into the page before it
+    MergedPage,
+    /// The largest split is far larger than the smallest
+    Imbalance,
+    /// A link points at a heading in another split file and was left unchanged
+    CrossSplitAnchor,
+}
+
+/// A problem found while splitting, collected for `--warnings-report`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SplitWarning {
+    pub kind: WarningKind,
+    pub source: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub split_number: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub page: Option<usize>,
+    pub message: String,
+}
+
+/// A line repeated across most pages, such as a running header or footer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BoilerplateLine {
+    /// First occurrence of the line; later ones may differ in numbers and spacing
+    pub text: String,
+    /// Number of pages it appears on
+    pub pages: usize,
+}
+
+/// A fenced code block written to its own file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct CodeBlockInfo {
+    /// 1-based position among the document's code blocks
+    pub index: usize,
+    /// Language from the fence's info string
+    pub language: Option<String>,
+    /// File name within the code directory
+    pub filename: String,
+    /// Page holding the block
+    pub page: usize,
+    /// Source lines spanned by the block, fences included (end exclusive, like pages)
+    pub start_line: usize,
+    pub end_line: usize,
+}
+
+/// An intra-document `[text](#anchor)` link separated from its heading by splitting
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CrossSplitAnchor {
+    /// Split containing the link
+    pub split_number: usize,
+    pub anchor: String,
+    /// Split containing the heading
+    pub target_split_number: usize,
+    /// Path of the target split relative to the linking split
+    pub target_file: String,
+    /// Whether the link was rewritten to `target_file#anchor`
+    pub rewritten: bool,
+}
+
+/// One split encoded as a data URI, as listed in `<name>_data_uris.json`
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct DataUriSplit {
+    pub split_number: usize,
+    pub document_order: usize,
+    /// File name the split would have been written under
+    pub filename: String,
+    pub start_page: usize,
+    pub end_page: usize,
+    pub bytes: usize,
+    pub title: Option<String>,
+    pub chunk_id: String,
+    /// `data:text/markdown;base64,...` holding the full split content
+    pub data_uri: String,
+}
+
+/// What `--manifest` does with a manifest left by an earlier run
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ManifestMode {
+    /// Replace it with the sources of this run
+    #[default]
+    Overwrite,
+    /// Update the entries of this run's sources and keep all others
+    Merge,
+}
+
+/// Split files written for each source, keyed by source path or URL
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct SplitManifest {
+    pub sources: BTreeMap<String, ManifestEntry>,
+}
+
+/// The most recent split of one source recorded in a [`SplitManifest`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct ManifestEntry {
+    /// When the source was last split (RFC 3339)
+    pub updated_at: String,
+    pub files: Vec<String>,
+    pub bytes: usize,
+}
+
+/// How a [`SplitPlan`] divides the pages between files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PlanStrategy {
+    /// Equal page counts, with leftovers placed per `SplitConfig::remainder_policy`
+    EvenPages,
+    /// Page ranges balanced by a cost function, such as bytes or words
+    Balanced,
+    /// One file per H1 section, divided into parts over `SplitConfig::section_limit`
+    Sections,
+    /// Recursive halving by size to `SplitConfig::bisect_depth`
+    Bisect,
+    /// Whole pages packed in order up to the budget of `SplitConfig::strategy`
+    Packed,
+    /// One file per value of `SplitConfig::group_by_field`
+    Group,
+}
+
+/// The files a split would produce, without writing any of them
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct SplitPlan {
+    pub source: String,
+    pub strategy: PlanStrategy,
+    pub total_pages: usize,
+    pub files: Vec<PlannedSplit>,
+}
+
+/// Where a source line ends up when the document is split
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct LineLocation {
+    /// 1-based source line
+    pub line: usize,
+    pub page: usize,
+    pub split_number: usize,
+    pub filename: String,
+}
+
+/// One file of a [`SplitPlan`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct PlannedSplit {
+    pub split_number: usize,
+    pub document_order: usize,
+    pub filename: String,
+    /// First and last page, 1-based and inclusive
+    pub start_page: usize,
+    pub end_page: usize,
+    /// Size of the file as it would be written, header included
+    pub bytes: usize,
+    pub words: usize,
+    /// Rough token count, at four bytes per token
+    pub estimated_tokens: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SplitInfo {
+    /// Number in the file name (document position, or size rank with `SplitOrder::Size`)
+    pub split_number: usize,
+    /// 1-based position of the split in the source document
+    #[serde(default)]
+    pub document_order: usize,
+    pub filename: String,
+    pub path: String,
+    #[serde(default)]
+    pub bytes: usize,
+    #[serde(default)]
+    pub lines: usize,
+    #[serde(default)]
+    pub words: usize,
+    #[serde(default)]
+    pub start_page: usize,
+    #[serde(default)]
+    pub end_page: usize,
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub pages: Vec<PageInfo>,
+    /// Shard subdirectory holding the file, when output is sharded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shard: Option<usize>,
+    /// Hash of the source identifier and page contents, stable across runs
+    #[serde(default)]
+    pub chunk_id: String,
+    /// Kind of source the split was cut from
+    #[serde(default)]
+    pub source_type: Option<SourceType>,
+    /// Path or URL of the source document
+    #[serde(default)]
+    pub origin: String,
+    /// One-line summary from `SplitConfig::summarizer`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+    /// Section and part, when splitting by section
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub section: Option<SectionPart>,
+    /// Headings inside the split, in document order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub outline: Vec<OutlineEntry>,
+    /// Position in the bisection tree, e.g. `1.2` for the second half of the first half
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tree_path: Option<String>,
+    /// Slugified frontmatter value shared by the pages, with `SplitConfig::group_by_field`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    /// Pages of the previous split repeated in the header, with `SplitConfig::overlap_pages`;
+    /// they are not listed in `pages`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub overlap_pages: Vec<usize>,
+    /// Text injected before the first page
+    #[serde(default)]
+    pub header: String,
+    /// Text written after the last page
+    #[serde(default)]
+    pub footer: String,
+}
+
+/// A heading inside a split, as listed in its metadata outline
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct OutlineEntry {
+    pub level: usize,
+    pub text: String,
+    /// Source line of the heading (0-based, like page `start_line`)
+    pub line: usize,
+}
+
+/// Location of a page within the source document, as recorded in split metadata
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct PageInfo {
+    pub number: usize,
+    pub start_line: usize,
+    pub end_line: usize,
+    /// Byte span of the page in the source content (end exclusive)
+    #[serde(default)]
+    pub start_offset: usize,
+    #[serde(default)]
+    pub end_offset: usize,
+    pub break_marker: Option<String>,
+    /// Length of the page content in bytes
+    #[serde(default)]
+    pub bytes: usize,
+    /// Frontmatter block opening the page
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frontmatter: Option<BTreeMap<String, String>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SplitMetadata {
+    pub source: String,
+    pub total_pages: usize,
+    pub total_splits: usize,
+    pub split_files: Vec<String>,
+    pub document_metadata: DocumentMetadata,
+    pub split_info: Vec<SplitInfo>,
+    #[serde(default)]
+    pub statistics: SplitStatistics,
+    /// Text written between consecutive pages of a split
+    #[serde(default)]
+    pub page_separator: String,
+    /// Whether the source document ended with a line break
+    #[serde(default)]
+    pub trailing_newline: bool,
+    /// Line ending the split files were written with; `Auto` in metadata written before
+    /// it was recorded, meaning `Lf`
+    #[serde(default)]
+    pub line_ending: LineEnding,
+    /// ISO 639-3 code of the document's primary language, when detected
+    #[serde(default)]
+    pub detected_language: Option<String>,
+    /// Code blocks extracted to separate files
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub code_blocks: Vec<CodeBlockInfo>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct SplitStatistics {
+    pub bytes: Distribution,
+    pub lines: Distribution,
+    pub words: Distribution,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct Distribution {
+    pub min: usize,
+    pub max: usize,
+    pub mean: f64,
+    pub median: f64,
+}
+
+impl Distribution {
+    /// Summarizes the values; all zero when there are none
+    pub fn of(values: impl IntoIterator<Item = usize>) -> Self {
+        let mut values: Vec<usize> = values.into_iter().collect();
+        values.sort_unstable();
+
+        if values.is_empty() {
+            return Self::default();
+        }
+
+        let mid = values.len() / 2;
+        let median = if values.len().is_multiple_of(2) {
+            (values[mid - 1] + values[mid]) as f64 / 2.0
+        } else {
+            values[mid] as f64
+        };
+
+        Self {
+            min: values[0],
+            max: values[values.len() - 1],
+            mean: values.iter().sum::<usize>() as f64 / values.len() as f64,
+            median,
+        }
+    }
+}
+
+/// Page statistics reported by `analyze`
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct ParsingStats {
+    pub total_pages: usize,
+    pub total_lines: usize,
+    pub page_breaks: usize,
+    pub pages_with_titles: usize,
+    pub avg_lines_per_page: f64,
+    /// Page sizes in bytes
+    #[serde(default)]
+    pub page_bytes: Distribution,
+    /// Words per page
+    #[serde(default)]
+    pub page_words: Distribution,
+}
+
+/// One source's entry in the `analyze --json-output` report
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct AnalysisReport {
+    pub document: MarkdownDocument,
+    pub stats: ParsingStats,
+    /// Raw pages folded into a neighbour (`analyze --explain-merges`)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub merges: Vec<PageMerge>,
+    /// Broken `StructureLimits`, one message each
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub violations: Vec<String>,
+}
+
+/// Expectations a document's structure is checked against by `analyze`
+#[derive(Debug, Clone, Default)]
+pub struct StructureLimits {
+    pub min_pages: Option<usize>,
+    pub max_page_bytes: Option<usize>,
+    /// Every page must have a title
+    pub require_titles: bool,
+}
+
+/// Reachability of a source, checked without downloading its content
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccessCheck {
+    pub source: String,
+    /// Request that produced `status`: `HEAD`, or `GET` for a one-byte range when
+    /// the server refuses HEAD; `None` for local files
+    pub method: Option<String>,
+    pub status: Option<u16>,
+    pub reachable: bool,
+    pub error: Option<String>,
+}
+
+/// A raw page that the parser merged into the page before it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct PageMerge {
+    /// Number of the page as cut at its break, before merging
+    pub raw_page: usize,
+    /// Number of the final page it became part of
+    pub into_page: usize,
+    pub lines: usize,
+    pub reason: String,
+}